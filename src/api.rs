@@ -0,0 +1,368 @@
+use futures_util::{Stream, TryStreamExt};
+use graphql_client::GraphQLQuery;
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+  cursor::Page,
+  metadata::AppMetadata,
+  schema::{
+    self, GetAppLogs, GetDeploymentLogs, RunDeploymentCreation, RunDeploymentList,
+    RunDeploymentPreparation,
+  },
+  service::{GqlResponseExt, Service},
+};
+
+/// How many deployments are fetched per page.
+const DEPLOYMENT_PAGE_SIZE: i64 = 100;
+
+/// The service answered a call with neither data nor an error.
+#[derive(Error, Debug, Diagnostic)]
+#[error("service returned no data for {0}")]
+#[diagnostic(code(boatctl::api::missing_data))]
+pub struct MissingData(pub &'static str);
+
+/// A deployment of an app, as listed by `Service::list_deployments`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Deployment {
+  pub id: String,
+  pub created_at: String,
+  pub live: bool,
+  pub message: Option<String>,
+  pub labels: Vec<DeploymentLabel>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeploymentLabel {
+  pub key: String,
+  pub value: String,
+}
+
+/// Where to upload the package of a new deployment, from `Service::prepare_deployment`.
+#[derive(Debug, Clone)]
+pub struct PreparedDeployment {
+  /// Package to create the deployment from once it is uploaded whole to `url`.
+  pub package: String,
+  pub url: String,
+  /// Header to send the base64 SHA-256 of the package in, if `url` is signed with it.
+  pub checksum_header: Option<String>,
+  /// Offered if an earlier deployment shares files with the package.
+  pub diff: Option<DiffUpload>,
+  /// Offered for a package large enough to upload in parts.
+  pub multipart: Option<MultipartUpload>,
+}
+
+/// Upload of the difference between the package and an earlier deployment.
+#[derive(Debug, Clone)]
+pub struct DiffUpload {
+  pub base_deployment_id: String,
+  /// `PackageManifest` of the base deployment, as JSON.
+  pub base_manifest: String,
+  pub url: String,
+  /// Package to create the deployment from once the diff is uploaded.
+  pub package: String,
+}
+
+/// Upload of the package in parts of `part_size` bytes, one url each.
+#[derive(Debug, Clone)]
+pub struct MultipartUpload {
+  pub upload_id: String,
+  pub part_size: u64,
+  pub part_urls: Vec<String>,
+}
+
+/// A deployment just created by `Service::create_deployment`.
+#[derive(Debug, Clone)]
+pub struct CreatedDeployment {
+  pub id: String,
+  pub app_id: String,
+  pub url: String,
+  pub created_at: String,
+  /// SHA-256 of the package as stored by the service, if it reports it.
+  pub package_sha256: Option<String>,
+}
+
+/// Whose logs `Service::get_logs` fetches.
+#[derive(Debug, Clone, Copy)]
+pub enum LogSource<'a> {
+  /// The current deployment of an app.
+  App(&'a str),
+  Deployment(&'a str),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+  pub ts: i64,
+  pub request_id: String,
+  pub seq: i64,
+  pub message: String,
+}
+
+/// A page of log entries, newest first, with the cursor of the page of older ones.
+#[derive(Debug, Clone)]
+pub struct LogPage {
+  pub entries: Vec<LogEntry>,
+  pub cursor: Option<String>,
+}
+
+impl Service {
+  /// Sends query `Q` and returns its data, which must be there if the service reported no
+  /// error.
+  pub(crate) async fn fetch<Q: GraphQLQuery>(
+    &self,
+    variables: Q::Variables,
+  ) -> anyhow::Result<Q::ResponseData> {
+    let query = Q::build_query(variables);
+    let operation = query.operation_name;
+    self
+      .call::<_, Q::ResponseData>(query)
+      .await?
+      .check_service_error()?
+      .data
+      .ok_or_else(|| MissingData(operation).into())
+  }
+
+  /// Pages of the deployments of an app, in the order of the service.
+  pub fn deployment_pages<'a>(
+    &'a self,
+    app_id: &'a str,
+  ) -> impl Stream<Item = anyhow::Result<Vec<Deployment>>> + 'a {
+    Self::paginate(move |offset: Option<i64>| async move {
+      let offset = offset.unwrap_or(0);
+      self.throttle().await;
+      let data = self
+        .fetch::<RunDeploymentList>(schema::run_deployment_list::Variables {
+          app_id: app_id.to_string(),
+          first: Some(DEPLOYMENT_PAGE_SIZE),
+          offset: Some(offset),
+        })
+        .await?;
+      let items = data
+        .list_deployment
+        .into_iter()
+        .map(|x| Deployment {
+          id: x.id,
+          created_at: x.created_at,
+          live: x.live,
+          message: x.message,
+          labels: x
+            .labels
+            .into_iter()
+            .map(|x| DeploymentLabel {
+              key: x.key,
+              value: x.value,
+            })
+            .collect(),
+        })
+        .collect::<Vec<_>>();
+      // A short page is the last one.
+      let next = (items.len() as i64 == DEPLOYMENT_PAGE_SIZE).then(|| offset + items.len() as i64);
+      Ok(Page { items, next })
+    })
+  }
+
+  /// Lists all deployments of an app, newest first.
+  pub async fn list_deployments(&self, app_id: &str) -> anyhow::Result<Vec<Deployment>> {
+    let mut deployments = self.deployment_pages(app_id).try_concat().await?;
+    deployments.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(deployments)
+  }
+
+  /// Asks where to upload a package with `package_sha256` for a new deployment of an app.
+  pub async fn prepare_deployment(
+    &self,
+    app_id: &str,
+    package_sha256: &str,
+  ) -> anyhow::Result<PreparedDeployment> {
+    let prep = self
+      .fetch::<RunDeploymentPreparation>(schema::run_deployment_preparation::Variables {
+        app_id: app_id.to_string(),
+        package_sha256: Some(package_sha256.to_string()),
+      })
+      .await?
+      .prepare_deployment;
+    let multipart = match prep.multipart {
+      Some(x) => Some(MultipartUpload {
+        upload_id: x.upload_id,
+        part_size: u64::try_from(x.part_size)
+          .ok()
+          .filter(|x| *x > 0)
+          .ok_or_else(|| anyhow::anyhow!("invalid part size: {}", x.part_size))?,
+        part_urls: x.part_urls,
+      }),
+      None => None,
+    };
+    Ok(PreparedDeployment {
+      package: prep.package,
+      url: prep.url,
+      checksum_header: prep.checksum_header,
+      diff: prep.diff.map(|x| DiffUpload {
+        base_deployment_id: x.base_deployment_id,
+        base_manifest: x.base_manifest,
+        url: x.url,
+        package: x.package,
+      }),
+      multipart,
+    })
+  }
+
+  /// Creates a deployment of an app from an uploaded `package`, which the service checks
+  /// against `package_sha256`.
+  pub async fn create_deployment(
+    &self,
+    app_id: &str,
+    package: &str,
+    package_sha256: &str,
+    metadata: &AppMetadata,
+  ) -> anyhow::Result<CreatedDeployment> {
+    let annotations = &metadata.annotations;
+    let created = self
+      .fetch::<RunDeploymentCreation>(schema::run_deployment_creation::Variables {
+        app_id: app_id.to_string(),
+        package: package.to_string(),
+        message: annotations.message.clone(),
+        labels: Some(
+          annotations
+            .labels
+            .iter()
+            .map(
+              |(k, v)| schema::run_deployment_creation::DeploymentLabelInput {
+                key: k.clone(),
+                value: v.clone(),
+              },
+            )
+            .collect(),
+        ),
+        metadata: serde_json::to_string(metadata)?,
+        package_sha256: Some(package_sha256.to_string()),
+      })
+      .await?
+      .create_deployment;
+    Ok(CreatedDeployment {
+      id: created.id,
+      app_id: created.app_id,
+      url: created.url,
+      created_at: created.created_at,
+      package_sha256: created.package_sha256,
+    })
+  }
+
+  /// Fetches up to `first` log entries of `source` from before the `before` cursor, or the
+  /// latest ones without it.
+  pub async fn get_logs(
+    &self,
+    source: LogSource<'_>,
+    first: u32,
+    before: Option<String>,
+  ) -> anyhow::Result<LogPage> {
+    let first = Some(first as i64);
+    let (cursor, entries) = match source {
+      LogSource::App(id) => {
+        let logs = self
+          .fetch::<GetAppLogs>(schema::get_app_logs::Variables {
+            id: id.to_string(),
+            first,
+            before,
+          })
+          .await?
+          .app
+          .ok_or_else(|| anyhow::anyhow!("app {} not found", id))?
+          .current_deployment
+          .ok_or_else(|| anyhow::anyhow!("app {} has no current deployment", id))?
+          .logs;
+        let entries = logs.data.into_iter().map(|x| LogEntry {
+          ts: x.ts,
+          request_id: x.request_id,
+          seq: x.seq,
+          message: x.message,
+        });
+        (logs.cursor, entries.collect())
+      }
+      LogSource::Deployment(id) => {
+        let logs = self
+          .fetch::<GetDeploymentLogs>(schema::get_deployment_logs::Variables {
+            id: id.to_string(),
+            first,
+            before,
+          })
+          .await?
+          .deployment
+          .ok_or_else(|| anyhow::anyhow!("deployment {} not found", id))?
+          .logs;
+        let entries = logs.data.into_iter().map(|x| LogEntry {
+          ts: x.ts,
+          request_id: x.request_id,
+          seq: x.seq,
+          message: x.message,
+        });
+        (logs.cursor, entries.collect())
+      }
+    };
+    Ok(LogPage { entries, cursor })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures_util::StreamExt;
+  use serde_json::json;
+
+  use super::*;
+  use crate::{service::tests::service, transport::MockTransport};
+
+  fn deployments(count: usize) -> serde_json::Value {
+    let deployments = (0..count)
+      .map(|i| {
+        json!({
+          "id": format!("d{}", i),
+          "createdAt": format!("2022-07-01T00:00:{:02}Z", i % 60),
+          "live": false,
+          "message": null,
+          "labels": [],
+        })
+      })
+      .collect::<Vec<_>>();
+    json!({ "data": { "listDeployment": deployments } })
+  }
+
+  fn offsets(mock: &MockTransport) -> Vec<i64> {
+    mock
+      .requests()
+      .iter()
+      .map(|x| {
+        let body: serde_json::Value = serde_json::from_slice(x.body.as_ref().unwrap()).unwrap();
+        body["variables"]["offset"].as_i64().unwrap()
+      })
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn stops_after_a_short_page() {
+    let mock = MockTransport::new();
+    mock.on_graphql("RunDeploymentList", deployments(3));
+    let service = service(&mock);
+    let deployments = service.list_deployments("app").await.unwrap();
+    assert_eq!(deployments.len(), 3);
+    assert_eq!(deployments[0].id, "d2");
+    assert_eq!(offsets(&mock), [0]);
+  }
+
+  #[tokio::test]
+  async fn fetches_the_next_page_after_a_full_one() {
+    let mock = MockTransport::new();
+    mock.on_graphql(
+      "RunDeploymentList",
+      deployments(DEPLOYMENT_PAGE_SIZE as usize),
+    );
+    let service = service(&mock);
+    let pages = service
+      .deployment_pages("app")
+      .take(2)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(pages.len(), 2);
+    assert_eq!(offsets(&mock), [0, DEPLOYMENT_PAGE_SIZE]);
+  }
+}
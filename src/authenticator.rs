@@ -1,13 +1,20 @@
 use std::{
-  path::PathBuf,
+  collections::BTreeMap,
+  path::{Path, PathBuf},
   time::{SystemTime, UNIX_EPOCH},
 };
 
-use data_encoding::{BASE32_NOPAD, BASE64};
+use data_encoding::{BASE32_NOPAD, BASE64, BASE64_NOPAD};
 use ed25519_dalek::{ed25519::signature::Signature, Keypair, PublicKey, SecretKey, Signer};
+use miette::Diagnostic;
 use regex::Regex;
 use reqwest::{header::HeaderValue, Request};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Profile of a credentials file that is used unless another is selected.
+pub const DEFAULT_PROFILE: &str = "default";
 
 #[derive(Deserialize)]
 pub struct CredentialsJson {
@@ -15,23 +22,76 @@ pub struct CredentialsJson {
   pub secret_key: String,
 }
 
+/// Where to load credentials from, from `--credentials` and `--profile-auth`.
+#[derive(Debug, Clone, Default)]
+pub struct CredentialsOptions {
+  /// Credentials file to use instead of `~/.boat/credentials.json`.
+  pub file: Option<String>,
+  /// Profile of the credentials file to use. If set, the file is used even if
+  /// `BOAT_ACCESS_KEY` and `BOAT_SECRET_KEY` are set.
+  pub profile: Option<String>,
+}
+
 pub struct Credentials {
   ak: String,
   keypair: ed25519_dalek::Keypair,
+  source: CredentialsSource,
+}
+
+/// Where a set of credentials was loaded from.
+#[derive(Debug, Clone)]
+pub enum CredentialsSource {
+  Env,
+  /// A credentials file, and the profile in it if it has several.
+  File {
+    path: PathBuf,
+    profile: Option<String>,
+  },
 }
 
+impl std::fmt::Display for CredentialsSource {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CredentialsSource::Env => write!(f, "BOAT_ACCESS_KEY/BOAT_SECRET_KEY environment variables"),
+      CredentialsSource::File {
+        path,
+        profile: None,
+      } => write!(f, "{}", path.display()),
+      CredentialsSource::File {
+        path,
+        profile: Some(profile),
+      } => write!(f, "{} (profile {})", path.display(), profile),
+    }
+  }
+}
+
+/// Every failure to load credentials, with the reason as its source.
+#[derive(Error, Debug, Diagnostic)]
+#[error("cannot load credentials")]
+#[diagnostic(
+  code(boatctl::auth::credentials),
+  help("set BOAT_ACCESS_KEY and BOAT_SECRET_KEY, or create ~/.boat/credentials.json")
+)]
+pub struct CredentialsError(#[source] Box<dyn std::error::Error + Send + Sync>);
+
 impl Credentials {
-  pub fn init(credentials_file: &Option<String>) -> anyhow::Result<Self> {
+  pub fn init(options: &CredentialsOptions) -> anyhow::Result<Self> {
+    Self::load(options).map_err(|e| CredentialsError(e.into()).into())
+  }
+
+  fn load(options: &CredentialsOptions) -> anyhow::Result<Self> {
     let ak_regex = Regex::new(r#"^lha_([0-9a-z]{1,100})$"#).unwrap();
     let sk_regex = Regex::new(r#"^lhs_([0-9a-z]{1,100})$"#).unwrap();
 
-    let (ak, sk) = if let (Ok(ak), Ok(sk)) = (
+    let env = (
       std::env::var("BOAT_ACCESS_KEY"),
       std::env::var("BOAT_SECRET_KEY"),
-    ) {
-      (ak, sk)
+    );
+    let (ak, sk, source) = if let ((Ok(ak), Ok(sk)), None) = (env, &options.profile) {
+      (ak, sk, CredentialsSource::Env)
     } else {
-      let path = credentials_file
+      let path = options
+        .file
         .as_ref()
         .map(|x| PathBuf::from(x.as_str()))
         .unwrap_or_else(|| {
@@ -39,13 +99,12 @@ impl Credentials {
             .unwrap_or_else(|| std::path::PathBuf::from("/"))
             .join(".boat/credentials.json")
         });
-
-      let raw_creds = std::fs::read(&path)
-        .map_err(|e| anyhow::Error::from(e).context("cannot read credentials file"))?;
-
-      let raw_creds: CredentialsJson = serde_json::from_slice(&raw_creds)
-        .map_err(|e| anyhow::Error::from(e).context("cannot decode credentials file"))?;
-      (raw_creds.access_key, raw_creds.secret_key)
+      let (creds, profile) = read_credentials_file(&path, options.profile.as_deref())?;
+      (
+        creds.access_key,
+        creds.secret_key,
+        CredentialsSource::File { path, profile },
+      )
     };
 
     if !ak_regex.is_match(&ak) {
@@ -83,7 +142,25 @@ impl Credentials {
       public: computed_pubkey,
     };
 
-    Ok(Self { ak, keypair })
+    Ok(Self {
+      ak,
+      keypair,
+      source,
+    })
+  }
+
+  pub fn access_key(&self) -> &str {
+    &self.ak
+  }
+
+  pub fn source(&self) -> &CredentialsSource {
+    &self.source
+  }
+
+  /// SHA-256 fingerprint of the public key, in the same format OpenSSH uses.
+  pub fn fingerprint(&self) -> String {
+    let digest = Sha256::digest(self.keypair.public.as_bytes());
+    format!("SHA256:{}", BASE64_NOPAD.encode(&digest))
   }
 
   pub fn annotate_request(&self, req: &mut Request) {
@@ -113,3 +190,86 @@ impl Credentials {
     BASE64.encode(sig.as_bytes())
   }
 }
+
+/// Reads the credentials of `profile`, or of the `default` one, from a credentials file. The
+/// file holds either a single `access_key` and `secret_key`, or objects with them keyed by
+/// profile name. Returns the profile along with the credentials if the file has several.
+fn read_credentials_file(
+  path: &Path,
+  profile: Option<&str>,
+) -> anyhow::Result<(CredentialsJson, Option<String>)> {
+  let raw_creds = std::fs::read(path)
+    .map_err(|e| anyhow::Error::from(e).context("cannot read credentials file"))?;
+  let raw_creds: serde_json::Value = serde_json::from_slice(&raw_creds)
+    .map_err(|e| anyhow::Error::from(e).context("cannot decode credentials file"))?;
+
+  if raw_creds.get("access_key").is_some() {
+    if let Some(profile) = profile.filter(|x| *x != DEFAULT_PROFILE) {
+      anyhow::bail!(
+        "profile `{}` selected, but {} has a single set of credentials rather than profiles",
+        profile,
+        path.display()
+      );
+    }
+    let creds = serde_json::from_value(raw_creds)
+      .map_err(|e| anyhow::Error::from(e).context("cannot decode credentials file"))?;
+    return Ok((creds, None));
+  }
+
+  let mut profiles: BTreeMap<String, CredentialsJson> = serde_json::from_value(raw_creds)
+    .map_err(|e| anyhow::Error::from(e).context("cannot decode credentials file"))?;
+  let profile = profile.unwrap_or(DEFAULT_PROFILE);
+  match profiles.remove(profile) {
+    Some(creds) => Ok((creds, Some(profile.to_string()))),
+    None => anyhow::bail!(
+      "no profile `{}` in {} (profiles: {})",
+      profile,
+      path.display(),
+      profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read(text: &str, profile: Option<&str>) -> anyhow::Result<(String, Option<String>)> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("credentials.json");
+    std::fs::write(&path, text).unwrap();
+    read_credentials_file(&path, profile).map(|(creds, profile)| (creds.access_key, profile))
+  }
+
+  #[test]
+  fn reads_single_credentials() {
+    let text = r#"{ "access_key": "ak", "secret_key": "sk" }"#;
+    assert_eq!(read(text, None).unwrap(), ("ak".into(), None));
+    assert_eq!(read(text, Some("default")).unwrap(), ("ak".into(), None));
+    assert!(read(text, Some("work")).is_err());
+  }
+
+  #[test]
+  fn reads_profiles() {
+    let text = r#"{
+      "default": { "access_key": "ak1", "secret_key": "sk1" },
+      "work": { "access_key": "ak2", "secret_key": "sk2" }
+    }"#;
+    assert_eq!(
+      read(text, None).unwrap(),
+      ("ak1".into(), Some("default".into()))
+    );
+    assert_eq!(
+      read(text, Some("work")).unwrap(),
+      ("ak2".into(), Some("work".into()))
+    );
+    let e = read(text, Some("home")).unwrap_err();
+    assert!(e.to_string().contains("(profiles: default, work)"), "{}", e);
+  }
+
+  #[test]
+  fn rejects_malformed_files() {
+    assert!(read("not json", None).is_err());
+    assert!(read(r#"{ "access_key": "ak" }"#, None).is_err());
+  }
+}
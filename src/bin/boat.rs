@@ -1,103 +1,432 @@
-use std::path::Path;
+use std::{
+  collections::BTreeSet,
+  fs::File,
+  io::{BufWriter, IsTerminal, Read, Write},
+  path::{Path, PathBuf},
+  process::Command,
+  time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::Context;
 use boatctl::{
-  config_loader,
-  metadata::{AppMetadata, PackedAppMetadata},
-  package_builder::build_package,
-  schema::{self, RunDeploymentList},
-  service::{GqlResponseExt, Service},
+  api::{Deployment, LogEntry},
+  authenticator::{Credentials, CredentialsError},
+  cli::{AppsCmd, Cmd, ConfigCmd, EnvCmd, Opt, SchemaCmd, SecretsCmd},
+  client_version::ClientTooOld,
+  config::{AppConfig, AppSpec, SourceMaps, ValueSource},
+  config_crypto,
+  config_editor::ConfigEditor,
+  config_loader::{self, ConfigFormat},
+  dev::ChangeWatcher,
+  doctor::{self, DoctorOptions},
+  env_render, formatter, hooks, json_schema, lint,
+  logloader::LogLoader,
+  metadata::{AppMetadata, DeploymentAnnotations, PackedAppMetadata},
+  migrate,
+  output::{self, ColorPreference, OutputFormat},
+  pack_format::{self, PackFormat},
+  package_builder::{
+    build_artifact, build_bundle, build_package, build_package_from_dir, build_sourcemap_archive,
+    list_package, read_manifest, BuildError, PackageManifest, BUNDLE_METADATA_PATH,
+    BUNDLE_PACKAGE_PATH,
+  },
+  package_file::PackageFile,
+  ping, progress,
+  prompt::{prompt, Confirmer},
+  provenance::Provenance,
+  scaffold::{self, Template},
+  schema,
+  secret_resolver::SecretResolver,
+  service::{DeployResult, NetworkError, Service, ServiceError, UploadError},
+  telemetry,
+  upload_state::UploadJournal,
+  user_config::{self, UserConfig},
+  workspace,
 };
-use graphql_client::GraphQLQuery;
+use dialoguer::{theme::ColorfulTheme, FuzzySelect, Password};
+use miette::Diagnostic;
+use serde::Serialize;
 use structopt::StructOpt;
 use tabled::{Style, Table, Tabled};
+use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
+use tracing::Instrument;
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "boat", about = "Blueboat Cloud CLI")]
-struct Opt {
-  /// Lighthouse service endpoint.
-  #[structopt(
-    long,
-    default_value = "https://magic.blueboat.io/graphql",
-    env = "BOAT_ENDPOINT"
-  )]
-  endpoint: String,
-
-  /// Path to API credentials.
-  #[structopt(long, env = "BOAT_CREDENTIALS")]
-  credentials: Option<String>,
-
-  /// Path to app specification.
-  #[structopt(long, default_value = "Boat.spec.toml", env = "BOAT_SPEC")]
-  spec: String,
-
-  /// Path to app config.
-  #[structopt(long, default_value = "Boat.toml", env = "BOAT_CONFIG")]
-  config: String,
-
-  #[structopt(subcommand)]
-  cmd: Cmd,
-}
-
-#[derive(Debug, StructOpt)]
-enum Cmd {
-  /// Create deployment.
-  Deploy,
-
-  /// Create package for single-tenant or custom deployment.
-  Pack {
-    /// Path to metadata output.
-    #[structopt(long, short = "o")]
-    output: String,
-  },
+/// Top-level failure of a command. Each variant exits with its own status code so that scripts
+/// can tell failure classes apart.
+#[derive(Debug)]
+enum CliError {
+  Config(miette::Report),
+  Auth(anyhow::Error),
+  Network(anyhow::Error),
+  Server(anyhow::Error),
+  Build(anyhow::Error),
+  Other(anyhow::Error),
+}
 
-  /// View logs.
-  #[structopt(alias = "log")]
-  Logs {
-    /// Deployment ID to query logs for. If unspecified, the current deployment is used.
-    deployment: Option<String>,
+impl CliError {
+  fn exit_code(&self) -> i32 {
+    match self {
+      CliError::Other(_) => 1,
+      CliError::Config(_) => 2,
+      CliError::Auth(_) => 3,
+      CliError::Network(_) => 4,
+      CliError::Server(_) => 5,
+      CliError::Build(_) => 6,
+    }
+  }
 
-    /// Page size.
-    #[structopt(short, long, default_value = "100")]
-    page_size: u32,
-  },
+  fn report(&self) {
+    match self {
+      CliError::Config(e) => eprintln!("{:?}", e),
+      CliError::Auth(e) | CliError::Network(e) | CliError::Server(e) => report_diagnostic(e),
+      CliError::Build(e) | CliError::Other(e) => eprintln!("Error: {:?}", e),
+    }
+  }
+}
 
-  /// List deployments.
-  List,
+/// Reports `e` as a diagnostic, with its code and help, if it comes from one of the
+/// diagnostic types of the service and credentials, after the context it was reported in.
+fn report_diagnostic(e: &anyhow::Error) {
+  let found = e.chain().enumerate().find_map(|(i, x)| {
+    let diagnostic: &dyn Diagnostic = if let Some(x) = x.downcast_ref::<ServiceError>() {
+      x
+    } else if let Some(x) = x.downcast_ref::<NetworkError>() {
+      x
+    } else if let Some(x) = x.downcast_ref::<UploadError>() {
+      x
+    } else if let Some(x) = x.downcast_ref::<ClientTooOld>() {
+      x
+    } else {
+      x.downcast_ref::<CredentialsError>()?
+    };
+    Some((i, diagnostic))
+  });
+  let (index, diagnostic) = match found {
+    Some(x) => x,
+    None => {
+      eprintln!("Error: {:?}", e);
+      return;
+    }
+  };
+  for context in e.chain().take(index) {
+    eprintln!("Error: {}", context);
+  }
+  let mut out = String::new();
+  let handler = match output::color() {
+    ColorPreference::Auto => miette::GraphicalReportHandler::new(),
+    ColorPreference::Always => {
+      miette::GraphicalReportHandler::new_themed(miette::GraphicalTheme::unicode())
+    }
+    ColorPreference::Never => {
+      miette::GraphicalReportHandler::new_themed(miette::GraphicalTheme::unicode_nocolor())
+    }
+  };
+  if handler.render_report(&mut out, diagnostic).is_err() {
+    eprintln!("Error: {:?}", e);
+    return;
+  }
+  eprint!("{}", out);
+}
+
+impl From<anyhow::Error> for CliError {
+  fn from(e: anyhow::Error) -> Self {
+    if e.downcast_ref::<BuildError>().is_some() {
+      return CliError::Build(e);
+    }
+    if e.downcast_ref::<CredentialsError>().is_some() {
+      return CliError::Auth(e);
+    }
+    if let Some(x) = e.downcast_ref::<ServiceError>() {
+      return if x.is_unauthorized() {
+        CliError::Auth(e)
+      } else {
+        CliError::Server(e)
+      };
+    }
+    if e.downcast_ref::<NetworkError>().is_some() {
+      return CliError::Network(e);
+    }
+    if e.downcast_ref::<ClientTooOld>().is_some() {
+      return CliError::Server(e);
+    }
+    if let Some(x) = e.downcast_ref::<UploadError>() {
+      return match x {
+        UploadError::Status(_) | UploadError::ChecksumMismatch { .. } => CliError::Server(e),
+        UploadError::Network(_) => CliError::Network(e),
+      };
+    }
+    if let Some(x) = e.downcast_ref::<reqwest::Error>() {
+      // A response that cannot be decoded did reach us; blame the server for it.
+      return if x.is_decode() {
+        CliError::Server(e)
+      } else {
+        CliError::Network(e)
+      };
+    }
+    CliError::Other(e)
+  }
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-  pretty_env_logger::init_timed();
+async fn main() {
+  let matches = Opt::clap().get_matches();
+  let mut opt = Opt::from_clap(&matches);
+  init_logger(&opt);
+  let user_config = user_config::load().unwrap_or_else(|e| {
+    log::warn!("ignoring user config: {:#}", e);
+    UserConfig::default()
+  });
+  opt.apply_user_config(&matches, &user_config);
+  output::set_quiet(opt.quiet);
+  output::set_color(opt.color.unwrap_or_default());
+  progress::set_enabled(!opt.quiet);
+  let telemetry = match user_config.telemetry {
+    Some(false) => None,
+    _ => telemetry::init(),
+  };
 
-  let opt = Opt::from_args();
+  let span = tracing::info_span!(
+    "boat",
+    command = matches.subcommand_name().unwrap_or_default(),
+    exit_code = tracing::field::Empty,
+  );
+  let result = run(&opt).instrument(span.clone()).await;
+  if let Err(e) = &result {
+    span.record("exit_code", &e.exit_code());
+    tracing::error!(parent: &span, "command failed with exit code {}", e.exit_code());
+  }
+  drop(span);
+  if let Some(telemetry) = telemetry {
+    telemetry.export().await;
+  }
 
-  let service = Service::new(&opt.endpoint, &opt.credentials)?;
-  let ((spec_path, spec), (_config_path, config)) =
-    match config_loader::load_from_file(&opt.spec, &opt.config) {
-      Ok(x) => x,
-      Err(e) => {
-        eprintln!("{:?}", e);
-        std::process::exit(1);
-      }
-    };
+  if let Err(e) = result {
+    e.report();
+    std::process::exit(e.exit_code());
+  }
+}
+
+async fn run(opt: &Opt) -> Result<(), CliError> {
+  match &opt.cmd {
+    Cmd::Init { template } => return Ok(init(opt, *template)?),
+    Cmd::Completions { shell } => {
+      Opt::gen_completions_to(*shell, &mut std::io::stdout());
+      return Ok(());
+    }
+    Cmd::Whoami => return Ok(whoami(opt).await?),
+    Cmd::Doctor => return Ok(doctor(opt).await?),
+    Cmd::Ping => return Ok(ping(opt).await?),
+    Cmd::Apps(cmd) => return Ok(apps(opt, cmd).await?),
+    Cmd::Secrets(cmd) => return Ok(secrets(opt, cmd).await?),
+    Cmd::Config(ConfigCmd::Migrate { dry_run }) => return Ok(migrate(opt, *dry_run)?),
+    Cmd::Config(cmd) => return Ok(user_settings(cmd)?),
+    Cmd::Fmt { check } => return Ok(fmt(opt, *check)?),
+    Cmd::Lint { list_rules: true } => {
+      let rows = lint::RULES
+        .iter()
+        .map(|x| RuleEntry {
+          id: x.id,
+          on_load: if x.on_load { "yes" } else { "" },
+          description: x.description,
+        })
+        .collect::<Vec<_>>();
+      println!("{}", Table::new(&rows).with(Style::psql()));
+      return Ok(());
+    }
+    Cmd::Schema(SchemaCmd::Export { output }) => {
+      let (_, spec) = config_loader::load_spec_from_file(&opt.spec).map_err(CliError::Config)?;
+      return Ok(export_schema(&spec, output.as_deref())?);
+    }
+    _ => {}
+  }
+
+  if !opt.all && opt.app.is_empty() {
+    return run_app(opt, &opt.spec, &opt.config, None).await;
+  }
+
+  if !matches!(
+    opt.cmd,
+    Cmd::Deploy { .. } | Cmd::Pack { .. } | Cmd::Validate | Cmd::Lint { .. } | Cmd::List
+  ) {
+    return Err(
+      anyhow::anyhow!(
+        "--all and --app are only supported by deploy, pack, validate, lint and list"
+      )
+      .into(),
+    );
+  }
+  let members =
+    workspace::load(&opt.workspace).map_err(|e| CliError::Config(miette::miette!("{:#}", e)))?;
+  let members = workspace::select(members, opt.all, &opt.app)?;
+  for member in &members {
+    if !opt.quiet {
+      print_member_header(&member.name)?;
+    }
+
+    let spec = member.dir.join(&opt.spec);
+    let config = member.dir.join(&opt.config);
+    run_app(
+      opt,
+      &spec.to_string_lossy(),
+      &config.to_string_lossy(),
+      Some(&member.name),
+    )
+    .await?;
+  }
+  Ok(())
+}
+
+fn print_member_header(name: &str) -> anyhow::Result<()> {
+  let mut stdout = StandardStream::stdout(output::color_choice());
+  stdout.set_color(ColorSpec::new().set_bold(true))?;
+  writeln!(&mut stdout, "==> {}", name)?;
+  stdout.reset()?;
+  Ok(())
+}
+
+/// Sets up logging from `-q`/`-v` and `--debug-http`. `RUST_LOG`, if set, takes precedence.
+fn init_logger(opt: &Opt) {
+  let level = if opt.quiet {
+    log::LevelFilter::Error
+  } else {
+    match opt.verbose {
+      0 => log::LevelFilter::Warn,
+      1 => log::LevelFilter::Info,
+      2 => log::LevelFilter::Debug,
+      _ => log::LevelFilter::Trace,
+    }
+  };
+  let mut builder = pretty_env_logger::formatted_timed_builder();
+  builder.filter_level(level);
+  if opt.debug_http {
+    builder.filter_module("boatctl::http_log", log::LevelFilter::Info);
+  }
+  if let Ok(filters) = std::env::var("RUST_LOG") {
+    builder.parse_filters(&filters);
+  }
+  builder.init();
+}
+
+/// Runs a command that operates on a single app. `workspace_app` is the app's name when the
+/// command was invoked for workspace members.
+async fn run_app(
+  opt: &Opt,
+  spec_path: &str,
+  config_path: &str,
+  workspace_app: Option<&str>,
+) -> Result<(), CliError> {
+  let ((spec_path, spec), (config_path, mut config)) =
+    config_loader::load_from_file(spec_path, config_path, &opt.load_options())
+      .map_err(CliError::Config)?;
+
+  if let Some(app_id) = &opt.app_id {
+    config.id = app_id.clone();
+  }
+
+  if let Cmd::Validate = &opt.cmd {
+    println!(
+      "{} and {} are valid.",
+      spec_path.display(),
+      config_path.display()
+    );
+    return Ok(());
+  }
+  if let Cmd::Lint { .. } = &opt.cmd {
+    println!(
+      "No lint problems in {} and {}.",
+      spec_path.display(),
+      config_path.display()
+    );
+    return Ok(());
+  }
+
+  Ok(run_command(opt, spec_path, spec, config, workspace_app).await?)
+}
+
+/// Runs a command against an app whose spec and config have been loaded.
+async fn run_command(
+  opt: &Opt,
+  spec_path: PathBuf,
+  spec: AppSpec,
+  mut config: AppConfig,
+  workspace_app: Option<&str>,
+) -> anyhow::Result<()> {
+  let service = Service::new(
+    &opt.endpoint,
+    &opt.credentials_options(),
+    &opt.connection_options(),
+  )?
+  .with_retry_policy(opt.retry_policy())
+  .with_persisted_queries(!opt.no_persisted_queries);
   match &opt.cmd {
     Cmd::List => {
-      let q = RunDeploymentList::build_query(schema::run_deployment_list::Variables {
-        app_id: config.id.clone(),
-        first: Some(100),
-        offset: None,
-      });
-      let rsp = service
-        .call::<_, schema::run_deployment_list::ResponseData>(q)
-        .await?
-        .check_service_error()?;
-      let x = rsp
-        .data
-        .as_ref()
-        .map(|x| x.list_deployment.as_slice())
-        .unwrap_or_default();
+      let x = service.list_deployments(&config.id).await?;
       let table_data = x
+        .iter()
+        .map(|x| DeploymentListEntry {
+          id: &x.id,
+          created_at: &x.created_at,
+          live: if x.live { "✔" } else { "" },
+          message: x.message.as_deref().unwrap_or(""),
+          labels: x
+            .labels
+            .iter()
+            .map(|x| format!("{}={}", x.key, x.value))
+            .collect::<Vec<_>>()
+            .join(", "),
+        })
+        .collect::<Vec<_>>();
+      print_output(opt.output, &x, &table_data)?;
+    }
+    Cmd::DeleteDeployment {
+      deployment_ids,
+      keep_last,
+    } => {
+      let deployments = service.list_deployments(&config.id).await?;
+      let targets = if let Some(keep_last) = keep_last {
+        deployments
+          .iter()
+          .skip(*keep_last)
+          .filter(|x| !x.live)
+          .map(|x| x.id.clone())
+          .collect::<Vec<_>>()
+      } else if deployment_ids.is_empty() {
+        anyhow::bail!("specify deployment ids or --keep-last");
+      } else {
+        for id in deployment_ids {
+          if deployments.iter().any(|x| x.id == *id && x.live) {
+            anyhow::bail!("refusing to delete live deployment {}", id);
+          }
+        }
+        deployment_ids.clone()
+      };
+      if targets.is_empty() {
+        println!("Nothing to delete.");
+        return Ok(());
+      }
+      Confirmer::new(opt.yes).confirm_typed(
+        &format!(
+          "Delete {} deployment(s) of app {}?",
+          targets.len(),
+          config.id
+        ),
+        &config.id,
+      )?;
+
+      let mut deleted = vec![];
+      let mut failed = vec![];
+      for (result, id) in service
+        .delete_deployments(&targets)
+        .await
+        .into_iter()
+        .zip(&targets)
+      {
+        match result {
+          Ok(x) => deleted.push(x),
+          Err(e) => failed.push(e.context(format!("cannot delete deployment {}", id))),
+        }
+      }
+      let table_data = deleted
         .iter()
         .map(|x| DeploymentEntry {
           id: &x.id,
@@ -105,44 +434,1226 @@ async fn main() -> anyhow::Result<()> {
           live: if x.live { "✔" } else { "" },
         })
         .collect::<Vec<_>>();
-      let table = Table::new(&table_data).with(Style::psql());
-      println!("{}", table);
+      print_output(opt.output, &deleted, &table_data)?;
+      if let Some(e) = failed.pop() {
+        for e in &failed {
+          log::error!("{:#}", e);
+        }
+        return Err(e);
+      }
+    }
+    Cmd::Status => {
+      let app = service.app_status(&config.id).await?;
+      let table_data = app
+        .current_deployment
+        .iter()
+        .map(|x| DeploymentStatusEntry {
+          id: &x.id,
+          created_at: &x.created_at,
+          live: if x.live { "✔" } else { "" },
+          url: &x.url,
+          package_size: x
+            .package_size
+            .map(|x| format!("{} bytes", x))
+            .unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+      if table_data.is_empty() && !opt.output.is_structured() {
+        println!("App {} has no live deployment.", app.name);
+      } else {
+        print_output(opt.output, &app, &table_data)?;
+      }
     }
     Cmd::Logs {
-      deployment: _deployment,
-      page_size: _page_size,
+      deployment,
+      page_size,
+      follow,
+      interactive,
     } => {
-      anyhow::bail!("Not implemented");
+      let mut deployment = deployment.clone();
+      if deployment.is_none() && interactive_enabled(*interactive) {
+        let deployments = service.list_deployments(&config.id).await?;
+        let index = pick_deployment(&deployments, "Deployment to view logs for")?;
+        deployment = Some(deployments[index].id.clone());
+      }
+      let mut loader = LogLoader::new(&service, &config.id, deployment.as_deref());
+      if *follow {
+        print_log_stream(opt.output, &loader.poll_new_logs(*page_size).await?)?;
+        loop {
+          print_log_stream(opt.output, &loader.wait_for_new_logs(*page_size).await?)?;
+        }
+      } else {
+        let mut logs = loader.load_logs(*page_size).await?;
+        logs.sort_by_key(|x| (x.ts, x.seq));
+        if opt.output.is_structured() {
+          opt.output.print(&logs)?;
+        } else {
+          print_logs(&logs);
+        }
+      }
+    }
+    Cmd::Deploy {
+      dry_run,
+      message,
+      label,
+      wait,
+      promote,
+      wait_timeout,
+      resume,
+      ..
+    } => {
+      let spec_dir = spec_path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("cannot resolve spec parent dir"))?;
+      let resumed = if *resume {
+        let journal = UploadJournal::open(spec_dir)?;
+        let app_id = journal.state().app_id;
+        if app_id != config.id {
+          anyhow::bail!(
+            "the interrupted deployment is of app {}, not {}",
+            app_id,
+            config.id
+          );
+        }
+        Some(journal)
+      } else {
+        None
+      };
+      let started_at = SystemTime::now();
+      let (package, provenance) = match &resumed {
+        Some(journal) => (journal.package()?, journal.state().provenance),
+        None => (
+          build_package(&spec_path, &spec, &config, &opt.build_options())?,
+          Some(Provenance::collect(spec_dir, started_at)),
+        ),
+      };
+      resolve_secrets(&mut config).await?;
+      let mut metadata = AppMetadata::from_config(&spec, &config);
+      metadata.provenance = provenance;
+      metadata.annotations = match &resumed {
+        Some(journal) => journal.state().annotations,
+        None => DeploymentAnnotations {
+          message: message.clone(),
+          labels: label.iter().cloned().collect(),
+        },
+      };
+      if *dry_run {
+        println!("Package size: {} bytes", package.len());
+        println!("Package SHA-256: {}", package.sha256());
+        println!("Files:");
+        for (path, size) in list_package(package.open()?)? {
+          println!("  {} ({} bytes)", path, size);
+        }
+        if spec.sourcemaps == SourceMaps::Upload {
+          if let Some(archive) = build_sourcemap_archive(&spec_path, &spec)? {
+            println!("Source maps to upload:");
+            for file in read_manifest(&archive[..])?.files {
+              println!("  {} ({} bytes)", file.path, file.size);
+            }
+          }
+        }
+        println!("Metadata:");
+        println!("{}", serde_json::to_string_pretty(&metadata.redacted())?);
+        println!("Annotations:");
+        println!("{}", serde_json::to_string_pretty(&metadata.annotations)?);
+        println!("Dry run, not deploying to app {}.", config.id);
+      } else {
+        if let Some(script) = &spec.hooks.pre_deploy {
+          hooks::run(
+            &spec_path,
+            "pre_deploy",
+            script,
+            &[
+              ("BOAT_APP_ID", &config.id),
+              ("BOAT_PACKAGE_SHA256", package.sha256()),
+            ],
+          )?;
+        }
+        let journal = match resumed {
+          Some(x) => x,
+          None => UploadJournal::create(
+            spec_dir,
+            &config.id,
+            &package,
+            &metadata.annotations,
+            metadata.provenance.as_ref(),
+          )?,
+        };
+        let mut result = service
+          .deploy(&config.id, &metadata, &package, Some(&journal))
+          .await
+          .inspect_err(|_| {
+            if !opt.quiet {
+              eprintln!("Run `boat deploy --resume` to resume this deployment.");
+            }
+          })?;
+        journal.finish()?;
+        upload_sourcemaps(&service, &spec_path, &spec, &result.id).await?;
+        if *wait {
+          service
+            .wait_for_deployment(&result.id, false, Duration::from_secs(*wait_timeout))
+            .await?;
+        }
+        if *promote {
+          result.live = service.promote(&result.id).await?.live;
+        }
+        print_deploy_result(opt.output, &result)?;
+        if let Some(script) = &spec.hooks.post_deploy {
+          // The deployment exists whatever the hook does, so a failure does not fail the command.
+          let ran = hooks::run(
+            &spec_path,
+            "post_deploy",
+            script,
+            &[
+              ("BOAT_APP_ID", &config.id),
+              ("BOAT_DEPLOYMENT_ID", &result.id),
+              ("BOAT_PREVIEW_URL", &result.url),
+              ("BOAT_LIVE", if result.live { "true" } else { "false" }),
+            ],
+          );
+          if let Err(e) = ran {
+            log::warn!("{:#}", e);
+          }
+        }
+      }
+    }
+    Cmd::Env(EnvCmd::Print {
+      format,
+      redact_secrets,
+      sources,
+    }) => {
+      let extra_secrets = if config.detached_secrets && Path::new(&opt.secrets).exists() {
+        ConfigEditor::open(Path::new(&opt.secrets))?.entries("secrets")
+      } else {
+        vec![]
+      };
+      let vars = env_render::effective_env(&spec, &config, &extra_secrets, *redact_secrets);
+      let mut value_sources = config.sources.clone();
+      for (k, _) in &extra_secrets {
+        value_sources.insert(
+          k.clone(),
+          ValueSource {
+            file: opt.secrets.clone(),
+            position: None,
+          },
+        );
+      }
+      let sources = if *sources { Some(&value_sources) } else { None };
+      print!("{}", env_render::render(&vars, sources, *format)?);
+    }
+    Cmd::History { deployment_id } => {
+      let d = service.deployment_history(deployment_id).await?;
+      if opt.output.is_structured() {
+        return opt.output.print(&d);
+      }
+      let or_dash = |x: Option<&str>| x.unwrap_or("-").to_string();
+      println!("ID:          {}", d.id);
+      println!("App:         {}", d.app_id);
+      println!("Created at:  {}", d.created_at);
+      println!("Created by:  {}", or_dash(d.creator.as_deref()));
+      println!("Live:        {}", if d.live { "yes" } else { "no" });
+      println!("URL:         {}", d.url);
+      println!("Message:     {}", or_dash(d.message.as_deref()));
+      println!(
+        "Labels:      {}",
+        d.labels
+          .iter()
+          .map(|x| format!("{}={}", x.key, x.value))
+          .collect::<Vec<_>>()
+          .join(", ")
+      );
+      println!(
+        "Package:     {} bytes, sha256 {}",
+        d.package_size
+          .map(|x| x.to_string())
+          .unwrap_or_else(|| "?".into()),
+        or_dash(d.package_sha256.as_deref())
+      );
+      println!("Env keys:    {}", d.env_keys.join(", "));
+      println!(
+        "Log entries: {}",
+        d.log_count
+          .map(|x| x.to_string())
+          .unwrap_or_else(|| "-".into())
+      );
+      if d.promotions.is_empty() {
+        println!("Never promoted.");
+      } else {
+        let table_data = d
+          .promotions
+          .iter()
+          .map(|x| PromotionEntry {
+            promoted_at: &x.promoted_at,
+            promoted_by: x.promoted_by.as_deref().unwrap_or(""),
+            demoted_at: x.demoted_at.as_deref().unwrap_or("(live)"),
+          })
+          .collect::<Vec<_>>();
+        println!("{}", Table::new(&table_data).with(Style::psql()));
+      }
+    }
+    Cmd::Wait {
+      deployment_id,
+      live,
+      timeout,
+    } => {
+      let d = service
+        .wait_for_deployment(deployment_id, *live, Duration::from_secs(*timeout))
+        .await?;
+      if opt.output.is_structured() {
+        return opt.output.print(&d);
+      }
+      println!(
+        "Deployment {} is {}.",
+        d.id,
+        if d.live {
+          "healthy and live"
+        } else {
+          "healthy"
+        }
+      );
+    }
+    Cmd::Open { deployment, print } => {
+      let url = match deployment {
+        Some(id) => service.get_deployment(id).await?.url,
+        None => {
+          service
+            .app_status(&config.id)
+            .await?
+            .current_deployment
+            .ok_or_else(|| anyhow::anyhow!("app has no live deployment"))?
+            .url
+        }
+      };
+      if *print {
+        println!("{}", url);
+      } else {
+        open_url(&url)?;
+      }
+    }
+    Cmd::Promote { deployment_id } => {
+      Confirmer::new(opt.yes).confirm(&format!("Promote deployment {} to live?", deployment_id))?;
+      print_promoted(&service.promote(deployment_id).await?)?;
     }
-    Cmd::Deploy => {
-      let package = build_package(&spec_path, &spec, &config)
-        .map_err(|e| e.context("failed to build package"))?;
-      let metadata = AppMetadata::from_config(&config);
-      service.deploy(&config.id, &metadata, &package).await?;
+    Cmd::Rollback { to, interactive } => {
+      let deployments = service.list_deployments(&config.id).await?;
+      let live_index = deployments.iter().position(|x| x.live);
+      let mut to = to.clone();
+      if to.is_none() && interactive_enabled(*interactive) {
+        let index = pick_deployment(&deployments, "Deployment to roll back to")?;
+        to = Some(deployments[index].id.clone());
+      }
+      let target = match &to {
+        Some(to) => deployments
+          .iter()
+          .find(|x| x.id == *to)
+          .ok_or_else(|| anyhow::anyhow!("deployment {} not found in recent deployments", to))?,
+        None => {
+          let live_index =
+            live_index.ok_or_else(|| anyhow::anyhow!("app has no live deployment"))?;
+          deployments
+            .get(live_index + 1)
+            .ok_or_else(|| anyhow::anyhow!("no deployment found before the live one"))?
+        }
+      };
+      if target.live {
+        anyhow::bail!("deployment {} is already live", target.id);
+      }
+      if let Some(live) = live_index.map(|i| &deployments[i]) {
+        println!(
+          "Current live deployment: {} (created at {})",
+          live.id, live.created_at
+        );
+      }
+      Confirmer::new(opt.yes).confirm(&format!(
+        "Roll back to deployment {} (created at {})?",
+        target.id, target.created_at
+      ))?;
+      print_promoted(&service.promote(&target.id).await?)?;
     }
-    Cmd::Pack { output } => {
-      if !output.ends_with(".json") {
-        anyhow::bail!("metadata output path must end with .json");
+    Cmd::Lock { no_build } => {
+      if ConfigFormat::from_path(&spec_path.to_string_lossy()) != ConfigFormat::Toml {
+        anyhow::bail!("lock only supports TOML specs");
+      }
+      let sha256 = build_artifact(&spec_path, &spec, &config, *no_build)?;
+      let mut editor = ConfigEditor::open(&spec_path)?;
+      editor.set_str("artifact_sha256", &sha256);
+      editor.save()?;
+      println!(
+        "Pinned {} to sha256 {} in {}.",
+        spec.artifact,
+        sha256,
+        spec_path.display()
+      );
+    }
+    Cmd::Pack {
+      output,
+      format,
+      bundle,
+      report,
+      top,
+      from_dir,
+      artifact,
+      ..
+    } => {
+      let package = match from_dir {
+        Some(dir) => build_package_from_dir(
+          &spec_path,
+          &spec,
+          Path::new(dir),
+          artifact.as_deref().unwrap_or("index.js"),
+          &opt.build_options(),
+        )?,
+        None => build_package(&spec_path, &spec, &config, &opt.build_options())?,
+      };
+      resolve_secrets(&mut config).await?;
+      let metadata = AppMetadata::from_config(&spec, &config);
+      match workspace_app {
+        Some(name) => {
+          let output = member_output(output, name);
+          write_pack(
+            &output.to_string_lossy(),
+            *format,
+            *bundle,
+            &metadata,
+            &package,
+          )?;
+        }
+        None => write_pack(output, *format, *bundle, &metadata, &package)?,
       }
-      let package_output = format!("{}.tar", output.strip_suffix(".json").unwrap());
+      if *report {
+        print_package_report(opt.output, &read_manifest(package.open()?)?, *top)?;
+      }
+    }
+    Cmd::Dev {
+      deploy,
+      output,
+      debounce_ms,
+    } => {
+      dev(
+        opt,
+        &service,
+        &spec_path,
+        *deploy,
+        output,
+        Duration::from_millis(*debounce_ms),
+      )
+      .await?;
+    }
+    Cmd::Init { .. }
+    | Cmd::Validate
+    | Cmd::Lint { .. }
+    | Cmd::Completions { .. }
+    | Cmd::Whoami
+    | Cmd::Doctor
+    | Cmd::Ping
+    | Cmd::Apps(_)
+    | Cmd::Secrets(_)
+    | Cmd::Config(_)
+    | Cmd::Fmt { .. }
+    | Cmd::Schema(_) => {
+      unreachable!()
+    }
+  }
+  Ok(())
+}
+
+/// Keeps outputs of workspace members apart: `out.json` becomes `out.<name>.json`. Only the file
+/// name is changed, so dots in directory names are left alone.
+fn member_output(output: &str, name: &str) -> PathBuf {
+  let path = Path::new(output);
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let file_name = match path.extension() {
+    Some(ext) => format!("{}.{}.{}", stem, name, ext.to_string_lossy()),
+    None => format!("{}.{}", stem, name),
+  };
+  path.with_file_name(file_name)
+}
 
-      let package = build_package(&spec_path, &spec, &config)
-        .map_err(|e| e.context("failed to build package"))?;
-      let package_filename = Path::new(&package_output)
-        .file_name()
-        .expect("failed to extract file name from package path")
-        .to_string_lossy();
-      let metadata = AppMetadata::from_config(&config);
-      let metadata = PackedAppMetadata::new(&metadata, &package_filename)?;
-      std::fs::write(output, serde_json::to_string_pretty(&metadata)?)
-        .with_context(|| format!("failed to write metadata to {}", output))?;
-      std::fs::write(&package_output, &package)
-        .with_context(|| format!("failed to write package to {}", package_output))?;
+/// Writes `package` and its metadata for single-tenant or custom deployment.
+fn write_pack(
+  output: &str,
+  format: PackFormat,
+  bundle: bool,
+  metadata: &AppMetadata,
+  package: &PackageFile,
+) -> anyhow::Result<()> {
+  match format {
+    PackFormat::Tar => {}
+    PackFormat::Zip => {
+      if !output.ends_with(".zip") {
+        anyhow::bail!("zip output path must end with .zip");
+      }
+      let metadata = PackedAppMetadata::new(metadata, BUNDLE_PACKAGE_PATH, package.sha256())?;
+      let metadata = serde_json::to_vec_pretty(&metadata)?;
+      let write = || -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(output)?);
+        pack_format::write_zip(
+          file,
+          vec![
+            (BUNDLE_METADATA_PATH, Box::new(&metadata[..])),
+            (BUNDLE_PACKAGE_PATH, Box::new(package.open()?)),
+          ],
+        )?
+        .flush()?;
+        Ok(())
+      };
+      write().with_context(|| format!("failed to write zip to {}", output))?;
+      return Ok(());
+    }
+    PackFormat::Oci => {
+      let metadata = PackedAppMetadata::new(metadata, BUNDLE_PACKAGE_PATH, package.sha256())?;
+      let (layer, _) = PackageFile::write(|out| build_bundle(out, &metadata, package).map(drop))?;
+      pack_format::write_oci_image(Path::new(output), &layer)
+        .with_context(|| format!("failed to write OCI image to {}", output))?;
+      return Ok(());
     }
   }
+
+  if bundle {
+    if !output.ends_with(".tar") {
+      anyhow::bail!("bundle output path must end with .tar");
+    }
+    let metadata = PackedAppMetadata::new(metadata, BUNDLE_PACKAGE_PATH, package.sha256())?;
+    let write = || -> anyhow::Result<()> {
+      let file = BufWriter::new(File::create(output)?);
+      build_bundle(file, &metadata, package)?.flush()?;
+      Ok(())
+    };
+    write().with_context(|| format!("failed to write bundle to {}", output))?;
+    return Ok(());
+  }
+
+  if !output.ends_with(".json") {
+    anyhow::bail!("metadata output path must end with .json");
+  }
+  let package_output = format!("{}.tar", output.strip_suffix(".json").unwrap());
+
+  let package_filename = Path::new(&package_output)
+    .file_name()
+    .expect("failed to extract file name from package path")
+    .to_string_lossy();
+  let metadata = PackedAppMetadata::new(metadata, &package_filename, package.sha256())?;
+  std::fs::write(output, serde_json::to_string_pretty(&metadata)?)
+    .with_context(|| format!("failed to write metadata to {}", output))?;
+  std::fs::copy(package.path(), &package_output)
+    .with_context(|| format!("failed to write package to {}", package_output))?;
+  Ok(())
+}
+
+async fn dev(
+  opt: &Opt,
+  service: &Service,
+  spec_path: &Path,
+  deploy: bool,
+  output: &str,
+  debounce: Duration,
+) -> anyhow::Result<()> {
+  let spec_dir = spec_path
+    .parent()
+    .ok_or_else(|| anyhow::anyhow!("cannot resolve spec parent dir"))?;
+  let output_dir = match Path::new(output).parent() {
+    Some(x) if !x.as_os_str().is_empty() => {
+      std::fs::create_dir_all(x)?;
+      x.canonicalize()?
+    }
+    _ => std::env::current_dir()?,
+  };
+  let mut ignored = vec![];
+  if !deploy {
+    ignored.push(output_dir);
+  }
+  let mut watcher = ChangeWatcher::new(spec_dir, ignored)?;
+
+  if !opt.quiet {
+    println!("Watching {} for changes.", spec_dir.display());
+  }
+  let mut changed = BTreeSet::new();
+  for cycle in 1.. {
+    let start = Instant::now();
+    let result = dev_cycle(opt, service, deploy, output).await;
+    watcher.discard_pending();
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let mut stdout = StandardStream::stdout(output::color_choice());
+    match result {
+      Ok(summary) => {
+        stdout.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Green)))?;
+        write!(&mut stdout, "[cycle {}] ok", cycle)?;
+        stdout.reset()?;
+        writeln!(
+          &mut stdout,
+          " in {:.1}s, {} changed file(s): {}",
+          elapsed,
+          changed.len(),
+          summary
+        )?;
+      }
+      Err(e) => {
+        stdout.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Red)))?;
+        write!(&mut stdout, "[cycle {}] failed", cycle)?;
+        stdout.reset()?;
+        writeln!(&mut stdout, " in {:.1}s: {:?}", elapsed, e)?;
+      }
+    }
+
+    changed = watcher.next_change(debounce).await?;
+  }
+  Ok(())
+}
+
+/// Uploads the source maps of a new deployment, with `sourcemaps = "upload"` in the spec.
+async fn upload_sourcemaps(
+  service: &Service,
+  spec_path: &Path,
+  spec: &AppSpec,
+  deployment_id: &str,
+) -> anyhow::Result<()> {
+  if spec.sourcemaps != SourceMaps::Upload {
+    return Ok(());
+  }
+  if let Some(archive) = build_sourcemap_archive(spec_path, spec)? {
+    service
+      .upload_sourcemaps(deployment_id, &archive)
+      .await
+      .context("failed to upload source maps")?;
+  }
+  Ok(())
+}
+
+/// Replaces secrets in `config` that refer to an external store with their values. Relative
+/// paths in references were resolved against the directory of their config when it was loaded.
+async fn resolve_secrets(config: &mut AppConfig) -> anyhow::Result<()> {
+  SecretResolver::with_default_backends()
+    .resolve_config(config)
+    .await
+}
+
+/// Runs one build of `boat dev` and returns a short summary of what was produced.
+async fn dev_cycle(
+  opt: &Opt,
+  service: &Service,
+  deploy: bool,
+  output: &str,
+) -> anyhow::Result<String> {
+  let ((spec_path, spec), (_, mut config)) =
+    config_loader::load_from_file(&opt.spec, &opt.config, &opt.load_options())
+      .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+  if let Some(app_id) = &opt.app_id {
+    config.id = app_id.clone();
+  }
+  let started_at = SystemTime::now();
+  let package = build_package(&spec_path, &spec, &config, &opt.build_options())?;
+  let spec_dir = spec_path.parent().unwrap_or_else(|| Path::new(""));
+  let provenance = Provenance::collect(spec_dir, started_at);
+  resolve_secrets(&mut config).await?;
+  let mut metadata = AppMetadata::from_config(&spec, &config);
+  metadata.provenance = Some(provenance);
+  if deploy {
+    let result = service
+      .deploy(&config.id, &metadata, &package, None)
+      .await?;
+    upload_sourcemaps(service, &spec_path, &spec, &result.id).await?;
+    Ok(format!(
+      "deployed {} bytes as {} ({})",
+      result.package_size, result.id, result.url
+    ))
+  } else {
+    write_pack(output, PackFormat::Tar, false, &metadata, &package)?;
+    Ok(format!("packed {} bytes to {}", package.len(), output))
+  }
+}
+
+async fn doctor(opt: &Opt) -> anyhow::Result<()> {
+  let results = doctor::run_checks(&DoctorOptions {
+    endpoint: &opt.endpoint,
+    credentials: opt.credentials_options(),
+    connection: opt.connection_options(),
+    spec: &opt.spec,
+    config: &opt.config,
+    load: opt.load_options(),
+  })
+  .await;
+
+  let mut stdout = StandardStream::stdout(output::color_choice());
+  for result in &results {
+    let (label, color) = if result.ok {
+      ("PASS", Color::Green)
+    } else {
+      ("FAIL", Color::Red)
+    };
+    stdout.set_color(ColorSpec::new().set_bold(true).set_fg(Some(color)))?;
+    write!(&mut stdout, "[{}]", label)?;
+    stdout.reset()?;
+    writeln!(&mut stdout, " {}: {}", result.name, result.detail)?;
+    if let Some(hint) = &result.hint {
+      writeln!(&mut stdout, "       hint: {}", hint)?;
+    }
+  }
+
+  let failed = results.iter().filter(|x| !x.ok).count();
+  if failed != 0 {
+    anyhow::bail!("{} check(s) failed", failed);
+  }
+  Ok(())
+}
+
+async fn ping(opt: &Opt) -> anyhow::Result<()> {
+  if opt.proxy.is_some() {
+    log::warn!("--proxy is not used by `boat ping`, which connects to the endpoint directly");
+  }
+  let report = ping::ping(&opt.endpoint, &opt.connection_options()).await?;
+  let ms = |x: Duration| x.as_secs_f64() * 1000.0;
+
+  if opt.output.is_structured() {
+    opt.output.print(&PingOutput {
+      endpoint: &opt.endpoint,
+      address: report.address.clone(),
+      region: report.region.as_deref(),
+      status: report.status,
+      dns_ms: ms(report.dns),
+      connect_ms: ms(report.connect),
+      tls_ms: report.tls.map(ms),
+      ttfb_ms: ms(report.ttfb),
+      total_ms: ms(report.total),
+    })?;
+  } else {
+    println!("Endpoint: {}", opt.endpoint);
+    println!("Address:  {}", report.address);
+    println!("Region:   {}", report.region.as_deref().unwrap_or("-"));
+    println!("Status:   {}", report.status);
+    println!("DNS:      {:.1} ms", ms(report.dns));
+    println!("Connect:  {:.1} ms", ms(report.connect));
+    match report.tls {
+      Some(tls) => println!("TLS:      {:.1} ms", ms(tls)),
+      None => println!("TLS:      -"),
+    }
+    println!("TTFB:     {:.1} ms", ms(report.ttfb));
+    println!("Total:    {:.1} ms", ms(report.total));
+  }
+
+  // The query needs no credentials, so any error status is the service's.
+  if !(200..300).contains(&report.status) {
+    anyhow::bail!("endpoint returned error status: {}", report.status);
+  }
+  Ok(())
+}
+
+async fn whoami(opt: &Opt) -> anyhow::Result<()> {
+  // Load credentials directly so that errors are reported instead of logged.
+  let creds = Credentials::init(&opt.credentials_options())?;
+  let service = Service::with_credentials(&opt.endpoint, Some(creds), &opt.connection_options())?
+    .with_retry_policy(opt.retry_policy())
+    .with_persisted_queries(!opt.no_persisted_queries);
+  let creds = service.credentials().unwrap();
+  let viewer = service.viewer().await?;
+
+  if opt.output.is_structured() {
+    return opt.output.print(&WhoamiOutput {
+      account_id: &viewer.account_id,
+      account_name: &viewer.account_name,
+      organization: viewer.organization.as_deref(),
+      access_key: creds.access_key(),
+      fingerprint: creds.fingerprint(),
+      source: creds.source().to_string(),
+    });
+  }
+  println!(
+    "Account:      {} ({})",
+    viewer.account_name, viewer.account_id
+  );
+  println!(
+    "Organization: {}",
+    viewer.organization.as_deref().unwrap_or("-")
+  );
+  println!("Access key:   {}", creds.access_key());
+  println!("Fingerprint:  {}", creds.fingerprint());
+  println!("Loaded from:  {}", creds.source());
+  Ok(())
+}
+
+async fn apps(opt: &Opt, cmd: &AppsCmd) -> anyhow::Result<()> {
+  let service = Service::new(
+    &opt.endpoint,
+    &opt.credentials_options(),
+    &opt.connection_options(),
+  )?
+  .with_retry_policy(opt.retry_policy())
+  .with_persisted_queries(!opt.no_persisted_queries);
+  match cmd {
+    AppsCmd::List => {
+      let apps = service.list_apps().await?;
+      let table_data = apps
+        .iter()
+        .map(|x| AppEntry {
+          id: &x.id,
+          name: &x.name,
+          created_at: &x.created_at,
+          current_deployment: x.current_deployment_id.as_deref().unwrap_or(""),
+        })
+        .collect::<Vec<_>>();
+      print_output(opt.output, &apps, &table_data)?;
+    }
+  }
+  Ok(())
+}
+
+fn fmt(opt: &Opt, check: bool) -> anyhow::Result<()> {
+  let files = [
+    (
+      &opt.spec,
+      formatter::format_spec as fn(&str) -> anyhow::Result<String>,
+    ),
+    (&opt.config, formatter::format_config),
+  ];
+  let mut unformatted = vec![];
+  for (path, format) in files {
+    let path = config_loader::find_source_file(path);
+    if ConfigFormat::from_path(&path.to_string_lossy()) != ConfigFormat::Toml {
+      log::warn!(
+        "skipping {}: only TOML files can be formatted",
+        path.display()
+      );
+      continue;
+    }
+    let text =
+      std::fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?;
+    if config_crypto::is_encrypted(&text) {
+      log::warn!("skipping {}: file is encrypted", path.display());
+      continue;
+    }
+    let formatted = format(&text).with_context(|| format!("cannot format {}", path.display()))?;
+    if formatted == text {
+      continue;
+    }
+    if check {
+      println!("{} is not formatted.", path.display());
+      unformatted.push(path);
+    } else {
+      std::fs::write(&path, formatted)
+        .with_context(|| format!("cannot write {}", path.display()))?;
+      println!("Formatted {}.", path.display());
+    }
+  }
+  if !unformatted.is_empty() {
+    anyhow::bail!("run `boat fmt` to format the files");
+  }
+  Ok(())
+}
+
+fn migrate(opt: &Opt, dry_run: bool) -> anyhow::Result<()> {
+  let files = [
+    (&opt.spec, migrate::migrate_spec as migrate::Migration),
+    (&opt.config, migrate::migrate_config),
+  ];
+  for (path, migrate) in files {
+    let path = config_loader::find_source_file(path);
+    if ConfigFormat::from_path(&path.to_string_lossy()) != ConfigFormat::Toml {
+      anyhow::bail!("{}: only TOML files can be migrated", path.display());
+    }
+    let text =
+      std::fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?;
+    let (migrated, changes) =
+      migrate(&text).with_context(|| format!("cannot migrate {}", path.display()))?;
+    if changes.is_empty() {
+      println!("{} is up to date.", path.display());
+      continue;
+    }
+    for change in &changes {
+      println!("{}: {}", path.display(), change);
+    }
+    if !dry_run {
+      std::fs::write(&path, migrated)
+        .with_context(|| format!("cannot write {}", path.display()))?;
+    }
+  }
+  Ok(())
+}
+
+/// `boat config get`, `set` and `unset`, on `~/.boat/config.toml`.
+fn user_settings(cmd: &ConfigCmd) -> anyhow::Result<()> {
+  match cmd {
+    ConfigCmd::Get { key: Some(key) } => match user_config::get(key)? {
+      Some(value) => println!("{}", value),
+      None => anyhow::bail!("`{}` is not set", key),
+    },
+    ConfigCmd::Get { key: None } => {
+      for (key, value) in user_config::entries()? {
+        println!("{} = {}", key, value);
+      }
+    }
+    ConfigCmd::Set { key, value } => {
+      let path = user_config::set(key, value)?;
+      if !output::is_quiet() {
+        println!("Set `{}` in {}.", key, path.display());
+      }
+    }
+    ConfigCmd::Unset { key } => {
+      if !user_config::unset(key)? && !output::is_quiet() {
+        println!("`{}` was not set.", key);
+      }
+    }
+    ConfigCmd::Migrate { .. } => unreachable!(),
+  }
+  Ok(())
+}
+
+fn export_schema(spec: &AppSpec, output: Option<&str>) -> anyhow::Result<()> {
+  let schema = serde_json::to_string_pretty(&json_schema::config_schema(spec))?;
+  match output {
+    Some(path) => {
+      std::fs::write(path, schema + "\n").with_context(|| format!("cannot write {}", path))?;
+      if !output::is_quiet() {
+        println!("Wrote config schema to {}.", path);
+      }
+    }
+    None => println!("{}", schema),
+  }
+  Ok(())
+}
+
+async fn secrets(opt: &Opt, cmd: &SecretsCmd) -> anyhow::Result<()> {
+  let config = ConfigEditor::open(Path::new(&opt.config))?;
+  let detached = config.get_bool("detached_secrets").unwrap_or(false);
+
+  // With detached secrets, values live in a separate uncommitted file and in the service.
+  let (mut editor, path) = if detached {
+    (
+      ConfigEditor::open_or_create(Path::new(&opt.secrets))?,
+      opt.secrets.as_str(),
+    )
+  } else {
+    (
+      ConfigEditor::open(Path::new(&opt.config))?,
+      opt.config.as_str(),
+    )
+  };
+  let save = |editor: &ConfigEditor| {
+    if detached {
+      editor.save_private()
+    } else {
+      editor.save()
+    }
+  };
+
+  match cmd {
+    SecretsCmd::Set { key } => {
+      if config.contains("env", key) {
+        anyhow::bail!("{} is defined as env in {}", key, opt.config);
+      }
+      let value = if std::io::stdin().is_terminal() {
+        Password::new()
+          .with_prompt(format!("Value for {}", key))
+          .interact()?
+      } else {
+        let mut value = String::new();
+        std::io::stdin().read_to_string(&mut value)?;
+        value.trim_end_matches(&['\r', '\n'][..]).to_string()
+      };
+      editor.set("secrets", key, &value)?;
+      save(&editor)?;
+      println!("Set secret {} in {}.", key, path);
+    }
+    SecretsCmd::Unset { key } => {
+      if !editor.unset("secrets", key) {
+        anyhow::bail!("secret {} is not defined in {}", key, path);
+      }
+      save(&editor)?;
+      println!("Removed secret {} from {}.", key, path);
+    }
+    SecretsCmd::List => {
+      let keys = editor.keys("secrets");
+      let table_data = keys
+        .iter()
+        .map(|x| SecretEntry { key: x })
+        .collect::<Vec<_>>();
+      print_output(opt.output, &keys, &table_data)?;
+    }
+    SecretsCmd::Push | SecretsCmd::Pull => {
+      if !detached {
+        anyhow::bail!(
+          "secrets push/pull require `detached_secrets = true` in {}",
+          opt.config
+        );
+      }
+      let app_id = match &opt.app_id {
+        Some(x) => x.clone(),
+        None => config
+          .get_str("id")
+          .ok_or_else(|| anyhow::anyhow!("missing app id in {}", opt.config))?
+          .to_string(),
+      };
+      let service = Service::new(
+        &opt.endpoint,
+        &opt.credentials_options(),
+        &opt.connection_options(),
+      )?
+      .with_retry_policy(opt.retry_policy())
+      .with_persisted_queries(!opt.no_persisted_queries);
+      if let SecretsCmd::Push = cmd {
+        let secrets = editor.entries("secrets");
+        service.put_app_secrets(&app_id, &secrets).await?;
+        println!("Pushed {} secret(s) to app {}.", secrets.len(), app_id);
+      } else {
+        let secrets = service.app_secrets(&app_id).await?;
+        for key in editor.keys("secrets") {
+          editor.unset("secrets", &key);
+        }
+        for (k, v) in &secrets {
+          editor.set("secrets", k, v)?;
+        }
+        save(&editor)?;
+        println!("Pulled {} secret(s) into {}.", secrets.len(), path);
+      }
+    }
+    SecretsCmd::Encrypt {
+      age_recipients,
+      output,
+    } => {
+      let input = Path::new(&opt.config);
+      let output = match output {
+        Some(x) => PathBuf::from(x),
+        None => config_crypto::encrypted_path(input),
+      };
+      config_crypto::encrypt(input, &output, age_recipients)?;
+      println!("Encrypted {} into {}.", input.display(), output.display());
+      if !opt.quiet {
+        println!(
+          "Remove {} or keep it out of version control; {} is used when it is missing.",
+          input.display(),
+          output.display()
+        );
+      }
+    }
+  }
+  Ok(())
+}
+
+#[derive(Serialize)]
+struct WhoamiOutput<'a> {
+  account_id: &'a str,
+  account_name: &'a str,
+  organization: Option<&'a str>,
+  access_key: &'a str,
+  fingerprint: String,
+  source: String,
+}
+
+#[derive(Serialize)]
+struct PingOutput<'a> {
+  endpoint: &'a str,
+  address: String,
+  region: Option<&'a str>,
+  status: u16,
+  dns_ms: f64,
+  connect_ms: f64,
+  tls_ms: Option<f64>,
+  ttfb_ms: f64,
+  total_ms: f64,
+}
+
+fn init(opt: &Opt, template: Template) -> anyhow::Result<()> {
+  let app_id = prompt("App ID")?;
+  if app_id.is_empty() {
+    anyhow::bail!("app id is required");
+  }
+
+  let mut env = vec![];
+  for env_spec in template.env_specs()? {
+    let value = loop {
+      let value = if env_spec.optional {
+        prompt(&format!("Value for {} (optional)", env_spec.key))?
+      } else {
+        prompt(&format!("Value for {}", env_spec.key))?
+      };
+      if !value.is_empty() || env_spec.optional {
+        break value;
+      }
+    };
+    if !value.is_empty() {
+      env.push((env_spec.key, value));
+    }
+  }
+
+  let config = scaffold::render_config(&app_id, &env)?;
+  let mut files = vec![
+    (opt.spec.as_str(), template.spec()),
+    (opt.config.as_str(), &config),
+  ];
+  files.extend_from_slice(template.files());
+  scaffold::write_files(Path::new("."), &files)?;
+  for (path, _) in &files {
+    println!("Created {}", path);
+  }
+  Ok(())
+}
+
+fn open_url(url: &str) -> anyhow::Result<()> {
+  let status = {
+    cfg_if::cfg_if! {
+      if #[cfg(target_os = "macos")] {
+        Command::new("open").arg(url).status()
+      } else if #[cfg(target_os = "windows")] {
+        Command::new("cmd").args(["/C", "start", "", url]).status()
+      } else {
+        Command::new("xdg-open").arg(url).status()
+      }
+    }
+  }
+  .map_err(|e| anyhow::Error::from(e).context("cannot launch browser"))?;
+  if !status.success() {
+    anyhow::bail!("cannot open {}: {}", url, status);
+  }
+  Ok(())
+}
+
+/// Returns whether interactive prompts were requested and can be shown.
+fn interactive_enabled(requested: bool) -> bool {
+  if requested && !std::io::stdout().is_terminal() {
+    log::warn!("stdout is not a terminal, ignoring --interactive");
+    return false;
+  }
+  requested
+}
+
+/// Lets the user pick one of `deployments` and returns its index.
+fn pick_deployment(deployments: &[Deployment], prompt: &str) -> anyhow::Result<usize> {
+  if deployments.is_empty() {
+    anyhow::bail!("app has no deployments");
+  }
+  let items = deployments
+    .iter()
+    .map(|x| {
+      format!(
+        "{}  {}{}",
+        x.id,
+        x.created_at,
+        if x.live { "  (live)" } else { "" }
+      )
+    })
+    .collect::<Vec<_>>();
+  let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
+    .with_prompt(prompt)
+    .items(&items)
+    .default(0)
+    .interact_opt()?;
+  selection.ok_or_else(|| anyhow::anyhow!("aborted"))
+}
+
+fn print_logs(logs: &[LogEntry]) {
+  for log in logs {
+    println!("{} [{}] {}", log.ts, log.request_id, log.message);
+  }
+}
+
+fn print_log_stream(output: OutputFormat, logs: &[LogEntry]) -> anyhow::Result<()> {
+  if !output.is_structured() {
+    print_logs(logs);
+    return Ok(());
+  }
+  for log in logs {
+    output.print_record(log)?;
+  }
+  Ok(())
+}
+
+fn print_deploy_result(output: OutputFormat, result: &DeployResult) -> anyhow::Result<()> {
+  if output.is_structured() {
+    return output.print(result);
+  }
+  let mut stdout = StandardStream::stdout(output::color_choice());
+  stdout.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Cyan)))?;
+  writeln!(&mut stdout, "Created deployment {}.", result.id)?;
+  if result.live {
+    writeln!(&mut stdout, "Promoted deployment {} to live.", result.id)?;
+  }
+  stdout.reset()?;
+  println!("Package SHA-256: {}", result.package_sha256);
+  if result.live {
+    println!("URL: {}", result.url);
+    return Ok(());
+  }
+  println!("Preview: {}", result.url);
+  if !output::is_quiet() {
+    println!(
+      "Run `boat promote {}` or visit the dashboard to promote this deployment to live.",
+      result.id
+    );
+  }
+  Ok(())
+}
+
+fn print_promoted(
+  d: &schema::run_deployment_promotion::RunDeploymentPromotionPromoteDeployment,
+) -> anyhow::Result<()> {
+  let mut stdout = StandardStream::stdout(output::color_choice());
+  stdout.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Cyan)))?;
+  writeln!(&mut stdout, "Promoted deployment {} to live.", d.id)?;
+  stdout.reset()?;
+  println!("URL: {}", d.url);
+  Ok(())
+}
+
+/// Prints the number and total size of the files in a package and the `top` largest of them,
+/// or the whole manifest in a structured format.
+fn print_package_report(
+  output: OutputFormat,
+  manifest: &PackageManifest,
+  top: usize,
+) -> anyhow::Result<()> {
+  if output.is_structured() {
+    return output.print(manifest);
+  }
+  let total: u64 = manifest.files.iter().map(|x| x.size).sum();
+  println!("{} files, {} bytes in total.", manifest.files.len(), total);
+  let mut files = manifest.files.iter().collect::<Vec<_>>();
+  files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+  let rows = files
+    .into_iter()
+    .take(top)
+    .map(|x| PackageFileEntry {
+      path: &x.path,
+      size: x.size,
+      share: format!("{:.1}%", x.size as f64 * 100.0 / total.max(1) as f64),
+    })
+    .collect::<Vec<_>>();
+  println!("{}", Table::new(&rows).with(Style::psql()));
+  Ok(())
+}
+
+/// Prints `data` in the requested structured format, or `rows` as a table.
+fn print_output<T: Serialize + ?Sized, R: Tabled>(
+  output: OutputFormat,
+  data: &T,
+  rows: &[R],
+) -> anyhow::Result<()> {
+  if output.is_structured() {
+    output.print(data)?;
+  } else {
+    println!("{}", Table::new(rows).with(Style::psql()));
+  }
   Ok(())
 }
 
+#[derive(Tabled)]
+struct PackageFileEntry<'a> {
+  #[tabled(rename = "Path")]
+  path: &'a str,
+  #[tabled(rename = "Size")]
+  size: u64,
+  #[tabled(rename = "Share")]
+  share: String,
+}
+
+#[derive(Tabled)]
+struct RuleEntry {
+  #[tabled(rename = "Rule")]
+  id: &'static str,
+  #[tabled(rename = "On load")]
+  on_load: &'static str,
+  #[tabled(rename = "Description")]
+  description: &'static str,
+}
+
 #[derive(Tabled)]
 struct DeploymentEntry<'a> {
   #[tabled(rename = "ID")]
@@ -152,3 +1663,59 @@ struct DeploymentEntry<'a> {
   #[tabled(rename = "Live")]
   live: &'static str,
 }
+
+#[derive(Tabled)]
+struct DeploymentStatusEntry<'a> {
+  #[tabled(rename = "ID")]
+  id: &'a str,
+  #[tabled(rename = "Created at")]
+  created_at: &'a str,
+  #[tabled(rename = "Live")]
+  live: &'static str,
+  #[tabled(rename = "URL")]
+  url: &'a str,
+  #[tabled(rename = "Package size")]
+  package_size: String,
+}
+
+#[derive(Tabled)]
+struct AppEntry<'a> {
+  #[tabled(rename = "ID")]
+  id: &'a str,
+  #[tabled(rename = "Name")]
+  name: &'a str,
+  #[tabled(rename = "Created at")]
+  created_at: &'a str,
+  #[tabled(rename = "Current deployment")]
+  current_deployment: &'a str,
+}
+
+#[derive(Tabled)]
+struct DeploymentListEntry<'a> {
+  #[tabled(rename = "ID")]
+  id: &'a str,
+  #[tabled(rename = "Created at")]
+  created_at: &'a str,
+  #[tabled(rename = "Live")]
+  live: &'static str,
+  #[tabled(rename = "Message")]
+  message: &'a str,
+  #[tabled(rename = "Labels")]
+  labels: String,
+}
+
+#[derive(Tabled)]
+struct SecretEntry<'a> {
+  #[tabled(rename = "Key")]
+  key: &'a str,
+}
+
+#[derive(Tabled)]
+struct PromotionEntry<'a> {
+  #[tabled(rename = "Promoted at")]
+  promoted_at: &'a str,
+  #[tabled(rename = "Promoted by")]
+  promoted_by: &'a str,
+  #[tabled(rename = "Demoted at")]
+  demoted_at: &'a str,
+}
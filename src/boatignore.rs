@@ -0,0 +1,134 @@
+use std::{fmt::Display, path::Path};
+
+use regex::Regex;
+
+use crate::package_builder::glob_regex;
+
+/// Name of the ignore file, looked up in the spec directory.
+pub const FILE_NAME: &str = ".boatignore";
+
+/// Patterns in gitignore syntax for files to leave out of the package. Paths are relative to
+/// the spec directory and `/`-separated.
+#[derive(Debug, Default)]
+pub struct BoatIgnore {
+  rules: Vec<IgnoreRule>,
+}
+
+/// One pattern of a `.boatignore` file.
+#[derive(Debug)]
+pub struct IgnoreRule {
+  pattern: String,
+  line: usize,
+  negated: bool,
+  dir_only: bool,
+  regex: Regex,
+}
+
+impl Display for IgnoreRule {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "`{}` on line {} of {}",
+      self.pattern, self.line, FILE_NAME
+    )
+  }
+}
+
+impl BoatIgnore {
+  /// Loads the `.boatignore` file in `dir`, which ignores nothing if the file does not exist.
+  pub fn load(dir: &Path) -> anyhow::Result<Self> {
+    let path = dir.join(FILE_NAME);
+    match std::fs::read_to_string(&path) {
+      Ok(text) => Self::parse(&text),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+      Err(e) => Err(anyhow::Error::from(e).context(format!("cannot read {}", path.display()))),
+    }
+  }
+
+  pub fn parse(text: &str) -> anyhow::Result<Self> {
+    let mut rules = vec![];
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim_end();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let (negated, glob) = match line.strip_prefix('!') {
+        Some(x) => (true, x),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+      };
+      let (dir_only, glob) = match glob.strip_suffix('/') {
+        Some(x) => (true, x),
+        None => (false, glob),
+      };
+      // As in gitignore, a pattern with a slash is relative to the spec directory, and one
+      // without matches at any depth.
+      let glob = match glob.strip_prefix('/') {
+        Some(x) => x.to_string(),
+        None if glob.contains('/') => glob.to_string(),
+        None => format!("**/{}", glob),
+      };
+      let regex = glob_regex(&glob).map_err(|e| {
+        e.context(format!(
+          "invalid pattern on line {} of {}",
+          i + 1,
+          FILE_NAME
+        ))
+      })?;
+      rules.push(IgnoreRule {
+        pattern: line.to_string(),
+        line: i + 1,
+        negated,
+        dir_only,
+        regex,
+      });
+    }
+    Ok(Self { rules })
+  }
+
+  /// The rule that ignores `path`, if any. The last matching pattern wins, so a later `!`
+  /// pattern can re-include a path.
+  pub fn matched(&self, path: &str, is_dir: bool) -> Option<&IgnoreRule> {
+    self
+      .rules
+      .iter()
+      .rev()
+      .find(|x| (is_dir || !x.dir_only) && x.regex.is_match(path))
+      .filter(|x| !x.negated)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ignored(ignore: &BoatIgnore, path: &str, is_dir: bool) -> bool {
+    ignore.matched(path, is_dir).is_some()
+  }
+
+  #[test]
+  fn matches_like_gitignore() {
+    let ignore =
+      BoatIgnore::parse("# comment\n\n*.log\n/build\ndocs/*.md\ncache/\n!keep.log\n\\!important\n")
+        .unwrap();
+    assert!(ignored(&ignore, "debug.log", false));
+    assert!(ignored(&ignore, "src/debug.log", false));
+    assert!(!ignored(&ignore, "keep.log", false));
+    assert!(ignored(&ignore, "build", true));
+    assert!(!ignored(&ignore, "src/build", true));
+    assert!(ignored(&ignore, "docs/README.md", false));
+    assert!(!ignored(&ignore, "README.md", false));
+    assert!(ignored(&ignore, "src/cache", true));
+    assert!(!ignored(&ignore, "src/cache", false));
+    assert!(ignored(&ignore, "!important", false));
+    assert!(!ignored(&ignore, "main.js", false));
+  }
+
+  #[test]
+  fn reports_the_matching_rule() {
+    let ignore = BoatIgnore::parse("*.tmp\n\n*.log\n").unwrap();
+    assert_eq!(
+      ignore.matched("a.log", false).unwrap().to_string(),
+      "`*.log` on line 3 of .boatignore"
+    );
+  }
+}
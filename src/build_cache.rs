@@ -0,0 +1,190 @@
+use std::{
+  fs::File,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+use data_encoding::HEXLOWER;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{
+  boatignore::BoatIgnore,
+  config::{AppConfig, AppSpec, BuildCommand},
+  package_builder::{allowed_env, slash_path},
+  package_file::PackageFile,
+};
+
+/// Number of packages kept in the cache. The least recently used ones are removed when a new
+/// one is added.
+const MAX_ENTRIES: usize = 32;
+
+/// Directory of cached packages, `~/.boat/cache`.
+fn cache_dir() -> PathBuf {
+  dirs::home_dir()
+    .unwrap_or_else(|| PathBuf::from("/"))
+    .join(".boat/cache")
+}
+
+/// Key of the package built from the app in `spec_dir` as it is now: a hash of the build and
+/// verify commands, the config env and the host variables in `allow_env`, and the contents of
+/// every file in the directory except those under `.git` or `.boat` or matched by
+/// `.boatignore`. The artifacts are always included if they exist; before the first build they
+/// do not.
+pub fn key(
+  spec_dir: &Path,
+  spec: &AppSpec,
+  config: &AppConfig,
+  ignore: &BoatIgnore,
+) -> anyhow::Result<String> {
+  let mut hasher = Sha256::new();
+  let mut add = |x: &[u8]| {
+    hasher.update((x.len() as u64).to_le_bytes());
+    hasher.update(x);
+  };
+  add(env!("CARGO_PKG_VERSION").as_bytes());
+  match &spec.build {
+    Some(BuildCommand::Shell(x)) => add(x.as_bytes()),
+    Some(BuildCommand::Args(x)) => {
+      for arg in x {
+        add(arg.as_bytes());
+      }
+    }
+    None => {}
+  }
+  add(
+    spec
+      .build_container
+      .as_deref()
+      .unwrap_or_default()
+      .as_bytes(),
+  );
+  for command in spec.verify.iter().flat_map(|x| x.commands()) {
+    add(command.as_bytes());
+  }
+  let config_env = config
+    .env
+    .iter()
+    .map(|(k, v)| (k.get_ref().clone(), v.clone()))
+    .collect();
+  for mut env in [config_env, allowed_env(spec).collect::<Vec<_>>()] {
+    env.sort();
+    // Counted, so that a variable cannot move between the two lists without changing the key.
+    add(&(env.len() as u64).to_le_bytes());
+    for (k, v) in env {
+      add(k.as_bytes());
+      add(v.as_bytes());
+    }
+  }
+
+  let artifacts = spec
+    .artifact
+    .entries()
+    .into_iter()
+    .map(|(_, source)| spec_dir.join(source))
+    .collect::<Vec<_>>();
+  let walker = WalkDir::new(spec_dir)
+    .sort_by_file_name()
+    .into_iter()
+    .filter_entry(|entry| {
+      if entry.file_name() == ".git" || entry.file_name() == ".boat" {
+        return false;
+      }
+      match entry.path().strip_prefix(spec_dir) {
+        Ok(x) if !x.as_os_str().is_empty() => ignore
+          .matched(&slash_path(x), entry.file_type().is_dir())
+          .is_none(),
+        _ => true,
+      }
+    });
+  let mut files = vec![];
+  for entry in walker {
+    let entry = entry?;
+    if entry.file_type().is_file() {
+      files.push(entry.into_path());
+    }
+  }
+  for path in artifacts {
+    if path.is_file() && !files.contains(&path) {
+      files.push(path);
+    }
+  }
+  for path in files {
+    let data = std::fs::read(&path)
+      .map_err(|e| anyhow::Error::from(e).context(format!("cannot read {}", path.display())))?;
+    add(slash_path(path.strip_prefix(spec_dir).unwrap_or(&path)).as_bytes());
+    add(&Sha256::digest(&data));
+  }
+  Ok(HEXLOWER.encode(&hasher.finalize()))
+}
+
+/// The cached package with `key`, if there is one. It is marked as used, so that `put` keeps
+/// it over packages that have not been used for longer.
+pub fn get(key: &str) -> Option<PackageFile> {
+  let path = cache_dir().join(format!("{}.tar", key));
+  // Copied, so that a concurrent `put` cannot remove it while it is in use.
+  let package = PackageFile::copy_of(&path).ok()?;
+  if let Err(e) = File::options()
+    .write(true)
+    .open(&path)
+    .and_then(|x| x.set_modified(SystemTime::now()))
+  {
+    log::debug!("cannot mark {} as used: {}", path.display(), e);
+  }
+  Some(package)
+}
+
+/// Stores `package` under `key`, and removes the least recently used packages beyond
+/// `MAX_ENTRIES`.
+pub fn put(key: &str, package: &PackageFile) -> anyhow::Result<()> {
+  let dir = cache_dir();
+  std::fs::create_dir_all(&dir)?;
+  // Write to a temporary file first, so that a concurrent `get` never sees a partial package.
+  let tmp = dir.join(format!("{}.tar.tmp", key));
+  std::fs::copy(package.path(), &tmp)?;
+  std::fs::rename(&tmp, dir.join(format!("{}.tar", key)))?;
+
+  let mut entries = std::fs::read_dir(&dir)?
+    .filter_map(|x| x.ok())
+    .filter(|x| x.path().extension().map(|x| x == "tar").unwrap_or(false))
+    .filter_map(|x| Some((x.metadata().ok()?.modified().ok()?, x.path())))
+    .collect::<Vec<_>>();
+  entries.sort();
+  for (_, path) in entries.iter().rev().skip(MAX_ENTRIES) {
+    std::fs::remove_file(path)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key_of(dir: &Path, spec: &str) -> String {
+    let spec: AppSpec = toml::from_str(spec).unwrap();
+    let config: AppConfig = toml::from_str("id = \"app\"\nenv = { A = \"1\" }").unwrap();
+    key(dir, &spec, &config, &BoatIgnore::default()).unwrap()
+  }
+
+  #[test]
+  fn keys_on_commands_and_env() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("index.js"), "1").unwrap();
+    let spec = "artifact = \"index.js\"\nbuild = \"make\"\nallow_env = [\"BOAT_CACHE_TEST_*\"]\n";
+    let base = key_of(dir.path(), spec);
+    assert_eq!(key_of(dir.path(), spec), base);
+    assert_ne!(
+      key_of(
+        dir.path(),
+        &format!("{}verify = \"node --check index.js\"\n", spec)
+      ),
+      base
+    );
+    std::env::set_var("BOAT_CACHE_TEST_TOKEN", "t");
+    let with_env = key_of(dir.path(), spec);
+    std::env::remove_var("BOAT_CACHE_TEST_TOKEN");
+    assert_ne!(with_env, base);
+    std::fs::write(dir.path().join("index.js"), "2").unwrap();
+    assert_ne!(key_of(dir.path(), spec), base);
+  }
+}
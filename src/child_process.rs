@@ -0,0 +1,212 @@
+use std::{
+  process::{Command, ExitStatus},
+  time::{Duration, Instant},
+};
+
+/// How often a running command is checked for exit, expiry and Ctrl-C.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a command gets to exit after `SIGTERM` before it is killed.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// How a command run by `run` ended.
+#[derive(Debug)]
+pub enum Outcome {
+  Exited(ExitStatus),
+  /// The command ran longer than the timeout and was killed.
+  TimedOut,
+  /// Ctrl-C was pressed and the command was killed, or exited.
+  Interrupted,
+}
+
+/// Runs `command` until it exits, it runs longer than `timeout`, or Ctrl-C is pressed.
+///
+/// On Unix, the command runs in its own process group, so that processes it started itself are
+/// terminated along with it. If stdin is a terminal, that group is made the foreground group of
+/// the terminal while the command runs, so that it can read input and gets Ctrl-C directly;
+/// otherwise Ctrl-C is caught by boat and forwarded to the group as `SIGINT`. Either way the
+/// group is killed if it has not exited `KILL_GRACE` after being signalled. Elsewhere, only the
+/// command itself is killed.
+pub fn run(command: &mut Command, timeout: Option<Duration>) -> anyhow::Result<Outcome> {
+  #[cfg(unix)]
+  let _foreground = {
+    use std::{io::IsTerminal, os::unix::process::CommandExt};
+    command.process_group(0);
+    let terminal = std::io::stdin().is_terminal();
+    if terminal {
+      // SAFETY: the closure only calls async-signal-safe functions.
+      unsafe {
+        command.pre_exec(|| {
+          terminal::set_foreground(libc::getpgrp());
+          Ok(())
+        })
+      };
+    }
+    terminal.then(terminal::Restore::new)
+  };
+  let _interrupt = interrupt::catch();
+  let mut child = command.spawn()?;
+  let deadline = timeout.map(|x| Instant::now() + x);
+  loop {
+    if let Some(status) = child.try_wait()? {
+      // In the foreground, the command gets Ctrl-C instead of boat.
+      if interrupt::caught() || killed_by_sigint(&status) {
+        // Background processes of a shell ignore Ctrl-C, and would otherwise keep running.
+        kill_group(&child);
+        return Ok(Outcome::Interrupted);
+      }
+      return Ok(Outcome::Exited(status));
+    }
+    let outcome = if interrupt::caught() {
+      Outcome::Interrupted
+    } else if deadline.map(|x| Instant::now() >= x).unwrap_or(false) {
+      Outcome::TimedOut
+    } else {
+      std::thread::sleep(POLL_INTERVAL);
+      continue;
+    };
+    terminate(&mut child, matches!(outcome, Outcome::Interrupted))?;
+    return Ok(outcome);
+  }
+}
+
+#[cfg(unix)]
+fn killed_by_sigint(status: &ExitStatus) -> bool {
+  use std::os::unix::process::ExitStatusExt;
+  status.signal() == Some(libc::SIGINT)
+}
+
+#[cfg(not(unix))]
+fn killed_by_sigint(_status: &ExitStatus) -> bool {
+  false
+}
+
+/// Sends `SIGINT` if `interrupted` and `SIGTERM` otherwise to the process group of `child`,
+/// then kills the group once the command has exited or `KILL_GRACE` has passed, as processes
+/// started by the command may outlive it.
+#[cfg(unix)]
+fn terminate(child: &mut std::process::Child, interrupted: bool) -> anyhow::Result<()> {
+  let group = -(child.id() as libc::pid_t);
+  let signal = if interrupted {
+    libc::SIGINT
+  } else {
+    libc::SIGTERM
+  };
+  // SAFETY: `kill` has no memory safety requirements.
+  unsafe { libc::kill(group, signal) };
+  let deadline = Instant::now() + KILL_GRACE;
+  while child.try_wait()?.is_none() && Instant::now() < deadline {
+    std::thread::sleep(POLL_INTERVAL);
+  }
+  kill_group(child);
+  child.wait()?;
+  Ok(())
+}
+
+/// Kills whatever is left of the process group of `child`.
+#[cfg(unix)]
+fn kill_group(child: &std::process::Child) {
+  // SAFETY: `kill` has no memory safety requirements.
+  unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+}
+
+#[cfg(not(unix))]
+fn kill_group(_child: &std::process::Child) {}
+
+#[cfg(not(unix))]
+fn terminate(child: &mut std::process::Child, _interrupted: bool) -> anyhow::Result<()> {
+  child.kill()?;
+  child.wait()?;
+  Ok(())
+}
+
+#[cfg(unix)]
+mod terminal {
+  /// Makes `group` the foreground process group of the terminal on stdin. `SIGTTOU`, which a
+  /// process outside the foreground group gets for this, is blocked meanwhile. Only calls
+  /// async-signal-safe functions, so that it can run between `fork` and `exec`.
+  pub fn set_foreground(group: libc::pid_t) {
+    // SAFETY: the signal sets are initialized by `sigemptyset` and `pthread_sigmask` before
+    // they are read.
+    unsafe {
+      let mut block: libc::sigset_t = std::mem::zeroed();
+      libc::sigemptyset(&mut block);
+      libc::sigaddset(&mut block, libc::SIGTTOU);
+      let mut previous: libc::sigset_t = std::mem::zeroed();
+      libc::pthread_sigmask(libc::SIG_BLOCK, &block, &mut previous);
+      libc::tcsetpgrp(libc::STDIN_FILENO, group);
+      libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+    }
+  }
+
+  /// Gives the terminal back to the process group of boat when dropped.
+  pub struct Restore(libc::pid_t);
+
+  impl Restore {
+    pub fn new() -> Self {
+      // SAFETY: `getpgrp` has no memory safety requirements.
+      Restore(unsafe { libc::getpgrp() })
+    }
+  }
+
+  impl Drop for Restore {
+    fn drop(&mut self) {
+      set_foreground(self.0);
+    }
+  }
+}
+
+#[cfg(unix)]
+mod interrupt {
+  use std::sync::atomic::{AtomicBool, Ordering};
+
+  static CAUGHT: AtomicBool = AtomicBool::new(false);
+
+  extern "C" fn handle(_: libc::c_int) {
+    CAUGHT.store(true, Ordering::SeqCst);
+  }
+
+  /// Catches `SIGINT` until dropped, when the previous handler is restored.
+  pub struct Guard {
+    previous: libc::sigaction,
+  }
+
+  pub fn catch() -> Guard {
+    CAUGHT.store(false, Ordering::SeqCst);
+    // SAFETY: `handle` only stores to an atomic, which is async-signal-safe, and the previous
+    // action is written to a zeroed `sigaction`.
+    unsafe {
+      let mut action: libc::sigaction = std::mem::zeroed();
+      action.sa_sigaction = handle as *const () as libc::sighandler_t;
+      libc::sigemptyset(&mut action.sa_mask);
+      let mut previous: libc::sigaction = std::mem::zeroed();
+      libc::sigaction(libc::SIGINT, &action, &mut previous);
+      Guard { previous }
+    }
+  }
+
+  pub fn caught() -> bool {
+    CAUGHT.load(Ordering::SeqCst)
+  }
+
+  impl Drop for Guard {
+    fn drop(&mut self) {
+      // SAFETY: `previous` was filled in by `sigaction` in `catch`.
+      unsafe { libc::sigaction(libc::SIGINT, &self.previous, std::ptr::null_mut()) };
+    }
+  }
+}
+
+#[cfg(not(unix))]
+mod interrupt {
+  /// Ctrl-C reaches the command directly, as it shares the console of boat.
+  pub struct Guard;
+
+  pub fn catch() -> Guard {
+    Guard
+  }
+
+  pub fn caught() -> bool {
+    false
+  }
+}
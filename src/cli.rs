@@ -0,0 +1,679 @@
+use std::{path::PathBuf, time::Duration};
+
+use reqwest::header::{HeaderName, HeaderValue};
+use structopt::{
+  clap::{ArgMatches, Shell},
+  StructOpt,
+};
+
+use crate::{
+  authenticator::CredentialsOptions,
+  config_loader::LoadOptions,
+  env_render::EnvFormat,
+  output::{ColorPreference, OutputFormat},
+  pack_format::PackFormat,
+  package_builder::BuildOptions,
+  retry::RetryPolicy,
+  scaffold::Template,
+  service::ConnectionOptions,
+  tls::TlsOptions,
+  user_config::UserConfig,
+};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+  name = "boat",
+  about = "Blueboat Cloud CLI",
+  after_help = "Settings not given by a flag or env variable are taken from ~/.boat/config.toml, \
+                see `boat config set --help`."
+)]
+pub struct Opt {
+  /// Lighthouse service endpoint. A locally running control plane can be reached over its Unix
+  /// socket, e.g. `unix:///run/lighthouse.sock`, or over plain HTTP with `--insecure-http`.
+  /// Defaults to `endpoint` in `~/.boat/config.toml`.
+  #[structopt(
+    long,
+    default_value = "https://magic.blueboat.io/graphql",
+    env = "BOAT_ENDPOINT"
+  )]
+  pub endpoint: String,
+
+  /// Path to API credentials. Defaults to `credentials` in `~/.boat/config.toml`, then
+  /// `~/.boat/credentials.json`.
+  #[structopt(long, env = "BOAT_CREDENTIALS")]
+  pub credentials: Option<String>,
+
+  /// Profile of the credentials file to authenticate with, for a file with named profiles like
+  /// `{"default": {...}, "work": {...}}`. Defaults to `auth_profile` in `~/.boat/config.toml`,
+  /// then `default`. A selected profile is used even if `BOAT_ACCESS_KEY` and
+  /// `BOAT_SECRET_KEY` are set.
+  #[structopt(long, env = "BOAT_AUTH_PROFILE")]
+  pub profile_auth: Option<String>,
+
+  /// Path to app specification.
+  #[structopt(long, default_value = "Boat.spec.toml", env = "BOAT_SPEC")]
+  pub spec: String,
+
+  /// Path to app config.
+  #[structopt(long, default_value = "Boat.toml", env = "BOAT_CONFIG")]
+  pub config: String,
+
+  /// Profile of the app config to merge over the base config. `Boat.spec.<profile>.toml` is
+  /// merged over the spec, if it exists. Defaults to `profile` in `~/.boat/config.toml`.
+  #[structopt(long, env = "BOAT_PROFILE")]
+  pub profile: Option<String>,
+
+  /// Fail on config env, secrets, mysql and pubsub entries that are not declared in the spec
+  /// instead of warning about them.
+  #[structopt(long)]
+  pub strict: bool,
+
+  /// Warn about secrets in the app config that look weak or malformed.
+  #[structopt(long)]
+  pub lint_secrets: bool,
+
+  /// Print each asset that `.boatignore` leaves out of the package.
+  #[structopt(long)]
+  pub debug_ignore: bool,
+
+  /// Always run the build command, instead of reusing the package of an earlier build with the
+  /// same inputs from `~/.boat/cache`.
+  #[structopt(long)]
+  pub no_cache: bool,
+
+  /// App ID to use instead of the `id` in the app config.
+  #[structopt(long, env = "BOAT_APP_ID")]
+  pub app_id: Option<String>,
+
+  /// Path to the local secrets file, used instead of the app config when
+  /// `detached_secrets` is set.
+  #[structopt(long, default_value = "Boat.secrets.toml", env = "BOAT_SECRETS")]
+  pub secrets: String,
+
+  /// Only print errors and essential output; no progress indicators or hints.
+  #[structopt(long, short = "q", conflicts_with = "verbose")]
+  pub quiet: bool,
+
+  /// Print more log messages. Repeat for more detail (-v, -vv, -vvv).
+  #[structopt(long, short = "v", parse(from_occurrences))]
+  pub verbose: u8,
+
+  /// Skip confirmation prompts of destructive commands.
+  #[structopt(long, short = "y")]
+  pub yes: bool,
+
+  /// Path to workspace definition, used with `--all` and `--app`.
+  #[structopt(long, default_value = "Boat.workspace.toml", env = "BOAT_WORKSPACE")]
+  pub workspace: String,
+
+  /// Run the command for every app in the workspace.
+  #[structopt(long, conflicts_with_all = &["app", "app-id"])]
+  pub all: bool,
+
+  /// Run the command for the named workspace app. Can be given multiple times.
+  #[structopt(long, number_of_values = 1, conflicts_with = "app-id")]
+  pub app: Vec<String>,
+
+  /// Seconds to wait for an API call to complete before giving up.
+  #[structopt(long, default_value = "60", env = "BOAT_TIMEOUT")]
+  pub timeout: u64,
+
+  /// Seconds to wait for a connection to the service or to storage to be established.
+  #[structopt(long, default_value = "10", env = "BOAT_CONNECT_TIMEOUT")]
+  pub connect_timeout: u64,
+
+  /// Allow a plain `http://` endpoint, e.g. `http://localhost:8080/graphql` for local
+  /// development. Deployments, secrets included, are then sent unencrypted.
+  #[structopt(long)]
+  pub insecure_http: bool,
+
+  /// Proxy url for all requests, e.g. `http://proxy:3128`. By default, the `HTTP_PROXY` and
+  /// `HTTPS_PROXY` env variables are used.
+  #[structopt(long, env = "BOAT_PROXY", conflicts_with = "no-proxy")]
+  pub proxy: Option<String>,
+
+  /// Connect directly, ignoring the `HTTP_PROXY` and `HTTPS_PROXY` env variables.
+  #[structopt(long)]
+  pub no_proxy: bool,
+
+  /// Extra header of each API call, as `name=value`, e.g. for a gateway to route on. Can be
+  /// given multiple times.
+  #[structopt(long, number_of_values = 1, parse(try_from_str = parse_header))]
+  pub header: Vec<(HeaderName, HeaderValue)>,
+
+  /// PEM file with CA certificates to trust in addition to the system ones, e.g. for TLS
+  /// interception. Defaults to `ca_cert` in the `[tls]` table of `~/.boat/config.toml`.
+  #[structopt(long, env = "BOAT_CA_CERT")]
+  pub ca_cert: Option<PathBuf>,
+
+  /// PEM file with a client certificate to present to an mTLS gateway. Defaults to
+  /// `client_cert` in the `[tls]` table of `~/.boat/config.toml`.
+  #[structopt(long, env = "BOAT_CLIENT_CERT", requires = "client-key")]
+  pub client_cert: Option<PathBuf>,
+
+  /// PEM file with the PKCS#8 private key of `--client-cert`. Defaults to `client_key` in the
+  /// `[tls]` table of `~/.boat/config.toml`.
+  #[structopt(long, env = "BOAT_CLIENT_KEY", requires = "client-cert")]
+  pub client_key: Option<PathBuf>,
+
+  /// Log every HTTP request and response, with secrets redacted, e.g. to attach to a support
+  /// ticket.
+  #[structopt(long)]
+  pub debug_http: bool,
+
+  /// Always send GraphQL queries in full instead of by hash first.
+  #[structopt(long)]
+  pub no_persisted_queries: bool,
+
+  /// How many times to send an API call or upload at most, retrying after connection errors,
+  /// 5xx statuses and 429 Too Many Requests.
+  #[structopt(long, default_value = "4", env = "BOAT_RETRY_ATTEMPTS")]
+  pub retry_attempts: u32,
+
+  /// Fraction of each retry delay, between 0 and 1, to randomize.
+  #[structopt(long, default_value = "0.5", env = "BOAT_RETRY_JITTER", parse(try_from_str = parse_jitter))]
+  pub retry_jitter: f64,
+
+  /// Output format for read commands: table, json or yaml.
+  #[structopt(long, short = "O", default_value = "table", env = "BOAT_OUTPUT")]
+  pub output: OutputFormat,
+
+  /// When to color output: auto, always or never. Defaults to `color` in
+  /// `~/.boat/config.toml`, then auto.
+  #[structopt(long, env = "BOAT_COLOR", possible_values = ColorPreference::VARIANTS)]
+  pub color: Option<ColorPreference>,
+
+  #[structopt(subcommand)]
+  pub cmd: Cmd,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Cmd {
+  /// Create deployment.
+  Deploy {
+    /// Build the package and print what would be deployed without deploying it.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Message describing the deployment.
+    #[structopt(long, short = "m")]
+    message: Option<String>,
+
+    /// Label to attach to the deployment, as `key=value`. Can be given multiple times.
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_label))]
+    label: Vec<(String, String)>,
+
+    /// Wait until the new deployment is healthy.
+    #[structopt(long)]
+    wait: bool,
+
+    /// Promote the new deployment to live once it is created (and healthy, with `--wait`).
+    #[structopt(long)]
+    promote: bool,
+
+    /// Seconds to wait with `--wait` before giving up.
+    #[structopt(long, default_value = "300")]
+    wait_timeout: u64,
+
+    /// Resume the last deployment whose upload was interrupted, from the package and progress
+    /// kept in `.boat/upload-state.json`, instead of building and uploading again. The message
+    /// and labels are those of that deployment.
+    #[structopt(long, conflicts_with_all = &["dry-run", "message", "label"])]
+    resume: bool,
+
+    #[structopt(flatten)]
+    overrides: ConfigOverrides,
+  },
+
+  /// Create package for single-tenant or custom deployment.
+  Pack {
+    /// Path to metadata output, or to the bundle output with `--bundle`. With `--format zip`,
+    /// path to the `.zip` archive. With `--format oci`, path to the OCI image layout directory,
+    /// or to an image layout tarball if it ends with `.tar`.
+    #[structopt(long, short = "o")]
+    output: String,
+
+    /// Output format: tar, zip or oci. `zip` writes a single archive with the same contents as
+    /// a bundle, and `oci` a single-layer OCI image whose layer is the bundle, to distribute
+    /// single-tenant builds through container registries.
+    #[structopt(long, default_value = "tar")]
+    format: PackFormat,
+
+    /// Write a single `.tar` archive containing both the metadata and the package.
+    #[structopt(long)]
+    bundle: bool,
+
+    /// Print the number and total size of the packaged files, and the largest of them.
+    #[structopt(long)]
+    report: bool,
+
+    /// Number of the largest files to list with `--report`.
+    #[structopt(long, default_value = "10")]
+    top: usize,
+
+    /// Package this directory of prebuilt files instead of running the build command of the
+    /// spec, e.g. when the build runs in a separate CI stage.
+    #[structopt(long)]
+    from_dir: Option<String>,
+
+    /// Path of the entry point in `--from-dir`, packaged as `index.js` [default: index.js].
+    #[structopt(long, requires = "from-dir")]
+    artifact: Option<String>,
+
+    #[structopt(flatten)]
+    overrides: ConfigOverrides,
+  },
+
+  /// View logs.
+  #[structopt(alias = "log")]
+  Logs {
+    /// Deployment ID to query logs for. If unspecified, the current deployment is used.
+    deployment: Option<String>,
+
+    /// Page size.
+    #[structopt(short, long, default_value = "100")]
+    page_size: u32,
+
+    /// Keep polling for new log entries and print them as they arrive.
+    #[structopt(short, long)]
+    follow: bool,
+
+    /// Pick the deployment from a list when no deployment ID is given.
+    #[structopt(short, long)]
+    interactive: bool,
+  },
+
+  /// List deployments.
+  List,
+
+  /// Promote a deployment to live.
+  Promote {
+    /// Deployment ID to promote.
+    deployment_id: String,
+  },
+
+  /// Promote the previously live deployment back to live.
+  Rollback {
+    /// Deployment ID to roll back to. If unspecified, the deployment created before the current
+    /// live one is used.
+    #[structopt(long)]
+    to: Option<String>,
+
+    /// Pick the deployment from a list when `--to` is not given.
+    #[structopt(short, long)]
+    interactive: bool,
+  },
+
+  /// Create a starter app in the current directory.
+  Init {
+    /// Project template: nodejs, static or worker.
+    #[structopt(long, default_value = "nodejs")]
+    template: Template,
+  },
+
+  /// Delete non-live deployments.
+  DeleteDeployment {
+    /// Deployment IDs to delete.
+    deployment_ids: Vec<String>,
+
+    /// Delete all non-live deployments except the N most recent ones.
+    #[structopt(long, conflicts_with = "deployment-ids")]
+    keep_last: Option<usize>,
+  },
+
+  /// Show the current live deployment.
+  Status,
+
+  /// Show details and promotion history of a deployment.
+  History {
+    /// Deployment ID.
+    deployment_id: String,
+  },
+
+  /// Wait until a deployment is healthy.
+  Wait {
+    /// Deployment ID.
+    deployment_id: String,
+
+    /// Also wait until the deployment is live.
+    #[structopt(long)]
+    live: bool,
+
+    /// Seconds to wait before giving up.
+    #[structopt(long, default_value = "300")]
+    timeout: u64,
+  },
+
+  /// Open a deployment in the browser.
+  Open {
+    /// Deployment ID to open. If unspecified, the current deployment is used.
+    deployment: Option<String>,
+
+    /// Print the URL instead of opening it.
+    #[structopt(long)]
+    print: bool,
+  },
+
+  /// Check the app specification and config without contacting the service.
+  Validate,
+
+  /// Check the spec and config with every lint rule, and fail if any finds a problem. Findings
+  /// can be suppressed with a `# boat-lint: allow(<rule>)` comment on or above their line.
+  Lint {
+    /// List the lint rules instead.
+    #[structopt(long)]
+    list_rules: bool,
+  },
+
+  /// Build the artifact and pin its SHA-256 as `artifact_sha256` in the spec, so that later
+  /// builds producing a different artifact fail.
+  Lock {
+    /// Hash the existing artifact without running the build command.
+    #[structopt(long)]
+    no_build: bool,
+  },
+
+  /// Reformat the spec and config canonically: fixed key order, sorted env keys and
+  /// normalized whitespace. Comments are kept.
+  Fmt {
+    /// Only check that the files are formatted, and fail if they are not.
+    #[structopt(long)]
+    check: bool,
+  },
+
+  /// Check the local environment and connectivity to the service.
+  Doctor,
+
+  /// Show the account the configured credentials belong to.
+  Whoami,
+
+  /// Send a trivial query to the endpoint and report how long DNS, connecting, the TLS
+  /// handshake and the first byte of the response took, to tell CLI problems from service
+  /// problems. The query is sent directly, not through --proxy.
+  Ping,
+
+  /// Rebuild the app whenever its files change.
+  Dev {
+    /// Deploy each successful build as a new preview deployment instead of packing it locally.
+    #[structopt(long)]
+    deploy: bool,
+
+    /// Path to metadata output when packing locally.
+    #[structopt(long, short = "o", default_value = ".boat/dev.json")]
+    output: String,
+
+    /// Milliseconds to wait for further changes before rebuilding.
+    #[structopt(long, default_value = "300")]
+    debounce_ms: u64,
+  },
+
+  /// Inspect the app environment.
+  Env(EnvCmd),
+
+  /// Manage secret values in the app config.
+  Secrets(SecretsCmd),
+
+  /// Manage apps.
+  Apps(AppsCmd),
+
+  /// Maintain the spec and config files.
+  Config(ConfigCmd),
+
+  /// Work with the JSON Schema of the app config.
+  Schema(SchemaCmd),
+
+  /// Generate shell completions and print them to stdout.
+  Completions {
+    /// Shell to generate completions for.
+    #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+    shell: Shell,
+  },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AppsCmd {
+  /// List apps accessible with the configured credentials.
+  List,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ConfigCmd {
+  /// Rewrite the spec and config in the current format, keeping comments where possible.
+  Migrate {
+    /// Only print what would change.
+    #[structopt(long)]
+    dry_run: bool,
+  },
+
+  /// Print a setting of `~/.boat/config.toml`, or every setting without a key.
+  Get { key: Option<String> },
+
+  /// Change a setting of `~/.boat/config.toml`, the defaults of every app.
+  ///
+  /// A setting is taken from the first of: its command-line flag, its env variable,
+  /// `~/.boat/config.toml`, the built-in default. The settings are `endpoint`, `profile`,
+  /// `color` (auto, always or never), `telemetry` (false to never export spans),
+  /// `credentials`, `auth_profile` and `tls.ca_cert`, `tls.client_cert` and `tls.client_key`.
+  /// Relative paths are relative to `~/.boat`.
+  Set { key: String, value: String },
+
+  /// Remove a setting of `~/.boat/config.toml`, restoring its default.
+  Unset { key: String },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SchemaCmd {
+  /// Print a JSON Schema of the valid app configs for the spec, for editors to validate and
+  /// complete the config with.
+  Export {
+    /// Write the schema to this path instead of stdout.
+    #[structopt(long, short = "o")]
+    output: Option<String>,
+  },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum EnvCmd {
+  /// Print the effective environment of the app, for reproducing it locally.
+  Print {
+    /// Output format: shell, dotenv or json.
+    #[structopt(long, short = "f", default_value = "shell")]
+    format: EnvFormat,
+
+    /// Replace secret values with a placeholder.
+    #[structopt(long)]
+    redact_secrets: bool,
+
+    /// Show the file and position each value was defined at.
+    #[structopt(long)]
+    sources: bool,
+  },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SecretsCmd {
+  /// Set a secret. The value is read from stdin, or prompted for on a terminal.
+  Set {
+    /// Secret name.
+    key: String,
+  },
+
+  /// Remove a secret.
+  Unset {
+    /// Secret name.
+    key: String,
+  },
+
+  /// List secret names.
+  List,
+
+  /// Upload the secrets file to the service, replacing the app's stored secrets.
+  Push,
+
+  /// Download the app's stored secrets into the secrets file.
+  Pull,
+
+  /// Encrypt the app config into `Boat.enc.toml`, which is decrypted in memory when loaded.
+  ///
+  /// Uses `age` when recipients are given, and `sops` with its own creation rules otherwise.
+  Encrypt {
+    /// age recipient (public key) to encrypt for. May be repeated.
+    #[structopt(long = "age-recipient", short = "r")]
+    age_recipients: Vec<String>,
+
+    /// Output path. Defaults to the config path with `.enc` before the extension.
+    #[structopt(long, short = "o")]
+    output: Option<String>,
+  },
+}
+
+// `--set` and `--set-secret` of the commands that build the app. Not a doc comment, as
+// structopt would take it as the about text of those commands.
+#[derive(Debug, StructOpt)]
+pub struct ConfigOverrides {
+  /// Set an env variable in the config, as `KEY=VALUE`. Can be given multiple times.
+  #[structopt(
+    long = "set",
+    value_name = "KEY=VALUE",
+    number_of_values = 1,
+    parse(try_from_str = parse_assignment)
+  )]
+  pub set_env: Vec<(String, String)>,
+
+  /// Set a secret in the config, as `KEY=VALUE`. Can be given multiple times.
+  #[structopt(
+    long = "set-secret",
+    value_name = "KEY=VALUE",
+    number_of_values = 1,
+    parse(try_from_str = parse_assignment)
+  )]
+  pub set_secret: Vec<(String, String)>,
+}
+
+fn parse_label(s: &str) -> anyhow::Result<(String, String)> {
+  match s.split_once('=') {
+    Some((k, v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+    _ => anyhow::bail!("label must be in the form key=value"),
+  }
+}
+
+fn parse_assignment(s: &str) -> anyhow::Result<(String, String)> {
+  match s.split_once('=') {
+    Some((k, v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+    _ => anyhow::bail!("expected KEY=VALUE"),
+  }
+}
+
+fn parse_jitter(s: &str) -> anyhow::Result<f64> {
+  match s.parse::<f64>() {
+    Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+    _ => anyhow::bail!("jitter must be a number between 0 and 1"),
+  }
+}
+
+fn parse_header(s: &str) -> anyhow::Result<(HeaderName, HeaderValue)> {
+  let (name, value) = s
+    .split_once('=')
+    .ok_or_else(|| anyhow::anyhow!("header must be given as name=value"))?;
+  let name = HeaderName::from_bytes(name.trim().as_bytes())
+    .map_err(|_| anyhow::anyhow!("invalid header name `{}`", name))?;
+  let value = HeaderValue::from_str(value.trim())
+    .map_err(|_| anyhow::anyhow!("invalid value of header `{}`", name))?;
+  Ok((name, value))
+}
+
+impl Opt {
+  /// Fills in the settings given by neither a flag nor an env variable from the user config.
+  pub fn apply_user_config(&mut self, matches: &ArgMatches, config: &UserConfig) {
+    if matches.occurrences_of("endpoint") == 0 && std::env::var_os("BOAT_ENDPOINT").is_none() {
+      if let Some(endpoint) = &config.endpoint {
+        self.endpoint = endpoint.clone();
+      }
+    }
+    if self.credentials.is_none() {
+      self.credentials = config
+        .credentials
+        .as_ref()
+        .map(|x| x.to_string_lossy().into_owned());
+    }
+    // Keys in the env take precedence over the profile of the user config, though not over one
+    // selected by `--profile-auth` or `BOAT_AUTH_PROFILE`.
+    if self.profile_auth.is_none() && std::env::var_os("BOAT_ACCESS_KEY").is_none() {
+      self.profile_auth = config.auth_profile.clone();
+    }
+    if self.profile.is_none() {
+      self.profile = config.profile.clone();
+    }
+    if self.color.is_none() {
+      self.color = config.color;
+    }
+  }
+
+  /// Connection options of the service, from `--timeout`, `--connect-timeout`, `--proxy`,
+  /// `--no-proxy`, `--header`, `--debug-http`, `--insecure-http` and the TLS options.
+  pub fn connection_options(&self) -> ConnectionOptions {
+    ConnectionOptions {
+      timeout: Duration::from_secs(self.timeout),
+      connect_timeout: Duration::from_secs(self.connect_timeout),
+      proxy: self.proxy.clone(),
+      no_proxy: self.no_proxy,
+      headers: self.header.iter().cloned().collect(),
+      debug_http: self.debug_http,
+      insecure_http: self.insecure_http,
+      tls: TlsOptions {
+        ca_cert: self.ca_cert.clone(),
+        client_cert: self.client_cert.clone(),
+        client_key: self.client_key.clone(),
+      },
+    }
+  }
+
+  /// Where to load credentials from, from `--credentials` and `--profile-auth`.
+  pub fn credentials_options(&self) -> CredentialsOptions {
+    CredentialsOptions {
+      file: self.credentials.clone(),
+      profile: self.profile_auth.clone(),
+    }
+  }
+
+  /// Retry policy of API calls and uploads, from `--retry-attempts` and `--retry-jitter`.
+  pub fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy {
+      attempts: self.retry_attempts.max(1),
+      jitter: self.retry_jitter,
+    }
+  }
+
+  /// Options for loading the spec and config, from `--profile`, `--strict`, `--lint-secrets`
+  /// and the `--set` and `--set-secret` options of the command.
+  pub fn load_options(&self) -> LoadOptions<'_> {
+    let (set_env, set_secret) = match &self.cmd {
+      Cmd::Deploy { overrides, .. } | Cmd::Pack { overrides, .. } => {
+        (&overrides.set_env[..], &overrides.set_secret[..])
+      }
+      _ => (&[][..], &[][..]),
+    };
+    LoadOptions {
+      profile: self.profile.as_deref(),
+      strict: self.strict,
+      lint_secrets: self.lint_secrets,
+      lint: matches!(self.cmd, Cmd::Lint { .. }),
+      set_env,
+      set_secret,
+      spec_overlay: None,
+    }
+  }
+
+  /// Options for building the package, from `--debug-ignore` and `--no-cache`.
+  pub fn build_options(&self) -> BuildOptions {
+    BuildOptions {
+      debug_ignore: self.debug_ignore,
+      cache: !self.no_cache,
+    }
+  }
+
+  /// Writes completions for `shell` to `out`.
+  pub fn gen_completions_to<W: std::io::Write>(shell: Shell, out: &mut W) {
+    Self::clap().gen_completions_to("boat", shell, out);
+  }
+}
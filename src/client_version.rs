@@ -0,0 +1,194 @@
+use std::{cmp::Ordering, collections::HashMap, fmt, str::FromStr};
+
+use miette::Diagnostic;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Response extension in which the service reports the versions of the CLI it supports, e.g.
+/// `"clientVersion": { "minimum": "0.1.0", "latest": "0.2.3" }`.
+const EXTENSION: &str = "clientVersion";
+
+/// A `major.minor.patch` version. A pre-release, like `0.2.0-beta.1`, is older than the release;
+/// pre-releases of the same version are ordered by their tags as text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+  major: u64,
+  minor: u64,
+  patch: u64,
+  pre: Option<String>,
+}
+
+impl Version {
+  /// The version of this CLI.
+  pub fn current() -> Self {
+    env!("CARGO_PKG_VERSION").parse().unwrap()
+  }
+}
+
+impl FromStr for Version {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let version = s.trim().trim_start_matches('v');
+    let version = version.split('+').next().unwrap_or_default();
+    let (version, pre) = match version.split_once('-') {
+      Some((x, pre)) => (x, Some(pre.to_string())),
+      None => (version, None),
+    };
+    let mut parts = version.split('.').map(|x| x.parse::<u64>());
+    let mut next = || -> anyhow::Result<u64> {
+      match parts.next() {
+        Some(x) => Ok(x.map_err(|_| anyhow::anyhow!("invalid version: {}", s))?),
+        None => Ok(0),
+      }
+    };
+    let parsed = Version {
+      major: next()?,
+      minor: next()?,
+      patch: next()?,
+      pre,
+    };
+    if parts.next().is_some() {
+      anyhow::bail!("invalid version: {}", s);
+    }
+    Ok(parsed)
+  }
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+    if let Some(pre) = &self.pre {
+      write!(f, "-{}", pre)?;
+    }
+    Ok(())
+  }
+}
+
+impl Ord for Version {
+  fn cmp(&self, other: &Self) -> Ordering {
+    (self.major, self.minor, self.patch)
+      .cmp(&(other.major, other.minor, other.patch))
+      .then_with(|| match (&self.pre, &other.pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => a.cmp(b),
+      })
+  }
+}
+
+impl PartialOrd for Version {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/// The service no longer supports this version of the CLI. Reported instead of whatever the
+/// call failed with, which is likely a confusing schema mismatch.
+#[derive(Error, Debug, Diagnostic)]
+#[error("boat {current} is no longer supported by the service, which requires {minimum} or newer")]
+#[diagnostic(
+  code(boatctl::service::client_too_old),
+  help("upgrade with `cargo install boatctl`, then run the command again")
+)]
+pub struct ClientTooOld {
+  pub current: Version,
+  pub minimum: Version,
+}
+
+/// Versions of the CLI that the service supports, from the `clientVersion` extension of its
+/// responses.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct VersionPolicy {
+  /// Oldest version the service still works with.
+  pub minimum: Option<String>,
+  /// Newest released version.
+  pub latest: Option<String>,
+}
+
+impl VersionPolicy {
+  /// The policy reported in the `extensions` of a GraphQL response body, if any. It is read
+  /// from the raw body as an unsupported CLI may fail to decode the rest. A malformed policy is
+  /// ignored, like an unknown extension.
+  pub fn from_response(body: &[u8]) -> Option<Self> {
+    #[derive(Deserialize)]
+    struct Response {
+      extensions: Option<HashMap<String, serde_json::Value>>,
+    }
+    let response: Response = serde_json::from_slice(body).ok()?;
+    serde_json::from_value(response.extensions?.remove(EXTENSION)?).ok()
+  }
+
+  /// Fails if `current` is older than the minimum, and otherwise returns the latest version if
+  /// `current` is older than that.
+  pub fn check(&self, current: &Version) -> Result<Option<Version>, ClientTooOld> {
+    if let Some(minimum) = parse(&self.minimum) {
+      if *current < minimum {
+        return Err(ClientTooOld {
+          current: current.clone(),
+          minimum,
+        });
+      }
+    }
+    Ok(parse(&self.latest).filter(|x| current < x))
+  }
+}
+
+fn parse(version: &Option<String>) -> Option<Version> {
+  let version = version.as_deref()?;
+  match version.parse() {
+    Ok(x) => Some(x),
+    Err(e) => {
+      log::debug!("ignoring client version: {}", e);
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn version(s: &str) -> Version {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn parses_versions() {
+    assert_eq!(version("v1.2.3").to_string(), "1.2.3");
+    assert_eq!(version("1.2").to_string(), "1.2.0");
+    assert_eq!(version("0.2.0-beta.1+build.5").to_string(), "0.2.0-beta.1");
+    assert!("1.2.3.4".parse::<Version>().is_err());
+    assert!("1.x".parse::<Version>().is_err());
+  }
+
+  #[test]
+  fn orders_versions() {
+    assert!(version("0.1.10") > version("0.1.9"));
+    assert!(version("1.0.0") > version("0.99.99"));
+    assert!(version("0.2.0") > version("0.2.0-rc.1"));
+    assert!(version("0.2.0-rc.1") > version("0.2.0-beta.2"));
+    assert!(version("0.2.0-alpha") > version("0.1.9"));
+    assert_eq!(version("1.0"), version("1.0.0"));
+  }
+
+  #[test]
+  fn checks_policy() {
+    let body =
+      br#"{"data":null,"extensions":{"clientVersion":{"minimum":"0.2.0","latest":"0.3.1"}}}"#;
+    let policy = VersionPolicy::from_response(body).unwrap();
+    assert!(policy.check(&version("0.1.9")).is_err());
+    assert_eq!(
+      policy.check(&version("0.2.0")).unwrap(),
+      Some(version("0.3.1"))
+    );
+    assert_eq!(policy.check(&version("0.3.1")).unwrap(), None);
+
+    assert!(VersionPolicy::from_response(br#"{"data":null}"#).is_none());
+    assert!(VersionPolicy::from_response(b"not json").is_none());
+    let malformed = br#"{"extensions":{"clientVersion":{"minimum":"soon"}}}"#;
+    let policy = VersionPolicy::from_response(malformed).unwrap();
+    assert_eq!(policy.check(&version("0.0.1")).unwrap(), None);
+  }
+}
@@ -1,11 +1,21 @@
-use std::borrow::Cow;
+use std::{
+  borrow::Cow,
+  time::{Duration, SystemTime},
+};
 
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use toml::Spanned;
+
+use crate::spanned::Spanned;
+
+/// Current version of the spec and config formats. Files without a `version` are from before
+/// versioning and are still accepted; `boat config migrate` upgrades them.
+pub const FORMAT_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AppSpec {
+  pub version: Option<u32>,
+
   #[serde(default)]
   pub env: Vec<Spanned<EnvSpecOrPlain>>,
   #[serde(default)]
@@ -17,12 +27,391 @@ pub struct AppSpec {
   #[serde(default)]
   pub pubsub: Vec<Spanned<String>>,
 
-  pub build: Option<String>,
+  pub build: Option<BuildCommand>,
+
+  /// Docker image to run the build command in, instead of on the host.
+  pub build_container: Option<String>,
+
+  /// Directory the build in `build_container` writes its outputs to. The rest of the spec
+  /// directory is read-only in the container.
+  pub build_output: Option<String>,
+
+  /// Run the build command with only the `BLUEBOAT_*` variables, `PATH` and the variables in
+  /// `allow_env`, instead of the whole environment of boat.
+  #[serde(default)]
+  pub clean_env: bool,
+
+  /// Variables passed from the environment of boat to the build command with `clean_env` or
+  /// `build_container`. A name ending with `*` passes every variable with that prefix.
+  #[serde(default)]
+  pub allow_env: Vec<String>,
+
+  /// Longest time the build command may run, as seconds or a duration like `"10m"`. On expiry,
+  /// the command and every process it started are killed.
+  pub build_timeout: Option<Spanned<TimeSpan>>,
 
+  /// Directory copied into the package as is. `assets` is more flexible.
   #[serde(rename = "static")]
   pub _static: Option<String>,
 
-  pub artifact: String,
+  #[serde(default)]
+  pub assets: Vec<AssetSet>,
+
+  /// What to do with symlinks among the static and asset files: package the files they point
+  /// to (the default), package them as symlinks, or fail.
+  #[serde(default)]
+  pub symlinks: Symlinks,
+
+  /// Keep the executable bit of static and asset files, which are otherwise packaged with mode
+  /// `0644`.
+  #[serde(default)]
+  pub preserve_executable: bool,
+
+  /// The built script, packaged as `index.js`, or a map of package paths to built scripts.
+  pub artifact: Artifact,
+
+  /// Commands run after the build and before packaging, e.g. to check that the artifact
+  /// parses. Packaging fails if any of them fails.
+  pub verify: Option<VerifyCommands>,
+
+  /// Expected SHA-256 of the built artifact, written by `boat lock`.
+  pub artifact_sha256: Option<String>,
+
+  /// Largest allowed package, as bytes or a size like `"20MB"`.
+  pub max_package_size: Option<Spanned<ByteSize>>,
+
+  /// Whether the artifact may import Node.js built-in modules.
+  pub node_compat: Option<bool>,
+
+  /// Module format the artifact must be in.
+  pub artifact_type: Option<ArtifactType>,
+
+  /// What to do with `.map` files: keep them in the package (the default), strip them, or
+  /// strip them and upload them to the service for symbolicating stack traces in logs.
+  #[serde(default)]
+  pub sourcemaps: SourceMaps,
+
+  /// Rename static and asset files to content-hashed names, e.g. `app.3fa9c2e1.js`, so that
+  /// they can be cached forever. Enabled by a `[fingerprint]` table.
+  pub fingerprint: Option<Fingerprint>,
+
+  #[serde(default)]
+  pub hooks: Hooks,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Artifact {
+  Single(String),
+  Map(IndexMap<String, String>),
+}
+
+impl Artifact {
+  /// The files to package, as `(package path, source path)`.
+  pub fn entries(&self) -> Vec<(&str, &str)> {
+    match self {
+      Artifact::Single(x) => vec![("index.js", x.as_str())],
+      Artifact::Map(x) => x.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(),
+    }
+  }
+}
+
+impl std::fmt::Display for Artifact {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Artifact::Single(x) => write!(f, "{}", x),
+      Artifact::Map(x) => {
+        let sources: Vec<&str> = x.values().map(|x| x.as_str()).collect();
+        write!(f, "{}", sources.join(", "))
+      }
+    }
+  }
+}
+
+/// Command that builds the artifact, run in the spec directory. A string is run by the shell of
+/// the platform, and an array is the program and its arguments, run without a shell.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum BuildCommand {
+  Shell(String),
+  Args(Vec<String>),
+}
+
+impl BuildCommand {
+  /// The program the command runs, if it can be told without running a shell.
+  pub fn program(&self) -> Option<&str> {
+    match self {
+      BuildCommand::Shell(x) => x.split_whitespace().next(),
+      BuildCommand::Args(x) => x.first().map(|x| x.as_str()),
+    }
+  }
+}
+
+impl std::fmt::Display for BuildCommand {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      BuildCommand::Shell(x) => write!(f, "{}", x),
+      BuildCommand::Args(x) => write!(f, "{}", x.join(" ")),
+    }
+  }
+}
+
+/// A `verify` command, or a list of them, run like a `build` string.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum VerifyCommands {
+  One(String),
+  Many(Vec<String>),
+}
+
+impl VerifyCommands {
+  pub fn commands(&self) -> Vec<&str> {
+    match self {
+      VerifyCommands::One(x) => vec![x.as_str()],
+      VerifyCommands::Many(x) => x.iter().map(|x| x.as_str()).collect(),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactType {
+  /// ES module, using `import` and `export`.
+  Esm,
+  /// CommonJS module, using `require` and `module.exports`.
+  Cjs,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceMaps {
+  #[default]
+  Include,
+  Strip,
+  Upload,
+}
+
+/// Scripts that `boat deploy` runs with the shell of the platform in the spec directory, with
+/// `BOAT_APP_ID` set. A failing `pre_deploy` hook stops the deployment.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Hooks {
+  /// Run before uploading the package, with `BOAT_PACKAGE_SHA256` set.
+  pub pre_deploy: Option<String>,
+  /// Run after the deployment is created, and waited for or promoted if asked, with
+  /// `BOAT_DEPLOYMENT_ID`, `BOAT_PREVIEW_URL` and `BOAT_LIVE` set. A failure is only reported
+  /// as a warning, as the deployment has been made by then.
+  pub post_deploy: Option<String>,
+}
+
+/// Settings of asset fingerprinting. The package gets a JSON object mapping the original path
+/// of each renamed file to its new path, whose package path is in the env variable
+/// `manifest_env`. Artifacts, HTML files and source maps keep their names.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fingerprint {
+  /// Globs of package paths that keep their names.
+  #[serde(default)]
+  pub exclude: Vec<String>,
+  /// Env variable set to the path of the manifest in the package.
+  #[serde(default = "default_manifest_env")]
+  pub manifest_env: String,
+}
+
+fn default_manifest_env() -> String {
+  "BOAT_ASSET_MANIFEST".into()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Symlinks {
+  #[default]
+  Follow,
+  /// Only symlinks to relative paths inside the package are allowed.
+  Preserve,
+  Error,
+}
+
+/// A size in bytes, written as a number or as a string with a unit, e.g. `"512KB"` or `"20MiB"`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ByteSize {
+  Bytes(u64),
+  Text(String),
+}
+
+impl ByteSize {
+  /// The size in bytes, which must not be zero.
+  pub fn bytes(&self) -> Result<u64, String> {
+    let text = match self {
+      ByteSize::Bytes(0) => return Err("size must be greater than zero".into()),
+      ByteSize::Bytes(x) => return Ok(*x),
+      ByteSize::Text(x) => x.trim(),
+    };
+    let split = text
+      .find(|c: char| !c.is_ascii_digit() && c != '.')
+      .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+      .parse()
+      .map_err(|_| format!("invalid size `{}`", text))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+      "" | "B" => 1,
+      "KB" | "K" => 1000,
+      "MB" | "M" => 1000 * 1000,
+      "GB" | "G" => 1000 * 1000 * 1000,
+      "KIB" => 1 << 10,
+      "MIB" => 1 << 20,
+      "GIB" => 1 << 30,
+      _ => {
+        return Err(format!(
+          "unknown size unit `{}` (expected B, KB, MB, GB, KiB, MiB or GiB)",
+          unit.trim()
+        ))
+      }
+    };
+    match (number * multiplier as f64) as u64 {
+      0 => Err(format!("size `{}` must be at least 1 byte", text)),
+      x => Ok(x),
+    }
+  }
+}
+
+/// A duration, written as a number of seconds or as a string with a unit, e.g. `"90s"` or
+/// `"10m"`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum TimeSpan {
+  Seconds(u64),
+  Text(String),
+}
+
+impl TimeSpan {
+  /// The duration, which must not be zero.
+  pub fn duration(&self) -> Result<Duration, String> {
+    let text = match self {
+      TimeSpan::Seconds(0) => return Err("duration must be greater than zero".into()),
+      TimeSpan::Seconds(x) => return Ok(Duration::from_secs(*x)),
+      TimeSpan::Text(x) => x.trim(),
+    };
+    let split = text
+      .find(|c: char| !c.is_ascii_digit() && c != '.')
+      .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+      .parse()
+      .map_err(|_| format!("invalid duration `{}`", text))?;
+    let multiplier: f64 = match unit.trim() {
+      "" | "s" => 1.0,
+      "m" => 60.0,
+      "h" => 3600.0,
+      _ => {
+        return Err(format!(
+          "unknown duration unit `{}` (expected s, m or h)",
+          unit.trim()
+        ))
+      }
+    };
+    match Duration::try_from_secs_f64(number * multiplier) {
+      Ok(x) if x.is_zero() => Err(format!("duration `{}` must be greater than zero", text)),
+      Ok(x) => Ok(x),
+      Err(_) => Err(format!("invalid duration `{}`", text)),
+    }
+  }
+}
+
+impl AppSpec {
+  /// Defaults of optional env variables, as `(key, default)`.
+  pub fn env_defaults(&self) -> impl Iterator<Item = (String, String)> + '_ {
+    self.env.iter().filter_map(|x| {
+      let spec = x.get_ref().to_env_spec();
+      Some((spec.key.clone(), spec.default.clone()?))
+    })
+  }
+
+  /// Merges `overlay` over the spec. Returns the keys whose definitions now come from the
+  /// overlay.
+  pub fn apply_overlay(&mut self, overlay: SpecOverlay) -> Vec<String> {
+    let mut keys = vec![];
+    let entries = overlay
+      .env
+      .into_iter()
+      .map(|x| (x, false))
+      .chain(overlay.secrets.into_iter().map(|x| (x, true)));
+    for (item, is_secret) in entries {
+      let key = item.get_ref().to_env_spec().key.clone();
+      if !keys.contains(&key) {
+        // An entry may also move a variable between `env` and `secrets`.
+        self.env.retain(|x| x.get_ref().to_env_spec().key != key);
+        self
+          .secrets
+          .retain(|x| x.get_ref().to_env_spec().key != key);
+        keys.push(key);
+      }
+      if is_secret {
+        self.secrets.push(item);
+      } else {
+        self.env.push(item);
+      }
+    }
+    if let Some(x) = overlay.mysql {
+      keys.extend(x.iter().map(|x| x.get_ref().clone()));
+      self.mysql = x;
+    }
+    if let Some(x) = overlay.pubsub {
+      keys.extend(x.iter().map(|x| x.get_ref().clone()));
+      self.pubsub = x;
+    }
+    if let Some(x) = overlay.max_package_size {
+      keys.push("max_package_size".into());
+      self.max_package_size = Some(x);
+    }
+    if let Some(x) = overlay.node_compat {
+      self.node_compat = Some(x);
+    }
+    if let Some(x) = overlay.artifact_type {
+      self.artifact_type = Some(x);
+    }
+    keys
+  }
+}
+
+/// Changes to the spec for one profile, read from `Boat.spec.<profile>.toml`.
+///
+/// An env or secret entry replaces the base entry with the same key as a whole, so that
+/// constraints like `regex` can be dropped. The other keys replace the base values.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SpecOverlay {
+  pub version: Option<u32>,
+  #[serde(default)]
+  pub env: Vec<Spanned<EnvSpecOrPlain>>,
+  #[serde(default)]
+  pub secrets: Vec<Spanned<EnvSpecOrPlain>>,
+  pub mysql: Option<Vec<Spanned<String>>>,
+  pub pubsub: Option<Vec<Spanned<String>>>,
+  pub max_package_size: Option<Spanned<ByteSize>>,
+  pub node_compat: Option<bool>,
+  pub artifact_type: Option<ArtifactType>,
+}
+
+/// Files copied into the package from a directory, selected by globs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssetSet {
+  /// Source directory, relative to the spec.
+  #[serde(default = "default_asset_dir")]
+  pub dir: String,
+  /// Globs of the files to copy, relative to `dir`. All files by default.
+  #[serde(default)]
+  pub include: Vec<String>,
+  /// Globs of the files to leave out, relative to `dir`.
+  #[serde(default)]
+  pub exclude: Vec<String>,
+  /// Directory inside the package to copy the files to.
+  #[serde(default)]
+  pub prefix: String,
+}
+
+fn default_asset_dir() -> String {
+  ".".into()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -46,6 +435,15 @@ impl EnvSpecOrPlain {
         key: name.clone(),
         regex: None,
         optional: false,
+        ty: None,
+        allowed_values: None,
+        min: None,
+        max: None,
+        default: None,
+        encoding: None,
+        deprecated: false,
+        removed_after: None,
+        required_if: None,
       }),
     }
   }
@@ -57,11 +455,177 @@ pub struct EnvSpec {
   pub regex: Option<String>,
   #[serde(default)]
   pub optional: bool,
+  #[serde(rename = "type")]
+  pub ty: Option<EnvType>,
+  pub allowed_values: Option<Vec<String>>,
+  pub min: Option<i64>,
+  pub max: Option<i64>,
+  /// Value used when an optional variable is missing from the config.
+  pub default: Option<String>,
+  pub encoding: Option<EnvEncoding>,
+  /// Warn when the variable is set in a config.
+  #[serde(default)]
+  pub deprecated: bool,
+  /// Date after which setting the variable is an error, as `YYYY-MM-DD`. Implies `deprecated`.
+  pub removed_after: Option<String>,
+  /// Makes an optional variable required when another variable has a given value.
+  pub required_if: Option<RequiredIf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RequiredIf {
+  pub key: String,
+  pub equals: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvType {
+  Int,
+  Bool,
+  Enum,
+  Url,
+  Port,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvEncoding {
+  Base64,
+}
+
+impl EnvSpec {
+  /// Checks that the type constraints of the spec itself make sense.
+  pub fn check(&self) -> Result<(), String> {
+    let numeric = matches!(self.ty, Some(EnvType::Int) | Some(EnvType::Port));
+    if (self.min.is_some() || self.max.is_some()) && !numeric {
+      return Err("`min` and `max` require `type = \"int\"` or `type = \"port\"`".into());
+    }
+    if let (Some(min), Some(max)) = (self.min, self.max) {
+      if min > max {
+        return Err(format!("`min` ({}) is greater than `max` ({})", min, max));
+      }
+    }
+    if self.ty == Some(EnvType::Enum) && self.allowed_values.is_none() {
+      return Err("`type = \"enum\"` requires `allowed_values`".into());
+    }
+    if let Some(cond) = &self.required_if {
+      if !self.optional {
+        return Err("`required_if` requires `optional = true`".into());
+      }
+      if cond.key == self.key {
+        return Err("`required_if` cannot refer to the variable itself".into());
+      }
+    }
+    if let Some(date) = &self.removed_after {
+      parse_date(date).map_err(|e| format!("invalid `removed_after`: {}", e))?;
+    }
+    if let Some(default) = &self.default {
+      if !self.optional {
+        return Err("`default` requires `optional = true`".into());
+      }
+      self
+        .check_value(default)
+        .map_err(|e| format!("invalid default: {}", e))?;
+    }
+    Ok(())
+  }
+
+  /// Whether the variable is deprecated, explicitly or by having a removal date.
+  pub fn is_deprecated(&self) -> bool {
+    self.deprecated || self.removed_after.is_some()
+  }
+
+  /// Whether the removal date of the variable has passed, in UTC.
+  pub fn is_removed(&self) -> bool {
+    let today = SystemTime::now()
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .map(|x| (x.as_secs() / 86400) as i64)
+      .unwrap_or_default();
+    self
+      .removed_after
+      .as_deref()
+      .and_then(|x| parse_date(x).ok())
+      .is_some_and(|x| today > x)
+  }
+
+  /// Checks `value` against the type, allowed values and range of the spec.
+  pub fn check_value(&self, value: &str) -> Result<(), String> {
+    if let Some(allowed) = &self.allowed_values {
+      if !allowed.iter().any(|x| x == value) {
+        return Err(format!("expected one of: {}", allowed.join(", ")));
+      }
+    }
+    let n = match self.ty {
+      None | Some(EnvType::Enum) => return Ok(()),
+      Some(EnvType::Bool) => {
+        return match value {
+          "true" | "false" => Ok(()),
+          _ => Err("expected `true` or `false`".into()),
+        }
+      }
+      Some(EnvType::Url) => {
+        return reqwest::Url::parse(value)
+          .map(|_| ())
+          .map_err(|e| format!("expected a URL: {}", e))
+      }
+      Some(EnvType::Int) => value
+        .parse::<i64>()
+        .map_err(|_| "expected an integer".to_string())?,
+      Some(EnvType::Port) => match value.parse::<u16>() {
+        Ok(x) if x != 0 => x as i64,
+        _ => return Err("expected a port number between 1 and 65535".into()),
+      },
+    };
+    match (self.min, self.max) {
+      (Some(min), _) if n < min => Err(format!("expected at least {}", min)),
+      (_, Some(max)) if n > max => Err(format!("expected at most {}", max)),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Parses a `YYYY-MM-DD` date into days since 1970-01-01.
+fn parse_date(date: &str) -> Result<i64, String> {
+  let invalid = || format!("expected a date like `2024-06-01`, found `{}`", date);
+  let parts = date
+    .split('-')
+    .map(|x| x.parse::<i64>().ok().filter(|_| !x.is_empty()))
+    .collect::<Option<Vec<_>>>()
+    .ok_or_else(invalid)?;
+  let (y, m, d) = match parts[..] {
+    [y, m, d] => (y, m, d),
+    _ => return Err(invalid()),
+  };
+  let leap = y % 4 == 0 && (y % 100 != 0 || y % 400 == 0);
+  let month_days = match m {
+    2 if leap => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    1..=12 => 31,
+    _ => return Err(invalid()),
+  };
+  if d < 1 || d > month_days {
+    return Err(invalid());
+  }
+  // Days from civil, shifting the year to start in March so that leap days come last.
+  let (y, m) = if m <= 2 { (y - 1, m + 9) } else { (y, m - 3) };
+  let era = y.div_euclid(400);
+  let yoe = y - era * 400;
+  let doy = (153 * m + 2) / 5 + d - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  Ok(era * 146097 + doe - 719468)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AppConfig {
+  pub version: Option<u32>,
+  /// May be left out in configs that are only used through `extends`.
+  #[serde(default)]
   pub id: String,
+  /// Configs to inherit from, relative to this one. Later entries win over earlier ones.
+  #[serde(default)]
+  pub extends: Vec<String>,
   #[serde(default)]
   pub env: IndexMap<Spanned<String>, String>,
   #[serde(default)]
@@ -72,6 +636,43 @@ pub struct AppConfig {
   pub pubsub: IndexMap<Spanned<String>, PubsubMetadataOrPlain>,
   #[serde(default)]
   pub detached_secrets: bool,
+  #[serde(default)]
+  pub profile: IndexMap<String, ConfigProfile>,
+
+  /// Where each env and secret value was defined, filled in by the loader after merging.
+  #[serde(skip)]
+  pub sources: IndexMap<String, ValueSource>,
+}
+
+/// Location of the definition of a config value.
+#[derive(Serialize, Debug, Clone)]
+pub struct ValueSource {
+  pub file: String,
+  /// 1-based line and column, if known.
+  pub position: Option<(usize, usize)>,
+}
+
+impl std::fmt::Display for ValueSource {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self.position {
+      Some((line, column)) => write!(f, "{}:{}:{}", self.file, line, column),
+      None => write!(f, "{}", self.file),
+    }
+  }
+}
+
+/// Overrides selected with `--profile`, merged over the base config.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigProfile {
+  pub id: Option<String>,
+  #[serde(default)]
+  pub env: IndexMap<Spanned<String>, String>,
+  #[serde(default)]
+  pub secrets: IndexMap<Spanned<String>, String>,
+  #[serde(default)]
+  pub mysql: IndexMap<Spanned<String>, MysqlMetadata>,
+  #[serde(default)]
+  pub pubsub: IndexMap<Spanned<String>, PubsubMetadataOrPlain>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -87,6 +688,55 @@ pub enum PubsubMetadataOrPlain {
 }
 
 impl AppConfig {
+  /// Merges the profile `name` over the base config. Returns `false` if no such profile exists.
+  pub fn apply_profile(&mut self, name: &str) -> bool {
+    let profile = match self.profile.shift_remove(name) {
+      Some(x) => x,
+      None => return false,
+    };
+    self.merge(profile);
+    true
+  }
+
+  /// Merges `overrides` over this config.
+  pub fn merge(&mut self, overrides: ConfigProfile) {
+    if let Some(id) = overrides.id {
+      self.id = id;
+    }
+    merge_overrides(&mut self.env, overrides.env);
+    merge_overrides(&mut self.secrets, overrides.secrets);
+    merge_overrides(&mut self.mysql, overrides.mysql);
+    merge_overrides(&mut self.pubsub, overrides.pubsub);
+  }
+
+  /// Fills in everything this config leaves unset from `parent`.
+  pub fn inherit(&mut self, mut parent: AppConfig) {
+    // A key moved between env and secrets replaces the parent's definition in either table.
+    for key in self.env.keys().chain(self.secrets.keys()) {
+      parent.env.shift_remove(key.get_ref().as_str());
+      parent.secrets.shift_remove(key.get_ref().as_str());
+    }
+    if self.id.is_empty() {
+      self.id = parent.id;
+    }
+    self.detached_secrets |= parent.detached_secrets;
+    inherit_entries(&mut self.env, parent.env);
+    inherit_entries(&mut self.secrets, parent.secrets);
+    inherit_entries(&mut self.mysql, parent.mysql);
+    inherit_entries(&mut self.pubsub, parent.pubsub);
+  }
+
+  /// Keys defined in any of the env, secrets, mysql and pubsub tables.
+  pub fn keys(&self) -> impl Iterator<Item = &str> {
+    self
+      .env
+      .keys()
+      .chain(self.secrets.keys())
+      .chain(self.mysql.keys())
+      .chain(self.pubsub.keys())
+      .map(|x| x.get_ref().as_str())
+  }
+
   pub fn normalize(&mut self) {
     for x in self.pubsub.values_mut() {
       if let PubsubMetadataOrPlain::Plain(value) = x {
@@ -99,6 +749,26 @@ impl AppConfig {
   }
 }
 
+/// Inserts `overrides` into `base`, replacing both the value and the key of existing entries so
+/// that diagnostics point at the override.
+fn merge_overrides<V>(
+  base: &mut IndexMap<Spanned<String>, V>,
+  overrides: IndexMap<Spanned<String>, V>,
+) {
+  for (k, v) in overrides {
+    base.shift_remove(k.get_ref().as_str());
+    base.insert(k, v);
+  }
+}
+
+fn inherit_entries<V>(
+  child: &mut IndexMap<Spanned<String>, V>,
+  parent: IndexMap<Spanned<String>, V>,
+) {
+  let overrides = std::mem::replace(child, parent);
+  merge_overrides(child, overrides);
+}
+
 impl PubsubMetadataOrPlain {
   pub fn unwrap_as_metadata(&self) -> &PubsubMetadata {
     match self {
@@ -107,3 +777,139 @@ impl PubsubMetadataOrPlain {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn size(text: &str) -> Result<u64, String> {
+    ByteSize::Text(text.into()).bytes()
+  }
+
+  fn span(text: &str) -> Result<Duration, String> {
+    TimeSpan::Text(text.into()).duration()
+  }
+
+  fn env_spec(toml: &str) -> EnvSpec {
+    toml::from_str(&format!("key = \"K\"\n{}", toml)).unwrap()
+  }
+
+  #[test]
+  fn checks_typed_values() {
+    let spec = env_spec("type = \"int\"\nmin = -1\nmax = 10");
+    assert_eq!(spec.check(), Ok(()));
+    assert_eq!(spec.check_value("-1"), Ok(()));
+    assert_eq!(spec.check_value("10"), Ok(()));
+    assert!(spec.check_value("-2").is_err());
+    assert!(spec.check_value("11").is_err());
+    assert!(spec.check_value("1.5").is_err());
+    assert!(spec.check_value("").is_err());
+
+    let spec = env_spec("type = \"port\"");
+    assert_eq!(spec.check_value("1"), Ok(()));
+    assert_eq!(spec.check_value("65535"), Ok(()));
+    assert!(spec.check_value("0").is_err());
+    assert!(spec.check_value("65536").is_err());
+
+    let spec = env_spec("type = \"bool\"");
+    assert_eq!(spec.check_value("false"), Ok(()));
+    assert!(spec.check_value("yes").is_err());
+
+    let spec = env_spec("type = \"url\"");
+    assert_eq!(spec.check_value("https://example.com/a"), Ok(()));
+    assert!(spec.check_value("example.com").is_err());
+
+    let spec = env_spec("type = \"enum\"\nallowed_values = [\"a\", \"b\"]");
+    assert_eq!(spec.check_value("b"), Ok(()));
+    assert_eq!(spec.check_value("c"), Err("expected one of: a, b".into()));
+
+    assert_eq!(env_spec("").check_value("anything"), Ok(()));
+  }
+
+  #[test]
+  fn checks_specs() {
+    assert!(env_spec("min = 1").check().is_err());
+    assert!(env_spec("type = \"int\"\nmin = 2\nmax = 1")
+      .check()
+      .is_err());
+    assert!(env_spec("type = \"int\"\nmin = 1\nmax = 1").check().is_ok());
+    assert!(env_spec("type = \"enum\"").check().is_err());
+    assert!(env_spec("default = \"1\"").check().is_err());
+    assert!(env_spec("optional = true\ntype = \"int\"\ndefault = \"x\"")
+      .check()
+      .is_err());
+    assert!(env_spec("optional = true\ntype = \"int\"\ndefault = \"1\"")
+      .check()
+      .is_ok());
+  }
+
+  #[test]
+  fn parses_dates() {
+    assert_eq!(parse_date("1970-01-01"), Ok(0));
+    assert_eq!(parse_date("1969-12-31"), Ok(-1));
+    assert_eq!(parse_date("2000-03-01"), Ok(11017));
+    assert_eq!(parse_date("2024-02-29"), Ok(19782));
+    assert!(parse_date("2023-02-29").is_err());
+    assert!(parse_date("1900-02-29").is_err());
+    assert!(parse_date("2024-04-31").is_err());
+    assert!(parse_date("2024-13-01").is_err());
+    assert!(parse_date("2024-00-10").is_err());
+    assert!(parse_date("2024-06").is_err());
+    assert!(parse_date("2024--06-01").is_err());
+    assert!(parse_date("June 1").is_err());
+  }
+
+  #[test]
+  fn tracks_removal() {
+    let spec = env_spec("deprecated = true");
+    assert!(spec.is_deprecated());
+    assert!(!spec.is_removed());
+    let spec = env_spec("removed_after = \"2000-01-01\"");
+    assert!(spec.is_deprecated());
+    assert!(spec.is_removed());
+    let spec = env_spec("removed_after = \"9999-12-31\"");
+    assert!(spec.is_deprecated());
+    assert!(!spec.is_removed());
+    assert!(env_spec("removed_after = \"soon\"").check().is_err());
+    assert!(!env_spec("").is_deprecated());
+  }
+
+  #[test]
+  fn parses_sizes() {
+    assert_eq!(ByteSize::Bytes(512).bytes(), Ok(512));
+    assert_eq!(size("512"), Ok(512));
+    assert_eq!(size(" 20MB "), Ok(20_000_000));
+    assert_eq!(size("1.5k"), Ok(1500));
+    assert_eq!(size("20 MiB"), Ok(20 << 20));
+    assert_eq!(size("1gib"), Ok(1 << 30));
+    assert_eq!(size("1B"), Ok(1));
+    assert!(size("0.5B").is_err());
+    assert!(size("0KB").is_err());
+    assert!(ByteSize::Bytes(0).bytes().is_err());
+    assert!(size("").is_err());
+    assert!(size("MB").is_err());
+    assert!(size("1.2.3MB").is_err());
+    assert!(size("20TB").is_err());
+    assert!(size("-5").is_err());
+  }
+
+  #[test]
+  fn parses_durations() {
+    assert_eq!(
+      TimeSpan::Seconds(90).duration(),
+      Ok(Duration::from_secs(90))
+    );
+    assert_eq!(span("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(span("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(span(" 10m "), Ok(Duration::from_secs(600)));
+    assert_eq!(span("1.5h"), Ok(Duration::from_secs(5400)));
+    assert_eq!(span("0.5s"), Ok(Duration::from_millis(500)));
+    assert!(span("0s").is_err());
+    assert!(span("0").is_err());
+    assert!(TimeSpan::Seconds(0).duration().is_err());
+    assert!(span("").is_err());
+    assert!(span("10d").is_err());
+    assert!(span("m").is_err());
+    assert!(span("1e999").is_err());
+  }
+}
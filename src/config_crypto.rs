@@ -0,0 +1,145 @@
+use std::{
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result};
+
+const AGE_ARMOR_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Path of the encrypted counterpart of a config, e.g. `Boat.enc.toml` for `Boat.toml`.
+pub fn encrypted_path(path: &Path) -> PathBuf {
+  match (path.file_stem(), path.extension()) {
+    (Some(stem), Some(ext)) => path.with_file_name(format!(
+      "{}.enc.{}",
+      stem.to_string_lossy(),
+      ext.to_string_lossy()
+    )),
+    _ => path.with_extension("enc"),
+  }
+}
+
+/// Whether `text` is an armored age file or a file encrypted by `sops` in binary mode.
+pub fn is_encrypted(text: &str) -> bool {
+  if text.trim_start().starts_with(AGE_ARMOR_HEADER) {
+    return true;
+  }
+  serde_json::from_str::<serde_json::Value>(text)
+    .map(|x| x["sops"].is_object() && x["data"].is_string())
+    .unwrap_or(false)
+}
+
+/// Decrypts the encrypted file at `path` whose contents are `text`. Nothing is written to disk.
+///
+/// age files are decrypted with the identity in `BOAT_AGE_IDENTITY`, falling back to the key
+/// file `sops` itself uses.
+pub fn decrypt(path: &Path, text: &str) -> Result<String> {
+  let mut cmd;
+  if text.trim_start().starts_with(AGE_ARMOR_HEADER) {
+    cmd = Command::new("age");
+    cmd.arg("--decrypt").arg("--identity").arg(age_identity()?);
+  } else {
+    cmd = Command::new("sops");
+    cmd.args([
+      "--decrypt",
+      "--input-type",
+      "binary",
+      "--output-type",
+      "binary",
+    ]);
+  }
+  cmd.arg(path);
+  let program = cmd.get_program().to_string_lossy().into_owned();
+  let output = run(&mut cmd).with_context(|| format!("cannot decrypt {}", path.display()))?;
+  String::from_utf8(output).with_context(|| format!("{} output is not valid UTF-8", program))
+}
+
+/// Encrypts `input` into `output`, for `age_recipients` with `age`, or with `sops` and its
+/// creation rules (`.sops.yaml`, `SOPS_AGE_RECIPIENTS`, ...) if there are no recipients.
+pub fn encrypt(input: &Path, output: &Path, age_recipients: &[String]) -> Result<()> {
+  let encrypted = if age_recipients.is_empty() {
+    run(
+      Command::new("sops")
+        .args([
+          "--encrypt",
+          "--input-type",
+          "binary",
+          "--output-type",
+          "json",
+        ])
+        .arg(input),
+    )?
+  } else {
+    let mut cmd = Command::new("age");
+    cmd.arg("--encrypt").arg("--armor");
+    for r in age_recipients {
+      cmd.arg("--recipient").arg(r);
+    }
+    run(cmd.arg(input))?
+  };
+  std::fs::write(output, encrypted).with_context(|| format!("cannot write {}", output.display()))
+}
+
+fn age_identity() -> Result<PathBuf> {
+  if let Some(x) =
+    std::env::var_os("BOAT_AGE_IDENTITY").or_else(|| std::env::var_os("SOPS_AGE_KEY_FILE"))
+  {
+    return Ok(x.into());
+  }
+  dirs::config_dir()
+    .map(|x| x.join("sops").join("age").join("keys.txt"))
+    .filter(|x| x.exists())
+    .context("no age identity found; set BOAT_AGE_IDENTITY to the path of one")
+}
+
+fn run(cmd: &mut Command) -> Result<Vec<u8>> {
+  let program = cmd.get_program().to_string_lossy().into_owned();
+  let output = cmd
+    .stdin(Stdio::null())
+    .output()
+    .with_context(|| format!("cannot run {}", program))?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "{} exited with {}: {}",
+      program,
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+  Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn names_encrypted_configs() {
+    assert_eq!(
+      encrypted_path(Path::new("conf/Boat.toml")),
+      Path::new("conf/Boat.enc.toml")
+    );
+    assert_eq!(
+      encrypted_path(Path::new("boat.prod.json")),
+      Path::new("boat.prod.enc.json")
+    );
+    assert_eq!(
+      encrypted_path(Path::new("Boatfile")),
+      Path::new("Boatfile.enc")
+    );
+  }
+
+  #[test]
+  fn recognizes_encrypted_files() {
+    assert!(is_encrypted(
+      "\n-----BEGIN AGE ENCRYPTED FILE-----\nYWdl\n-----END AGE ENCRYPTED FILE-----\n"
+    ));
+    assert!(is_encrypted(
+      r#"{"data":"ENC[AES256_GCM,data:...]","sops":{"version":"3.7.3"}}"#
+    ));
+    assert!(!is_encrypted(r#"{"id":"app","sops":{"version":"3.7.3"}}"#));
+    assert!(!is_encrypted(r#"{"id":"app","data":"x"}"#));
+    assert!(!is_encrypted("id = \"app\"\n"));
+    assert!(!is_encrypted(""));
+  }
+}
@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use toml_edit::Document;
+
+/// Edits an app config in place, keeping comments, formatting and key order intact.
+pub struct ConfigEditor {
+  path: PathBuf,
+  doc: Document,
+}
+
+impl ConfigEditor {
+  pub fn open(path: &Path) -> anyhow::Result<Self> {
+    let text = std::fs::read_to_string(path)
+      .map_err(|e| anyhow::Error::from(e).context("cannot read config"))?;
+    let doc = text
+      .parse::<Document>()
+      .map_err(|e| anyhow::Error::from(e).context("cannot parse config"))?;
+    Ok(Self {
+      path: path.to_path_buf(),
+      doc,
+    })
+  }
+
+  /// Opens `path`, or starts an empty document if it does not exist yet.
+  pub fn open_or_create(path: &Path) -> anyhow::Result<Self> {
+    if path.exists() {
+      return Self::open(path);
+    }
+    Ok(Self {
+      path: path.to_path_buf(),
+      doc: Document::new(),
+    })
+  }
+
+  /// Top-level string value.
+  pub fn get_str(&self, key: &str) -> Option<&str> {
+    self.doc.get(key).and_then(|x| x.as_str())
+  }
+
+  /// Top-level boolean value.
+  pub fn get_bool(&self, key: &str) -> Option<bool> {
+    self.doc.get(key).and_then(|x| x.as_bool())
+  }
+
+  /// Sets a top-level string value.
+  pub fn set_str(&mut self, key: &str, value: &str) {
+    self.doc.insert(key, toml_edit::value(value));
+  }
+
+  /// String entries of the `section` table, in file order.
+  pub fn entries(&self, section: &str) -> Vec<(String, String)> {
+    self
+      .doc
+      .get(section)
+      .and_then(|x| x.as_table_like())
+      .map(|x| {
+        x.iter()
+          .filter_map(|(k, v)| Some((k.to_string(), v.as_str()?.to_string())))
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  /// Keys defined in the `section` table, in file order.
+  pub fn keys(&self, section: &str) -> Vec<String> {
+    self
+      .doc
+      .get(section)
+      .and_then(|x| x.as_table_like())
+      .map(|x| x.iter().map(|(k, _)| k.to_string()).collect())
+      .unwrap_or_default()
+  }
+
+  pub fn contains(&self, section: &str, key: &str) -> bool {
+    self
+      .doc
+      .get(section)
+      .and_then(|x| x.as_table_like())
+      .map(|x| x.contains_key(key))
+      .unwrap_or(false)
+  }
+
+  /// Sets `key` in the `section` table to a string value, creating the table if needed.
+  pub fn set(&mut self, section: &str, key: &str, value: &str) -> anyhow::Result<()> {
+    let table = self
+      .doc
+      .entry(section)
+      .or_insert_with(toml_edit::table)
+      .as_table_like_mut()
+      .ok_or_else(|| anyhow::anyhow!("`{}` in config is not a table", section))?;
+    table.insert(key, toml_edit::value(value));
+    Ok(())
+  }
+
+  /// Removes `key` from the `section` table. Returns whether it was present.
+  pub fn unset(&mut self, section: &str, key: &str) -> bool {
+    self
+      .doc
+      .get_mut(section)
+      .and_then(|x| x.as_table_like_mut())
+      .and_then(|x| x.remove(key))
+      .is_some()
+  }
+
+  pub fn save(&self) -> anyhow::Result<()> {
+    std::fs::write(&self.path, self.doc.to_string())
+      .map_err(|e| anyhow::Error::from(e).context(format!("cannot write {}", self.path.display())))
+  }
+
+  /// Like `save`, but makes the file readable by the owner only.
+  pub fn save_private(&self) -> anyhow::Result<()> {
+    self.save()?;
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+  }
+}
@@ -1,11 +1,22 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+};
 
-use crate::config::{AppConfig, AppSpec};
-use miette::{Diagnostic, IntoDiagnostic, NamedSource, SourceOffset, SourceSpan};
+use crate::{
+  config::{
+    AppConfig, AppSpec, ConfigProfile, PubsubMetadataOrPlain, SpecOverlay, ValueSource,
+    FORMAT_VERSION,
+  },
+  config_crypto, lint, output, secret_resolver,
+  spanned::{self, Spanned},
+  template::{self, TemplateContext},
+};
+use indexmap::IndexMap;
+use miette::{Diagnostic, IntoDiagnostic, NamedSource, Severity, SourceOffset, SourceSpan};
 use regex::Regex;
 use serde::Deserialize;
 use thiserror::Error;
-use toml::Spanned;
 
 #[derive(Error, Debug, Diagnostic)]
 #[error("cannot parse config")]
@@ -18,17 +29,6 @@ struct ConfigParseError {
   bad_bit: Option<SourceSpan>,
 }
 
-#[derive(Error, Debug, Diagnostic)]
-#[error("config validation failed")]
-#[diagnostic(code(boatctl::config::validate))]
-struct ConfigValidationError {
-  #[source_code]
-  src: NamedSource,
-
-  #[label("This bit here")]
-  bad_bit: Option<SourceSpan>,
-}
-
 #[derive(Error, Debug, Diagnostic)]
 #[error("duplicate environment variable in spec")]
 #[diagnostic(code(boatctl::config::dup_env))]
@@ -90,6 +90,20 @@ struct UndefinedPubsubError {
   def: SourceSpan,
 }
 
+#[derive(Error, Debug, Diagnostic)]
+#[error("invalid value in spec")]
+#[diagnostic(code(boatctl::config::invalid_spec))]
+struct InvalidSpecValueError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("specified here")]
+  def: SourceSpan,
+
+  #[help]
+  help: String,
+}
+
 #[derive(Error, Debug, Diagnostic)]
 #[error("invalid regex for environment variable")]
 #[diagnostic(code(boatctl::config::invalid_regex))]
@@ -101,6 +115,20 @@ struct InvalidEnvRegexError {
   def: SourceSpan,
 }
 
+#[derive(Error, Debug, Diagnostic)]
+#[error("invalid spec for environment variable")]
+#[diagnostic(code(boatctl::config::invalid_env_spec))]
+struct InvalidEnvSpecError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("specified here")]
+  def: SourceSpan,
+
+  #[help]
+  help: String,
+}
+
 #[derive(Error, Debug, Diagnostic)]
 #[error("environment variable value does not match spec")]
 #[diagnostic(code(boatctl::config::invalid_env))]
@@ -126,235 +154,1209 @@ struct SecretDefinedAsEnv {
   def: SourceSpan,
 }
 
+#[derive(Error, Debug, Diagnostic)]
+#[error("undefined profile `{name}`")]
+#[diagnostic(code(boatctl::config::undefined_profile))]
+struct UndefinedProfileError {
+  name: String,
+
+  #[help]
+  help: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("found {} problems in the spec and config", .errors.len())]
+#[diagnostic(code(boatctl::config::invalid))]
+struct ValidationErrors {
+  #[related]
+  errors: Vec<miette::Report>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("cannot expand config value: {reason}")]
+#[diagnostic(
+  code(boatctl::config::interpolation),
+  help("use `${{env:VAR}}` or `${{file:path}}`, and `$${{` for a literal `${{`")
+)]
+struct InterpolationError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("in the value of this key")]
+  def: Option<SourceSpan>,
+
+  reason: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("cannot render template: {reason}")]
+#[diagnostic(code(boatctl::config::template), help("{help}"))]
+struct TemplateError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("in the value of this key")]
+  def: SourceSpan,
+
+  reason: String,
+  help: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("cannot extend config: {reason}")]
+#[diagnostic(code(boatctl::config::extends))]
+struct ExtendsError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("extended here")]
+  def: SourceSpan,
+
+  reason: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("missing app id")]
+#[diagnostic(
+  code(boatctl::config::missing_id),
+  help("set `id` in the config or in a config it extends")
+)]
+struct MissingIdError {
+  #[source_code]
+  src: NamedSource,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("`{key}` is required when `{cond_key}` is `{equals}`")]
+#[diagnostic(code(boatctl::config::required_if))]
+struct ConditionallyUndefinedEnvError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("required by this spec")]
+  def: SourceSpan,
+
+  #[label("condition on this variable")]
+  cond: Option<SourceSpan>,
+
+  key: String,
+  cond_key: String,
+  equals: String,
+
+  #[help]
+  help: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("`{key}` was removed after {date}")]
+#[diagnostic(code(boatctl::config::removed_env), help("remove it from the config"))]
+struct RemovedEnvError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("set here")]
+  def: SourceSpan,
+
+  key: String,
+  date: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("unsupported format version {version}")]
+#[diagnostic(
+  code(boatctl::config::version),
+  help("this version of boat supports up to version {supported}; upgrade boat")
+)]
+struct UnsupportedVersionError {
+  #[source_code]
+  src: NamedSource,
+
+  #[label("declared here")]
+  def: SourceSpan,
+
+  version: u32,
+  supported: u32,
+}
+
+/// Options for `load` and `load_from_file`.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct LoadOptions<'a> {
+  /// Profile of the config to merge over the base config.
+  pub profile: Option<&'a str>,
+
+  /// Treat config keys that are not declared in the spec as errors instead of warnings.
+  pub strict: bool,
+
+  /// Warn about inline secret values that look weak or malformed.
+  pub lint_secrets: bool,
+
+  /// Run every lint rule, and fail if any of them finds a problem.
+  pub lint: bool,
+
+  /// Env variables to set in the config after merging, as `(key, value)`.
+  pub set_env: &'a [(String, String)],
+
+  /// Secrets to set in the config after merging, as `(key, value)`.
+  pub set_secret: &'a [(String, String)],
+
+  /// Overlay merged over the spec, as `(name, text)`. `load_from_file` reads it from
+  /// `Boat.spec.<profile>.toml` next to the spec, if there is one.
+  pub spec_overlay: Option<(&'a str, &'a str)>,
+}
+
+/// Loads and validates a spec and a config. The config is merged over the configs it
+/// `extends`, and with `profile`, the matching `[profile.<name>]` sections are merged over
+/// the base config before validation.
 pub fn load(
   (spec_name, spec): (&str, &str),
   (config_name, config): (&str, &str),
+  opts: &LoadOptions,
 ) -> miette::Result<(AppSpec, AppConfig)> {
-  let parsed_spec: AppSpec = parse_toml(spec_name, spec)?;
-  let mut parsed_config: AppConfig = parse_toml(config_name, config)?;
-  parsed_config.normalize();
+  let profile = opts.profile;
+  let mut spec_src = SourceFile::new(spec_name, spec)?;
+  let mut config_src = SourceFile::new(config_name, config)?;
+  let mut parsed_spec: AppSpec = spec_src.parse()?;
+  check_version(&spec_src, parsed_spec.version)?;
+  if let Some(overlay) = opts.spec_overlay {
+    apply_spec_overlay((&mut spec_src, &mut parsed_spec), overlay)?;
+  }
+  let template = TemplateContext::new(
+    Path::new(config_name)
+      .parent()
+      .unwrap_or_else(|| Path::new("")),
+    profile,
+  );
+  let mut ctx = ResolveContext {
+    profile,
+    profile_found: false,
+    profiles: vec![],
+    chain: vec![],
+    template: &template,
+  };
+  let mut parsed_config = resolve_config(&mut config_src, &mut ctx)?;
+  if let Some(name) = profile {
+    if !ctx.profile_found {
+      return Err(
+        UndefinedProfileError {
+          name: name.to_string(),
+          help: if ctx.profiles.is_empty() {
+            format!("{} defines no profiles", config_name)
+          } else {
+            format!("defined profiles: {}", ctx.profiles.join(", "))
+          },
+        }
+        .into(),
+      );
+    }
+  }
+  apply_overrides((&mut config_src, &mut parsed_config), opts)?;
+  if parsed_config.id.is_empty() {
+    return Err(
+      MissingIdError {
+        src: config_src.named_source(),
+      }
+      .into(),
+    );
+  }
 
-  validate_spec_no_dup_env_or_secret((spec_name, spec, &parsed_spec))?;
-  validate_config_no_dup_env_or_secret((config_name, config, &parsed_config))?;
+  let mut errors = vec![];
+  validate_spec_no_dup_env_or_secret((&spec_src, &parsed_spec), &mut errors);
+  validate_spec_constraints((&spec_src, &parsed_spec), &mut errors);
+  validate_config_no_dup_env_or_secret((&config_src, &parsed_config), &mut errors);
   validate_env_defined_and_valid(
-    (spec_name, spec, &parsed_spec),
-    (config_name, config, &parsed_config),
-  )?;
+    (&spec_src, &parsed_spec),
+    (&config_src, &parsed_config),
+    &mut errors,
+  );
   validate_no_secret_defined_as_env(
-    (spec_name, spec, &parsed_spec),
-    (config_name, config, &parsed_config),
-  )?;
+    (&spec_src, &parsed_spec),
+    (&config_src, &parsed_config),
+    &mut errors,
+  );
   validate_mysql_defined(
-    (spec_name, spec, &parsed_spec),
-    (config_name, config, &parsed_config),
-  )?;
+    (&spec_src, &parsed_spec),
+    (&config_src, &parsed_config),
+    &mut errors,
+  );
   validate_pubsub_defined(
-    (spec_name, spec, &parsed_spec),
-    (config_name, config, &parsed_config),
-  )?;
+    (&spec_src, &parsed_spec),
+    (&config_src, &parsed_config),
+    &mut errors,
+  );
+  validate_env_not_removed(
+    (&spec_src, &parsed_spec),
+    (&config_src, &parsed_config),
+    &mut errors,
+  );
+  let findings = lint::run(
+    (&spec_src, &parsed_spec),
+    (&config_src, &parsed_config),
+    &|rule| opts.lint || rule.on_load || (rule.id == lint::WEAK_SECRET.id && opts.lint_secrets),
+  );
+  for mut finding in findings {
+    if opts.strict && finding.rule.id == lint::UNDECLARED.id {
+      finding.severity = Severity::Error;
+      errors.push(finding.into());
+    } else if opts.lint {
+      errors.push(finding.into());
+    } else if !output::is_quiet() {
+      eprintln!("{:?}", miette::Report::from(finding));
+    }
+  }
+  into_result(errors)?;
 
+  parsed_config.sources = value_sources((&spec_src, &parsed_spec), (&config_src, &parsed_config));
   Ok((parsed_spec, parsed_config))
 }
 
+/// Locates the definitions of the effective env and secret values: spec defaults first, then
+/// the config or the ancestor that defines each key.
+fn value_sources(
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  (config_src, config): (&SourceFile, &AppConfig),
+) -> IndexMap<String, ValueSource> {
+  let mut out = IndexMap::new();
+  for item in spec.env.iter() {
+    let env_spec = item.get_ref().to_env_spec();
+    if env_spec.default.is_some() {
+      out.insert(
+        env_spec.key.clone(),
+        spec_src
+          .origin(&env_spec.key)
+          .value_source(spec_src.span(item, &env_spec.key)),
+      );
+    }
+  }
+  for k in config.env.keys().chain(config.secrets.keys()) {
+    let name = k.get_ref();
+    let source = config_src
+      .origin(name)
+      .value_source(config_src.span(k, name));
+    out.insert(name.clone(), source);
+  }
+  out
+}
+
+/// Loads a spec on its own, for commands that do not need a config.
+pub fn load_spec_from_file(spec_path: &str) -> miette::Result<(PathBuf, AppSpec)> {
+  let spec_path = std::fs::canonicalize(find_source_file(spec_path))
+    .into_diagnostic()
+    .map_err(|e| e.context("cannot resolve spec path"))?;
+  let spec = std::fs::read_to_string(&spec_path)
+    .into_diagnostic()
+    .map_err(|e| e.context("cannot read spec"))?;
+  let spec_src = SourceFile::new(&spec_path.to_string_lossy(), &spec)?;
+  let parsed_spec: AppSpec = spec_src.parse()?;
+  check_version(&spec_src, parsed_spec.version)?;
+
+  let mut errors = vec![];
+  validate_spec_no_dup_env_or_secret((&spec_src, &parsed_spec), &mut errors);
+  validate_spec_constraints((&spec_src, &parsed_spec), &mut errors);
+  into_result(errors)?;
+
+  Ok((spec_path, parsed_spec))
+}
+
+/// Merges the spec overlay `(name, text)` over the spec, keeping the overlay as a source of
+/// its own for diagnostics.
+fn apply_spec_overlay(
+  (src, spec): (&mut SourceFile, &mut AppSpec),
+  (name, text): (&str, &str),
+) -> miette::Result<()> {
+  let overlay_src = SourceFile::new(name, text)?;
+  let overlay: SpecOverlay = overlay_src.parse()?;
+  check_version(&overlay_src, overlay.version)?;
+  for key in spec.apply_overlay(overlay) {
+    src.origins.insert(key, src.ancestors.len());
+  }
+  src.ancestors.push(overlay_src);
+  Ok(())
+}
+
+/// Path of the overlay of `spec_path` for `profile`, e.g. `Boat.spec.staging.toml` for
+/// `Boat.spec.toml`, if it exists.
+fn spec_overlay_path(spec_path: &Path, profile: &str) -> Option<PathBuf> {
+  let stem = spec_path.file_stem()?.to_string_lossy();
+  let path = spec_path.with_file_name(format!("{}.{}.toml", stem, profile));
+  Some(find_source_file(&path.to_string_lossy())).filter(|x| x.exists())
+}
+
+/// Name of the source holding `--set` and `--set-secret` values in diagnostics.
+const OVERRIDES_SOURCE: &str = "<command line>";
+
+/// Merges `set_env` and `set_secret` of `opts` over the config. The values are kept as a TOML
+/// source of their own, so that diagnostics and value sources can point at them.
+fn apply_overrides(
+  (src, config): (&mut SourceFile, &mut AppConfig),
+  opts: &LoadOptions,
+) -> miette::Result<()> {
+  if opts.set_env.is_empty() && opts.set_secret.is_empty() {
+    return Ok(());
+  }
+  let table = |entries: &[(String, String)]| {
+    toml::Value::Table(
+      entries
+        .iter()
+        .map(|(k, v)| (k.clone(), toml::Value::String(v.clone())))
+        .collect(),
+    )
+  };
+  let mut root = toml::value::Table::new();
+  root.insert("env".into(), table(opts.set_env));
+  root.insert("secrets".into(), table(opts.set_secret));
+  let text = toml::to_string(&toml::Value::Table(root)).into_diagnostic()?;
+  let overrides_src = SourceFile::new(OVERRIDES_SOURCE, &text)?;
+  let overrides: ConfigProfile = overrides_src.parse()?;
+
+  // A key set as env replaces a secret of the same name and the other way round.
+  for key in overrides.env.keys().chain(overrides.secrets.keys()) {
+    config.env.shift_remove(key.get_ref().as_str());
+    config.secrets.shift_remove(key.get_ref().as_str());
+    src
+      .origins
+      .insert(key.get_ref().clone(), src.ancestors.len());
+  }
+  src.ancestors.push(overrides_src);
+  config.merge(overrides);
+  Ok(())
+}
+
+fn check_version(src: &SourceFile, version: Option<u32>) -> Result<(), UnsupportedVersionError> {
+  match version {
+    Some(version) if version > FORMAT_VERSION => Err(UnsupportedVersionError {
+      src: src.named_source(),
+      def: src.find("version"),
+      version,
+      supported: FORMAT_VERSION,
+    }),
+    _ => Ok(()),
+  }
+}
+
+/// Returns the only error as is, or all of them together.
+fn into_result(mut errors: Vec<miette::Report>) -> miette::Result<()> {
+  if errors.len() == 1 {
+    return Err(errors.pop().unwrap());
+  }
+  if !errors.is_empty() {
+    return Err(ValidationErrors { errors }.into());
+  }
+  Ok(())
+}
+
 pub fn load_from_file(
   spec_path: &str,
   config_path: &str,
+  opts: &LoadOptions,
 ) -> miette::Result<((PathBuf, AppSpec), (PathBuf, AppConfig))> {
-  let spec_path = std::fs::canonicalize(spec_path)
+  let spec_path = std::fs::canonicalize(find_source_file(spec_path))
     .into_diagnostic()
     .map_err(|e| e.context("cannot resolve spec path"))?;
   let spec = std::fs::read_to_string(&spec_path)
     .into_diagnostic()
     .map_err(|e| e.context("cannot read spec"))?;
 
-  let config_path = std::fs::canonicalize(config_path)
+  let config_path = std::fs::canonicalize(find_source_file(config_path))
     .into_diagnostic()
     .map_err(|e| e.context("cannot resolve config path"))?;
-  let config = std::fs::read_to_string(&config_path)
-    .into_diagnostic()
-    .map_err(|e| e.context("cannot read config"))?;
+  let config = read_config(&config_path)
+    .map_err(|e| miette::miette!("{:#}", e.context("cannot read config")))?;
+
+  let overlay_path = opts.profile.and_then(|x| spec_overlay_path(&spec_path, x));
+  let overlay = match &overlay_path {
+    Some(path) => Some((
+      path.to_string_lossy(),
+      std::fs::read_to_string(path)
+        .into_diagnostic()
+        .map_err(|e| e.context("cannot read spec overlay"))?,
+    )),
+    None => None,
+  };
+  let opts = LoadOptions {
+    spec_overlay: overlay
+      .as_ref()
+      .map(|(name, text)| (name.as_ref(), text.as_str())),
+    ..*opts
+  };
 
   let (spec, config) = load(
     (spec_path.to_string_lossy().as_ref(), &spec),
     (config_path.to_string_lossy().as_ref(), &config),
+    &opts,
   )?;
 
   Ok(((spec_path, spec), (config_path, config)))
 }
 
-fn parse_toml<T: for<'de> Deserialize<'de>>(name: &str, text: &str) -> Result<T, ConfigParseError> {
-  toml::from_str(&text).map_err(|e| {
-    let loc = e
-      .line_col()
-      .map(|(line, col)| SourceOffset::from_location(text, line, col));
-    ConfigParseError {
-      src: NamedSource::new(name, text.to_string()),
-      bad_bit: loc.map(|loc| SourceSpan::new(loc, loc)),
+/// Falls back to a JSON or YAML file with the same stem when a `.toml` path does not exist, so
+/// that the default `Boat.toml` also finds `Boat.json` or `Boat.yaml`, and finally to the
+/// encrypted `Boat.enc.toml`.
+pub fn find_source_file(path: &str) -> PathBuf {
+  let path = Path::new(path);
+  if path.exists() || path.extension() != Some("toml".as_ref()) {
+    return path.to_path_buf();
+  }
+  ["json", "yaml", "yml"]
+    .iter()
+    .map(|ext| path.with_extension(ext))
+    .chain(std::iter::once(config_crypto::encrypted_path(path)))
+    .find(|x| x.exists())
+    .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Reads a config, decrypting it in memory if it is encrypted.
+fn read_config(path: &Path) -> anyhow::Result<String> {
+  let text = std::fs::read_to_string(path)?;
+  if config_crypto::is_encrypted(&text) {
+    return config_crypto::decrypt(path, &text);
+  }
+  Ok(text)
+}
+
+/// Formats a spec or config can be written in, picked by file extension.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+  Toml,
+  Json,
+  Yaml,
+}
+
+impl ConfigFormat {
+  pub fn from_path(path: &str) -> Self {
+    match Path::new(path).extension().and_then(|x| x.to_str()) {
+      Some("json") => ConfigFormat::Json,
+      Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+      _ => ConfigFormat::Toml,
     }
-  })
+  }
 }
 
-fn validate_spec_no_dup_env_or_secret(
-  (spec_name, spec_text, spec): (&str, &str, &AppSpec),
+/// A spec or config file. Only TOML files have spans; values parsed from other formats are
+/// located by searching for their key instead.
+pub(crate) struct SourceFile {
+  name: String,
+  text: String,
+  format: ConfigFormat,
+
+  /// Configs this one extends, directly or indirectly.
+  ancestors: Vec<SourceFile>,
+
+  /// Index into `ancestors` of the file each inherited key comes from.
+  origins: HashMap<String, usize>,
+}
+
+impl SourceFile {
+  fn new(name: &str, text: &str) -> Result<Self, ConfigParseError> {
+    let src = Self {
+      name: name.to_string(),
+      text: text.to_string(),
+      format: ConfigFormat::from_path(name),
+      ancestors: vec![],
+      origins: HashMap::new(),
+    };
+    // Reject syntax errors up front, before anything is read from the file.
+    match src.format {
+      ConfigFormat::Toml => src.parse::<toml::Value>().map(|_| ()),
+      ConfigFormat::Json => src.parse::<serde_json::Value>().map(|_| ()),
+      ConfigFormat::Yaml => src.parse::<serde_yaml::Value>().map(|_| ()),
+    }?;
+    Ok(src)
+  }
+
+  fn parse<T: for<'de> Deserialize<'de>>(&self) -> Result<T, ConfigParseError> {
+    let text = &self.text;
+    let loc = match self.format {
+      ConfigFormat::Toml => match toml::from_str(text) {
+        Ok(x) => return Ok(x),
+        Err(e) => e.line_col().map(|(line, col)| offset_at(text, line, col)),
+      },
+      ConfigFormat::Json => match spanned::without_spans(|| serde_json::from_str(text)) {
+        Ok(x) => return Ok(x),
+        Err(e) => Some(offset_at(
+          text,
+          e.line().saturating_sub(1),
+          e.column().saturating_sub(1),
+        )),
+      },
+      ConfigFormat::Yaml => match spanned::without_spans(|| serde_yaml::from_str(text)) {
+        Ok(x) => return Ok(x),
+        Err(e) => e.location().map(|x| SourceOffset::from(x.index())),
+      },
+    };
+    Err(ConfigParseError {
+      src: self.named_source(),
+      bad_bit: loc.map(|loc| SourceSpan::new(loc, 0.into())),
+    })
+  }
+
+  pub(crate) fn text(&self) -> &str {
+    &self.text
+  }
+
+  pub(crate) fn named_source(&self) -> NamedSource {
+    NamedSource::new(&self.name, self.text.clone())
+  }
+
+  /// The file `key` is defined in: this one, or the ancestor it was inherited from.
+  pub(crate) fn origin(&self, key: &str) -> &SourceFile {
+    match self.origins.get(key) {
+      Some(&i) => &self.ancestors[i],
+      None => self,
+    }
+  }
+
+  /// Source of the file that defines `key`, for labels created with `span`.
+  fn named_source_for(&self, key: &str) -> NamedSource {
+    self.origin(key).named_source()
+  }
+
+  /// Span of a parsed value in the original text of the file that defines `key`. For converted
+  /// files the span is not known, so this points at the first place `key` appears as a key or
+  /// list item instead.
+  pub(crate) fn span<T>(&self, spanned: &Spanned<T>, key: &str) -> SourceSpan {
+    let src = self.origin(key);
+    if src.format == ConfigFormat::Toml {
+      return SourceSpan::from(spanned.start()..spanned.end());
+    }
+    src.find(key)
+  }
+
+  /// Location of `span` in this file.
+  fn value_source(&self, span: SourceSpan) -> ValueSource {
+    let before = &self.text[..span.offset().min(self.text.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    ValueSource {
+      file: self.name.clone(),
+      position: Some((line, column)),
+    }
+  }
+
+  /// First place `token` appears as a key or list item.
+  fn find(&self, token: &str) -> SourceSpan {
+    let re = Regex::new(&format!(
+      r#"(?m)(?:^|[\s\[{{,-])("{0}"|'{0}'|{0})\s*(?:[:=,\]}}]|$)"#,
+      regex::escape(token)
+    ))
+    .unwrap();
+    re.captures(&self.text)
+      .and_then(|x| x.get(1))
+      .map(|x| SourceSpan::from(x.start()..x.end()))
+      .unwrap_or_else(|| SourceSpan::from(0..0))
+  }
+}
+
+/// Offset of 0-based `line` and `column`, counted in characters, in `text`.
+fn offset_at(text: &str, line: usize, column: usize) -> SourceOffset {
+  let start = text
+    .split_inclusive('\n')
+    .take(line)
+    .map(str::len)
+    .sum::<usize>();
+  let rest = text[start..].split('\n').next().unwrap_or_default();
+  let column = rest
+    .char_indices()
+    .nth(column)
+    .map(|(i, _)| i)
+    .unwrap_or(rest.len());
+  SourceOffset::from(start + column)
+}
+
+/// State kept while following `extends` from one config to another.
+struct ResolveContext<'a> {
+  profile: Option<&'a str>,
+  profile_found: bool,
+
+  /// Names of all profiles seen, for diagnostics.
+  profiles: Vec<String>,
+
+  /// Configs currently being resolved, to detect cycles.
+  chain: Vec<PathBuf>,
+
+  template: &'a TemplateContext,
+}
+
+/// Parses the config in `src` and merges it over the configs it extends. Profiles,
+/// interpolation and templates are applied to each file on its own, before merging.
+fn resolve_config(src: &mut SourceFile, ctx: &mut ResolveContext) -> miette::Result<AppConfig> {
+  let mut config: AppConfig = src.parse()?;
+  check_version(src, config.version)?;
+  for name in config.profile.keys() {
+    if !ctx.profiles.contains(name) {
+      ctx.profiles.push(name.clone());
+    }
+  }
+  if let Some(name) = ctx.profile {
+    ctx.profile_found |= config.apply_profile(name);
+  }
+  config.profile.clear();
+  config.normalize();
+  // Templates are rendered first, so that values read from files or env variables are taken
+  // as they are rather than as templates.
+  render_templates((src, &mut config), ctx.template)?;
+  interpolate_config((src, &mut config))?;
+
+  let path = Path::new(&src.name);
+  let base_dir = path.parent().map(|x| x.to_path_buf()).unwrap_or_default();
+  ctx
+    .chain
+    .push(std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+
+  let mut base: Option<AppConfig> = None;
+  for parent in std::mem::take(&mut config.extends) {
+    let err = |reason: String| ExtendsError {
+      src: src.named_source(),
+      def: src.find(&parent),
+      reason,
+    };
+    let parent_path =
+      std::fs::canonicalize(find_source_file(&base_dir.join(&parent).to_string_lossy()))
+        .map_err(|e| err(format!("cannot resolve {}: {}", parent, e)))?;
+    if let Some(start) = ctx.chain.iter().position(|x| *x == parent_path) {
+      let cycle = ctx.chain[start..]
+        .iter()
+        .chain(std::iter::once(&parent_path))
+        .map(|x| x.display().to_string())
+        .collect::<Vec<_>>();
+      return Err(err(format!("cycle: {}", cycle.join(" -> "))).into());
+    }
+    let text = read_config(&parent_path)
+      .map_err(|e| err(format!("cannot read {}: {:#}", parent_path.display(), e)))?;
+
+    let mut parent_src = SourceFile::new(&parent_path.to_string_lossy(), &text)?;
+    let mut parent_config = resolve_config(&mut parent_src, ctx)?;
+
+    // The parent's ancestors go first, followed by the parent itself.
+    let offset = src.ancestors.len();
+    let parent_index = offset + parent_src.ancestors.len();
+    let parent_origins = std::mem::take(&mut parent_src.origins);
+    for key in parent_config.keys() {
+      let origin = parent_origins
+        .get(key)
+        .map(|x| x + offset)
+        .unwrap_or(parent_index);
+      src.origins.insert(key.to_string(), origin);
+    }
+    src.ancestors.append(&mut parent_src.ancestors);
+    src.ancestors.push(parent_src);
+
+    if let Some(base) = base.take() {
+      parent_config.inherit(base);
+    }
+    base = Some(parent_config);
+  }
+  ctx.chain.pop();
+
+  if let Some(base) = base {
+    for key in config.keys() {
+      src.origins.remove(key);
+    }
+    config.inherit(base);
+  }
+  Ok(config)
+}
+
+/// Expands `${env:VAR}` and `${file:path}` references in config values. Relative file paths,
+/// including those of `sops:` secrets, are resolved against the directory of the config.
+fn interpolate_config((config_src, config): (&SourceFile, &mut AppConfig)) -> miette::Result<()> {
+  let base_dir = Path::new(&config_src.name)
+    .parent()
+    .map(|x| x.to_path_buf())
+    .unwrap_or_default();
+  let err = |def: Option<SourceSpan>, reason: String| InterpolationError {
+    src: config_src.named_source(),
+    def,
+    reason,
+  };
+
+  config.id = interpolate(&config.id, &base_dir).map_err(|e| err(None, e))?;
+  for (k, v) in config.env.iter_mut().chain(config.secrets.iter_mut()) {
+    *v = interpolate(v, &base_dir).map_err(|e| err(Some(config_src.span(k, k.get_ref())), e))?;
+  }
+  for v in config.secrets.values_mut() {
+    *v = secret_resolver::rebase_sops_reference(v, &base_dir);
+  }
+  for (k, v) in config.mysql.iter_mut() {
+    let span = Some(config_src.span(k, k.get_ref()));
+    v.url = interpolate(&v.url, &base_dir).map_err(|e| err(span, e))?;
+    if let Some(cert) = &mut v.root_certificate {
+      *cert = interpolate(cert, &base_dir).map_err(|e| err(span, e))?;
+    }
+  }
+  for (k, v) in config.pubsub.iter_mut() {
+    if let PubsubMetadataOrPlain::Metadata(v) = v {
+      v.namespace = interpolate(&v.namespace, &base_dir)
+        .map_err(|e| err(Some(config_src.span(k, k.get_ref())), e))?;
+    }
+  }
+  Ok(())
+}
+
+/// Renders `{{ ... }}` templates in env values.
+fn render_templates(
+  (config_src, config): (&SourceFile, &mut AppConfig),
+  ctx: &TemplateContext,
 ) -> miette::Result<()> {
+  for (k, v) in config.env.iter_mut() {
+    *v = template::render(v, ctx).map_err(|reason| TemplateError {
+      src: config_src.named_source(),
+      def: config_src.span(k, k.get_ref()),
+      reason,
+      help: format!("available variables: {}", template::VARIABLES.join(", ")),
+    })?;
+  }
+  Ok(())
+}
+
+fn interpolate(value: &str, base_dir: &Path) -> Result<String, String> {
+  let mut out = String::with_capacity(value.len());
+  let mut rest = value;
+  while let Some(start) = rest.find('$') {
+    out.push_str(&rest[..start]);
+    rest = &rest[start..];
+    if let Some(escaped) = rest.strip_prefix("$${") {
+      out.push_str("${");
+      rest = escaped;
+      continue;
+    }
+    let inner = match rest.strip_prefix("${") {
+      Some(x) => x,
+      None => {
+        out.push('$');
+        rest = &rest[1..];
+        continue;
+      }
+    };
+    let end = inner
+      .find('}')
+      .ok_or_else(|| format!("unterminated reference in `{}`", value))?;
+    let reference = &inner[..end];
+    let expanded = match reference.split_once(':') {
+      Some(("env", name)) => {
+        std::env::var(name).map_err(|_| format!("environment variable `{}` is not set", name))?
+      }
+      Some(("file", path)) => {
+        let path = base_dir.join(path);
+        std::fs::read_to_string(&path)
+          .map_err(|e| format!("cannot read {}: {}", path.display(), e))?
+          .trim_end_matches(&['\r', '\n'][..])
+          .to_string()
+      }
+      _ => return Err(format!("unknown reference `${{{}}}`", reference)),
+    };
+    out.push_str(&expanded);
+    rest = &inner[end + 1..];
+  }
+  out.push_str(rest);
+  Ok(out)
+}
+
+fn validate_spec_no_dup_env_or_secret(
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  errors: &mut Vec<miette::Report>,
+) {
   let mut seen: HashMap<String, SourceSpan> = HashMap::new();
   for item in spec.env.iter().chain(spec.secrets.iter()) {
     let spec = item.get_ref().to_env_spec();
-    let span = toml_spanned_to_source_span(item);
+    let span = spec_src.span(item, &spec.key);
     if let Some(&prev_span) = seen.get(&spec.key) {
       let (prev_def, redef) = if prev_span.offset() < span.offset() {
         (prev_span, span)
       } else {
         (span, prev_span)
       };
-      return Err(
+      errors.push(
         DuplicateSpecEnvError {
-          src: NamedSource::new(spec_name, spec_text.to_string()),
+          src: spec_src.named_source_for(&spec.key),
           prev_def,
           redef,
         }
         .into(),
       );
+      continue;
     }
     seen.insert(spec.key.clone(), span);
   }
-  Ok(())
+}
+
+fn validate_spec_constraints(
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  errors: &mut Vec<miette::Report>,
+) {
+  if let Some(size) = &spec.max_package_size {
+    if let Err(help) = size.get_ref().bytes() {
+      errors.push(
+        InvalidSpecValueError {
+          src: spec_src.named_source_for("max_package_size"),
+          def: spec_src.span(size, "max_package_size"),
+          help,
+        }
+        .into(),
+      );
+    }
+  }
+  if let Some(timeout) = &spec.build_timeout {
+    if let Err(help) = timeout.get_ref().duration() {
+      errors.push(
+        InvalidSpecValueError {
+          src: spec_src.named_source_for("build_timeout"),
+          def: spec_src.span(timeout, "build_timeout"),
+          help,
+        }
+        .into(),
+      );
+    }
+  }
 }
 
 fn validate_config_no_dup_env_or_secret(
-  (config_name, config_text, config): (&str, &str, &AppConfig),
-) -> miette::Result<()> {
+  (config_src, config): (&SourceFile, &AppConfig),
+  errors: &mut Vec<miette::Report>,
+) {
   let mut seen: HashMap<String, SourceSpan> = HashMap::new();
   for item in config.env.iter().chain(config.secrets.iter()) {
     let key = item.0;
-    let span = toml_spanned_to_source_span(key);
+    let span = config_src.span(key, key.get_ref());
     if let Some(&prev_span) = seen.get(key.get_ref()) {
       let (prev_def, redef) = if prev_span.offset() < span.offset() {
         (prev_span, span)
       } else {
         (span, prev_span)
       };
-      return Err(
+      errors.push(
         DuplicateConfigEnvError {
-          src: NamedSource::new(config_name, config_text.to_string()),
+          src: config_src.named_source_for(key.get_ref()),
           prev_def,
           redef,
         }
         .into(),
       );
+      continue;
     }
     seen.insert(key.get_ref().clone(), span);
   }
-  Ok(())
 }
 
 fn validate_no_secret_defined_as_env(
-  (_spec_name, _spec_text, spec): (&str, &str, &AppSpec),
-  (config_name, config_text, config): (&str, &str, &AppConfig),
-) -> miette::Result<()> {
+  (_spec_src, spec): (&SourceFile, &AppSpec),
+  (config_src, config): (&SourceFile, &AppConfig),
+  errors: &mut Vec<miette::Report>,
+) {
   for item in spec.secrets.iter() {
     let env_spec = item.get_ref().to_env_spec();
     if let Some((env_key, _)) = config.env.get_key_value(env_spec.key.as_str()) {
-      return Err(
+      errors.push(
         SecretDefinedAsEnv {
-          src: NamedSource::new(config_name, config_text.to_string()),
-          def: toml_spanned_to_source_span(env_key),
+          src: config_src.named_source_for(env_key.get_ref()),
+          def: config_src.span(env_key, env_key.get_ref()),
         }
         .into(),
       );
     }
   }
-  Ok(())
 }
 
 fn validate_env_defined_and_valid(
-  (spec_name, spec_text, spec): (&str, &str, &AppSpec),
-  (config_name, config_text, config): (&str, &str, &AppConfig),
-) -> miette::Result<()> {
-  for item in spec.env.iter().chain(spec.secrets.iter()) {
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  (config_src, config): (&SourceFile, &AppConfig),
+  errors: &mut Vec<miette::Report>,
+) {
+  let secrets = spec.secrets.iter().map(|x| (x, true));
+  for (item, is_secret) in spec.env.iter().map(|x| (x, false)).chain(secrets) {
     let env_spec = item.get_ref().to_env_spec();
+    let checked = if is_secret && env_spec.default.is_some() {
+      Err("secrets cannot have a default".to_string())
+    } else {
+      env_spec.check()
+    };
+    if let Err(help) = checked {
+      errors.push(
+        InvalidEnvSpecError {
+          src: spec_src.named_source_for(&env_spec.key),
+          def: spec_src.span(item, &env_spec.key),
+          help,
+        }
+        .into(),
+      );
+      continue;
+    }
+
     let kv = config
       .env
       .get_key_value(env_spec.key.as_str())
       .or_else(|| config.secrets.get_key_value(env_spec.key.as_str()));
-    if !env_spec.optional && kv.is_none() {
-      return Err(
+    // Detached secrets are stored by the service and may be absent from the config.
+    let detached = is_secret && config.detached_secrets;
+    if let Some(cond) = &env_spec.required_if {
+      let cond_item = spec
+        .env
+        .iter()
+        .chain(spec.secrets.iter())
+        .find(|x| x.get_ref().to_env_spec().key == cond.key);
+      let cond_item = match cond_item {
+        Some(x) => x,
+        None => {
+          errors.push(
+            InvalidEnvSpecError {
+              src: spec_src.named_source_for(&env_spec.key),
+              def: spec_src.span(item, &env_spec.key),
+              help: format!(
+                "`required_if` refers to `{}`, which is not declared in the spec",
+                cond.key
+              ),
+            }
+            .into(),
+          );
+          continue;
+        }
+      };
+      let cond_value = match config
+        .env
+        .get_key_value(cond.key.as_str())
+        .or_else(|| config.secrets.get_key_value(cond.key.as_str()))
+      {
+        Some((k, v)) => Some((
+          v.clone(),
+          config_src
+            .origin(k.get_ref())
+            .value_source(config_src.span(k, k.get_ref())),
+        )),
+        None => cond_item.get_ref().to_env_spec().default.clone().map(|v| {
+          (
+            v,
+            spec_src
+              .origin(&cond.key)
+              .value_source(spec_src.span(cond_item, &cond.key)),
+          )
+        }),
+      };
+      if let Some((_, source)) = cond_value.filter(|(v, _)| *v == cond.equals) {
+        if !detached && kv.is_none() {
+          errors.push(
+            ConditionallyUndefinedEnvError {
+              src: spec_src.named_source_for(&env_spec.key),
+              def: spec_src.span(item, &env_spec.key),
+              // Both labels must be in the same file.
+              cond: Some(spec_src.span(cond_item, &cond.key)).filter(|_| {
+                std::ptr::eq(spec_src.origin(&cond.key), spec_src.origin(&env_spec.key))
+              }),
+              key: env_spec.key.clone(),
+              cond_key: cond.key.clone(),
+              equals: cond.equals.clone(),
+              help: format!("`{}` is `{}`, defined at {}", cond.key, cond.equals, source),
+            }
+            .into(),
+          );
+          continue;
+        }
+      }
+    }
+    if !env_spec.optional && !detached && !env_spec.is_removed() && kv.is_none() {
+      errors.push(
         UndefinedEnvError {
-          src: NamedSource::new(spec_name, spec_text.to_string()),
-          def: toml_spanned_to_source_span(item),
+          src: spec_src.named_source_for(&env_spec.key),
+          def: spec_src.span(item, &env_spec.key),
         }
         .into(),
       );
+      continue;
     }
+    // External secret references are resolved at deploy time, so there is no value to check yet.
+    let kv = kv.filter(|(k, v)| {
+      !(config.secrets.contains_key(k.get_ref().as_str())
+        && secret_resolver::is_external_reference(v))
+    });
 
     if let Some(regex) = &env_spec.regex {
       let re = match Regex::new(regex) {
         Ok(x) => x,
         Err(_) => {
-          return Err(
+          errors.push(
             InvalidEnvRegexError {
-              src: NamedSource::new(spec_name, spec_text.to_string()),
-              def: toml_spanned_to_source_span(item),
+              src: spec_src.named_source_for(&env_spec.key),
+              def: spec_src.span(item, &env_spec.key),
             }
             .into(),
-          )
+          );
+          continue;
         }
       };
+      if env_spec.default.as_deref().is_some_and(|x| !re.is_match(x)) {
+        errors.push(
+          InvalidEnvSpecError {
+            src: spec_src.named_source_for(&env_spec.key),
+            def: spec_src.span(item, &env_spec.key),
+            help: format!("default does not match regex: {}", regex),
+          }
+          .into(),
+        );
+        continue;
+      }
       if let Some(kv) = kv {
         if !re.is_match(kv.1) {
-          return Err(
+          errors.push(
             EnvDoesNotMatchSpec {
-              src: NamedSource::new(config_name, config_text.to_string()),
-              def: toml_spanned_to_source_span(kv.0),
+              src: config_src.named_source_for(kv.0.get_ref()),
+              def: config_src.span(kv.0, kv.0.get_ref()),
               help: format!("regex: {}", regex),
             }
             .into(),
           );
+          continue;
         }
       }
     }
+
+    if let Some(kv) = kv {
+      if let Err(help) = env_spec.check_value(kv.1) {
+        errors.push(
+          EnvDoesNotMatchSpec {
+            src: config_src.named_source_for(kv.0.get_ref()),
+            def: config_src.span(kv.0, kv.0.get_ref()),
+            help,
+          }
+          .into(),
+        );
+      }
+    }
+  }
+}
+
+/// Rejects config values for variables that are past their removal date.
+fn validate_env_not_removed(
+  (_spec_src, spec): (&SourceFile, &AppSpec),
+  (config_src, config): (&SourceFile, &AppConfig),
+  errors: &mut Vec<miette::Report>,
+) {
+  for item in spec.env.iter().chain(spec.secrets.iter()) {
+    let env_spec = item.get_ref().to_env_spec();
+    if !env_spec.is_removed() {
+      continue;
+    }
+    let key = match config
+      .env
+      .get_key_value(env_spec.key.as_str())
+      .or_else(|| config.secrets.get_key_value(env_spec.key.as_str()))
+    {
+      Some((k, _)) => k,
+      None => continue,
+    };
+    errors.push(
+      RemovedEnvError {
+        src: config_src.named_source_for(key.get_ref()),
+        def: config_src.span(key, key.get_ref()),
+        key: env_spec.key.clone(),
+        date: env_spec.removed_after.clone().unwrap_or_default(),
+      }
+      .into(),
+    );
   }
-  Ok(())
 }
 
 fn validate_mysql_defined(
-  (spec_name, spec_text, spec): (&str, &str, &AppSpec),
-  (_config_name, _config_text, config): (&str, &str, &AppConfig),
-) -> miette::Result<()> {
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  (_config_src, config): (&SourceFile, &AppConfig),
+  errors: &mut Vec<miette::Report>,
+) {
   for item in spec.mysql.iter() {
     let value = config.mysql.get(item.get_ref().as_str());
     if value.is_none() {
-      return Err(
+      errors.push(
         UndefinedMysqlError {
-          src: NamedSource::new(spec_name, spec_text.to_string()),
-          def: toml_spanned_to_source_span(item),
+          src: spec_src.named_source_for(item.get_ref()),
+          def: spec_src.span(item, item.get_ref()),
         }
         .into(),
       );
     }
   }
-  Ok(())
 }
 
 fn validate_pubsub_defined(
-  (spec_name, spec_text, spec): (&str, &str, &AppSpec),
-  (_config_name, _config_text, config): (&str, &str, &AppConfig),
-) -> miette::Result<()> {
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  (_config_src, config): (&SourceFile, &AppConfig),
+  errors: &mut Vec<miette::Report>,
+) {
   for item in spec.pubsub.iter() {
     let value = config.pubsub.get(item.get_ref().as_str());
     if value.is_none() {
-      return Err(
+      errors.push(
         UndefinedPubsubError {
-          src: NamedSource::new(spec_name, spec_text.to_string()),
-          def: toml_spanned_to_source_span(item),
+          src: spec_src.named_source_for(item.get_ref()),
+          def: spec_src.span(item, item.get_ref()),
         }
         .into(),
       );
     }
   }
-  Ok(())
 }
 
-fn toml_spanned_to_source_span<T>(spanned: &Spanned<T>) -> SourceSpan {
-  SourceSpan::from(spanned.start()..spanned.end())
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TOML: &str = "id = \"app\"\n\n[env]\nA = \"1\"\nB = \"2\"\n";
+  const JSON: &str = "{\n  \"id\": \"app\",\n  \"env\": { \"A\": \"1\", \"B\": \"2\" }\n}\n";
+  const YAML: &str = "id: app\nenv:\n  A: \"1\"\n  B: \"2\"\n";
+
+  fn env(config: &AppConfig) -> Vec<(&str, &str)> {
+    config
+      .env
+      .iter()
+      .map(|(k, v)| (k.get_ref().as_str(), v.as_str()))
+      .collect()
+  }
+
+  #[test]
+  fn detects_formats() {
+    assert_eq!(ConfigFormat::from_path("Boat.json"), ConfigFormat::Json);
+    assert_eq!(ConfigFormat::from_path("conf/Boat.yml"), ConfigFormat::Yaml);
+    assert_eq!(ConfigFormat::from_path("Boat.yaml"), ConfigFormat::Yaml);
+    assert_eq!(ConfigFormat::from_path("Boat.toml"), ConfigFormat::Toml);
+  }
+
+  #[test]
+  fn parses_every_format_alike() {
+    for (name, text) in [("a.toml", TOML), ("a.json", JSON), ("a.yaml", YAML)] {
+      let config: AppConfig = SourceFile::new(name, text).unwrap().parse().unwrap();
+      assert_eq!(config.id, "app", "{}", name);
+      assert_eq!(env(&config), [("A", "1"), ("B", "2")], "{}", name);
+    }
+  }
+
+  #[test]
+  fn locates_values() {
+    let src = SourceFile::new("a.toml", TOML).unwrap();
+    let config: AppConfig = src.parse().unwrap();
+    let (key, _) = config.env.get_index(1).unwrap();
+    let span = src.span(key, "B");
+    assert_eq!(&TOML[span.offset()..span.offset() + span.len()], "B");
+    assert_eq!(src.value_source(span).position, Some((5, 1)));
+
+    let src = SourceFile::new("a.json", JSON).unwrap();
+    let config: AppConfig = src.parse().unwrap();
+    let (key, _) = config.env.get_index(1).unwrap();
+    assert_eq!((key.start(), key.end()), (0, 0));
+    let span = src.span(key, "B");
+    assert_eq!(&JSON[span.offset()..span.offset() + span.len()], "\"B\"");
+    assert_eq!(src.value_source(span).position, Some((3, 22)));
+
+    let src = SourceFile::new("a.yaml", YAML).unwrap();
+    let span = src.find("B");
+    assert_eq!(src.value_source(span).position, Some((4, 3)));
+  }
+
+  #[test]
+  fn reports_syntax_errors_with_a_location() {
+    for (name, text, offset) in [
+      ("a.toml", "id = \"app\"\nenv = ?\n", 17),
+      ("a.json", "{\n  \"id\": \"app\",\n}\n", 17),
+      ("a.yaml", "id: app\nenv: ]\n", 13),
+    ] {
+      let e = SourceFile::new(name, text).err().unwrap();
+      let span = e.bad_bit.expect(name);
+      assert_eq!(span.offset(), offset, "{}", name);
+    }
+  }
 }
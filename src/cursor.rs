@@ -13,3 +13,9 @@ impl<T> ServiceCursor<T> {
     }
   }
 }
+
+/// A page of a paginated query, with the cursor of the next page if there is one.
+pub struct Page<T, C> {
+  pub items: Vec<T>,
+  pub next: Option<C>,
+}
@@ -0,0 +1,86 @@
+use std::{
+  collections::BTreeSet,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// Directory names whose contents never trigger a rebuild.
+const IGNORED_DIRS: &[&str] = &[".git", "node_modules", "target"];
+
+/// Watches a directory tree for changes, for `boat dev`.
+pub struct ChangeWatcher {
+  _watcher: RecommendedWatcher,
+  rx: UnboundedReceiver<notify::Result<notify::Event>>,
+  ignored: Vec<PathBuf>,
+}
+
+impl ChangeWatcher {
+  /// Starts watching `dir` recursively. Changes under any of `ignored` are skipped.
+  pub fn new(dir: &Path, ignored: Vec<PathBuf>) -> anyhow::Result<Self> {
+    let (tx, rx) = unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res| {
+      let _ = tx.send(res);
+    })?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+    Ok(Self {
+      _watcher: watcher,
+      rx,
+      ignored,
+    })
+  }
+
+  /// Waits for a change, then keeps collecting changes until none arrive for `debounce`.
+  ///
+  /// Returns the set of changed paths.
+  pub async fn next_change(&mut self, debounce: Duration) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut changed = BTreeSet::new();
+    while changed.is_empty() {
+      let event = self
+        .rx
+        .recv()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("file watcher stopped"))?;
+      self.collect(event, &mut changed);
+    }
+    while let Ok(event) = tokio::time::timeout(debounce, self.rx.recv()).await {
+      let event = event.ok_or_else(|| anyhow::anyhow!("file watcher stopped"))?;
+      self.collect(event, &mut changed);
+    }
+    Ok(changed)
+  }
+
+  /// Drops changes received so far, e.g. the ones caused by running the build.
+  pub fn discard_pending(&mut self) {
+    while self.rx.try_recv().is_ok() {}
+  }
+
+  fn collect(&self, event: notify::Result<notify::Event>, out: &mut BTreeSet<PathBuf>) {
+    let event = match event {
+      Ok(x) => x,
+      Err(e) => {
+        log::warn!("file watcher error: {}", e);
+        return;
+      }
+    };
+    if event.kind.is_access() {
+      return;
+    }
+    for path in event.paths {
+      if !self.is_ignored(&path) {
+        out.insert(path);
+      }
+    }
+  }
+
+  fn is_ignored(&self, path: &Path) -> bool {
+    self.ignored.iter().any(|x| path.starts_with(x))
+      || path.components().any(|x| {
+        IGNORED_DIRS
+          .iter()
+          .any(|dir| x.as_os_str() == std::ffi::OsStr::new(dir))
+      })
+  }
+}
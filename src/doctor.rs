@@ -0,0 +1,228 @@
+use std::{
+  path::PathBuf,
+  time::{Duration, SystemTime},
+};
+
+use crate::{
+  authenticator::{Credentials, CredentialsOptions},
+  client_version::{Version, VersionPolicy},
+  config::BuildCommand,
+  config_loader::{self, LoadOptions},
+  package_builder,
+  service::{ConnectionOptions, Service},
+};
+
+/// Clock skew beyond which request signatures may be rejected.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Outcome of one `boat doctor` check.
+pub struct CheckResult {
+  pub name: &'static str,
+  pub ok: bool,
+  pub detail: String,
+  pub hint: Option<String>,
+}
+
+impl CheckResult {
+  fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+    Self {
+      name,
+      ok: true,
+      detail: detail.into(),
+      hint: None,
+    }
+  }
+
+  fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+    Self {
+      name,
+      ok: false,
+      detail: detail.into(),
+      hint: Some(hint.into()),
+    }
+  }
+}
+
+pub struct DoctorOptions<'a> {
+  pub endpoint: &'a str,
+  pub credentials: CredentialsOptions,
+  pub connection: ConnectionOptions,
+  pub spec: &'a str,
+  pub config: &'a str,
+  pub load: LoadOptions<'a>,
+}
+
+pub async fn run_checks(opts: &DoctorOptions<'_>) -> Vec<CheckResult> {
+  let mut out = vec![];
+
+  let build = match config_loader::load_from_file(opts.spec, opts.config, &opts.load) {
+    Ok(((_, spec), _)) => {
+      out.push(CheckResult::pass("config", "spec and config are valid"));
+      spec.build.map(|x| (x, spec.build_container.is_some()))
+    }
+    Err(e) => {
+      out.push(CheckResult::fail(
+        "config",
+        e.to_string(),
+        "run `boat validate` for details",
+      ));
+      None
+    }
+  };
+
+  match &build {
+    // The build tools are in the image rather than on the host.
+    Some((_, true)) => out.push(check_tool("tools", "docker")),
+    Some((build, false)) => {
+      if let BuildCommand::Shell(_) = build {
+        out.push(check_tool("tools", package_builder::shell().0));
+      }
+      if let Some(tool) = build.program() {
+        out.push(check_tool("build tool", tool));
+      }
+    }
+    None => {}
+  }
+
+  let creds = match Credentials::init(&opts.credentials) {
+    Ok(x) => {
+      out.push(CheckResult::pass(
+        "credentials",
+        format!("loaded from {}", x.source()),
+      ));
+      Some(x)
+    }
+    Err(e) => {
+      out.push(CheckResult::fail(
+        "credentials",
+        format!("{:#}", e),
+        "set BOAT_ACCESS_KEY/BOAT_SECRET_KEY or create ~/.boat/credentials.json",
+      ));
+      None
+    }
+  };
+  let have_creds = creds.is_some();
+
+  let service = match Service::with_credentials(opts.endpoint, creds, &opts.connection) {
+    Ok(x) => x,
+    Err(e) => {
+      out.push(CheckResult::fail(
+        "endpoint",
+        format!("{:#}", e),
+        "check --endpoint / BOAT_ENDPOINT and --proxy",
+      ));
+      return out;
+    }
+  };
+
+  match service.probe().await {
+    Ok(probe) => {
+      out.push(CheckResult::pass(
+        "endpoint",
+        format!(
+          "{} reachable in {} ms",
+          opts.endpoint,
+          probe.latency.as_millis()
+        ),
+      ));
+      out.push(check_clock_skew(probe.server_time));
+      out.push(check_version(probe.version_policy.as_ref()));
+    }
+    Err(e) => {
+      out.push(CheckResult::fail(
+        "endpoint",
+        format!("{}: {}", e, e.root_cause()),
+        "check your network connection and --endpoint / BOAT_ENDPOINT",
+      ));
+      return out;
+    }
+  }
+
+  if have_creds {
+    match service.viewer().await {
+      Ok(viewer) => out.push(CheckResult::pass(
+        "authentication",
+        format!("authenticated as {}", viewer.account_name),
+      )),
+      Err(e) => out.push(CheckResult::fail(
+        "authentication",
+        format!("{:#}", e),
+        "check that the access key is still active and the clock is correct",
+      )),
+    }
+  }
+
+  out
+}
+
+fn check_tool(name: &'static str, tool: &str) -> CheckResult {
+  match find_in_path(tool) {
+    Some(path) => CheckResult::pass(name, format!("{} found at {}", tool, path.display())),
+    None => CheckResult::fail(
+      name,
+      format!("{} not found in PATH", tool),
+      format!("install {} or add it to PATH", tool),
+    ),
+  }
+}
+
+fn check_clock_skew(server_time: Option<SystemTime>) -> CheckResult {
+  let server_time = match server_time {
+    Some(x) => x,
+    None => return CheckResult::pass("clock", "server did not report its time, skipped"),
+  };
+  let now = SystemTime::now();
+  let skew = now
+    .duration_since(server_time)
+    .or_else(|_| server_time.duration_since(now))
+    .unwrap_or_default();
+  if skew > MAX_CLOCK_SKEW {
+    CheckResult::fail(
+      "clock",
+      format!("local clock differs from server by {}s", skew.as_secs()),
+      "synchronize the system clock, request signatures are time-based",
+    )
+  } else {
+    CheckResult::pass("clock", format!("skew {}s", skew.as_secs()))
+  }
+}
+
+fn check_version(policy: Option<&VersionPolicy>) -> CheckResult {
+  let current = Version::current();
+  let policy = match policy {
+    Some(x) => x,
+    None => {
+      return CheckResult::pass(
+        "version",
+        format!("boat {}, server did not report supported versions", current),
+      )
+    }
+  };
+  match policy.check(&current) {
+    Ok(Some(latest)) => CheckResult::pass(
+      "version",
+      format!("boat {}, {} is available", current, latest),
+    ),
+    Ok(None) => CheckResult::pass("version", format!("boat {} is up to date", current)),
+    Err(e) => CheckResult::fail(
+      "version",
+      e.to_string(),
+      "upgrade with `cargo install boatctl`",
+    ),
+  }
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+  if name.contains(std::path::MAIN_SEPARATOR) {
+    let path = PathBuf::from(name);
+    return path.is_file().then_some(path);
+  }
+  let paths = std::env::var_os("PATH")?;
+  std::env::split_paths(&paths)
+    .flat_map(|dir| {
+      let candidate = dir.join(name);
+      let exe = dir.join(format!("{}.exe", name));
+      [candidate, exe]
+    })
+    .find(|x| x.is_file())
+}
@@ -0,0 +1,69 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use anyhow::Context;
+use reqwest::Url;
+
+/// Path that API calls over a Unix socket are sent to, like on the default endpoint.
+pub const UNIX_SOCKET_API_PATH: &str = "/graphql";
+
+/// Where the Lighthouse service is reached, from `--endpoint`.
+#[derive(Debug, Clone)]
+pub enum Endpoint {
+  /// An `https://` url, or with `--insecure-http` an `http://` one.
+  Http(Url),
+  /// The socket of a locally running control plane, from a url like
+  /// `unix:///run/lighthouse.sock`.
+  Unix(PathBuf),
+}
+
+impl Endpoint {
+  /// Parses an endpoint url. A plain `http://` one is refused unless `insecure_http` is set, as
+  /// deployments, secrets included, would be sent unencrypted.
+  pub fn parse(endpoint: &str, insecure_http: bool) -> anyhow::Result<Self> {
+    let url = Url::parse(endpoint).with_context(|| format!("invalid endpoint `{}`", endpoint))?;
+    match url.scheme() {
+      "https" => Ok(Endpoint::Http(url)),
+      "http" if !insecure_http => anyhow::bail!(
+        "endpoint `{}` is plain HTTP, which sends deployments and secrets unencrypted; pass \
+         --insecure-http to use it anyway",
+        endpoint
+      ),
+      "http" => {
+        if !is_loopback(&url) {
+          log::warn!(
+            "sending requests to {} unencrypted over the network",
+            url.host_str().unwrap_or_default()
+          );
+        }
+        Ok(Endpoint::Http(url))
+      }
+      "unix" => {
+        if !cfg!(unix) {
+          anyhow::bail!("Unix socket endpoints are not supported on this platform");
+        }
+        if url.host_str().map(|x| !x.is_empty()).unwrap_or(false) || url.path().len() <= 1 {
+          anyhow::bail!(
+            "endpoint `{}` must be the absolute path of a socket, like \
+             `unix:///run/lighthouse.sock`",
+            endpoint
+          );
+        }
+        Ok(Endpoint::Unix(PathBuf::from(url.path())))
+      }
+      x => anyhow::bail!("unsupported endpoint scheme `{}`", x),
+    }
+  }
+}
+
+fn is_loopback(url: &Url) -> bool {
+  match url.host_str() {
+    Some("localhost") => true,
+    Some(host) => host
+      .trim_start_matches('[')
+      .trim_end_matches(']')
+      .parse::<IpAddr>()
+      .map(|x| x.is_loopback())
+      .unwrap_or(false),
+    None => false,
+  }
+}
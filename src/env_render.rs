@@ -0,0 +1,204 @@
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::config::{AppConfig, AppSpec, ValueSource};
+
+const REDACTED: &str = "<redacted>";
+
+/// Output format for `boat env print`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvFormat {
+  Shell,
+  Dotenv,
+  Json,
+}
+
+impl FromStr for EnvFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "shell" => Ok(EnvFormat::Shell),
+      "dotenv" => Ok(EnvFormat::Dotenv),
+      "json" => Ok(EnvFormat::Json),
+      _ => anyhow::bail!("unknown env format: {} (expected shell, dotenv or json)", s),
+    }
+  }
+}
+
+/// Collects the environment an app would see: spec defaults, config env, then secrets.
+///
+/// `extra_secrets` are secrets kept outside the config, e.g. in a detached secrets file.
+pub fn effective_env(
+  spec: &AppSpec,
+  config: &AppConfig,
+  extra_secrets: &[(String, String)],
+  redact_secrets: bool,
+) -> IndexMap<String, String> {
+  let mut out: IndexMap<String, String> = spec.env_defaults().collect();
+  for (k, v) in &config.env {
+    out.insert(k.get_ref().clone(), v.clone());
+  }
+  let secrets = config
+    .secrets
+    .iter()
+    .map(|(k, v)| (k.get_ref(), v))
+    .chain(extra_secrets.iter().map(|(k, v)| (k, v)));
+  for (k, v) in secrets {
+    let v = if redact_secrets { REDACTED } else { v.as_str() };
+    out.insert(k.clone(), v.to_string());
+  }
+  out
+}
+
+/// Renders `vars` in `format`. With `sources`, each value is annotated with where it was
+/// defined: a comment line before it, or a `{ "value", "source" }` object in JSON.
+pub fn render(
+  vars: &IndexMap<String, String>,
+  sources: Option<&IndexMap<String, ValueSource>>,
+  format: EnvFormat,
+) -> anyhow::Result<String> {
+  let mut out = String::new();
+  let comment = |out: &mut String, k: &str| {
+    if let Some(source) = sources.and_then(|x| x.get(k)) {
+      out.push_str(&format!("# {}\n", source));
+    }
+  };
+  match format {
+    EnvFormat::Shell => {
+      for (k, v) in vars {
+        comment(&mut out, k);
+        out.push_str(&format!("export {}={}\n", k, shell_quote(v)));
+      }
+    }
+    EnvFormat::Dotenv => {
+      for (k, v) in vars {
+        comment(&mut out, k);
+        out.push_str(&format!("{}={}\n", k, dotenv_quote(v)));
+      }
+    }
+    EnvFormat::Json => {
+      out = match sources {
+        Some(sources) => {
+          let annotated: IndexMap<&String, serde_json::Value> = vars
+            .iter()
+            .map(|(k, v)| {
+              let source = sources.get(k).map(|x| x.to_string());
+              (k, serde_json::json!({ "value": v, "source": source }))
+            })
+            .collect();
+          serde_json::to_string_pretty(&annotated)?
+        }
+        None => serde_json::to_string_pretty(vars)?,
+      };
+      out.push('\n');
+    }
+  }
+  Ok(out)
+}
+
+fn shell_quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn dotenv_quote(value: &str) -> String {
+  let mut out = String::from("\"");
+  for c in value.chars() {
+    match c {
+      '\\' => out.push_str("\\\\"),
+      '"' => out.push_str("\\\""),
+      '$' => out.push_str("\\$"),
+      '\n' => out.push_str("\\n"),
+      _ => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars(pairs: &[(&str, &str)]) -> IndexMap<String, String> {
+    pairs
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn layers_defaults_config_and_secrets() {
+    let spec: AppSpec = toml::from_str(
+      r#"
+      artifact = "dist/index.js"
+      env = ["A", { key = "B", optional = true, default = "b0" }, { key = "C", optional = true, default = "c0" }]
+      "#,
+    )
+    .unwrap();
+    let config: AppConfig = toml::from_str(
+      r#"
+      id = "app"
+      env = { A = "a", B = "b" }
+      secrets = { S = "s" }
+      "#,
+    )
+    .unwrap();
+    let extra = [("T".to_string(), "t".to_string())];
+    let env = effective_env(&spec, &config, &extra, false);
+    assert_eq!(
+      env,
+      vars(&[("B", "b"), ("C", "c0"), ("A", "a"), ("S", "s"), ("T", "t")])
+    );
+    let env = effective_env(&spec, &config, &extra, true);
+    assert_eq!(env["S"], REDACTED);
+    assert_eq!(env["T"], REDACTED);
+    assert_eq!(env["A"], "a");
+  }
+
+  #[test]
+  fn quotes_values() {
+    let vars = vars(&[("A", "it's $HOME"), ("B", "line\n\"two\"\\")]);
+    assert_eq!(
+      render(&vars, None, EnvFormat::Shell).unwrap(),
+      "export A='it'\\''s $HOME'\nexport B='line\n\"two\"\\'\n"
+    );
+    assert_eq!(
+      render(&vars, None, EnvFormat::Dotenv).unwrap(),
+      "A=\"it's \\$HOME\"\nB=\"line\\n\\\"two\\\"\\\\\"\n"
+    );
+  }
+
+  #[test]
+  fn annotates_sources() {
+    let vars = vars(&[("A", "1"), ("B", "2")]);
+    let mut sources = IndexMap::new();
+    sources.insert(
+      "A".to_string(),
+      ValueSource {
+        file: "Boat.toml".into(),
+        position: Some((3, 1)),
+      },
+    );
+    assert_eq!(
+      render(&vars, Some(&sources), EnvFormat::Dotenv).unwrap(),
+      "# Boat.toml:3:1\nA=\"1\"\nB=\"2\"\n"
+    );
+    let json: serde_json::Value =
+      serde_json::from_str(&render(&vars, Some(&sources), EnvFormat::Json).unwrap()).unwrap();
+    assert_eq!(
+      json,
+      serde_json::json!({
+        "A": { "value": "1", "source": "Boat.toml:3:1" },
+        "B": { "value": "2", "source": null },
+      })
+    );
+  }
+
+  #[test]
+  fn parses_formats() {
+    assert_eq!("dotenv".parse::<EnvFormat>().unwrap(), EnvFormat::Dotenv);
+    assert!("yaml".parse::<EnvFormat>().is_err());
+  }
+}
@@ -0,0 +1,345 @@
+use toml_edit::{Array, Decor, Document, InlineTable, Item, Table, Value};
+
+/// Order of the top-level keys of a spec. Keys not listed go last, in their original order.
+const SPEC_KEYS: &[&str] = &[
+  "version",
+  "build",
+  "build_container",
+  "build_output",
+  "build_timeout",
+  "clean_env",
+  "allow_env",
+  "artifact",
+  "artifact_sha256",
+  "artifact_type",
+  "verify",
+  "node_compat",
+  "max_package_size",
+  "sourcemaps",
+  "static",
+  "symlinks",
+  "preserve_executable",
+  "mysql",
+  "pubsub",
+  "env",
+  "secrets",
+  "assets",
+  "fingerprint",
+  "hooks",
+];
+
+/// Order of the keys of an env or secret entry in a spec.
+const ENV_SPEC_KEYS: &[&str] = &[
+  "key",
+  "type",
+  "regex",
+  "allowed_values",
+  "min",
+  "max",
+  "optional",
+  "default",
+  "required_if",
+  "encoding",
+  "deprecated",
+  "removed_after",
+];
+
+/// Order of the top-level keys of a config.
+const CONFIG_KEYS: &[&str] = &[
+  "version",
+  "id",
+  "extends",
+  "detached_secrets",
+  "env",
+  "secrets",
+  "mysql",
+  "pubsub",
+  "profile",
+];
+
+/// Order of the keys of a profile in a config.
+const PROFILE_KEYS: &[&str] = &["id", "env", "secrets", "mysql", "pubsub"];
+
+/// Reformats a TOML spec canonically, keeping comments. Top-level keys and the keys of env and
+/// secret entries are put in a fixed order, and entries that are tables go on a line each.
+pub fn format_spec(text: &str) -> anyhow::Result<String> {
+  let (mut doc, header) = parse(text)?;
+  let root = doc.as_table_mut();
+  sort_by_order(root, SPEC_KEYS);
+  for section in ["env", "secrets"] {
+    if let Some(array) = root.get_mut(section).and_then(|x| x.as_array_mut()) {
+      for item in array.iter_mut() {
+        if let Some(table) = item.as_inline_table_mut() {
+          table.sort_values_by(|a, _, b, _| rank(ENV_SPEC_KEYS, a).cmp(&rank(ENV_SPEC_KEYS, b)));
+        }
+      }
+    }
+  }
+  Ok(finish(doc, &header))
+}
+
+/// Reformats a TOML config canonically, keeping comments. Top-level and profile keys are put in
+/// a fixed order, and env, secrets, mysql and pubsub entries are sorted by name.
+pub fn format_config(text: &str) -> anyhow::Result<String> {
+  let (mut doc, header) = parse(text)?;
+  let root = doc.as_table_mut();
+  sort_by_order(root, CONFIG_KEYS);
+  sort_sections(root);
+  if let Some(profiles) = root.get_mut("profile").and_then(|x| x.as_table_mut()) {
+    for (_, profile) in profiles.iter_mut() {
+      if let Some(profile) = profile.as_table_mut() {
+        sort_by_order(profile, PROFILE_KEYS);
+        sort_sections(profile);
+      }
+    }
+  }
+  Ok(finish(doc, &header))
+}
+
+/// Parses `text`, splitting off the comments at the start of the file that are followed by a
+/// blank line. They stay at the top, while other comments move with the item they precede.
+fn parse(text: &str) -> anyhow::Result<(Document, String)> {
+  let mut doc = text
+    .parse::<Document>()
+    .map_err(|e| anyhow::Error::from(e).context("cannot parse file"))?;
+  let header = split_header(doc.as_table_mut());
+  Ok((doc, header))
+}
+
+fn split_header(root: &mut Table) -> String {
+  let mut key = match root.iter_mut().next() {
+    Some((key, Item::Value(_))) => key,
+    _ => return String::new(),
+  };
+  let prefix = key.decor().prefix().unwrap_or_default().to_string();
+  match prefix.rfind("\n\n") {
+    Some(i) => {
+      key.decor_mut().set_prefix(&prefix[i + 1..]);
+      comment_block(Some(&prefix[..i + 1]), false)
+    }
+    None => String::new(),
+  }
+}
+
+fn rank(order: &[&str], key: &str) -> usize {
+  order.iter().position(|x| *x == key).unwrap_or(order.len())
+}
+
+fn sort_by_order(table: &mut Table, order: &[&str]) {
+  table.sort_values_by(|a, _, b, _| rank(order, a.get()).cmp(&rank(order, b.get())));
+}
+
+/// Sorts the entries of the env, secrets, mysql and pubsub tables of a config or profile.
+fn sort_sections(table: &mut Table) {
+  for section in ["env", "secrets", "mysql", "pubsub"] {
+    match table.get_mut(section) {
+      Some(Item::Table(x)) => x.sort_values(),
+      Some(Item::Value(Value::InlineTable(x))) => x.sort_values(),
+      _ => {}
+    }
+  }
+}
+
+/// Normalizes the whitespace of the whole document and renders it.
+fn finish(mut doc: Document, header: &str) -> String {
+  let mut position = 0;
+  normalize_table(doc.as_table_mut(), true, &mut position);
+  let trailing = comment_block(Some(doc.trailing()), false);
+  doc.set_trailing(&trailing);
+  let out = doc.to_string();
+  let out = out.trim_start_matches('\n').trim_end_matches('\n');
+  if header.is_empty() {
+    format!("{}\n", out)
+  } else {
+    format!("{}\n{}\n", header, out)
+  }
+}
+
+/// Normalizes the values of `table` and of its subtables, and renumbers the subtables so that
+/// they are written in the order of their keys.
+fn normalize_table(table: &mut Table, is_root: bool, position: &mut usize) {
+  table.set_position(*position);
+  *position += 1;
+  if !is_root {
+    let header_comment = trailing_comment(table.decor().suffix());
+    let prefix = format!("\n{}", comment_block(table.decor().prefix(), false));
+    *table.decor_mut() = Decor::new(prefix, header_comment);
+  }
+  let mut first = true;
+  for (mut key, item) in table.iter_mut() {
+    match item {
+      Item::Value(value) => {
+        let prefix = comment_block(key.decor().prefix(), !first);
+        *key.decor_mut() = Decor::new(prefix, " ");
+        normalize_value(value);
+        let suffix = trailing_comment(value.decor().suffix());
+        *value.decor_mut() = Decor::new(" ", suffix);
+        first = false;
+      }
+      Item::Table(x) if x.is_dotted() => {}
+      Item::Table(x) => normalize_table(x, false, position),
+      Item::ArrayOfTables(x) => {
+        for t in x.iter_mut() {
+          normalize_table(t, false, position);
+        }
+      }
+      Item::None => {}
+    }
+  }
+}
+
+fn normalize_value(value: &mut Value) {
+  match value {
+    Value::Array(x) => normalize_array(x),
+    Value::InlineTable(x) => normalize_inline_table(x),
+    _ => {}
+  }
+}
+
+fn normalize_inline_table(table: &mut InlineTable) {
+  table.fmt();
+  for (_, value) in table.iter_mut() {
+    normalize_value(value);
+  }
+}
+
+/// Puts arrays of tables, and arrays with comments, on a line per element, and other arrays on
+/// one line.
+fn normalize_array(array: &mut Array) {
+  for value in array.iter_mut() {
+    normalize_value(value);
+  }
+  let has_comments = array
+    .iter()
+    .any(|x| !comments(x.decor().prefix()).is_empty() || !comments(x.decor().suffix()).is_empty())
+    || !comments(Some(array.trailing())).is_empty();
+  let has_tables = array.iter().any(|x| x.is_inline_table());
+  if !has_comments && !has_tables {
+    array.fmt();
+    return;
+  }
+  for value in array.iter_mut() {
+    let mut prefix = String::from("\n");
+    for comment in comments(value.decor().prefix()) {
+      prefix.push_str(&format!("  {}\n", comment));
+    }
+    prefix.push_str("  ");
+    let suffix = trailing_comment(value.decor().suffix());
+    *value.decor_mut() = Decor::new(prefix, suffix);
+  }
+  let mut trailing = String::from("\n");
+  for comment in comments(Some(array.trailing())) {
+    trailing.push_str(&format!("  {}\n", comment));
+  }
+  array.set_trailing(&trailing);
+  array.set_trailing_comma(true);
+}
+
+/// Comment lines in `decor`, without the surrounding whitespace.
+fn comments(decor: Option<&str>) -> Vec<&str> {
+  decor
+    .unwrap_or_default()
+    .lines()
+    .map(|x| x.trim())
+    .filter(|x| x.starts_with('#'))
+    .collect()
+}
+
+/// The comment lines of `decor`, each on a line of its own. With `blank_line`, a single blank
+/// line is kept before them if `decor` started with one.
+fn comment_block(decor: Option<&str>, blank_line: bool) -> String {
+  let decor = decor.unwrap_or_default();
+  let mut out = String::new();
+  if blank_line
+    && decor.contains('\n')
+    && decor.lines().next().unwrap_or_default().trim().is_empty()
+  {
+    out.push('\n');
+  }
+  for comment in comments(Some(decor)) {
+    out.push_str(comment);
+    out.push('\n');
+  }
+  out
+}
+
+/// A comment after a value or table header on the same line, with a space before it.
+fn trailing_comment(decor: Option<&str>) -> String {
+  let decor = decor.unwrap_or_default().trim();
+  if decor.starts_with('#') {
+    format!(" {}", decor)
+  } else {
+    String::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn formats_specs() {
+    let spec = r#"# app spec
+
+env = [{ optional=true,   key = "B" }, "A"]
+artifact   =   "dist/index.js" # built by esbuild
+
+# runs first
+build = "npm run build"
+"#;
+    let text = format_spec(spec).unwrap();
+    assert_eq!(
+      text,
+      r#"# app spec
+
+# runs first
+build = "npm run build"
+artifact = "dist/index.js" # built by esbuild
+
+env = [
+  { key = "B", optional = true },
+  "A",
+]
+"#
+    );
+    assert_eq!(format_spec(&text).unwrap(), text);
+  }
+
+  #[test]
+  fn formats_configs() {
+    let config = r#"[env]
+B = "2"
+A = "1"
+
+[profile.staging]
+env = { Z = "z", Y = "y" }
+id = "app-staging"
+
+[secrets]
+S = "s"
+"#;
+    let text = format_config(&format!("id = \"app\"\n{}", config)).unwrap();
+    assert_eq!(
+      text,
+      r#"id = "app"
+
+[env]
+A = "1"
+B = "2"
+
+[secrets]
+S = "s"
+
+[profile.staging]
+id = "app-staging"
+env = { Y = "y", Z = "z" }
+"#
+    );
+    assert_eq!(format_config(&text).unwrap(), text);
+  }
+
+  #[test]
+  fn rejects_invalid_toml() {
+    assert!(format_config("id = \n").is_err());
+  }
+}
@@ -0,0 +1,39 @@
+use std::{
+  path::Path,
+  process::{Command, Stdio},
+};
+
+use crate::{
+  child_process::{self, Outcome},
+  package_builder::shell,
+};
+
+/// Runs the `name` hook `script` with the shell of the platform, in the spec directory and with
+/// `env` added to the environment. The output of the hook goes to stderr, so that it does not
+/// mix with the output of boat.
+pub fn run(spec_path: &Path, name: &str, script: &str, env: &[(&str, &str)]) -> anyhow::Result<()> {
+  let spec_dir = spec_path
+    .parent()
+    .ok_or_else(|| anyhow::anyhow!("cannot resolve spec parent dir"))?;
+  let (shell, flag) = shell();
+  let mut command = Command::new(shell);
+  command
+    .arg(flag)
+    .arg(script)
+    .current_dir(spec_dir)
+    .envs(env.iter().copied())
+    .stdout(Stdio::from(std::io::stderr()));
+  log::info!("running {} hook: {}", name, script);
+  match child_process::run(&mut command, None)
+    .map_err(|e| e.context(format!("cannot run {} hook `{}`", name, script)))?
+  {
+    Outcome::Exited(status) if status.success() => Ok(()),
+    Outcome::Exited(status) => anyhow::bail!(
+      "{} hook `{}` exited with {}",
+      name,
+      script,
+      status.code().unwrap_or(1)
+    ),
+    _ => anyhow::bail!("{} hook `{}` interrupted", name, script),
+  }
+}
@@ -0,0 +1,224 @@
+use serde_json::{json, Map, Value};
+
+use crate::{
+  config::{AppSpec, EnvSpec, EnvType, FORMAT_VERSION},
+  secret_resolver,
+};
+
+/// Builds a JSON Schema describing the configs that are valid for `spec`, for editors and other
+/// tooling to validate and complete `Boat.toml` with.
+///
+/// Required keys are only enforced on configs without `extends`, since a base config may
+/// provide them, and required secrets only without `detached_secrets`.
+pub fn config_schema(spec: &AppSpec) -> Value {
+  let env = spec.env.iter().map(|x| x.get_ref().to_env_spec());
+  let secrets = spec.secrets.iter().map(|x| x.get_ref().to_env_spec());
+  let env_schema = env_table_schema(env.clone().map(|x| (x, false)));
+  let secrets_schema = env_table_schema(secrets.clone().map(|x| (x, true)));
+  let required_env = required_keys(env);
+  let required_secrets = required_keys(secrets);
+
+  let mysql: Map<String, Value> = spec
+    .mysql
+    .iter()
+    .map(|x| (x.get_ref().clone(), mysql_schema()))
+    .collect();
+  let pubsub: Map<String, Value> = spec
+    .pubsub
+    .iter()
+    .map(|x| (x.get_ref().clone(), pubsub_schema()))
+    .collect();
+  let mysql_schema = json!({
+    "type": "object",
+    "properties": mysql,
+    "additionalProperties": mysql_schema(),
+  });
+  let pubsub_schema = json!({
+    "type": "object",
+    "properties": pubsub,
+    "additionalProperties": pubsub_schema(),
+  });
+
+  // A table is only required if something in it is.
+  let mut required_keys = vec!["id"];
+  for (key, non_empty) in [
+    ("env", !required_env.is_empty()),
+    ("mysql", !mysql.is_empty()),
+    ("pubsub", !pubsub.is_empty()),
+  ] {
+    if non_empty {
+      required_keys.push(key);
+    }
+  }
+  let required = json!({
+    "required": required_keys,
+    "properties": {
+      "env": { "required": required_env },
+      "mysql": { "required": mysql.keys().collect::<Vec<_>>() },
+      "pubsub": { "required": pubsub.keys().collect::<Vec<_>>() },
+    },
+  });
+  let required_secrets = if required_secrets.is_empty() {
+    json!({})
+  } else {
+    json!({
+      "if": {
+        "properties": { "detached_secrets": { "const": true } },
+        "required": ["detached_secrets"],
+      },
+      "else": {
+        "properties": { "secrets": { "required": required_secrets } },
+        "required": ["secrets"],
+      },
+    })
+  };
+
+  json!({
+    "$schema": "http://json-schema.org/draft-07/schema#",
+    "title": "Boat app config",
+    "type": "object",
+    "properties": {
+      "version": { "type": "integer", "maximum": FORMAT_VERSION },
+      "id": { "type": "string", "description": "App ID." },
+      "extends": {
+        "type": "array",
+        "items": { "type": "string" },
+        "description": "Configs to inherit from, relative to this one.",
+      },
+      "detached_secrets": {
+        "type": "boolean",
+        "description": "Keep secrets in a separate file and in the service instead of here.",
+      },
+      "env": env_schema,
+      "secrets": secrets_schema,
+      "mysql": mysql_schema,
+      "pubsub": pubsub_schema,
+      "profile": {
+        "type": "object",
+        "description": "Overrides selected with `--profile`.",
+        "additionalProperties": {
+          "type": "object",
+          "properties": {
+            "id": { "type": "string" },
+            "env": env_schema,
+            "secrets": secrets_schema,
+            "mysql": mysql_schema,
+            "pubsub": pubsub_schema,
+          },
+        },
+      },
+    },
+    "if": { "not": { "required": ["extends"] } },
+    "then": { "allOf": [required, required_secrets] },
+  })
+}
+
+fn required_keys<'a>(specs: impl Iterator<Item = std::borrow::Cow<'a, EnvSpec>>) -> Vec<String> {
+  specs
+    .filter(|x| !x.optional)
+    .map(|x| x.key.clone())
+    .collect()
+}
+
+fn env_table_schema<'a>(
+  specs: impl Iterator<Item = (std::borrow::Cow<'a, EnvSpec>, bool)>,
+) -> Value {
+  let properties: Map<String, Value> = specs
+    .map(|(spec, is_secret)| (spec.key.clone(), env_value_schema(&spec, is_secret)))
+    .collect();
+  json!({
+    "type": "object",
+    "properties": properties,
+    "additionalProperties": { "type": "string" },
+  })
+}
+
+fn env_value_schema(spec: &EnvSpec, is_secret: bool) -> Value {
+  let mut out = json!({ "type": "string" });
+  let mut patterns = vec![];
+  if let Some(regex) = &spec.regex {
+    patterns.push(regex.clone());
+  }
+  match spec.ty {
+    Some(EnvType::Int) => patterns.push("^-?[0-9]+$".into()),
+    Some(EnvType::Port) => patterns.push("^[1-9][0-9]{0,4}$".into()),
+    Some(EnvType::Bool) => out["enum"] = json!(["true", "false"]),
+    Some(EnvType::Url) => out["format"] = "uri".into(),
+    Some(EnvType::Enum) | None => {}
+  }
+  if let Some(allowed) = &spec.allowed_values {
+    out["enum"] = json!(allowed);
+  }
+  match patterns.len() {
+    0 => {}
+    1 => out["pattern"] = patterns.pop().unwrap().into(),
+    _ => {
+      out["allOf"] = patterns
+        .into_iter()
+        .map(|x| json!({ "pattern": x }))
+        .collect();
+    }
+  }
+  if let Some(default) = &spec.default {
+    out["default"] = default.clone().into();
+  }
+  let mut description = vec![];
+  if spec.optional {
+    description.push("Optional.".to_string());
+  }
+  if let Some(cond) = &spec.required_if {
+    description.push(format!(
+      "Required when `{}` is `{}`.",
+      cond.key, cond.equals
+    ));
+  }
+  match &spec.removed_after {
+    Some(date) => description.push(format!("Deprecated; removed after {}.", date)),
+    None if spec.deprecated => description.push("Deprecated.".to_string()),
+    None => {}
+  }
+  if let (Some(min), Some(max)) = (spec.min, spec.max) {
+    description.push(format!("Between {} and {}.", min, max));
+  } else if let Some(min) = spec.min {
+    description.push(format!("At least {}.", min));
+  } else if let Some(max) = spec.max {
+    description.push(format!("At most {}.", max));
+  }
+  if !description.is_empty() {
+    out["description"] = description.join(" ").into();
+  }
+  if is_secret {
+    // Secrets may instead refer to an external store, resolved at deploy time.
+    let reference = format!("^({}):", secret_resolver::DEFAULT_SCHEMES.join("|"));
+    return json!({
+      "anyOf": [out, { "type": "string", "pattern": reference }],
+    });
+  }
+  out
+}
+
+fn mysql_schema() -> Value {
+  json!({
+    "type": "object",
+    "properties": {
+      "url": { "type": "string" },
+      "root_certificate": { "type": "string" },
+    },
+    "required": ["url"],
+    "additionalProperties": false,
+  })
+}
+
+fn pubsub_schema() -> Value {
+  json!({
+    "anyOf": [
+      { "type": "string" },
+      {
+        "type": "object",
+        "properties": { "namespace": { "type": "string" } },
+        "required": ["namespace"],
+        "additionalProperties": false,
+      },
+    ],
+  })
+}
@@ -1,9 +1,46 @@
+pub mod api;
 pub mod authenticator;
+pub mod boatignore;
+pub mod build_cache;
+pub mod child_process;
+pub mod client_version;
+pub mod cli;
 pub mod config;
+pub mod config_crypto;
+pub mod config_editor;
 pub mod config_loader;
+pub mod dev;
+pub mod doctor;
+pub mod endpoint;
+pub mod env_render;
+pub mod formatter;
+pub mod hooks;
+pub mod http_log;
+pub mod json_schema;
+pub mod lint;
 pub mod metadata;
+pub mod migrate;
+pub mod output;
+pub mod pack_format;
+pub mod ping;
 pub mod package_builder;
+pub mod package_file;
+pub mod progress;
+pub mod provenance;
+pub mod rate_limit;
+pub mod prompt;
+pub mod retry;
+pub mod scaffold;
+pub mod secret_resolver;
 pub mod schema;
 pub mod service;
+pub mod spanned;
+pub mod telemetry;
+pub mod template;
+pub mod tls;
+pub mod transport;
+pub mod upload_state;
+pub mod user_config;
 pub mod logloader;
 pub mod cursor;
+pub mod workspace;
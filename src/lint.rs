@@ -0,0 +1,465 @@
+use std::fmt::Display;
+
+use data_encoding::BASE64;
+use miette::{Diagnostic, LabeledSpan, NamedSource, Severity, SourceCode, SourceSpan};
+use regex::Regex;
+use thiserror::Error;
+
+use crate::{
+  config::{AppConfig, AppSpec, EnvEncoding},
+  config_loader::SourceFile,
+  secret_resolver,
+};
+
+/// A check for problems that do not make the spec and config invalid, but are likely mistakes.
+#[derive(Debug)]
+pub struct Rule {
+  /// Name of the rule, used in `boatctl::lint::<id>` codes and in suppressions.
+  pub id: &'static str,
+  pub description: &'static str,
+  /// Whether the rule runs whenever the spec and config are loaded, and not only in `boat lint`.
+  pub on_load: bool,
+}
+
+pub const UNDECLARED: Rule = Rule {
+  id: "undeclared",
+  description: "config entries that are not declared in the spec",
+  on_load: true,
+};
+
+pub const DEPRECATED_ENV: Rule = Rule {
+  id: "deprecated-env",
+  description: "config values for env variables that the spec deprecates",
+  on_load: true,
+};
+
+pub const WEAK_SECRET: Rule = Rule {
+  id: "weak-secret",
+  description: "secret values that are empty, placeholders or not in the declared encoding",
+  on_load: false,
+};
+
+pub const BROAD_REGEX: Rule = Rule {
+  id: "broad-regex",
+  description: "spec regexes that are not anchored or that match any value",
+  on_load: false,
+};
+
+pub const UNUSED_SPEC_ENTRY: Rule = Rule {
+  id: "unused-spec-entry",
+  description: "optional spec entries without a default that the config never sets",
+  on_load: false,
+};
+
+pub const RULES: &[&Rule] = &[
+  &UNDECLARED,
+  &DEPRECATED_ENV,
+  &WEAK_SECRET,
+  &BROAD_REGEX,
+  &UNUSED_SPEC_ENTRY,
+];
+
+/// Comment that suppresses findings on its own line or the next one, as in
+/// `# boat-lint: allow(undeclared, weak-secret)`.
+const SUPPRESSION_PREFIX: &str = "boat-lint: allow(";
+
+/// A problem found by a lint rule.
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct LintDiagnostic {
+  pub rule: &'static Rule,
+  pub severity: Severity,
+  message: String,
+  src: NamedSource,
+  span: SourceSpan,
+  label: String,
+  help: Option<String>,
+}
+
+impl Diagnostic for LintDiagnostic {
+  fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+    Some(Box::new(format!("boatctl::lint::{}", self.rule.id)))
+  }
+
+  fn severity(&self) -> Option<Severity> {
+    Some(self.severity)
+  }
+
+  fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+    self
+      .help
+      .as_ref()
+      .map(|x| Box::new(x) as Box<dyn Display + 'a>)
+  }
+
+  fn source_code(&self) -> Option<&dyn SourceCode> {
+    Some(&self.src)
+  }
+
+  fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+    Some(Box::new(std::iter::once(LabeledSpan::new_with_span(
+      Some(self.label.clone()),
+      self.span,
+    ))))
+  }
+}
+
+/// Collects the findings of the enabled rules, leaving out suppressed ones.
+struct Linter<'a> {
+  enabled: &'a dyn Fn(&Rule) -> bool,
+  findings: Vec<LintDiagnostic>,
+}
+
+impl<'a> Linter<'a> {
+  fn enabled(&self, rule: &Rule) -> bool {
+    (self.enabled)(rule)
+  }
+
+  /// Records a finding at `span` in `src`, the file that defines the flagged item.
+  fn report(
+    &mut self,
+    rule: &'static Rule,
+    (src, span): (&SourceFile, SourceSpan),
+    message: String,
+    label: &str,
+    help: Option<String>,
+  ) {
+    if is_suppressed(src.text(), span, rule) {
+      return;
+    }
+    self.findings.push(LintDiagnostic {
+      rule,
+      severity: Severity::Warning,
+      message,
+      src: src.named_source(),
+      span,
+      label: label.to_string(),
+      help,
+    });
+  }
+}
+
+/// Runs the rules for which `enabled` returns true.
+pub(crate) fn run(
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  (config_src, config): (&SourceFile, &AppConfig),
+  enabled: &dyn Fn(&Rule) -> bool,
+) -> Vec<LintDiagnostic> {
+  let mut linter = Linter {
+    enabled,
+    findings: vec![],
+  };
+  undeclared(&mut linter, spec, (config_src, config));
+  deprecated_env(&mut linter, spec, (config_src, config));
+  weak_secret(&mut linter, spec, (config_src, config));
+  broad_regex(&mut linter, (spec_src, spec));
+  unused_spec_entry(&mut linter, (spec_src, spec), config);
+  linter.findings
+}
+
+/// Whether a suppression comment for `rule` is on the line of `span` or the line before it.
+fn is_suppressed(text: &str, span: SourceSpan, rule: &Rule) -> bool {
+  let line = text[..span.offset().min(text.len())].matches('\n').count();
+  text
+    .lines()
+    .skip(line.saturating_sub(1))
+    .take(if line == 0 { 1 } else { 2 })
+    .filter_map(|x| comment(x).map(str::trim))
+    .filter_map(|x| x.strip_prefix(SUPPRESSION_PREFIX)?.split_once(')'))
+    .any(|(rules, _)| rules.split(',').any(|x| x.trim() == rule.id))
+}
+
+/// The comment at the end of a TOML or YAML line, if any. A `#` inside a quoted string does not
+/// start a comment.
+fn comment(line: &str) -> Option<&str> {
+  let mut quote = None;
+  let mut escaped = false;
+  for (i, c) in line.char_indices() {
+    match quote {
+      _ if escaped => escaped = false,
+      Some('"') if c == '\\' => escaped = true,
+      Some(q) if c == q => quote = None,
+      Some(_) => {}
+      None if c == '"' || c == '\'' => quote = Some(c),
+      None if c == '#' => return Some(&line[i + 1..]),
+      None => {}
+    }
+  }
+  None
+}
+
+/// Flags env, secrets, mysql connections and pubsub namespaces in the config that the spec
+/// does not declare, which are usually typos or leftovers.
+fn undeclared(
+  linter: &mut Linter,
+  spec: &AppSpec,
+  (config_src, config): (&SourceFile, &AppConfig),
+) {
+  if !linter.enabled(&UNDECLARED) {
+    return;
+  }
+  let env = spec
+    .env
+    .iter()
+    .chain(spec.secrets.iter())
+    .map(|x| x.get_ref().to_env_spec().key.clone())
+    .collect::<Vec<_>>();
+  let mysql = spec
+    .mysql
+    .iter()
+    .map(|x| x.get_ref().clone())
+    .collect::<Vec<_>>();
+  let pubsub = spec
+    .pubsub
+    .iter()
+    .map(|x| x.get_ref().clone())
+    .collect::<Vec<_>>();
+  let tables = [
+    (
+      "env",
+      &env,
+      config
+        .env
+        .keys()
+        .chain(config.secrets.keys())
+        .collect::<Vec<_>>(),
+    ),
+    ("mysql connection", &mysql, config.mysql.keys().collect()),
+    ("pubsub namespace", &pubsub, config.pubsub.keys().collect()),
+  ];
+  for (kind, declared, keys) in tables {
+    for key in keys {
+      let name = key.get_ref();
+      if declared.contains(name) {
+        continue;
+      }
+      let help = declared
+        .iter()
+        .map(|x| (strsim::levenshtein(x, name), x))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, x)| format!("did you mean `{}`?", x));
+      linter.report(
+        &UNDECLARED,
+        (config_src.origin(name), config_src.span(key, name)),
+        format!("{} `{}` is not declared in the spec", kind, name),
+        "defined here",
+        help,
+      );
+    }
+  }
+}
+
+/// Flags config values for deprecated variables that are not yet past their removal date.
+fn deprecated_env(
+  linter: &mut Linter,
+  spec: &AppSpec,
+  (config_src, config): (&SourceFile, &AppConfig),
+) {
+  if !linter.enabled(&DEPRECATED_ENV) {
+    return;
+  }
+  for item in spec.env.iter().chain(spec.secrets.iter()) {
+    let env_spec = item.get_ref().to_env_spec();
+    if !env_spec.is_deprecated() || env_spec.is_removed() {
+      continue;
+    }
+    let key = match config
+      .env
+      .get_key_value(env_spec.key.as_str())
+      .or_else(|| config.secrets.get_key_value(env_spec.key.as_str()))
+    {
+      Some((k, _)) => k,
+      None => continue,
+    };
+    linter.report(
+      &DEPRECATED_ENV,
+      (
+        config_src.origin(key.get_ref()),
+        config_src.span(key, key.get_ref()),
+      ),
+      format!("`{}` is deprecated", env_spec.key),
+      "set here",
+      env_spec
+        .removed_after
+        .as_ref()
+        .map(|x| format!("it will be an error to set it after {}", x)),
+    );
+  }
+}
+
+/// Flags secret values in the config that are empty, placeholders, equal to their own key, or
+/// not in the encoding declared by the spec.
+fn weak_secret(
+  linter: &mut Linter,
+  spec: &AppSpec,
+  (config_src, config): (&SourceFile, &AppConfig),
+) {
+  if !linter.enabled(&WEAK_SECRET) {
+    return;
+  }
+  for (key, value) in config.secrets.iter() {
+    let name = key.get_ref();
+    if secret_resolver::is_external_reference(value) {
+      continue;
+    }
+    let encoding = spec
+      .env
+      .iter()
+      .chain(spec.secrets.iter())
+      .map(|x| x.get_ref().to_env_spec())
+      .find(|x| x.key == *name)
+      .and_then(|x| x.encoding);
+    let reason = if value.is_empty() {
+      "value is empty"
+    } else if value.to_ascii_lowercase().contains("changeme") {
+      "value contains `changeme`"
+    } else if value.eq_ignore_ascii_case(name) {
+      "value is the same as the key"
+    } else if encoding == Some(EnvEncoding::Base64) && BASE64.decode(value.as_bytes()).is_err() {
+      "value is not valid base64"
+    } else {
+      continue;
+    };
+    linter.report(
+      &WEAK_SECRET,
+      (config_src.origin(name), config_src.span(key, name)),
+      format!("secret `{}` looks weak or malformed", name),
+      reason,
+      None,
+    );
+  }
+}
+
+/// Flags regexes in the spec that are not anchored at both ends, and so accept any value that
+/// merely contains a match, or that accept any value at all.
+fn broad_regex(linter: &mut Linter, (spec_src, spec): (&SourceFile, &AppSpec)) {
+  if !linter.enabled(&BROAD_REGEX) {
+    return;
+  }
+  for item in spec.env.iter().chain(spec.secrets.iter()) {
+    let env_spec = item.get_ref().to_env_spec();
+    let regex = match &env_spec.regex {
+      Some(x) => x,
+      // Invalid regexes are reported by validation.
+      None => continue,
+    };
+    let re = match Regex::new(regex) {
+      Ok(x) => x,
+      Err(_) => continue,
+    };
+    let reason = if ["", " ", "0", "?", "-"].iter().all(|x| re.is_match(x)) {
+      "matches any value"
+    } else if !regex.starts_with('^') || !regex.ends_with('$') {
+      "not anchored with `^` and `$`"
+    } else {
+      continue;
+    };
+    linter.report(
+      &BROAD_REGEX,
+      (
+        spec_src.origin(&env_spec.key),
+        spec_src.span(item, &env_spec.key),
+      ),
+      format!("regex of `{}` is too broad", env_spec.key),
+      reason,
+      Some(format!("regex: {}", regex)),
+    );
+  }
+}
+
+/// Flags optional entries in the spec that have no default and that neither the config nor any
+/// of its profiles set, which are often left over from removed features.
+fn unused_spec_entry(
+  linter: &mut Linter,
+  (spec_src, spec): (&SourceFile, &AppSpec),
+  config: &AppConfig,
+) {
+  if !linter.enabled(&UNUSED_SPEC_ENTRY) {
+    return;
+  }
+  for item in spec.env.iter().chain(spec.secrets.iter()) {
+    let env_spec = item.get_ref().to_env_spec();
+    let key = env_spec.key.as_str();
+    let set_in_profile = config
+      .profile
+      .values()
+      .any(|x| x.env.contains_key(key) || x.secrets.contains_key(key));
+    if !env_spec.optional
+      || env_spec.default.is_some()
+      || config.env.contains_key(key)
+      || config.secrets.contains_key(key)
+      || set_in_profile
+    {
+      continue;
+    }
+    // Detached secrets may be set in the service instead.
+    if config.detached_secrets
+      && spec
+        .secrets
+        .iter()
+        .any(|x| x.get_ref().to_env_spec().key == key)
+    {
+      continue;
+    }
+    linter.report(
+      &UNUSED_SPEC_ENTRY,
+      (spec_src.origin(key), spec_src.span(item, key)),
+      format!("`{}` is never set", key),
+      "declared here",
+      Some("remove it from the spec, or give it a default".into()),
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn suppressed(text: &str, token: &str) -> bool {
+    let offset = text.find(token).unwrap();
+    is_suppressed(
+      text,
+      SourceSpan::from(offset..offset + token.len()),
+      &WEAK_SECRET,
+    )
+  }
+
+  #[test]
+  fn finds_comments() {
+    assert_eq!(comment("A = \"1\" # note"), Some(" note"));
+    assert_eq!(comment("A = \"1#2\""), None);
+    assert_eq!(comment("A = '1#2' #x"), Some("x"));
+    assert_eq!(comment(r##"A = "say \"#\" here" # real"##), Some(" real"));
+    assert_eq!(comment(r#"A = "ends with \\" # real"#), Some(" real"));
+    assert_eq!(comment(r"A = 'C:\dir\' # real"), Some(" real"));
+    assert_eq!(comment("A: \"it's\" # yaml"), Some(" yaml"));
+    assert_eq!(comment("# whole line"), Some(" whole line"));
+    assert_eq!(comment("A = \"unterminated # not a comment"), None);
+  }
+
+  #[test]
+  fn suppresses_on_the_same_line() {
+    let text = "id = \"app\"\nS = \"secret\" # boat-lint: allow(weak-secret) test value\n";
+    assert!(suppressed(text, "S ="));
+    let text = "id = \"app\"\nS = \"# boat-lint: allow(weak-secret)\"\n";
+    assert!(!suppressed(text, "S ="));
+  }
+
+  #[test]
+  fn suppresses_on_the_line_before() {
+    let text = "# boat-lint: allow(undeclared, weak-secret)\nS = \"secret\"\nT = \"secret\"\n";
+    assert!(suppressed(text, "S ="));
+    assert!(!suppressed(text, "T ="));
+    let text = "# boat-lint: allow(undeclared)\nS = \"secret\"\n";
+    assert!(!suppressed(text, "S ="));
+  }
+
+  #[test]
+  fn suppresses_on_the_first_line() {
+    let text = "S = \"secret\" # boat-lint: allow(weak-secret)\nT = \"secret\"\n";
+    assert!(suppressed(text, "S ="));
+    let text = "S = \"secret\"\nT = \"secret\" # boat-lint: allow(weak-secret)\n";
+    assert!(!suppressed(text, "S ="));
+  }
+}
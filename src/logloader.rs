@@ -1,32 +1,29 @@
-use graphql_client::{GraphQLQuery, Response};
+use std::{collections::HashSet, time::Duration};
+
+use futures_util::{Stream, TryStreamExt};
 
 use crate::{
-  cursor::ServiceCursor,
-  schema,
-  service::{GqlResponseExt, Service},
+  api::{LogEntry, LogPage, LogSource},
+  cursor::{Page, ServiceCursor},
+  service::Service,
 };
-use serde::Deserialize;
 
 pub struct LogLoader<'a> {
   service: &'a Service,
   cursor: ServiceCursor<String>,
   app_id: String,
   deployment_id: Option<String>,
+  watermark: Option<LogWatermark>,
 }
 
-#[derive(Deserialize)]
-pub struct GenericLog {
-  pub ts: i64,
-  pub request_id: String,
-  pub seq: i64,
-  pub message: String,
+/// Position of the newest log entry seen so far in follow mode.
+struct LogWatermark {
+  ts: i64,
+  seen: HashSet<(String, i64)>,
 }
 
-#[derive(Deserialize)]
-struct GenericLogList {
-  data: Vec<GenericLog>,
-  cursor: Option<String>,
-}
+const FOLLOW_MIN_INTERVAL: Duration = Duration::from_millis(500);
+const FOLLOW_MAX_INTERVAL: Duration = Duration::from_secs(10);
 
 impl<'a> LogLoader<'a> {
   pub fn new(service: &'a Service, app_id: &str, deployment_id: Option<&str>) -> Self {
@@ -35,74 +32,113 @@ impl<'a> LogLoader<'a> {
       cursor: ServiceCursor::Initial,
       app_id: app_id.to_string(),
       deployment_id: deployment_id.map(|s| s.to_string()),
+      watermark: None,
     }
   }
 
-  pub async fn load_logs(&mut self, page_size: u32) -> anyhow::Result<Vec<GenericLog>> {
+  pub async fn load_logs(&mut self, page_size: u32) -> anyhow::Result<Vec<LogEntry>> {
     if matches!(self.cursor, ServiceCursor::End) {
       return Ok(vec![]);
     }
 
-    let log_list = if let Some(deployment_id) = &self.deployment_id {
-      self
-        .query_logs_for_deployment(deployment_id, page_size, &self.cursor)
-        .await?
-    } else {
-      self
-        .query_logs_for_app(&self.app_id, page_size, &self.cursor)
-        .await?
-    };
+    let log_list = self
+      .query_page(page_size, self.cursor.as_request_cursor_ref().cloned())
+      .await?;
 
     self.cursor = if let Some(x) = log_list.cursor {
       ServiceCursor::Next(x)
     } else {
       ServiceCursor::End
     };
-    Ok(log_list.data)
+    Ok(log_list.entries)
+  }
+
+  /// Returns log entries that arrived since the previous call, oldest first.
+  ///
+  /// The first call returns the latest page and records its position; later
+  /// calls walk backwards from the newest entry until they reach that position.
+  pub async fn poll_new_logs(&mut self, page_size: u32) -> anyhow::Result<Vec<LogEntry>> {
+    let mut new_logs = vec![];
+    {
+      let mut pages = std::pin::pin!(self.pages(page_size));
+      while let Some(page) = pages.try_next().await? {
+        let page_len = page.len();
+        let mut reached_watermark = false;
+        for log in page {
+          if self.is_new(&log) {
+            new_logs.push(log);
+          } else {
+            reached_watermark = true;
+          }
+        }
+        if self.watermark.is_none() || reached_watermark || page_len == 0 {
+          break;
+        }
+      }
+    }
+
+    new_logs.sort_by_key(|x| (x.ts, x.seq));
+    self.advance_watermark(&new_logs);
+    Ok(new_logs)
+  }
+
+  /// Waits until new log entries are available, backing off while the
+  /// service returns nothing new.
+  pub async fn wait_for_new_logs(&mut self, page_size: u32) -> anyhow::Result<Vec<LogEntry>> {
+    let mut interval = FOLLOW_MIN_INTERVAL;
+    loop {
+      let logs = self.poll_new_logs(page_size).await?;
+      if !logs.is_empty() {
+        return Ok(logs);
+      }
+      tokio::time::sleep(interval).await;
+      interval = (interval * 2).min(FOLLOW_MAX_INTERVAL);
+    }
+  }
+
+  fn is_new(&self, log: &LogEntry) -> bool {
+    match &self.watermark {
+      Some(w) => {
+        log.ts > w.ts || (log.ts == w.ts && !w.seen.contains(&(log.request_id.clone(), log.seq)))
+      }
+      None => true,
+    }
+  }
+
+  fn advance_watermark(&mut self, logs: &[LogEntry]) {
+    let newest_ts = match logs.last() {
+      Some(x) => x.ts,
+      None => return,
+    };
+    let watermark = match &mut self.watermark {
+      Some(w) if w.ts == newest_ts => w,
+      _ => self.watermark.insert(LogWatermark {
+        ts: newest_ts,
+        seen: HashSet::new(),
+      }),
+    };
+    for log in logs.iter().filter(|x| x.ts == newest_ts) {
+      watermark.seen.insert((log.request_id.clone(), log.seq));
+    }
   }
 
-  async fn query_logs_for_app(
-    &self,
-    app_id: &str,
-    first: u32,
-    before: &ServiceCursor<String>,
-  ) -> anyhow::Result<GenericLogList> {
-    let query = schema::GetAppLogs::build_query(schema::get_app_logs::Variables {
-      id: app_id.to_string(),
-      first: Some(first as i64),
-      before: before.as_request_cursor_ref().cloned(),
-    });
-    let rsp: Response<schema::get_app_logs::ResponseData> =
-      self.service.call(query).await?.check_service_error()?;
-
-    let data = rsp
-      .data
-      .and_then(|x| x.app)
-      .and_then(|x| x.current_deployment.map(|x| x.logs))
-      .ok_or_else(|| anyhow::anyhow!("missing data"))?;
-
-    Ok(serde_json::from_str(&serde_json::to_string(&data)?)?)
+  /// Pages of log entries, newest first.
+  fn pages(&self, page_size: u32) -> impl Stream<Item = anyhow::Result<Vec<LogEntry>>> + '_ {
+    Service::paginate(move |cursor| async move {
+      self.service.throttle().await;
+      let log_list = self.query_page(page_size, cursor).await?;
+      Ok(Page {
+        items: log_list.entries,
+        next: log_list.cursor,
+      })
+    })
   }
 
-  async fn query_logs_for_deployment(
-    &self,
-    deployment_id: &str,
-    first: u32,
-    before: &ServiceCursor<String>,
-  ) -> anyhow::Result<GenericLogList> {
-    let query = schema::GetDeploymentLogs::build_query(schema::get_deployment_logs::Variables {
-      id: deployment_id.to_string(),
-      first: Some(first as i64),
-      before: before.as_request_cursor_ref().cloned(),
-    });
-    let rsp: Response<schema::get_deployment_logs::ResponseData> =
-      self.service.call(query).await?.check_service_error()?;
-
-    let data = rsp
-      .data
-      .and_then(|x| x.deployment.map(|x| x.logs))
-      .ok_or_else(|| anyhow::anyhow!("missing data"))?;
-
-    Ok(serde_json::from_str(&serde_json::to_string(&data)?)?)
+  async fn query_page(&self, page_size: u32, cursor: Option<String>) -> anyhow::Result<LogPage> {
+    let source = match &self.deployment_id {
+      Some(x) => LogSource::Deployment(x),
+      None => LogSource::App(&self.app_id),
+    };
+    self.service.get_logs(source, page_size, cursor).await
   }
 }
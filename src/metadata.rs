@@ -1,25 +1,59 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use anyhow::Result;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::config::{AppConfig, MysqlMetadata, PubsubMetadata};
+use crate::{
+  config::{AppConfig, AppSpec, MysqlMetadata, PubsubMetadata},
+  package_builder::ASSET_MANIFEST_PATH,
+  provenance::Provenance,
+};
 
-#[derive(Serialize)]
+const REDACTED: &str = "<redacted>";
+
+#[derive(Serialize, Clone)]
 pub struct AppMetadata {
   pub env: HashMap<String, String>,
   pub secrets: HashMap<String, String>,
   pub mysql: HashMap<String, MysqlMetadata>,
   pub pubsub: HashMap<String, PubsubMetadata>,
+
+  /// How the package was built, set by the caller after building it.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub provenance: Option<Provenance>,
+
+  /// Sent alongside the metadata when creating a deployment, not as part of it.
+  #[serde(skip)]
+  pub annotations: DeploymentAnnotations,
+}
+
+/// User-supplied description of a deployment.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct DeploymentAnnotations {
+  pub message: Option<String>,
+  pub labels: BTreeMap<String, String>,
 }
 
 impl AppMetadata {
-  pub fn from_config(config: &AppConfig) -> Self {
+  /// Builds the metadata of an app. Optional env variables missing from `config` get their
+  /// default from `spec`. With `fingerprint` in the spec, the `manifest_env` variable is set to
+  /// the path of the asset manifest.
+  pub fn from_config(spec: &AppSpec, config: &AppConfig) -> Self {
     Self {
-      env: config
-        .env
-        .iter()
-        .map(|(k, v)| (k.get_ref().clone(), v.clone()))
+      env: spec
+        .env_defaults()
+        .chain(
+          spec
+            .fingerprint
+            .iter()
+            .map(|x| (x.manifest_env.clone(), ASSET_MANIFEST_PATH.to_string())),
+        )
+        .chain(
+          config
+            .env
+            .iter()
+            .map(|(k, v)| (k.get_ref().clone(), v.clone())),
+        )
         .collect(),
       secrets: config
         .secrets
@@ -36,36 +70,55 @@ impl AppMetadata {
         .iter()
         .map(|(k, v)| (k.get_ref().clone(), v.unwrap_as_metadata().clone()))
         .collect(),
+      provenance: None,
+      annotations: DeploymentAnnotations::default(),
+    }
+  }
+
+  /// Returns a copy with secret values and mysql connection urls replaced, for display.
+  pub fn redacted(&self) -> Self {
+    let mut out = self.clone();
+    for v in out.secrets.values_mut() {
+      *v = REDACTED.into();
     }
+    for v in out.mysql.values_mut() {
+      v.url = REDACTED.into();
+    }
+    out
   }
 }
 
+/// Metadata written next to the package by `boat pack`. Maps are sorted, so that the same
+/// metadata is always written the same way.
 #[derive(Serialize)]
 pub struct PackedAppMetadata {
   pub version: String,
   pub package: String,
-  pub env: HashMap<String, String>,
+  /// Hex SHA-256 of the package, for checking that builds are reproducible.
+  pub package_sha256: String,
+  pub env: BTreeMap<String, String>,
 
   #[serde(default)]
-  pub mysql: HashMap<String, MysqlMetadata>,
+  pub mysql: BTreeMap<String, MysqlMetadata>,
 
   #[serde(default)]
-  pub pubsub: HashMap<String, PubsubMetadata>,
+  pub pubsub: BTreeMap<String, PubsubMetadata>,
 }
 
 impl PackedAppMetadata {
-  pub fn new(md: &AppMetadata, package_filename: &str) -> Result<Self> {
+  pub fn new(md: &AppMetadata, package_filename: &str, package_sha256: &str) -> Result<Self> {
     let out = Self {
       version: "app".into(),
       package: package_filename.into(),
+      package_sha256: package_sha256.into(),
       env: md
         .env
         .iter()
         .chain(md.secrets.iter())
         .map(|x| (x.0.clone(), x.1.clone()))
         .collect(),
-      mysql: md.mysql.clone(),
-      pubsub: md.pubsub.clone(),
+      mysql: md.mysql.clone().into_iter().collect(),
+      pubsub: md.pubsub.clone().into_iter().collect(),
     };
     Ok(out)
   }
@@ -0,0 +1,230 @@
+use toml_edit::{Array, ArrayOfTables, Document, InlineTable, Item, Table, Value};
+
+use crate::config::FORMAT_VERSION;
+
+/// Rewrites a file to the current format, returning the new text and the changes made.
+pub type Migration = fn(&str) -> anyhow::Result<(String, Vec<String>)>;
+
+/// Rewrites a TOML spec to the current format, keeping comments and formatting. Returns the new
+/// text and a description of each change, which is empty if the spec is already current.
+///
+/// Plain env and secret names become `{ key = "..." }` tables, and `static` becomes an
+/// `[[assets]]` entry.
+pub fn migrate_spec(text: &str) -> anyhow::Result<(String, Vec<String>)> {
+  let mut doc = parse(text)?;
+  let mut changes = vec![];
+  for section in ["env", "secrets"] {
+    if let Some(array) = doc.get_mut(section).and_then(|x| x.as_array_mut()) {
+      let n = plain_to_table(array, "key");
+      if n != 0 {
+        changes.push(format!(
+          "converted {} plain `{}` entries to tables",
+          n, section
+        ));
+      }
+    }
+  }
+  if let Some(dir) = doc
+    .get("static")
+    .and_then(|x| x.as_str())
+    .map(|x| x.to_string())
+  {
+    let mut table = Table::new();
+    table.insert("dir", toml_edit::value(dir));
+    // `static` is copied before `assets`, so it goes first.
+    let mut assets = ArrayOfTables::new();
+    assets.push(table);
+    if let Some(existing) = doc.get("assets").and_then(|x| x.as_array_of_tables()) {
+      for x in existing.iter() {
+        assets.push(x.clone());
+      }
+    }
+    doc.remove("static");
+    doc.insert("assets", Item::ArrayOfTables(assets));
+    changes.push("replaced `static` with an `[[assets]]` entry".into());
+  }
+  finish(doc, changes)
+}
+
+/// Rewrites a TOML config to the current format, keeping comments and formatting. Returns the
+/// new text and a description of each change, which is empty if the config is already current.
+///
+/// Plain pubsub namespaces become `{ namespace = "..." }` tables, also in profiles.
+pub fn migrate_config(text: &str) -> anyhow::Result<(String, Vec<String>)> {
+  let mut doc = parse(text)?;
+  let mut changes = vec![];
+  let mut n = doc
+    .get_mut("pubsub")
+    .map(plain_pubsub_to_table)
+    .unwrap_or(0);
+  if let Some(profiles) = doc.get_mut("profile").and_then(|x| x.as_table_like_mut()) {
+    for (_, profile) in profiles.iter_mut() {
+      n += profile
+        .get_mut("pubsub")
+        .map(plain_pubsub_to_table)
+        .unwrap_or(0);
+    }
+  }
+  if n != 0 {
+    changes.push(format!("converted {} plain `pubsub` entries to tables", n));
+  }
+  finish(doc, changes)
+}
+
+fn parse(text: &str) -> anyhow::Result<Document> {
+  text
+    .parse::<Document>()
+    .map_err(|e| anyhow::Error::from(e).context("cannot parse file"))
+}
+
+/// Sets `version` to the current version.
+fn finish(mut doc: Document, mut changes: Vec<String>) -> anyhow::Result<(String, Vec<String>)> {
+  let version = doc.get("version").and_then(|x| x.as_integer());
+  match version {
+    Some(x) if x > FORMAT_VERSION as i64 => anyhow::bail!(
+      "version {} is newer than this version of boat supports ({})",
+      x,
+      FORMAT_VERSION
+    ),
+    Some(x) if x == FORMAT_VERSION as i64 => {}
+    _ => {
+      doc.insert("version", toml_edit::value(FORMAT_VERSION as i64));
+      changes.push(format!("set `version = {}`", FORMAT_VERSION));
+    }
+  }
+  Ok((doc.to_string(), changes))
+}
+
+/// Replaces string items of `array` with inline tables holding the string under `key`.
+fn plain_to_table(array: &mut Array, key: &str) -> usize {
+  let mut n = 0;
+  for item in array.iter_mut() {
+    if let Value::String(s) = item {
+      let decor = s.decor().clone();
+      let mut table = InlineTable::new();
+      table.insert(key, s.value().as_str().into());
+      *item = Value::InlineTable(table);
+      *item.decor_mut() = decor;
+      n += 1;
+    }
+  }
+  n
+}
+
+fn plain_pubsub_to_table(pubsub: &mut Item) -> usize {
+  let table = match pubsub.as_table_like_mut() {
+    Some(x) => x,
+    None => return 0,
+  };
+  let mut n = 0;
+  for (_, item) in table.iter_mut() {
+    if let Some(namespace) = item.as_str() {
+      let mut inline = InlineTable::new();
+      inline.insert("namespace", namespace.into());
+      let decor = item
+        .as_value()
+        .map(|x| x.decor().clone())
+        .unwrap_or_default();
+      let mut value = Value::InlineTable(inline);
+      *value.decor_mut() = decor;
+      *item = Item::Value(value);
+      n += 1;
+    }
+  }
+  n
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn migrates_specs() {
+    let spec = r#"# app spec
+artifact = "dist/index.js"
+static = "public"
+env = [
+  "A", # first
+  { key = "B", optional = true },
+]
+secrets = ["S"]
+
+[[assets]]
+dir = "images"
+"#;
+    let (text, changes) = migrate_spec(spec).unwrap();
+    assert_eq!(
+      text,
+      r#"# app spec
+artifact = "dist/index.js"
+env = [
+  { key = "A" }, # first
+  { key = "B", optional = true },
+]
+secrets = [{ key = "S" }]
+version = 1
+
+[[assets]]
+dir = "public"
+
+[[assets]]
+dir = "images"
+"#
+    );
+    assert_eq!(
+      changes,
+      [
+        "converted 1 plain `env` entries to tables",
+        "converted 1 plain `secrets` entries to tables",
+        "replaced `static` with an `[[assets]]` entry",
+        "set `version = 1`",
+      ]
+    );
+    let (again, changes) = migrate_spec(&text).unwrap();
+    assert_eq!(again, text);
+    assert!(changes.is_empty());
+  }
+
+  #[test]
+  fn migrates_configs() {
+    let config = r#"id = "app"
+
+[pubsub]
+events = "ns1" # shared
+jobs = { namespace = "ns2" }
+
+[profile.staging.pubsub]
+events = "ns3"
+"#;
+    let (text, changes) = migrate_config(config).unwrap();
+    assert_eq!(
+      text,
+      r#"id = "app"
+version = 1
+
+[pubsub]
+events = { namespace = "ns1" } # shared
+jobs = { namespace = "ns2" }
+
+[profile.staging.pubsub]
+events = { namespace = "ns3" }
+"#
+    );
+    assert_eq!(
+      changes,
+      [
+        "converted 2 plain `pubsub` entries to tables",
+        "set `version = 1`"
+      ]
+    );
+    let (again, changes) = migrate_config(&text).unwrap();
+    assert_eq!(again, text);
+    assert!(changes.is_empty());
+  }
+
+  #[test]
+  fn rejects_newer_versions() {
+    assert!(migrate_config("version = 99\nid = \"app\"\n").is_err());
+    assert!(migrate_spec("version = [\n").is_err());
+  }
+}
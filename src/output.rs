@@ -0,0 +1,129 @@
+use std::{
+  str::FromStr,
+  sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicU8 = AtomicU8::new(ColorPreference::Auto as u8);
+
+/// Suppresses decorative output such as hints and section headers, for `--quiet`.
+pub fn set_quiet(quiet: bool) {
+  QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+  QUIET.load(Ordering::Relaxed)
+}
+
+/// Whether to color output, for `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorPreference {
+  /// Color output written to a terminal that supports it, unless `NO_COLOR` is set.
+  #[default]
+  Auto,
+  Always,
+  Never,
+}
+
+impl ColorPreference {
+  pub const VARIANTS: &'static [&'static str] = &["auto", "always", "never"];
+
+  pub fn as_str(self) -> &'static str {
+    Self::VARIANTS[self as usize]
+  }
+}
+
+impl FromStr for ColorPreference {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(ColorPreference::Auto),
+      "always" => Ok(ColorPreference::Always),
+      "never" => Ok(ColorPreference::Never),
+      _ => anyhow::bail!(
+        "unknown color preference: {} (expected auto, always or never)",
+        s
+      ),
+    }
+  }
+}
+
+pub fn set_color(color: ColorPreference) {
+  COLOR.store(color as u8, Ordering::Relaxed);
+}
+
+pub fn color() -> ColorPreference {
+  match COLOR.load(Ordering::Relaxed) {
+    1 => ColorPreference::Always,
+    2 => ColorPreference::Never,
+    _ => ColorPreference::Auto,
+  }
+}
+
+/// How to color a `termcolor` stream.
+pub fn color_choice() -> termcolor::ColorChoice {
+  match color() {
+    ColorPreference::Auto => termcolor::ColorChoice::Auto,
+    ColorPreference::Always => termcolor::ColorChoice::Always,
+    ColorPreference::Never => termcolor::ColorChoice::Never,
+  }
+}
+
+/// Output format for commands that print data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+  Table,
+  Json,
+  Yaml,
+}
+
+impl FromStr for OutputFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "table" => Ok(OutputFormat::Table),
+      "json" => Ok(OutputFormat::Json),
+      "yaml" => Ok(OutputFormat::Yaml),
+      _ => anyhow::bail!(
+        "unknown output format: {} (expected table, json or yaml)",
+        s
+      ),
+    }
+  }
+}
+
+impl OutputFormat {
+  pub fn is_structured(self) -> bool {
+    self != OutputFormat::Table
+  }
+
+  /// Prints `value` as a single structured document.
+  ///
+  /// Fails with `OutputFormat::Table`; table rendering is up to the caller.
+  pub fn print<T: Serialize + ?Sized>(self, value: &T) -> anyhow::Result<()> {
+    match self {
+      OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+      OutputFormat::Yaml => print!("{}", serde_yaml::to_string(value)?),
+      OutputFormat::Table => anyhow::bail!("table output is not structured"),
+    }
+    Ok(())
+  }
+
+  /// Prints `value` as one record of a stream, for commands that emit data incrementally.
+  ///
+  /// JSON records are written one per line; YAML records are separate documents.
+  /// Fails with `OutputFormat::Table`, like `print`.
+  pub fn print_record<T: Serialize + ?Sized>(self, value: &T) -> anyhow::Result<()> {
+    match self {
+      OutputFormat::Json => println!("{}", serde_json::to_string(value)?),
+      OutputFormat::Yaml => print!("{}", serde_yaml::to_string(value)?),
+      OutputFormat::Table => anyhow::bail!("table output is not structured"),
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,187 @@
+use std::{
+  fs::File,
+  io::{BufWriter, Read, Seek, Write},
+  path::{Path, PathBuf},
+  str::FromStr,
+};
+
+use data_encoding::HEXLOWER;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+
+use crate::package_file::PackageFile;
+
+/// Container format of the output of `boat pack`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackFormat {
+  /// A metadata JSON file and a package tar, or a single tar with `--bundle`.
+  Tar,
+  /// A zip archive with the same contents as a tar bundle.
+  Zip,
+  /// An OCI image with the tar bundle as its only layer.
+  Oci,
+}
+
+impl FromStr for PackFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "tar" => Ok(PackFormat::Tar),
+      "zip" => Ok(PackFormat::Zip),
+      "oci" => Ok(PackFormat::Oci),
+      _ => anyhow::bail!("unknown pack format: {} (expected tar, zip or oci)", s),
+    }
+  }
+}
+
+/// Writes a zip archive of `entries`, as `(path, data)`, to `out`, compressed with deflate.
+/// Timestamps are fixed, so that the same files always give the same archive.
+pub fn write_zip<W: Write + Seek>(
+  out: W,
+  entries: Vec<(&str, Box<dyn Read + '_>)>,
+) -> anyhow::Result<W> {
+  let options = FileOptions::default()
+    .compression_method(CompressionMethod::Deflated)
+    .last_modified_time(DateTime::default())
+    .unix_permissions(0o644)
+    .large_file(true);
+  let mut zip = ZipWriter::new(out);
+  for (path, mut data) in entries {
+    zip.start_file(path, options)?;
+    std::io::copy(&mut data, &mut zip)?;
+  }
+  Ok(zip.finish()?)
+}
+
+/// Writes an OCI image whose only layer is `layer`, a tar archive, to `output`: as an image
+/// layout tarball if `output` ends with `.tar`, and as an image layout directory otherwise.
+/// The image is tagged `latest`.
+pub fn write_oci_image(output: &Path, layer: &PackageFile) -> anyhow::Result<()> {
+  let layer_digest = format!("sha256:{}", layer.sha256());
+  let config = serde_json::to_vec(&json!({
+    "architecture": "amd64",
+    "os": "linux",
+    "rootfs": { "type": "layers", "diff_ids": [layer_digest] },
+  }))?;
+  let manifest = serde_json::to_vec(&json!({
+    "schemaVersion": 2,
+    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+    "config": {
+      "mediaType": "application/vnd.oci.image.config.v1+json",
+      "digest": digest(&config),
+      "size": config.len(),
+    },
+    "layers": [{
+      "mediaType": "application/vnd.oci.image.layer.v1.tar",
+      "digest": layer_digest,
+      "size": layer.len(),
+    }],
+  }))?;
+  let index = serde_json::to_vec(&json!({
+    "schemaVersion": 2,
+    "manifests": [{
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "digest": digest(&manifest),
+      "size": manifest.len(),
+      "annotations": { "org.opencontainers.image.ref.name": "latest" },
+    }],
+  }))?;
+  let layout = br#"{"imageLayoutVersion":"1.0.0"}"#;
+
+  // The layer is copied from its file; everything else is small.
+  let mut files = vec![
+    ("oci-layout".to_string(), &layout[..]),
+    ("index.json".to_string(), &index[..]),
+  ];
+  for blob in [&config[..], &manifest[..]] {
+    files.push((format!("blobs/sha256/{}", hex_sha256(blob)), blob));
+  }
+  let layer_path = format!("blobs/sha256/{}", layer.sha256());
+
+  if output.extension().map(|x| x == "tar").unwrap_or(false) {
+    let mut tar_builder = tar::Builder::new(BufWriter::new(File::create(output)?));
+    let mut entries = files
+      .into_iter()
+      .map(|(path, data)| (path, data.len() as u64, Box::new(data) as Box<dyn Read>))
+      .collect::<Vec<_>>();
+    entries.push((layer_path, layer.len(), Box::new(layer.open()?)));
+    for (path, size, data) in entries {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(size);
+      header.set_mode(0o644);
+      header.set_cksum();
+      tar_builder.append_data(&mut header, path, data)?;
+    }
+    tar_builder.into_inner()?.flush()?;
+  } else {
+    let write = |path: &str| -> anyhow::Result<PathBuf> {
+      let path = output.join(path);
+      if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+      }
+      Ok(path)
+    };
+    for (path, data) in files {
+      std::fs::write(write(&path)?, data)?;
+    }
+    std::fs::copy(layer.path(), write(&layer_path)?)?;
+  }
+  Ok(())
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+  HEXLOWER.encode(&Sha256::digest(data))
+}
+
+fn digest(data: &[u8]) -> String {
+  format!("sha256:{}", hex_sha256(data))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use zip::ZipArchive;
+
+  use super::*;
+
+  fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let entries = entries
+      .iter()
+      .map(|(path, data)| (*path, Box::new(*data) as Box<dyn Read>))
+      .collect();
+    write_zip(Cursor::new(vec![]), entries)
+      .unwrap()
+      .into_inner()
+  }
+
+  #[test]
+  fn writes_zip_archives() {
+    let large = vec![b'x'; 100_000];
+    let archive = zip(&[("metadata.json", b"{}"), ("package.tar", &large)]);
+    let mut archive = ZipArchive::new(Cursor::new(archive)).unwrap();
+    assert_eq!(archive.len(), 2);
+    let mut file = archive.by_name("package.tar").unwrap();
+    assert_eq!(file.compression(), CompressionMethod::Deflated);
+    assert_eq!(file.unix_mode(), Some(0o100644));
+    let mut data = vec![];
+    file.read_to_end(&mut data).unwrap();
+    assert_eq!(data, large);
+    drop(file);
+    let mut data = String::new();
+    archive
+      .by_name("metadata.json")
+      .unwrap()
+      .read_to_string(&mut data)
+      .unwrap();
+    assert_eq!(data, "{}");
+  }
+
+  #[test]
+  fn writes_the_same_zip_for_the_same_files() {
+    let entries: &[(&str, &[u8])] = &[("a.txt", b"a"), ("b.txt", b"b")];
+    assert_eq!(zip(entries), zip(entries));
+  }
+}
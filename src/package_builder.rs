@@ -1,67 +1,1212 @@
-use std::{collections::BTreeMap, path::Path, process::Command};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fs::File,
+  io::{Read, Write},
+  path::{Component, Path, PathBuf},
+  process::Command,
+};
 
-use crate::config::{AppConfig, AppSpec};
-use tempdir::TempDir;
+use crate::{
+  boatignore::BoatIgnore,
+  build_cache,
+  child_process::{self, Outcome},
+  config::{
+    AppConfig, AppSpec, Artifact, ArtifactType, AssetSet, BuildCommand, Fingerprint, SourceMaps,
+    Symlinks,
+  },
+  metadata::PackedAppMetadata,
+  package_file::PackageFile,
+  progress,
+};
+use data_encoding::HEXLOWER;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use walkdir::WalkDir;
 
+/// Path of the metadata inside a bundle created by `build_bundle`.
+pub const BUNDLE_METADATA_PATH: &str = "metadata.json";
+
+/// Path of the package inside a bundle created by `build_bundle`.
+pub const BUNDLE_PACKAGE_PATH: &str = "package.tar";
+
+/// Path of the manifest inside a package built by `build_package`.
+pub const MANIFEST_PATH: &str = ".boat-manifest.json";
+
+/// Path of the asset manifest inside a package built with `fingerprint` in the spec.
+pub const ASSET_MANIFEST_PATH: &str = "asset-manifest.json";
+
+/// Path of the `DiffDescriptor` inside a package diff built by `build_package_diff`.
+pub const DIFF_DESCRIPTOR_PATH: &str = ".boat-diff.json";
+
+/// List of the files in a package, embedded in it at `MANIFEST_PATH`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PackageManifest {
+  /// Sorted by path.
+  pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+  pub path: String,
+  pub size: u64,
+  /// Hex SHA-256 of the file, or of the target of a symlink.
+  pub sha256: String,
+  /// Target of a symlink kept with `symlinks = "preserve"`, relative to its directory.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub link: Option<String>,
+}
+
+/// How the service rebuilds a package from a package diff: the package is the entries of the
+/// diff, except this descriptor, and the `unchanged` files of the package of the base
+/// deployment. Files of the base package that are in neither are removed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiffDescriptor {
+  pub base_deployment_id: String,
+  /// Hex SHA-256 of the whole package.
+  pub package_sha256: String,
+  pub unchanged: Vec<String>,
+}
+
+/// Longest path component allowed in a package, as in most filesystems.
+const MAX_NAME_LEN: usize = 255;
+
+/// Longest path allowed in a package.
+const MAX_PATH_LEN: usize = 1024;
+
+/// Attached as context to every failure in `build_package`.
+#[derive(Error, Debug)]
+#[error("failed to build package")]
+pub struct BuildError;
+
+/// Options for `build_package`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuildOptions {
+  /// Print each asset that `.boatignore` leaves out of the package.
+  pub debug_ignore: bool,
+  /// Reuse the package of an earlier build with the same inputs instead of running the build
+  /// command, and cache the package otherwise.
+  pub cache: bool,
+}
+
+/// Builds the package of the app. Assets matching the `.boatignore` file in the spec directory
+/// are left out.
+#[tracing::instrument(skip_all, fields(spec = %spec_path.display()), err)]
 pub fn build_package(
   spec_path: &Path,
   spec: &AppSpec,
   config: &AppConfig,
-) -> anyhow::Result<Vec<u8>> {
-  let spec_dir = spec_path
-    .parent()
-    .ok_or_else(|| anyhow::anyhow!("cannot resolve spec parent dir"))?
-    .to_path_buf();
+  opts: &BuildOptions,
+) -> anyhow::Result<PackageFile> {
+  build_package_inner(spec_path, spec, config, opts).map_err(|e| e.context(BuildError))
+}
 
-  if let Some(build) = &spec.build {
-    let mut newenv: BTreeMap<String, String> = std::env::vars().collect();
-    for (k, v) in &config.env {
-      newenv.insert(format!("BLUEBOAT_{}", k.get_ref()), v.to_string());
+fn build_package_inner(
+  spec_path: &Path,
+  spec: &AppSpec,
+  config: &AppConfig,
+  opts: &BuildOptions,
+) -> anyhow::Result<PackageFile> {
+  let spec_dir = spec_dir(spec_path)?;
+  let ignore = BoatIgnore::load(&spec_dir)?;
+  // Only builds with a build command are cached, as packaging alone is cheap.
+  let cache = opts.cache && spec.build.is_some();
+  if cache {
+    let pb = progress::spinner("Checking build cache");
+    // A key that cannot be computed is a cache miss; the build reports any real problem.
+    let cached = match build_cache::key(&spec_dir, spec, config, &ignore) {
+      Ok(key) => build_cache::get(&key),
+      Err(e) => {
+        log::debug!("cannot compute cache key: {:?}", e);
+        None
+      }
+    };
+    if let Some(image) = cached {
+      pb.finish_with_message(format!("Reused cached package ({} bytes)", image.len()));
+      return Ok(image);
     }
+    pb.finish_and_clear();
+  }
+  run_build(&spec_dir, spec, config)?;
 
-    let status = Command::new("sh")
-      .envs(newenv)
-      .current_dir(&spec_dir)
-      .args(["-c", build.as_str()])
-      .status()?;
-    if !status.success() {
-      anyhow::bail!("build failed: {}", status.code().unwrap_or(1));
+  if let Some(expected) = &spec.artifact_sha256 {
+    let actual = hash_artifact(&spec_dir, spec)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+      anyhow::bail!(
+        "{} has sha256 {}, but the spec pins {}; run `boat lock` if the change is intended",
+        spec.artifact,
+        actual,
+        expected.trim()
+      );
     }
   }
 
-  let td = TempDir::new("bbcli-deploy")?;
-  if let Some(d) = &spec._static {
-    let status = {
-      #[cfg(target_os = "macos")]
+  for (_, source) in spec.artifact.entries() {
+    check_artifact(&spec_dir, spec, source)?;
+  }
+  run_verify(&spec_dir, spec, config)?;
+
+  let files = collect_files(&spec_dir, spec, (&ignore, opts.debug_ignore))?;
+  let image = create_package(spec, files)?;
+
+  if cache {
+    // Keyed by the files as the build left them, which is how the next build will find them.
+    let stored = build_cache::key(&spec_dir, spec, config, &ignore)
+      .and_then(|key| build_cache::put(&key, &image));
+    if let Err(e) = stored {
+      log::warn!("cannot cache package: {:?}", e);
+    }
+  }
+
+  Ok(image)
+}
+
+/// Builds a package from the prebuilt files in `dir`, with `artifact`, a path in `dir`, as the
+/// `index.js` entry point. The build and verify commands of the spec are not run, but
+/// `.boatignore`, `sourcemaps` and the checks on the artifact and the package size still apply.
+#[tracing::instrument(skip_all, fields(spec = %spec_path.display(), artifact), err)]
+pub fn build_package_from_dir(
+  spec_path: &Path,
+  spec: &AppSpec,
+  dir: &Path,
+  artifact: &str,
+  opts: &BuildOptions,
+) -> anyhow::Result<PackageFile> {
+  build_package_from_dir_inner(spec_path, spec, dir, artifact, opts)
+    .map_err(|e| e.context(BuildError))
+}
+
+fn build_package_from_dir_inner(
+  spec_path: &Path,
+  spec: &AppSpec,
+  dir: &Path,
+  artifact: &str,
+  opts: &BuildOptions,
+) -> anyhow::Result<PackageFile> {
+  let spec_dir = spec_dir(spec_path)?;
+  let ignore = BoatIgnore::load(&spec_dir)?;
+  let dir = dir
+    .canonicalize()
+    .map_err(|e| anyhow::Error::from(e).context(format!("cannot find {}", dir.display())))?;
+  let set = AssetSet {
+    dir: dir.to_string_lossy().into_owned(),
+    include: vec![],
+    exclude: vec![],
+    prefix: String::new(),
+  };
+  let mut files = BTreeMap::new();
+  collect_assets(
+    &spec_dir,
+    &set,
+    (&ignore, opts.debug_ignore),
+    spec.symlinks,
+    &mut files,
+  )?;
+  let artifact = artifact.trim_start_matches('/');
+  let source = files
+    .remove(artifact)
+    .ok_or_else(|| anyhow::anyhow!("cannot find artifact {} in {}", artifact, dir.display()))?;
+  check_artifact(&spec_dir, spec, &source.to_string_lossy())?;
+  files.insert("index.js".to_string(), source);
+  create_package(spec, files)
+}
+
+/// Writes the package of `files`, applying the `sourcemaps`, `fingerprint` and
+/// `max_package_size` settings of the spec.
+#[tracing::instrument(skip_all, fields(files = files.len()), err)]
+fn create_package(
+  spec: &AppSpec,
+  mut files: BTreeMap<String, PathBuf>,
+) -> anyhow::Result<PackageFile> {
+  let asset_manifest = spec.fingerprint.as_ref().map(|_| ASSET_MANIFEST_PATH);
+  // A file at the diff descriptor path would be taken for one when the package is diffed.
+  for path in [MANIFEST_PATH, DIFF_DESCRIPTOR_PATH]
+    .into_iter()
+    .chain(asset_manifest)
+  {
+    if files.contains_key(path) {
+      anyhow::bail!("`{}` is reserved for package metadata", path);
+    }
+  }
+  if let Some(path) = files.keys().find(|x| x.len() > MAX_PATH_LEN) {
+    anyhow::bail!(
+      "`{}` is longer than the {} bytes allowed in a package",
+      path,
+      MAX_PATH_LEN
+    );
+  }
+  if spec.sourcemaps != SourceMaps::Include {
+    files.retain(|path, _| !is_sourcemap(path));
+  }
+  let mut generated = vec![];
+  if let Some(fingerprint) = &spec.fingerprint {
+    let renamed = fingerprint_files(spec, fingerprint, &mut files)?;
+    generated.push((ASSET_MANIFEST_PATH, serde_json::to_vec_pretty(&renamed)?));
+  }
+
+  let pb = progress::spinner("Creating package");
+  let (image, manifest) = PackageFile::write(|out| {
+    Ok(write_package(out, &files, &generated, spec.preserve_executable)?.1)
+  })?;
+  pb.finish_with_message(format!("Created package ({} bytes)", image.len()));
+  log::info!("Image size is {} bytes.", image.len());
+
+  if let Some(max) = &spec.max_package_size {
+    let max = max.get_ref().bytes().map_err(anyhow::Error::msg)?;
+    if image.len() > max {
+      return Err(
+        PackageTooLargeError {
+          size: image.len(),
+          max,
+          dirs: size_by_dir(&manifest),
+        }
+        .into(),
+      );
+    }
+  }
+  Ok(image)
+}
+
+/// How many directories `PackageTooLargeError` lists.
+const MAX_LISTED_DIRS: usize = 10;
+
+/// The package is over the `max_package_size` of the spec.
+#[derive(Error, Debug)]
+#[error(
+  "package is {size} bytes, over the `max_package_size` of {max} bytes in the spec{}",
+  format_dirs(dirs)
+)]
+pub struct PackageTooLargeError {
+  pub size: u64,
+  pub max: u64,
+  /// Total size of the files under each top-level directory of the package, largest first.
+  /// Files at the top level are under `./`.
+  pub dirs: Vec<(String, u64)>,
+}
+
+fn format_dirs(dirs: &[(String, u64)]) -> String {
+  let mut out = String::from("\n\nLargest directories:");
+  for (dir, size) in dirs.iter().take(MAX_LISTED_DIRS) {
+    out.push_str(&format!("\n  {:<30} {} bytes", dir, size));
+  }
+  if dirs.len() > MAX_LISTED_DIRS {
+    out.push_str(&format!("\n  ({} more)", dirs.len() - MAX_LISTED_DIRS));
+  }
+  out
+}
+
+fn size_by_dir(manifest: &PackageManifest) -> Vec<(String, u64)> {
+  let mut sizes: BTreeMap<String, u64> = BTreeMap::new();
+  for file in &manifest.files {
+    let dir = match file.path.split_once('/') {
+      Some((dir, _)) => format!("{}/", dir),
+      None => "./".to_string(),
+    };
+    *sizes.entry(dir).or_default() += file.size;
+  }
+  let mut sizes = sizes.into_iter().collect::<Vec<_>>();
+  sizes.sort_by_key(|x| std::cmp::Reverse(x.1));
+  sizes
+}
+
+/// Writes a package of `files` to `out` as a tar archive, followed by its manifest and the
+/// `generated` metadata files, as `(path, data)`. Files are hashed in parallel first, then
+/// streamed into the archive one at a time, so that memory use does not grow with the size of
+/// the files.
+///
+/// Sources that are symlinks are packaged as symlinks, so `collect_assets` resolves those that
+/// are followed. The owner and mtime of entries are fixed, and so is the mode unless
+/// `preserve_executable` is set, so that the same files and metadata always give the same
+/// package whichever platform or checkout built it.
+fn write_package<W: Write>(
+  out: W,
+  files: &BTreeMap<String, PathBuf>,
+  generated: &[(&str, Vec<u8>)],
+  preserve_executable: bool,
+) -> anyhow::Result<(W, PackageManifest)> {
+  let manifest = PackageManifest {
+    files: hash_files(files)?,
+  };
+  let mut tar_builder = tar::Builder::new(out);
+  let mut dirs = BTreeSet::new();
+  for path in files.keys() {
+    let mut parent = Path::new(path).parent();
+    while let Some(dir) = parent.filter(|x| !x.as_os_str().is_empty()) {
+      dirs.insert(dir.to_path_buf());
+      parent = dir.parent();
+    }
+  }
+  for dir in &dirs {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_size(0);
+    header.set_mtime(0);
+    header.set_cksum();
+    tar_builder.append_data(&mut header, dir, std::io::empty())?;
+  }
+  for (entry, source) in manifest.files.iter().zip(files.values()) {
+    if let Some(link) = &entry.link {
+      let mut header = tar::Header::new_gnu();
+      header.set_entry_type(tar::EntryType::Symlink);
+      header.set_mode(0o777);
+      header.set_uid(0);
+      header.set_gid(0);
+      header.set_size(0);
+      header.set_mtime(0);
+      tar_builder.append_link(&mut header, &entry.path, link)?;
+      continue;
+    }
+    let file = File::open(source)
+      .map_err(|e| anyhow::Error::from(e).context(format!("cannot read {}", source.display())))?;
+    let metadata = file.metadata()?;
+    // The header holds the size from hashing, so the file must not have changed since.
+    if metadata.len() != entry.size {
+      anyhow::bail!("{} changed while creating the package", source.display());
+    }
+    let mode = if preserve_executable && is_executable(&metadata) {
+      0o755
+    } else {
+      0o644
+    };
+    append_data(&mut tar_builder, &entry.path, entry.size, mode, file)?;
+  }
+  let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+  append_data(
+    &mut tar_builder,
+    MANIFEST_PATH,
+    manifest_json.len() as u64,
+    0o644,
+    &manifest_json[..],
+  )?;
+  for (path, data) in generated {
+    append_data(&mut tar_builder, path, data.len() as u64, 0o644, &data[..])?;
+  }
+  Ok((tar_builder.into_inner()?, manifest))
+}
+
+/// Manifest entries of `files`, in the same order, hashed on a thread per CPU.
+fn hash_files(files: &BTreeMap<String, PathBuf>) -> anyhow::Result<Vec<ManifestEntry>> {
+  let files = files.iter().collect::<Vec<_>>();
+  let threads = std::thread::available_parallelism()
+    .map(|x| x.get())
+    .unwrap_or(1);
+  let chunk_size = files.len().div_ceil(threads).max(1);
+  std::thread::scope(|scope| {
+    let handles = files
+      .chunks(chunk_size)
+      .map(|chunk| {
+        scope.spawn(move || {
+          chunk
+            .iter()
+            .map(|(path, source)| hash_file(path, source))
+            .collect::<anyhow::Result<Vec<_>>>()
+        })
+      })
+      .collect::<Vec<_>>();
+    let mut out = Vec::with_capacity(files.len());
+    for handle in handles {
+      out.extend(handle.join().expect("hashing thread panicked")?);
+    }
+    Ok(out)
+  })
+}
+
+fn hash_file(path: &str, source: &Path) -> anyhow::Result<ManifestEntry> {
+  if source.symlink_metadata()?.file_type().is_symlink() {
+    let link = slash_path(&std::fs::read_link(source)?);
+    return Ok(ManifestEntry {
+      path: path.to_string(),
+      size: 0,
+      sha256: HEXLOWER.encode(&Sha256::digest(link.as_bytes())),
+      link: Some(link),
+    });
+  }
+  let mut file = File::open(source)
+    .map_err(|e| anyhow::Error::from(e).context(format!("cannot read {}", source.display())))?;
+  let mut hasher = Sha256::new();
+  let size = std::io::copy(&mut file, &mut hasher)?;
+  Ok(ManifestEntry {
+    path: path.to_string(),
+    size,
+    sha256: HEXLOWER.encode(&hasher.finalize()),
+    link: None,
+  })
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+  use std::os::unix::fs::PermissionsExt;
+  metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_: &std::fs::Metadata) -> bool {
+  false
+}
+
+/// Appends `size` bytes from `data` as a regular file at `path`.
+fn append_data<W: Write>(
+  tar_builder: &mut tar::Builder<W>,
+  path: &str,
+  size: u64,
+  mode: u32,
+  data: impl Read,
+) -> anyhow::Result<()> {
+  let mut header = tar::Header::new_gnu();
+  header.set_entry_type(tar::EntryType::Regular);
+  header.set_mode(mode);
+  header.set_uid(0);
+  header.set_gid(0);
+  header.set_size(size);
+  header.set_mtime(0);
+  header.set_cksum();
+  tar_builder.append_data(&mut header, path, data.take(size))?;
+  Ok(())
+}
+
+/// The files to package, as package path -> source file. Later asset sets win over earlier
+/// ones, and the artifacts over all of them.
+fn collect_files(
+  spec_dir: &Path,
+  spec: &AppSpec,
+  (ignore, debug_ignore): (&BoatIgnore, bool),
+) -> anyhow::Result<BTreeMap<String, PathBuf>> {
+  let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+  let static_set = spec._static.as_ref().map(|dir| AssetSet {
+    dir: dir.clone(),
+    include: vec![],
+    exclude: vec![],
+    prefix: String::new(),
+  });
+  for set in static_set.iter().chain(spec.assets.iter()) {
+    collect_assets(
+      spec_dir,
+      set,
+      (ignore, debug_ignore),
+      spec.symlinks,
+      &mut files,
+    )?;
+  }
+  for (target, source) in spec.artifact.entries() {
+    let target = target.trim_start_matches('/');
+    if target.is_empty() || target.split('/').any(|x| x == "..") {
+      anyhow::bail!("invalid artifact path `{}` in the package", target);
+    }
+    let source_path = spec_dir
+      .join(source)
+      .canonicalize()
+      .map_err(|e| anyhow::Error::from(e).context(format!("cannot find artifact {}", source)))?;
+    files.insert(target.to_string(), source_path);
+  }
+  Ok(files)
+}
+
+/// Renames the files of the package that `fingerprint` applies to, inserting the first 8 hex
+/// digits of their SHA-256 before the extension. Returns the asset manifest, mapping the
+/// original paths to the new ones.
+fn fingerprint_files(
+  spec: &AppSpec,
+  fingerprint: &Fingerprint,
+  files: &mut BTreeMap<String, PathBuf>,
+) -> anyhow::Result<BTreeMap<String, String>> {
+  let exclude = fingerprint
+    .exclude
+    .iter()
+    .map(|x| glob_regex(x))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  // `--from-dir` always packages its artifact as `index.js`.
+  let artifacts = spec
+    .artifact
+    .entries()
+    .into_iter()
+    .map(|(target, _)| target.trim_start_matches('/'))
+    .chain(["index.js"])
+    .collect::<Vec<_>>();
+  let selected = files
+    .iter()
+    .filter(|(path, _)| {
+      !artifacts.contains(&path.as_str())
+        && !path.ends_with(".html")
+        && !path.ends_with(".htm")
+        && !is_sourcemap(path)
+        && !exclude.iter().any(|x| x.is_match(path))
+    })
+    .map(|(path, source)| (path.clone(), source.clone()))
+    .collect::<BTreeMap<_, _>>();
+
+  let mut renamed = BTreeMap::new();
+  for entry in hash_files(&selected)? {
+    let name = fingerprinted_name(&entry.path, &entry.sha256[..8]);
+    if files.contains_key(&name) {
+      anyhow::bail!(
+        "cannot fingerprint `{}`: `{}` is already in the package",
+        entry.path,
+        name
+      );
+    }
+    let source = files.remove(&entry.path).unwrap();
+    files.insert(name.clone(), source);
+    renamed.insert(entry.path, name);
+  }
+  Ok(renamed)
+}
+
+/// `path` with `hash` inserted before the extension of the file name: `js/app.js` becomes
+/// `js/app.<hash>.js`, and `LICENSE` becomes `LICENSE.<hash>`.
+fn fingerprinted_name(path: &str, hash: &str) -> String {
+  let name_start = path.rfind('/').map(|x| x + 1).unwrap_or(0);
+  match path[name_start..].rfind('.').filter(|x| *x > 0) {
+    Some(dot) => {
+      let (stem, ext) = path.split_at(name_start + dot);
+      format!("{}.{}{}", stem, hash, ext)
+    }
+    None => format!("{}.{}", path, hash),
+  }
+}
+
+fn is_sourcemap(path: &str) -> bool {
+  path.ends_with(".map")
+}
+
+/// Builds an archive of the source maps that `sourcemaps = "upload"` leaves out of the package,
+/// in the same format as the package. Returns `None` if there are none. The build command is
+/// not run, so this should follow `build_package`.
+#[tracing::instrument(skip_all, err)]
+pub fn build_sourcemap_archive(
+  spec_path: &Path,
+  spec: &AppSpec,
+) -> anyhow::Result<Option<Vec<u8>>> {
+  let spec_dir = spec_dir(spec_path)?;
+  let ignore = BoatIgnore::load(&spec_dir)?;
+  let mut files = collect_files(&spec_dir, spec, (&ignore, false))?;
+  files.retain(|path, _| is_sourcemap(path));
+  if files.is_empty() {
+    return Ok(None);
+  }
+  Ok(Some(write_package(Vec::new(), &files, &[], false)?.0))
+}
+
+/// Node.js built-in modules, which are only available with `node_compat`.
+const NODE_BUILTINS: &[&str] = &[
+  "assert",
+  "buffer",
+  "child_process",
+  "cluster",
+  "crypto",
+  "dgram",
+  "dns",
+  "events",
+  "fs",
+  "http",
+  "http2",
+  "https",
+  "net",
+  "os",
+  "path",
+  "process",
+  "querystring",
+  "readline",
+  "stream",
+  "tls",
+  "url",
+  "util",
+  "vm",
+  "worker_threads",
+  "zlib",
+];
+
+/// Checks the artifact at `artifact` against `node_compat` and `artifact_type` in the spec. The
+/// checks look at the source text, so they catch mistakes rather than prove anything.
+fn check_artifact(spec_dir: &Path, spec: &AppSpec, artifact: &str) -> anyhow::Result<()> {
+  if spec.node_compat != Some(false) && spec.artifact_type.is_none() {
+    return Ok(());
+  }
+  let path = spec_dir.join(artifact);
+  let source = std::fs::read_to_string(&path)
+    .map_err(|e| anyhow::Error::from(e).context(format!("cannot read {}", path.display())))?;
+
+  if spec.node_compat == Some(false) {
+    let re = Regex::new(&format!(
+      r#"(?:\brequire\s*\(|\bimport\s*\(|\bfrom)\s*['"]((?:node:)?(?:{})(?:/[^'"]*)?)['"]"#,
+      NODE_BUILTINS.join("|")
+    ))
+    .unwrap();
+    if let Some(m) = re.captures(&source).and_then(|x| x.get(1)) {
+      anyhow::bail!(
+        "{} imports the Node.js module `{}`, but the spec sets `node_compat = false`",
+        artifact,
+        m.as_str()
+      );
+    }
+  }
+
+  let esm = Regex::new(r#"(?m)^\s*(?:import\s*[\w{*'"]|export\s)"#).unwrap();
+  let cjs = Regex::new(r"\brequire\s*\(|\bmodule\.exports\b|\bexports\.\w").unwrap();
+  let (found, kind, expected) = match spec.artifact_type {
+    Some(ArtifactType::Esm) => (cjs.find(&source), "CommonJS", "esm"),
+    Some(ArtifactType::Cjs) => (esm.find(&source), "ES module", "cjs"),
+    None => return Ok(()),
+  };
+  if let Some(m) = found {
+    let line = source[..m.start()].matches('\n').count() + 1;
+    anyhow::bail!(
+      "{} uses {} syntax at line {} (`{}`), but the spec sets `artifact_type = \"{}\"`",
+      artifact,
+      kind,
+      line,
+      m.as_str().trim(),
+      expected
+    );
+  }
+  Ok(())
+}
+
+/// Adds the files selected by `set` to `files`, keyed by their path in the package. `.boat` and
+/// `.git` directories, and files and directories under the spec directory that `ignore`
+/// matches, are skipped.
+fn collect_assets(
+  spec_dir: &Path,
+  set: &AssetSet,
+  (ignore, debug_ignore): (&BoatIgnore, bool),
+  symlinks: Symlinks,
+  files: &mut BTreeMap<String, PathBuf>,
+) -> anyhow::Result<()> {
+  let dir = spec_dir.join(&set.dir);
+  let include = set
+    .include
+    .iter()
+    .map(|x| glob_regex(x))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  let exclude = set
+    .exclude
+    .iter()
+    .map(|x| glob_regex(x))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  let prefix = set.prefix.trim_matches('/');
+  let not_ignored = |entry: &walkdir::DirEntry| {
+    // State of boat, like the package of an interrupted upload, and of git are never assets.
+    if entry.depth() > 0 && (entry.file_name() == ".boat" || entry.file_name() == ".git") {
+      return false;
+    }
+    let path = match entry.path().strip_prefix(spec_dir) {
+      Ok(x)
+        if x
+          .components()
+          .all(|x| matches!(x, Component::Normal(_) | Component::CurDir)) =>
       {
-        let mut d = spec_dir.join(d).canonicalize()?;
-        d.push("");
-        Command::new("cp")
-          .args([Path::new("-r"), &d, td.path()])
-          .status()?
+        slash_path(x)
       }
-      #[cfg(not(target_os = "macos"))]
-      {
-        let d = spec_dir.join(d).canonicalize()?;
-        Command::new("cp")
-          .args([Path::new("-rT"), &d, td.path()])
-          .status()?
+      // Outside of the spec directory.
+      _ => return true,
+    };
+    let is_dir = entry.file_type().is_dir();
+    match ignore.matched(&path, is_dir).filter(|_| !path.is_empty()) {
+      Some(rule) => {
+        if debug_ignore {
+          eprintln!(
+            "Ignored {}{} ({})",
+            path,
+            if is_dir { "/" } else { "" },
+            rule
+          );
+        }
+        false
+      }
+      None => true,
+    }
+  };
+  for entry in WalkDir::new(&dir)
+    .follow_links(symlinks == Symlinks::Follow)
+    .sort_by_file_name()
+    .into_iter()
+    .filter_entry(not_ignored)
+  {
+    let entry = entry
+      .map_err(|e| anyhow::Error::from(e).context(format!("cannot read assets in {}", set.dir)))?;
+    // Unless followed, symlinks have their own file type, even those to directories.
+    let is_link = entry.path_is_symlink();
+    if !entry.file_type().is_file() && !entry.file_type().is_symlink() {
+      continue;
+    }
+    let rel = package_path(entry.path().strip_prefix(&dir)?)?;
+    let included = include.is_empty() || include.iter().any(|x| x.is_match(&rel));
+    if !included || exclude.iter().any(|x| x.is_match(&rel)) {
+      continue;
+    }
+    let target = if prefix.is_empty() {
+      rel
+    } else {
+      format!("{}/{}", prefix, rel)
+    };
+    let source = match symlinks {
+      // `write_package` keeps symlinks, so resolve followed ones here.
+      Symlinks::Follow if is_link => entry.path().canonicalize()?,
+      Symlinks::Preserve if is_link => {
+        check_link(&target, &std::fs::read_link(entry.path())?)?;
+        entry.into_path()
+      }
+      Symlinks::Error if is_link => anyhow::bail!(
+        "{} is a symlink, which `symlinks = \"error\"` in the spec does not allow",
+        entry.path().display()
+      ),
+      _ => entry.into_path(),
+    };
+    files.insert(target, source);
+  }
+  Ok(())
+}
+
+/// `path`, which is relative, as a package path. Fails if it is not valid UTF-8, or if a
+/// component has control characters or is too long for common filesystems.
+fn package_path(path: &Path) -> anyhow::Result<String> {
+  let mut components = vec![];
+  for component in path.components() {
+    if component == Component::CurDir {
+      continue;
+    }
+    let name = component
+      .as_os_str()
+      .to_str()
+      .ok_or_else(|| anyhow::anyhow!("path {:?} is not valid UTF-8", path))?;
+    if name.chars().any(|c| c.is_control()) {
+      anyhow::bail!("path {:?} has control characters", path);
+    }
+    if name.len() > MAX_NAME_LEN {
+      anyhow::bail!(
+        "path {} has a component longer than {} bytes",
+        path.display(),
+        MAX_NAME_LEN
+      );
+    }
+    components.push(name);
+  }
+  Ok(components.join("/"))
+}
+
+/// Checks that the symlink at package path `path` to `target` stays inside the package.
+fn check_link(path: &str, target: &Path) -> anyhow::Result<()> {
+  let mut dir = path.split('/').collect::<Vec<_>>();
+  dir.pop();
+  for component in target.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir if dir.pop().is_some() => {}
+      Component::Normal(_) => dir.push(""),
+      _ => anyhow::bail!(
+        "symlink `{}` to {} points outside of the package",
+        path,
+        target.display()
+      ),
+    }
+  }
+  Ok(())
+}
+
+/// Joins the components of a relative path with `/`, leaving out `.` components.
+pub(crate) fn slash_path(path: &Path) -> String {
+  path
+    .components()
+    .filter(|x| !matches!(x, Component::CurDir))
+    .map(|x| x.as_os_str().to_string_lossy())
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+/// Converts a glob to a regex over `/`-separated paths. `*` and `?` stay within a path
+/// component, and `**` matches any number of components.
+pub(crate) fn glob_regex(glob: &str) -> anyhow::Result<Regex> {
+  let mut re = String::from("^");
+  let mut rest = glob;
+  while let Some(c) = rest.chars().next() {
+    if let Some(x) = rest.strip_prefix("**/") {
+      re.push_str("(?:.*/)?");
+      rest = x;
+    } else if let Some(x) = rest.strip_prefix("**") {
+      re.push_str(".*");
+      rest = x;
+    } else {
+      match c {
+        '*' => re.push_str("[^/]*"),
+        '?' => re.push_str("[^/]"),
+        _ => re.push_str(&regex::escape(&c.to_string())),
+      }
+      rest = &rest[c.len_utf8()..];
+    }
+  }
+  re.push('$');
+  Regex::new(&re).map_err(|e| anyhow::Error::from(e).context(format!("invalid glob `{}`", glob)))
+}
+
+/// Runs the build command of the spec and returns the SHA-256 of the resulting artifact, for
+/// pinning it with `artifact_sha256`.
+#[tracing::instrument(skip_all, fields(spec = %spec_path.display()), err)]
+pub fn build_artifact(
+  spec_path: &Path,
+  spec: &AppSpec,
+  config: &AppConfig,
+  skip_build: bool,
+) -> anyhow::Result<String> {
+  let spec_dir = spec_dir(spec_path)?;
+  if !skip_build {
+    run_build(&spec_dir, spec, config).map_err(|e| e.context(BuildError))?;
+  }
+  hash_artifact(&spec_dir, spec)
+}
+
+fn spec_dir(spec_path: &Path) -> anyhow::Result<PathBuf> {
+  Ok(
+    spec_path
+      .parent()
+      .ok_or_else(|| anyhow::anyhow!("cannot resolve spec parent dir"))?
+      .to_path_buf(),
+  )
+}
+
+#[tracing::instrument(skip_all, err)]
+fn run_build(spec_dir: &Path, spec: &AppSpec, config: &AppConfig) -> anyhow::Result<()> {
+  if let Some(build) = &spec.build {
+    let mut command = spec_command(spec_dir, spec, config, build)?;
+
+    let timeout = spec
+      .build_timeout
+      .as_ref()
+      .map(|x| x.get_ref().duration().map_err(anyhow::Error::msg))
+      .transpose()?;
+
+    // The build command writes to the terminal itself, so don't keep a spinner drawing over it.
+    let pb = progress::spinner("Running build command");
+    pb.disable_steady_tick();
+    let outcome = pb
+      .suspend(|| child_process::run(&mut command, timeout))
+      .map_err(|e| e.context(format!("cannot run `{}`", build)))?;
+    match outcome {
+      Outcome::Exited(status) if status.success() => {}
+      Outcome::Exited(status) => {
+        pb.abandon_with_message("Build failed");
+        anyhow::bail!("build failed: {}", status.code().unwrap_or(1));
+      }
+      Outcome::TimedOut => {
+        pb.abandon_with_message("Build timed out");
+        anyhow::bail!(
+          "build timed out after {}s and was killed",
+          timeout.unwrap_or_default().as_secs()
+        );
+      }
+      Outcome::Interrupted => {
+        pb.abandon_with_message("Build interrupted");
+        anyhow::bail!("build interrupted");
+      }
+    }
+    pb.finish_with_message("Build finished");
+  }
+  Ok(())
+}
+
+/// Runs the `verify` commands of the spec in order, stopping at the first that fails.
+#[tracing::instrument(skip_all, err)]
+fn run_verify(spec_dir: &Path, spec: &AppSpec, config: &AppConfig) -> anyhow::Result<()> {
+  let verify = match &spec.verify {
+    Some(x) => x,
+    None => return Ok(()),
+  };
+  for script in verify.commands() {
+    let build = BuildCommand::Shell(script.to_string());
+    let mut command = spec_command(spec_dir, spec, config, &build)?;
+    let pb = progress::spinner(&format!("Verifying with `{}`", script));
+    pb.disable_steady_tick();
+    let status = match pb
+      .suspend(|| child_process::run(&mut command, None))
+      .map_err(|e| e.context(format!("cannot run `{}`", script)))?
+    {
+      Outcome::Exited(status) => status,
+      _ => {
+        pb.abandon_with_message("Verification interrupted");
+        anyhow::bail!("verification interrupted");
       }
     };
     if !status.success() {
-      anyhow::bail!("copy static failed: {}", status.code().unwrap_or(1));
+      pb.abandon_with_message("Verification failed");
+      anyhow::bail!(
+        "verification failed: `{}` exited with {}",
+        script,
+        status.code().unwrap_or(1)
+      );
     }
+    pb.finish_and_clear();
   }
+  Ok(())
+}
 
-  let mut artifact_target_path = td.path().to_path_buf();
-  artifact_target_path.push("index.js");
-  let artifact_source_path = spec_dir.join(&spec.artifact).canonicalize()?;
-  std::fs::copy(&artifact_source_path, &artifact_target_path)?;
+/// A command that runs `build` the way the spec asks for: in `build_container` if it is set,
+/// and on the host otherwise, with the `BLUEBOAT_*` variables of the config.
+fn spec_command(
+  spec_dir: &Path,
+  spec: &AppSpec,
+  config: &AppConfig,
+  build: &BuildCommand,
+) -> anyhow::Result<Command> {
+  if let Some(image) = &spec.build_container {
+    return container_command(spec_dir, spec, config, image, build);
+  }
+  let mut newenv: BTreeMap<String, String> = if spec.clean_env {
+    allowed_env(spec)
+      .chain(std::env::var("PATH").map(|x| ("PATH".to_string(), x)))
+      .collect()
+  } else {
+    std::env::vars().collect()
+  };
+  for (k, v) in &config.env {
+    newenv.insert(format!("BLUEBOAT_{}", k.get_ref()), v.to_string());
+  }
+  let argv = build_argv(build, shell())?;
+  let mut command = Command::new(&argv[0]);
+  if spec.clean_env {
+    command.env_clear();
+  }
+  command.args(&argv[1..]).envs(newenv).current_dir(spec_dir);
+  Ok(command)
+}
 
-  let mut tar_builder = tar::Builder::new(Vec::new());
-  tar_builder.append_dir_all(".", td.path())?;
-  let image = tar_builder.into_inner()?;
-  log::info!("Image size is {} bytes.", image.len());
+/// The variables of the environment of boat that `allow_env` in the spec passes to the build.
+pub(crate) fn allowed_env(spec: &AppSpec) -> impl Iterator<Item = (String, String)> + '_ {
+  std::env::vars().filter(|(k, _)| {
+    spec.allow_env.iter().any(|x| match x.strip_suffix('*') {
+      Some(prefix) => k.starts_with(prefix),
+      None => k == x,
+    })
+  })
+}
 
-  Ok(image)
+/// Directory the spec directory is mounted at in a build container.
+const CONTAINER_SOURCE_DIR: &str = "/src";
+
+/// A `docker run` command that runs `build` in `image`. The spec directory is mounted
+/// read-only, except for `build_output`, and only the `BLUEBOAT_*` variables and those in
+/// `allow_env` are set.
+fn container_command(
+  spec_dir: &Path,
+  spec: &AppSpec,
+  config: &AppConfig,
+  image: &str,
+  build: &BuildCommand,
+) -> anyhow::Result<Command> {
+  let output = spec
+    .build_output
+    .as_deref()
+    .map(|x| x.trim_matches('/'))
+    .ok_or_else(|| anyhow::anyhow!("`build_container` requires `build_output`"))?;
+  if output.is_empty() || output.split('/').any(|x| x == "..") {
+    anyhow::bail!("invalid `build_output` path `{}`", output);
+  }
+  let output_dir = spec_dir.join(output);
+  std::fs::create_dir_all(&output_dir).map_err(|e| {
+    anyhow::Error::from(e).context(format!("cannot create {}", output_dir.display()))
+  })?;
+
+  let mut command = Command::new("docker");
+  command
+    .args(["run", "--rm", "--workdir", CONTAINER_SOURCE_DIR])
+    .arg("--volume")
+    .arg(format!(
+      "{}:{}:ro",
+      spec_dir.display(),
+      CONTAINER_SOURCE_DIR
+    ))
+    .arg("--volume")
+    .arg(format!(
+      "{}:{}/{}",
+      output_dir.display(),
+      CONTAINER_SOURCE_DIR,
+      output
+    ));
+  // Run as the owner of the spec directory, so that the outputs are not owned by root.
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(spec_dir)?;
+    command
+      .arg("--user")
+      .arg(format!("{}:{}", metadata.uid(), metadata.gid()));
+  }
+  // Only names are passed on the command line, where any user could read the values; docker
+  // takes the values from its own environment.
+  for (k, v) in allowed_env(spec) {
+    command.arg("--env").arg(&k).env(k, v);
+  }
+  for (k, v) in &config.env {
+    let k = format!("BLUEBOAT_{}", k.get_ref());
+    command.arg("--env").arg(&k).env(k, v);
+  }
+  // Containers are Linux, whatever the host is.
+  command.arg(image).args(build_argv(build, ("sh", "-c"))?);
+  Ok(command)
+}
+
+/// The shell that runs string build commands, and the flag that passes it a command:
+/// `cmd /C` on Windows and `sh -c` elsewhere.
+pub fn shell() -> (&'static str, &'static str) {
+  if cfg!(windows) {
+    ("cmd", "/C")
+  } else {
+    ("sh", "-c")
+  }
+}
+
+/// The program and arguments that run `build`, using `shell` for string commands.
+fn build_argv(build: &BuildCommand, (shell, flag): (&str, &str)) -> anyhow::Result<Vec<String>> {
+  match build {
+    BuildCommand::Shell(script) => Ok(vec![shell.into(), flag.into(), script.clone()]),
+    BuildCommand::Args(args) if args.is_empty() => anyhow::bail!("`build` is an empty array"),
+    BuildCommand::Args(args) => Ok(args.clone()),
+  }
+}
+
+/// SHA-256 of the artifact. With several artifacts, it is the hash of a manifest listing each
+/// package path and the hash of its file, sorted by path.
+fn hash_artifact(spec_dir: &Path, spec: &AppSpec) -> anyhow::Result<String> {
+  let hash_file = |source: &str| -> anyhow::Result<String> {
+    let path = spec_dir.join(source);
+    let data = std::fs::read(&path)
+      .map_err(|e| anyhow::Error::from(e).context(format!("cannot read {}", path.display())))?;
+    Ok(HEXLOWER.encode(&Sha256::digest(&data)))
+  };
+  match &spec.artifact {
+    Artifact::Single(source) => hash_file(source),
+    Artifact::Map(map) => {
+      let mut manifest = BTreeMap::new();
+      for (target, source) in map {
+        manifest.insert(target.trim_start_matches('/'), hash_file(source)?);
+      }
+      let manifest: String = manifest
+        .into_iter()
+        .map(|(target, hash)| format!("{}  {}\n", target, hash))
+        .collect();
+      Ok(HEXLOWER.encode(&Sha256::digest(manifest.as_bytes())))
+    }
+  }
+}
+
+/// Reads the manifest of a package built by `build_package`.
+pub fn read_manifest(package: impl Read) -> anyhow::Result<PackageManifest> {
+  read_json(package, MANIFEST_PATH)?.ok_or_else(|| anyhow::anyhow!("package has no manifest"))
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(
+  package: impl Read,
+  path: &str,
+) -> anyhow::Result<Option<T>> {
+  let mut archive = tar::Archive::new(package);
+  for entry in archive.entries()? {
+    let entry = entry?;
+    if entry.path()?.to_str() == Some(path) {
+      return Ok(Some(serde_json::from_reader(entry)?));
+    }
+  }
+  Ok(None)
+}
+
+/// Lists the files in a package built by `build_package`, as `(path, size)` pairs.
+pub fn list_package(package: impl Read) -> anyhow::Result<Vec<(String, u64)>> {
+  let mut archive = tar::Archive::new(package);
+  let mut out = vec![];
+  for entry in archive.entries()? {
+    let entry = entry?;
+    if entry.header().entry_type().is_file() {
+      out.push((entry.path()?.to_string_lossy().into_owned(), entry.size()));
+    }
+  }
+  Ok(out)
+}
+
+/// Builds a diff of `package` against `base`, the manifest of the package of deployment
+/// `base_deployment_id`. The diff is a tar archive of the entries of `package`, leaving out the
+/// files that are the same in `base`, followed by a `DiffDescriptor`.
+pub fn build_package_diff(
+  package: &PackageFile,
+  base: &PackageManifest,
+  base_deployment_id: &str,
+) -> anyhow::Result<PackageFile> {
+  let base = base
+    .files
+    .iter()
+    .map(|x| (x.path.as_str(), (&x.sha256, &x.link)))
+    .collect::<BTreeMap<_, _>>();
+  let unchanged = read_manifest(package.open()?)?
+    .files
+    .into_iter()
+    .filter(|x| base.get(x.path.as_str()) == Some(&(&x.sha256, &x.link)))
+    .map(|x| x.path)
+    .collect::<BTreeSet<_>>();
+
+  let (diff, ()) = PackageFile::write(|out| {
+    let mut tar_builder = tar::Builder::new(out);
+    let mut archive = tar::Archive::new(package.open()?);
+    for entry in archive.entries()? {
+      let mut entry = entry?;
+      let path = entry.path()?.into_owned();
+      if unchanged.contains(&slash_path(&path)) {
+        continue;
+      }
+      // Long paths are in extension entries before the header, so write them out again.
+      let mut header = entry.header().clone();
+      match entry.link_name()? {
+        Some(link) => {
+          let link = link.into_owned();
+          tar_builder.append_link(&mut header, &path, link)?
+        }
+        None => tar_builder.append_data(&mut header, &path, &mut entry)?,
+      }
+    }
+    let descriptor = serde_json::to_vec_pretty(&DiffDescriptor {
+      base_deployment_id: base_deployment_id.to_string(),
+      package_sha256: package.sha256().to_string(),
+      unchanged: unchanged.into_iter().collect(),
+    })?;
+    append_data(
+      &mut tar_builder,
+      DIFF_DESCRIPTOR_PATH,
+      descriptor.len() as u64,
+      0o644,
+      &descriptor[..],
+    )?;
+    tar_builder.finish()?;
+    Ok(())
+  })?;
+  Ok(diff)
+}
+
+/// Builds a single archive holding both the metadata and the package, for `pack --bundle`.
+///
+/// `metadata.package` should be `BUNDLE_PACKAGE_PATH`.
+pub fn build_bundle<W: Write>(
+  out: W,
+  metadata: &PackedAppMetadata,
+  package: &PackageFile,
+) -> anyhow::Result<W> {
+  let metadata = serde_json::to_vec_pretty(metadata)?;
+  let mut tar_builder = tar::Builder::new(out);
+  let entries: [(&str, u64, Box<dyn Read>); 2] = [
+    (
+      BUNDLE_METADATA_PATH,
+      metadata.len() as u64,
+      Box::new(&metadata[..]),
+    ),
+    (
+      BUNDLE_PACKAGE_PATH,
+      package.len(),
+      Box::new(package.open()?),
+    ),
+  ];
+  for (path, size, data) in entries {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar_builder.append_data(&mut header, path, data)?;
+  }
+  Ok(tar_builder.into_inner()?)
 }
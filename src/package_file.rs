@@ -0,0 +1,90 @@
+use std::{
+  fs::File,
+  io::{BufReader, BufWriter, Write},
+  path::Path,
+};
+
+use data_encoding::HEXLOWER;
+use sha2::{Digest, Sha256};
+use tempfile::TempPath;
+
+/// A package, or a diff of one, in a temporary file, so that it is never held in memory as a
+/// whole however large it is. The file is removed when this is dropped.
+pub struct PackageFile {
+  path: TempPath,
+  len: u64,
+  sha256: String,
+}
+
+impl PackageFile {
+  /// Creates a package file from what `write` writes to it, and returns it along with what
+  /// `write` returned. The file is hashed as it is written.
+  pub fn write<T>(
+    write: impl FnOnce(&mut dyn Write) -> anyhow::Result<T>,
+  ) -> anyhow::Result<(Self, T)> {
+    let (file, path) = tempfile::NamedTempFile::new()
+      .map_err(|e| anyhow::Error::from(e).context("cannot create temporary package file"))?
+      .into_parts();
+    let mut out = HashingWriter {
+      inner: BufWriter::new(file),
+      hasher: Sha256::new(),
+      len: 0,
+    };
+    let value = write(&mut out)?;
+    out.inner.flush()?;
+    let package = PackageFile {
+      path,
+      len: out.len,
+      sha256: HEXLOWER.encode(&out.hasher.finalize()),
+    };
+    Ok((package, value))
+  }
+
+  /// Creates a package file with a copy of the file at `path`.
+  pub fn copy_of(path: &Path) -> anyhow::Result<Self> {
+    let mut file = File::open(path)?;
+    Ok(Self::write(|out| Ok(std::io::copy(&mut file, out)?))?.0)
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Size of the package in bytes.
+  pub fn len(&self) -> u64 {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Hex SHA-256 of the package.
+  pub fn sha256(&self) -> &str {
+    &self.sha256
+  }
+
+  /// Opens the package for reading from the start.
+  pub fn open(&self) -> anyhow::Result<BufReader<File>> {
+    Ok(BufReader::new(File::open(&self.path)?))
+  }
+}
+
+struct HashingWriter<W> {
+  inner: W,
+  hasher: Sha256,
+  len: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    let n = self.inner.write(buf)?;
+    self.hasher.update(&buf[..n]);
+    self.len += n as u64;
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.inner.flush()
+  }
+}
@@ -0,0 +1,229 @@
+use std::{
+  path::Path,
+  time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use reqwest::{header::HeaderMap, Url};
+use tokio::{
+  io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+  net::TcpStream,
+};
+
+use crate::{
+  endpoint::{Endpoint, UNIX_SOCKET_API_PATH},
+  service::{user_agent, ConnectionOptions},
+};
+
+/// Response headers the service may report the region that served the request in.
+const REGION_HEADERS: &[&str] = &["x-lighthouse-region", "x-region", "fly-region"];
+
+/// Largest response that is read, which is far more than the answer to the query.
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+
+/// Timings of one request to the endpoint, by phase.
+pub struct PingReport {
+  /// IP address and port, or path of the Unix socket, connected to.
+  pub address: String,
+  pub dns: Duration,
+  pub connect: Duration,
+  /// `None` for a plain HTTP endpoint.
+  pub tls: Option<Duration>,
+  /// From sending the request to receiving the first byte of the response.
+  pub ttfb: Duration,
+  /// From the DNS lookup to receiving the whole response.
+  pub total: Duration,
+  pub status: u16,
+  pub region: Option<String>,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Sends a trivial query to `endpoint` on a new connection and times each phase of it. The
+/// connection is made directly, without any proxy, so that the timings are the endpoint's own.
+/// The timeout, headers and TLS settings of `connection` apply like to API calls.
+pub async fn ping(endpoint: &str, connection: &ConnectionOptions) -> anyhow::Result<PingReport> {
+  let exchange = async {
+    match Endpoint::parse(endpoint, connection.insecure_http)? {
+      Endpoint::Http(url) => ping_url(&url, connection).await,
+      Endpoint::Unix(path) => ping_unix(&path, connection).await,
+    }
+  };
+  tokio::time::timeout(connection.timeout, exchange)
+    .await
+    .map_err(|_| anyhow::anyhow!("no response within {}s", connection.timeout.as_secs()))?
+}
+
+#[cfg(unix)]
+async fn ping_unix(path: &Path, connection: &ConnectionOptions) -> anyhow::Result<PingReport> {
+  let start = Instant::now();
+  let stream = tokio::net::UnixStream::connect(path)
+    .await
+    .with_context(|| format!("cannot connect to {}", path.display()))?;
+  let connect = start.elapsed();
+  exchange(
+    Box::new(stream),
+    "localhost",
+    UNIX_SOCKET_API_PATH,
+    connection,
+    start,
+    Timings {
+      address: path.display().to_string(),
+      dns: Duration::ZERO,
+      connect,
+      tls: None,
+    },
+  )
+  .await
+}
+
+#[cfg(not(unix))]
+async fn ping_unix(_: &Path, _: &ConnectionOptions) -> anyhow::Result<PingReport> {
+  anyhow::bail!("Unix socket endpoints are not supported on this platform")
+}
+
+async fn ping_url(url: &Url, connection: &ConnectionOptions) -> anyhow::Result<PingReport> {
+  let host = url
+    .host_str()
+    .ok_or_else(|| anyhow::anyhow!("endpoint `{}` has no host", url))?;
+  let port = url
+    .port_or_known_default()
+    .ok_or_else(|| anyhow::anyhow!("endpoint `{}` has no port", url))?;
+  let start = Instant::now();
+
+  let address = tokio::net::lookup_host((host, port))
+    .await
+    .with_context(|| format!("cannot resolve `{}`", host))?
+    .next()
+    .ok_or_else(|| anyhow::anyhow!("`{}` resolved to no address", host))?;
+  let dns = start.elapsed();
+
+  let tcp = TcpStream::connect(address)
+    .await
+    .with_context(|| format!("cannot connect to {}", address))?;
+  let connect = start.elapsed() - dns;
+
+  let (stream, tls): (Box<dyn Stream>, _) = match url.scheme() {
+    "https" => {
+      let connector = match connection.tls.connector()? {
+        Some(x) => x,
+        None => native_tls::TlsConnector::new()?,
+      };
+      let connector = tokio_native_tls::TlsConnector::from(connector);
+      let tls = connector
+        .connect(host, tcp)
+        .await
+        .with_context(|| format!("TLS handshake with {} failed", host))?;
+      (Box::new(tls), Some(start.elapsed() - dns - connect))
+    }
+    _ => (Box::new(tcp), None),
+  };
+
+  let mut target = url.path().to_string();
+  if let Some(query) = url.query() {
+    target.push('?');
+    target.push_str(query);
+  }
+  exchange(
+    stream,
+    host,
+    &target,
+    connection,
+    start,
+    Timings {
+      address: address.to_string(),
+      dns,
+      connect,
+      tls,
+    },
+  )
+  .await
+}
+
+/// Timings of the phases before the request is sent.
+struct Timings {
+  address: String,
+  dns: Duration,
+  connect: Duration,
+  tls: Option<Duration>,
+}
+
+/// Sends the query to `target` over `stream` and times the response, which completes the
+/// report of a ping that started at `start`.
+async fn exchange(
+  mut stream: Box<dyn Stream>,
+  host: &str,
+  target: &str,
+  connection: &ConnectionOptions,
+  start: Instant,
+  timings: Timings,
+) -> anyhow::Result<PingReport> {
+  let body = serde_json::json!({ "query": "{ __typename }" }).to_string();
+  let mut request_headers = HeaderMap::new();
+  request_headers.insert("user-agent", user_agent().parse()?);
+  request_headers.extend(connection.headers.clone());
+  let mut request = format!(
+    "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+    target,
+    host,
+    body.len(),
+  )
+  .into_bytes();
+  for (name, value) in &request_headers {
+    request.extend_from_slice(name.as_str().as_bytes());
+    request.extend_from_slice(b": ");
+    request.extend_from_slice(value.as_bytes());
+    request.extend_from_slice(b"\r\n");
+  }
+  request.extend_from_slice(b"\r\n");
+  request.extend_from_slice(body.as_bytes());
+  stream.write_all(&request).await?;
+  stream.flush().await?;
+  let sent_at = Instant::now();
+
+  let mut response = vec![0u8; 1];
+  if stream.read(&mut response).await? == 0 {
+    anyhow::bail!("connection closed without a response");
+  }
+  let ttfb = sent_at.elapsed();
+  stream
+    .take(MAX_RESPONSE_SIZE)
+    .read_to_end(&mut response)
+    .await?;
+  let total = start.elapsed();
+
+  let (status, region) = parse_response_head(&response)?;
+  Ok(PingReport {
+    address: timings.address,
+    dns: timings.dns,
+    connect: timings.connect,
+    tls: timings.tls,
+    ttfb,
+    total,
+    status,
+    region,
+  })
+}
+
+/// The status and the reported region of a raw HTTP/1.1 response.
+fn parse_response_head(response: &[u8]) -> anyhow::Result<(u16, Option<String>)> {
+  let head = String::from_utf8_lossy(response);
+  let head = head.split("\r\n\r\n").next().unwrap_or_default();
+  let mut lines = head.split("\r\n");
+  let status = lines
+    .next()
+    .and_then(|x| x.split(' ').nth(1))
+    .and_then(|x| x.parse::<u16>().ok())
+    .ok_or_else(|| anyhow::anyhow!("malformed HTTP response"))?;
+  let region = lines
+    .filter_map(|x| x.split_once(':'))
+    .find(|(name, _)| {
+      REGION_HEADERS
+        .iter()
+        .any(|x| name.trim().eq_ignore_ascii_case(x))
+    })
+    .map(|(_, value)| value.trim().to_string());
+  Ok((status, region))
+}
@@ -0,0 +1,47 @@
+use std::{
+  io::IsTerminal,
+  sync::atomic::{AtomicBool, Ordering},
+  time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turns progress indicators on or off for the whole process, e.g. for `--quiet`.
+pub fn set_enabled(enabled: bool) {
+  ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+  ENABLED.load(Ordering::Relaxed) && std::io::stderr().is_terminal()
+}
+
+/// Spinner with elapsed time for a step of unknown length.
+pub fn spinner(message: &str) -> ProgressBar {
+  if !enabled() {
+    return ProgressBar::hidden();
+  }
+  let pb = ProgressBar::new_spinner();
+  pb.set_style(ProgressStyle::with_template("{spinner:.cyan} {msg} [{elapsed}]").unwrap());
+  pb.set_message(message.to_string());
+  pb.enable_steady_tick(Duration::from_millis(100));
+  pb
+}
+
+/// Progress bar for transferring `len` bytes.
+pub fn bytes(len: u64, message: &str) -> ProgressBar {
+  if !enabled() {
+    return ProgressBar::hidden();
+  }
+  let pb = ProgressBar::new(len);
+  pb.set_style(
+    ProgressStyle::with_template(
+      "{msg} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {elapsed})",
+    )
+    .unwrap()
+    .progress_chars("=> "),
+  );
+  pb.set_message(message.to_string());
+  pb
+}
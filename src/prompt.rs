@@ -0,0 +1,57 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Reads a line from stdin after printing `question`.
+pub fn prompt(question: &str) -> anyhow::Result<String> {
+  print!("{}: ", question);
+  std::io::stdout().flush()?;
+  let mut answer = String::new();
+  if std::io::stdin().lock().read_line(&mut answer)? == 0 {
+    anyhow::bail!("unexpected end of input");
+  }
+  Ok(answer.trim().to_string())
+}
+
+/// Asks the user to confirm destructive operations.
+pub struct Confirmer {
+  assume_yes: bool,
+}
+
+impl Confirmer {
+  /// With `assume_yes`, every confirmation passes without prompting.
+  pub fn new(assume_yes: bool) -> Self {
+    Self { assume_yes }
+  }
+
+  /// Asks a y/N question. Returns an error unless the answer is yes.
+  pub fn confirm(&self, question: &str) -> anyhow::Result<()> {
+    if self.assume_yes {
+      return Ok(());
+    }
+    self.ensure_interactive()?;
+    let answer = prompt(&format!("{} [y/N]", question))?;
+    if !matches!(answer.as_str(), "y" | "Y" | "yes") {
+      anyhow::bail!("aborted");
+    }
+    Ok(())
+  }
+
+  /// Asks the user to type `expected`, e.g. the app id, to confirm. Returns an error on mismatch.
+  pub fn confirm_typed(&self, question: &str, expected: &str) -> anyhow::Result<()> {
+    if self.assume_yes {
+      return Ok(());
+    }
+    self.ensure_interactive()?;
+    let answer = prompt(&format!("{} Type `{}` to confirm", question, expected))?;
+    if answer != expected {
+      anyhow::bail!("aborted");
+    }
+    Ok(())
+  }
+
+  fn ensure_interactive(&self) -> anyhow::Result<()> {
+    if !std::io::stdin().is_terminal() {
+      anyhow::bail!("confirmation required but stdin is not a terminal; pass --yes to proceed");
+    }
+    Ok(())
+  }
+}
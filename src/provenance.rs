@@ -0,0 +1,74 @@
+use std::{path::Path, process::Command, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+/// Where and from what a package was built, sent with its deployment. It is not part of the
+/// package itself, which only depends on the source and can be reused from the build cache.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Provenance {
+  /// Commit checked out in the spec directory, if it is in a git repository.
+  pub git_commit: Option<String>,
+  pub git_branch: Option<String>,
+  /// Whether the working tree had uncommitted changes.
+  pub git_dirty: Option<bool>,
+  /// Host name of the machine that ran the build.
+  pub builder: Option<String>,
+  pub cli_version: String,
+  /// Unix timestamps of the start and end of the build, or of the lookup in the build cache.
+  pub build_started_at: u64,
+  pub build_finished_at: u64,
+}
+
+impl Provenance {
+  /// Collects the provenance of a build in `dir` that started at `started_at` and just finished.
+  pub fn collect(dir: &Path, started_at: SystemTime) -> Self {
+    let git_commit = git(dir, &["rev-parse", "HEAD"]);
+    Self {
+      git_branch: git_commit
+        .as_ref()
+        .and_then(|_| git(dir, &["rev-parse", "--abbrev-ref", "HEAD"]))
+        .filter(|x| x != "HEAD"),
+      git_dirty: git_commit
+        .as_ref()
+        .and_then(|_| git(dir, &["status", "--porcelain"]))
+        .map(|x| !x.is_empty()),
+      git_commit,
+      builder: hostname(),
+      cli_version: env!("CARGO_PKG_VERSION").to_string(),
+      build_started_at: unix_time(started_at),
+      build_finished_at: unix_time(SystemTime::now()),
+    }
+  }
+}
+
+/// Output of a git command in `dir`, or `None` if it fails, e.g. outside of a repository.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+  let output = Command::new("git")
+    .arg("-C")
+    .arg(dir)
+    .args(args)
+    .output()
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn hostname() -> Option<String> {
+  if let Some(x) = ["HOSTNAME", "COMPUTERNAME"]
+    .iter()
+    .find_map(|x| std::env::var(x).ok())
+  {
+    return Some(x);
+  }
+  let output = Command::new("hostname").output().ok()?;
+  let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+  (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+fn unix_time(t: SystemTime) -> u64 {
+  t.duration_since(SystemTime::UNIX_EPOCH)
+    .map(|x| x.as_secs())
+    .unwrap_or_default()
+}
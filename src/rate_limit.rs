@@ -0,0 +1,152 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::HeaderMap;
+
+/// Below this many remaining requests, or `LOW_FRACTION` of the limit if that is more, bulk
+/// operations spread what is left over the rest of the window.
+const LOW_REMAINING: u64 = 10;
+
+const LOW_FRACTION: f64 = 0.1;
+
+/// A `*-Reset` value above this is a Unix timestamp rather than a number of seconds.
+const MIN_RESET_TIMESTAMP: u64 = 1_000_000_000;
+
+/// Rate limit state reported by the service in `RateLimit-*` or `X-RateLimit-*` headers.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+  /// Requests allowed per window, if reported.
+  pub limit: Option<u64>,
+  /// Requests left in the current window.
+  pub remaining: u64,
+  /// When the current window ends, if reported.
+  pub reset: Option<Instant>,
+}
+
+impl RateLimit {
+  /// The rate limit reported by the headers of a response, if any.
+  pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+    let remaining = header_u64(headers, "remaining")?;
+    let reset = header_u64(headers, "reset").map(|x| {
+      let secs = if x > MIN_RESET_TIMESTAMP {
+        let now = SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .unwrap_or_default()
+          .as_secs();
+        x.saturating_sub(now)
+      } else {
+        x
+      };
+      Instant::now() + Duration::from_secs(secs)
+    });
+    Some(RateLimit {
+      limit: header_u64(headers, "limit"),
+      remaining,
+      reset,
+    })
+  }
+
+  /// Time left until the current window ends.
+  pub fn reset_in(&self) -> Option<Duration> {
+    self
+      .reset
+      .map(|x| x.saturating_duration_since(Instant::now()))
+  }
+
+  /// How long to wait before the next request of a bulk operation: until the window ends if
+  /// nothing is left, an even share of the rest of the window if little is left, and no time
+  /// otherwise.
+  pub fn pace(&self) -> Duration {
+    let reset_in = match self.reset_in() {
+      Some(x) => x,
+      None => return Duration::ZERO,
+    };
+    let low = self
+      .limit
+      .map(|x| (x as f64 * LOW_FRACTION) as u64)
+      .unwrap_or(0)
+      .max(LOW_REMAINING);
+    if self.remaining == 0 {
+      reset_in
+    } else if self.remaining <= low {
+      reset_in.div_f64(self.remaining as f64)
+    } else {
+      Duration::ZERO
+    }
+  }
+}
+
+/// `RateLimit-<name>`, or `X-RateLimit-<name>` as many services still send it.
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+  [
+    format!("ratelimit-{}", name),
+    format!("x-ratelimit-{}", name),
+  ]
+  .iter()
+  .find_map(|x| headers.get(x.as_str()))
+  .and_then(|x| x.to_str().ok())
+  .and_then(|x| x.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rate_limit(limit: Option<u64>, remaining: u64, reset_in: Option<u64>) -> RateLimit {
+    RateLimit {
+      limit,
+      remaining,
+      reset: reset_in.map(|x| Instant::now() + Duration::from_secs(x)),
+    }
+  }
+
+  fn assert_about(actual: Duration, expected: Duration) {
+    assert!(
+      actual <= expected && expected - actual < Duration::from_secs(1),
+      "{:?} is not about {:?}",
+      actual,
+      expected
+    );
+  }
+
+  #[test]
+  fn parses_headers() {
+    let mut headers = HeaderMap::new();
+    assert!(RateLimit::from_headers(&headers).is_none());
+    headers.insert("x-ratelimit-remaining", "42".parse().unwrap());
+    headers.insert("ratelimit-limit", "100".parse().unwrap());
+    headers.insert("ratelimit-reset", "30".parse().unwrap());
+    let parsed = RateLimit::from_headers(&headers).unwrap();
+    assert_eq!(parsed.remaining, 42);
+    assert_eq!(parsed.limit, Some(100));
+    assert_about(parsed.reset_in().unwrap(), Duration::from_secs(30));
+
+    let reset = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs()
+      + 60;
+    headers.insert("ratelimit-reset", reset.to_string().parse().unwrap());
+    let parsed = RateLimit::from_headers(&headers).unwrap();
+    assert_about(parsed.reset_in().unwrap(), Duration::from_secs(60));
+  }
+
+  #[test]
+  fn paces_by_what_is_left() {
+    assert_eq!(rate_limit(Some(100), 50, Some(60)).pace(), Duration::ZERO);
+    assert_eq!(rate_limit(Some(100), 0, None).pace(), Duration::ZERO);
+    assert_about(
+      rate_limit(Some(100), 0, Some(60)).pace(),
+      Duration::from_secs(60),
+    );
+    assert_about(
+      rate_limit(Some(100), 5, Some(60)).pace(),
+      Duration::from_secs(12),
+    );
+    // 10% of a large limit is low even above `LOW_REMAINING`.
+    assert_about(
+      rate_limit(Some(1000), 60, Some(60)).pace(),
+      Duration::from_secs(1),
+    );
+    assert_eq!(rate_limit(None, 20, Some(60)).pace(), Duration::ZERO);
+  }
+}
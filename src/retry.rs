@@ -0,0 +1,257 @@
+use std::{
+  collections::hash_map::RandomState,
+  future::Future,
+  hash::{BuildHasher, Hasher},
+  time::{Duration, SystemTime},
+};
+
+use reqwest::{header::HeaderMap, Response, StatusCode};
+
+use crate::transport::TransportError;
+
+/// Delay before the first retry, doubled for each following one.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Longest `Retry-After` that is honored. A longer one is waited for only this long.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// When and how long to wait before sending a request again after a transient failure: a
+/// connection error, a timeout, a 5xx status or 429 Too Many Requests.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+  /// How many times a request is sent at most, including the first time.
+  pub attempts: u32,
+  /// Fraction of each delay, between 0 and 1, that is randomized so that clients that failed
+  /// together do not retry together.
+  pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy {
+      attempts: 4,
+      jitter: 0.5,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Sends a request built by `send` until it succeeds, fails for a reason that is not
+  /// transient, or runs out of attempts. Like `reqwest`, an error status is not an error: the
+  /// last response is returned whatever its status is.
+  pub async fn send<F, Fut>(&self, what: &str, send: F) -> Result<Response, TransportError>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, TransportError>>,
+  {
+    self.send_inner(what, true, send).await
+  }
+
+  /// Like `send`, for a request that must not take effect twice, such as a GraphQL mutation. It
+  /// is only sent again if it cannot have reached the service: the connection failed, or the
+  /// service turned it away with 429 Too Many Requests.
+  pub async fn send_unrepeatable<F, Fut>(
+    &self,
+    what: &str,
+    send: F,
+  ) -> Result<Response, TransportError>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, TransportError>>,
+  {
+    self.send_inner(what, false, send).await
+  }
+
+  async fn send_inner<F, Fut>(
+    &self,
+    what: &str,
+    repeatable: bool,
+    mut send: F,
+  ) -> Result<Response, TransportError>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, TransportError>>,
+  {
+    let mut attempt = 1;
+    loop {
+      let result = send().await;
+      if attempt >= self.attempts {
+        return result;
+      }
+      let (reason, delay) = match &result {
+        Ok(rsp)
+          if is_transient_status(rsp.status())
+            && (repeatable || rsp.status() == StatusCode::TOO_MANY_REQUESTS) =>
+        {
+          (
+            rsp.status().to_string(),
+            retry_after(rsp.headers()).unwrap_or_else(|| self.delay(attempt)),
+          )
+        }
+        Err(e) if e.is_transient() && (repeatable || e.is_connect_failure()) => {
+          (format!("{:#}", e), self.delay(attempt))
+        }
+        _ => return result,
+      };
+      log::warn!(
+        "{} failed ({}), retrying in {:.1}s (attempt {} of {})",
+        what,
+        reason,
+        delay.as_secs_f64(),
+        attempt + 1,
+        self.attempts
+      );
+      tokio::time::sleep(delay).await;
+      attempt += 1;
+    }
+  }
+
+  /// Exponential backoff before retry number `attempt`, with `jitter` of it randomized.
+  fn delay(&self, attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+      .saturating_mul(1 << (attempt - 1).min(16))
+      .min(MAX_DELAY);
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random)
+  }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+  status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// The delay asked for by a `Retry-After` header, either in seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+  let value = headers.get("retry-after")?.to_str().ok()?.trim();
+  let delay = match value.parse::<u64>() {
+    Ok(secs) => Duration::from_secs(secs),
+    Err(_) => httpdate::parse_http_date(value)
+      .ok()?
+      .duration_since(SystemTime::now())
+      .unwrap_or_default(),
+  };
+  Some(delay.min(MAX_RETRY_AFTER))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU32, Ordering};
+
+  use tokio::time::Instant;
+
+  use super::*;
+
+  fn response(status: StatusCode, retry_after: Option<&str>) -> Response {
+    let mut response = http::Response::builder().status(status);
+    if let Some(x) = retry_after {
+      response = response.header("retry-after", x);
+    }
+    Response::from(response.body(vec![]).unwrap())
+  }
+
+  #[test]
+  fn parses_retry_after() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(retry_after(&headers), None);
+    headers.insert("retry-after", "7".parse().unwrap());
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+    headers.insert("retry-after", "3600".parse().unwrap());
+    assert_eq!(retry_after(&headers), Some(MAX_RETRY_AFTER));
+    headers.insert(
+      "retry-after",
+      "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+    );
+    assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    headers.insert("retry-after", "soon".parse().unwrap());
+    assert_eq!(retry_after(&headers), None);
+  }
+
+  #[test]
+  fn backs_off_exponentially() {
+    let policy = RetryPolicy {
+      attempts: 10,
+      jitter: 0.0,
+    };
+    assert_eq!(policy.delay(1), BASE_DELAY);
+    assert_eq!(policy.delay(3), BASE_DELAY * 4);
+    assert_eq!(policy.delay(20), MAX_DELAY);
+    let policy = RetryPolicy {
+      attempts: 10,
+      jitter: 1.0,
+    };
+    assert!(policy.delay(2) <= BASE_DELAY * 2);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn waits_as_long_as_retry_after_asks() {
+    let sent = AtomicU32::new(0);
+    let start = Instant::now();
+    let rsp = RetryPolicy::default()
+      .send("test", || async {
+        let status = match sent.fetch_add(1, Ordering::Relaxed) {
+          0 => StatusCode::TOO_MANY_REQUESTS,
+          _ => StatusCode::OK,
+        };
+        Ok(response(status, Some("7")))
+      })
+      .await
+      .unwrap();
+    assert_eq!(rsp.status(), StatusCode::OK);
+    assert_eq!(sent.load(Ordering::Relaxed), 2);
+    assert_eq!(start.elapsed(), Duration::from_secs(7));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn returns_the_last_response_after_the_last_attempt() {
+    let sent = AtomicU32::new(0);
+    let rsp = RetryPolicy::default()
+      .send("test", || async {
+        sent.fetch_add(1, Ordering::Relaxed);
+        Ok(response(StatusCode::BAD_GATEWAY, None))
+      })
+      .await
+      .unwrap();
+    assert_eq!(rsp.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+      sent.load(Ordering::Relaxed),
+      RetryPolicy::default().attempts
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn does_not_retry_client_errors() {
+    let sent = AtomicU32::new(0);
+    let rsp = RetryPolicy::default()
+      .send("test", || async {
+        sent.fetch_add(1, Ordering::Relaxed);
+        Ok(response(StatusCode::NOT_FOUND, None))
+      })
+      .await
+      .unwrap();
+    assert_eq!(rsp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(sent.load(Ordering::Relaxed), 1);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn retries_unrepeatable_requests_only_if_turned_away() {
+    for (status, expected) in [
+      (StatusCode::SERVICE_UNAVAILABLE, 1),
+      (StatusCode::TOO_MANY_REQUESTS, 2),
+    ] {
+      let sent = AtomicU32::new(0);
+      RetryPolicy::default()
+        .send_unrepeatable("test", || async {
+          let status = match sent.fetch_add(1, Ordering::Relaxed) {
+            0 => status,
+            _ => StatusCode::OK,
+          };
+          Ok(response(status, None))
+        })
+        .await
+        .unwrap();
+      assert_eq!(sent.load(Ordering::Relaxed), expected, "{}", status);
+    }
+  }
+}
@@ -0,0 +1,105 @@
+use std::{path::Path, str::FromStr};
+
+use crate::config::{AppSpec, EnvSpec, FORMAT_VERSION};
+
+/// Starter project layout for `boat init`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+  Nodejs,
+  Static,
+  Worker,
+}
+
+impl FromStr for Template {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "nodejs" => Ok(Template::Nodejs),
+      "static" => Ok(Template::Static),
+      "worker" => Ok(Template::Worker),
+      _ => anyhow::bail!(
+        "unknown template: {} (expected nodejs, static or worker)",
+        s
+      ),
+    }
+  }
+}
+
+impl Template {
+  pub fn spec(self) -> &'static str {
+    match self {
+      Template::Nodejs => include_str!("templates/nodejs/Boat.spec.toml"),
+      Template::Static => include_str!("templates/static/Boat.spec.toml"),
+      Template::Worker => include_str!("templates/worker/Boat.spec.toml"),
+    }
+  }
+
+  /// Example source files, as `(relative path, contents)` pairs.
+  pub fn files(self) -> &'static [(&'static str, &'static str)] {
+    match self {
+      Template::Nodejs => &[
+        (
+          "package.json",
+          include_str!("templates/nodejs/package.json"),
+        ),
+        ("src/index.js", include_str!("templates/nodejs/index.js")),
+      ],
+      Template::Static => &[
+        ("index.js", include_str!("templates/static/index.js")),
+        (
+          "public/index.html",
+          include_str!("templates/static/index.html"),
+        ),
+      ],
+      Template::Worker => &[("index.js", include_str!("templates/worker/index.js"))],
+    }
+  }
+
+  /// Environment variables declared in the template spec.
+  pub fn env_specs(self) -> anyhow::Result<Vec<EnvSpec>> {
+    let spec: AppSpec = toml::from_str(self.spec())?;
+    Ok(
+      spec
+        .env
+        .iter()
+        .map(|x| x.get_ref().to_env_spec().into_owned())
+        .collect(),
+    )
+  }
+}
+
+/// Renders a `Boat.toml` for the given app id and environment variables.
+pub fn render_config(app_id: &str, env: &[(String, String)]) -> anyhow::Result<String> {
+  let mut env_table = toml::value::Table::new();
+  for (k, v) in env {
+    env_table.insert(k.clone(), toml::Value::String(v.clone()));
+  }
+  let mut root = toml::value::Table::new();
+  root.insert(
+    "version".into(),
+    toml::Value::Integer(FORMAT_VERSION.into()),
+  );
+  root.insert("id".into(), toml::Value::String(app_id.to_string()));
+  root.insert("env".into(), toml::Value::Table(env_table));
+  Ok(toml::to_string(&toml::Value::Table(root))?)
+}
+
+/// Writes `files` under `dir`, refusing to overwrite anything that already exists.
+pub fn write_files(dir: &Path, files: &[(&str, &str)]) -> anyhow::Result<()> {
+  for (path, _) in files {
+    let path = dir.join(path);
+    if path.exists() {
+      anyhow::bail!("refusing to overwrite existing file {}", path.display());
+    }
+  }
+  for (path, contents) in files {
+    let path = dir.join(path);
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, contents)
+      .map_err(|e| anyhow::Error::from(e).context(format!("cannot write {}", path.display())))?;
+  }
+  Ok(())
+}
@@ -19,10 +19,34 @@ pub struct RunDeploymentPreparation;
 #[derive(GraphQLQuery)]
 #[graphql(
   schema_path = "schema/api.graphql",
-  query_path = "schema/query.graphql"
+  query_path = "schema/query.graphql",
+  response_derives = "Serialize"
 )]
 pub struct RunDeploymentList;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql"
+)]
+pub struct RunDeploymentPromotion;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql",
+  response_derives = "Serialize"
+)]
+pub struct RunDeploymentDeletion;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql",
+  response_derives = "Serialize"
+)]
+pub struct GetAppStatus;
+
 #[derive(GraphQLQuery)]
 #[graphql(
   schema_path = "schema/api.graphql",
@@ -38,3 +62,63 @@ pub struct GetAppLogs;
   response_derives = "Serialize"
 )]
 pub struct GetDeploymentLogs;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql",
+  response_derives = "Serialize"
+)]
+pub struct GetViewer;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql",
+  response_derives = "Serialize"
+)]
+pub struct ListApps;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql",
+  response_derives = "Serialize"
+)]
+pub struct GetDeployment;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql",
+  response_derives = "Serialize"
+)]
+pub struct GetDeploymentHistory;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql"
+)]
+pub struct GetAppSecrets;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql"
+)]
+pub struct RunAppSecretsUpdate;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql"
+)]
+pub struct RunSourceMapUploadPreparation;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+  schema_path = "schema/api.graphql",
+  query_path = "schema/query.graphql"
+)]
+pub struct RunMultipartUploadCompletion;
@@ -0,0 +1,292 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::process::Command;
+
+use crate::config::AppConfig;
+
+/// Schemes of the backends registered by `SecretResolver::with_default_backends`.
+pub const DEFAULT_SCHEMES: &[&str] = &["vault", "aws-sm", "sops"];
+
+/// A store that secret values in the config can refer to as `<scheme>:<reference>`.
+#[async_trait]
+pub trait SecretBackend: Send + Sync {
+  /// Fetches the secret identified by `reference`, the part of the value after `<scheme>:`.
+  async fn fetch(&self, reference: &str) -> Result<String>;
+}
+
+/// Replaces secret values that refer to an external store with the secrets they refer to.
+#[derive(Default)]
+pub struct SecretResolver {
+  backends: HashMap<String, Box<dyn SecretBackend>>,
+}
+
+impl SecretResolver {
+  /// A resolver with the Vault, AWS Secrets Manager and SOPS backends.
+  pub fn with_default_backends() -> Self {
+    let mut out = Self::default();
+    out.register("vault", VaultBackend::default());
+    out.register("aws-sm", AwsSecretsManagerBackend);
+    out.register("sops", SopsBackend);
+    out
+  }
+
+  pub fn register(&mut self, scheme: &str, backend: impl SecretBackend + 'static) {
+    self.backends.insert(scheme.to_string(), Box::new(backend));
+  }
+
+  /// Resolves every secret of `config` whose value starts with a registered scheme.
+  pub async fn resolve_config(&self, config: &mut AppConfig) -> Result<()> {
+    for (key, value) in config.secrets.iter_mut() {
+      let (scheme, reference) = match value.split_once(':') {
+        Some((scheme, reference)) if self.backends.contains_key(scheme) => (scheme, reference),
+        _ => continue,
+      };
+      log::debug!("resolving secret {} from {}", key.get_ref(), scheme);
+      *value = self.backends[scheme]
+        .fetch(reference)
+        .await
+        .with_context(|| format!("cannot resolve secret `{}` from {}", key.get_ref(), value))?;
+    }
+    Ok(())
+  }
+}
+
+/// Whether `value` refers to one of the stores of `DEFAULT_SCHEMES` instead of being a secret
+/// itself.
+pub fn is_external_reference(value: &str) -> bool {
+  value
+    .split_once(':')
+    .is_some_and(|(scheme, _)| DEFAULT_SCHEMES.contains(&scheme))
+}
+
+/// Resolves the path of a `sops:` reference against `base_dir`, the directory of the config
+/// that contains it. Other values are returned unchanged.
+pub fn rebase_sops_reference(value: &str, base_dir: &Path) -> String {
+  let reference = match value.strip_prefix("sops:") {
+    Some(x) => x,
+    None => return value.to_string(),
+  };
+  let (path, field) = split_field(reference);
+  let mut out = format!("sops:{}", base_dir.join(path).display());
+  if let Some(field) = field {
+    out.push('#');
+    out.push_str(field);
+  }
+  out
+}
+
+/// Splits `path#field` into the path and the optional field.
+fn split_field(reference: &str) -> (&str, Option<&str>) {
+  match reference.rsplit_once('#') {
+    Some((path, field)) => (path, Some(field)),
+    None => (reference, None),
+  }
+}
+
+/// Picks `field` from a JSON object, or returns `value` itself without one.
+fn pick_field(value: String, field: Option<&str>) -> Result<String> {
+  let field = match field {
+    Some(x) => x,
+    None => return Ok(value),
+  };
+  let object: serde_json::Map<String, serde_json::Value> =
+    serde_json::from_str(&value).context("secret is not a JSON object")?;
+  match object.get(field) {
+    Some(serde_json::Value::String(x)) => Ok(x.clone()),
+    Some(x) => Ok(x.to_string()),
+    None => anyhow::bail!("secret has no field `{}`", field),
+  }
+}
+
+async fn run_command(cmd: &mut Command) -> Result<String> {
+  let output = cmd.output().await?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "exited with {}: {}",
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+  let mut out = String::from_utf8(output.stdout).context("output is not valid UTF-8")?;
+  if out.ends_with('\n') {
+    out.pop();
+  }
+  Ok(out)
+}
+
+/// HashiCorp Vault, read over HTTP with `VAULT_ADDR` and `VAULT_TOKEN` (or `~/.vault-token`).
+/// References are `<path>#<field>`, e.g. `secret/data/app#KEY`; both KV v1 and v2 are supported.
+#[derive(Default)]
+pub struct VaultBackend {
+  client: reqwest::Client,
+}
+
+#[async_trait]
+impl SecretBackend for VaultBackend {
+  async fn fetch(&self, reference: &str) -> Result<String> {
+    let (path, field) = split_field(reference);
+    let field = field.context("vault references must name a field, as in `<path>#<field>`")?;
+    let addr = std::env::var("VAULT_ADDR")
+      .ok()
+      .context("VAULT_ADDR is not set")?;
+    let token = match std::env::var("VAULT_TOKEN") {
+      Ok(x) => x,
+      Err(_) => dirs::home_dir()
+        .map(|x| x.join(".vault-token"))
+        .and_then(|x| std::fs::read_to_string(x).ok())
+        .map(|x| x.trim().to_string())
+        .context("VAULT_TOKEN is not set and ~/.vault-token does not exist")?,
+    };
+    let res = self
+      .client
+      .get(format!(
+        "{}/v1/{}",
+        addr.trim_end_matches('/'),
+        path.trim_start_matches('/')
+      ))
+      .header("X-Vault-Token", token)
+      .send()
+      .await?
+      .error_for_status()?;
+    let body: serde_json::Value = res.json().await?;
+    // KV v2 nests the secret one level deeper than KV v1.
+    let data = match &body["data"]["data"] {
+      serde_json::Value::Object(_) => &body["data"]["data"],
+      _ => &body["data"],
+    };
+    match &data[field] {
+      serde_json::Value::String(x) => Ok(x.clone()),
+      serde_json::Value::Null => anyhow::bail!("secret has no field `{}`", field),
+      x => Ok(x.to_string()),
+    }
+  }
+}
+
+/// AWS Secrets Manager, read with the `aws` CLI. References are a secret id or ARN, optionally
+/// followed by `#<field>` to pick a field of a JSON secret.
+pub struct AwsSecretsManagerBackend;
+
+#[async_trait]
+impl SecretBackend for AwsSecretsManagerBackend {
+  async fn fetch(&self, reference: &str) -> Result<String> {
+    let (id, field) = split_field(reference);
+    let value = run_command(Command::new("aws").args([
+      "secretsmanager",
+      "get-secret-value",
+      "--secret-id",
+      id,
+      "--query",
+      "SecretString",
+      "--output",
+      "text",
+    ]))
+    .await
+    .context("aws secretsmanager get-secret-value failed")?;
+    pick_field(value, field)
+  }
+}
+
+/// Files encrypted with SOPS, decrypted with the `sops` binary. References are a file path,
+/// optionally followed by `#<field>` to pick a top-level key. Relative paths in config files are
+/// made relative to the config by the loader, so any left are relative to the current directory.
+pub struct SopsBackend;
+
+#[async_trait]
+impl SecretBackend for SopsBackend {
+  async fn fetch(&self, reference: &str) -> Result<String> {
+    let (path, field) = split_field(reference);
+    let mut cmd = Command::new("sops");
+    cmd.arg("--decrypt");
+    if let Some(field) = field {
+      cmd.arg("--extract").arg(format!("[{:?}]", field));
+    }
+    cmd.arg(path);
+    run_command(&mut cmd).await.context("sops --decrypt failed")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct Echo;
+
+  #[async_trait]
+  impl SecretBackend for Echo {
+    async fn fetch(&self, reference: &str) -> Result<String> {
+      match reference {
+        "missing" => anyhow::bail!("not found"),
+        x => Ok(format!("<{}>", x)),
+      }
+    }
+  }
+
+  #[test]
+  fn splits_fields() {
+    assert_eq!(split_field("secret/app#KEY"), ("secret/app", Some("KEY")));
+    assert_eq!(split_field("a#b#c"), ("a#b", Some("c")));
+    assert_eq!(split_field("secret/app"), ("secret/app", None));
+  }
+
+  #[test]
+  fn picks_fields() {
+    let value = r#"{"user":"u","port":5432}"#.to_string();
+    assert_eq!(pick_field(value.clone(), Some("user")).unwrap(), "u");
+    assert_eq!(pick_field(value.clone(), Some("port")).unwrap(), "5432");
+    assert!(pick_field(value.clone(), Some("password")).is_err());
+    assert_eq!(pick_field(value.clone(), None).unwrap(), value);
+    assert!(pick_field("plain".into(), Some("user")).is_err());
+  }
+
+  #[test]
+  fn rebases_sops_references() {
+    let base = Path::new("/app/conf");
+    assert_eq!(
+      rebase_sops_reference("sops:secrets.enc.json#db", base),
+      "sops:/app/conf/secrets.enc.json#db"
+    );
+    assert_eq!(
+      rebase_sops_reference("sops:../secrets.yaml", base),
+      "sops:/app/conf/../secrets.yaml"
+    );
+    assert_eq!(
+      rebase_sops_reference("sops:/etc/secrets.json", base),
+      "sops:/etc/secrets.json"
+    );
+    assert_eq!(
+      rebase_sops_reference("vault:secret/app#KEY", base),
+      "vault:secret/app#KEY"
+    );
+  }
+
+  #[test]
+  fn recognizes_external_references() {
+    assert!(is_external_reference("vault:secret/app#KEY"));
+    assert!(is_external_reference("aws-sm:prod/db"));
+    assert!(!is_external_reference("https://example.com"));
+    assert!(!is_external_reference("plain"));
+  }
+
+  #[tokio::test]
+  async fn resolves_registered_schemes_only() {
+    let mut config: AppConfig = toml::from_str(
+      r#"
+      id = "app"
+      secrets = { A = "echo:a", B = "other:b", C = "plain" }
+      "#,
+    )
+    .unwrap();
+    let mut resolver = SecretResolver::default();
+    resolver.register("echo", Echo);
+    resolver.resolve_config(&mut config).await.unwrap();
+    let values = config.secrets.values().collect::<Vec<_>>();
+    assert_eq!(values, ["<a>", "other:b", "plain"]);
+
+    let mut config: AppConfig =
+      toml::from_str("id = \"app\"\nsecrets = { A = \"echo:missing\" }").unwrap();
+    let e = resolver.resolve_config(&mut config).await.unwrap_err();
+    assert_eq!(e.to_string(), "cannot resolve secret `A` from echo:missing");
+  }
+}
@@ -1,127 +1,1427 @@
+use anyhow::Context;
+use data_encoding::{BASE64, HEXLOWER};
+use futures_util::{Stream, StreamExt, TryStreamExt};
 use graphql_client::{GraphQLQuery, QueryBody};
-use reqwest::{header::HeaderValue, Body, Method, Request, Url};
+use indicatif::ProgressBar;
+use miette::Diagnostic;
+use reqwest::{
+  header::{HeaderMap, HeaderValue},
+  Body, Method, Request, StatusCode, Url,
+};
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::{
+  collections::{BTreeMap, HashMap},
+  io::SeekFrom,
+  path::Path,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 use crate::{
-  authenticator::Credentials,
+  api::DiffUpload,
+  authenticator::{Credentials, CredentialsOptions},
+  client_version::{ClientTooOld, Version, VersionPolicy},
+  cursor::{Page, ServiceCursor},
+  endpoint::Endpoint,
+  http_log::LoggingTransport,
   metadata::AppMetadata,
-  schema::{self, RunDeploymentCreation, RunDeploymentPreparation},
+  package_builder::{build_package_diff, PackageManifest},
+  package_file::PackageFile,
+  progress,
+  rate_limit::RateLimit,
+  retry::RetryPolicy,
+  schema::{
+    self, GetAppSecrets, GetAppStatus, GetDeployment, GetDeploymentHistory, GetViewer, ListApps,
+    RunAppSecretsUpdate, RunDeploymentDeletion, RunDeploymentPromotion,
+    RunMultipartUploadCompletion, RunSourceMapUploadPreparation,
+  },
+  tls::TlsOptions,
+  transport::{ReqwestTransport, Transport, TransportError},
+  upload_state::{MultipartState, PreparedUpload, UploadJournal},
 };
 
+const WAIT_MIN_INTERVAL: Duration = Duration::from_millis(500);
+const WAIT_MAX_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shortest wait for the rate limit that is worth telling the user about.
+const THROTTLE_WARN_DELAY: Duration = Duration::from_secs(1);
+
+/// Interval of HTTP/2 pings and TCP keepalive probes, so that a dead connection is noticed
+/// instead of waited on forever.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many parts of a multipart upload are uploaded at the same time.
+const MULTIPART_CONCURRENCY: usize = 4;
+
+/// How many API calls of `Service::call_many` are in flight at the same time.
+const CALL_CONCURRENCY: usize = 4;
+
+/// Result of `Service::probe`.
+pub struct ProbeResult {
+  pub latency: Duration,
+  /// Server time from the `Date` response header, if present.
+  pub server_time: Option<SystemTime>,
+  /// Versions of the CLI the service supports, if it reports them.
+  pub version_policy: Option<VersionPolicy>,
+}
+
+/// Outcome of `Service::deploy`.
+#[derive(Serialize, Debug, Clone)]
+pub struct DeployResult {
+  pub id: String,
+  pub app_id: String,
+  pub url: String,
+  pub created_at: String,
+  pub package_sha256: String,
+  pub package_size: u64,
+  /// Whether the deployment is live. New deployments are not live until promoted.
+  pub live: bool,
+  /// Unix timestamp of when the upload started.
+  pub started_at: u64,
+  /// Unix timestamp of when the deployment was created.
+  pub finished_at: u64,
+}
+
+/// How the service is connected to.
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+  /// Time limit of each API call. Uploads are not limited, as their length depends on the
+  /// package size, and rely on keepalive instead.
+  pub timeout: Duration,
+  pub connect_timeout: Duration,
+  /// Proxy for all requests, instead of the one from the `HTTP_PROXY`/`HTTPS_PROXY` env.
+  pub proxy: Option<String>,
+  /// Ignore the proxy env and connect directly.
+  pub no_proxy: bool,
+  /// Extra headers of each API call, which replace the default ones of the same name.
+  pub headers: HeaderMap,
+  /// Log every request and response, with secrets redacted.
+  pub debug_http: bool,
+  /// Allow a plain `http://` endpoint.
+  pub insecure_http: bool,
+  pub tls: TlsOptions,
+}
+
+/// `User-Agent` of all requests, e.g. `boat/0.1.2 (linux)`, so that CLI traffic can be told
+/// apart by proxies and by the service.
+pub fn user_agent() -> String {
+  format!(
+    "boat/{} ({})",
+    env!("CARGO_PKG_VERSION"),
+    std::env::consts::OS
+  )
+}
+
 pub struct Service {
+  /// Builds requests, which `transport` sends.
   client: reqwest::Client,
+  transport: Arc<dyn Transport>,
   creds: Option<Credentials>,
   endpoint: Url,
+  timeout: Duration,
+  headers: HeaderMap,
+  retry: RetryPolicy,
+  /// Rate limit reported by the last API call.
+  rate_limit: Mutex<Option<RateLimit>>,
+  /// Whether queries are sent by hash first. Turned off once the service turns out not to
+  /// support that.
+  persisted_queries: AtomicBool,
+  /// Whether a newer release of the CLI has been pointed out already.
+  upgrade_hinted: AtomicBool,
+  /// Where credentials failed to load from in `Service::new`, to report why once a call needs
+  /// them.
+  missing_credentials: Option<CredentialsOptions>,
 }
 
 impl Service {
-  pub fn new(endpoint: &str, credentials_file: &Option<String>) -> anyhow::Result<Self> {
-    let creds = match Credentials::init(credentials_file) {
-      Ok(creds) => Some(creds),
+  pub fn new(
+    endpoint: &str,
+    credentials: &CredentialsOptions,
+    connection: &ConnectionOptions,
+  ) -> anyhow::Result<Self> {
+    let (creds, missing_credentials) = match Credentials::init(credentials) {
+      Ok(creds) => (Some(creds), None),
+      // Not every command calls the API, so this only fails once a call needs credentials.
       Err(e) => {
-        log::warn!("failed to load credentials: {}", e);
-        None
+        log::debug!("{:#}", e);
+        (None, Some(credentials.clone()))
       }
     };
+    let mut service = Self::with_credentials(endpoint, creds, connection)?;
+    service.missing_credentials = missing_credentials;
+    Ok(service)
+  }
+
+  pub fn with_credentials(
+    endpoint: &str,
+    creds: Option<Credentials>,
+    connection: &ConnectionOptions,
+  ) -> anyhow::Result<Self> {
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    let parsed = Endpoint::parse(endpoint, connection.insecure_http)?;
     let endpoint =
       Url::parse(endpoint).map_err(|e| anyhow::Error::from(e).context("invalid endpoint url"))?;
+    let mut client = reqwest::Client::builder()
+      .user_agent(user_agent())
+      .connect_timeout(connection.connect_timeout)
+      .tcp_keepalive(KEEPALIVE_INTERVAL)
+      .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+      .http2_keep_alive_timeout(connection.connect_timeout)
+      .http2_keep_alive_while_idle(true);
+    if connection.no_proxy {
+      client = client.no_proxy();
+    }
+    if let Some(tls) = connection.tls.connector()? {
+      client = client.use_preconfigured_tls(tls);
+    }
+    if let Some(proxy) = &connection.proxy {
+      client = client.proxy(
+        reqwest::Proxy::all(proxy)
+          .map_err(|e| anyhow::Error::from(e).context("invalid proxy url"))?,
+      );
+    }
+    let client = client.build()?;
+    let mut transport: Arc<dyn Transport> = Arc::new(ReqwestTransport::new(client.clone()));
+    #[cfg(unix)]
+    if let Endpoint::Unix(path) = parsed {
+      transport = Arc::new(crate::transport::UnixTransport::new(path, transport));
+    }
+    if connection.debug_http {
+      transport = Arc::new(LoggingTransport::new(transport));
+    }
     Ok(Service {
-      client: reqwest::Client::new(),
+      transport,
+      client,
       creds,
       endpoint,
+      timeout: connection.timeout,
+      headers: connection.headers.clone(),
+      retry: RetryPolicy::default(),
+      rate_limit: Mutex::new(None),
+      persisted_queries: AtomicBool::new(true),
+      upgrade_hinted: AtomicBool::new(false),
+      missing_credentials: None,
     })
   }
 
+  /// Sends requests with `transport` instead of over the network.
+  pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+    self.transport = Arc::new(transport);
+    self
+  }
+
+  /// Sets whether queries are sent as persisted queries, by hash first. On by default; a
+  /// service without persisted queries is detected and sent queries in full anyway.
+  pub fn with_persisted_queries(self, enabled: bool) -> Self {
+    self.persisted_queries.store(enabled, Ordering::Relaxed);
+    self
+  }
+
+  /// Sets how API calls and uploads are retried after transient failures.
+  pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+    self.retry = retry;
+    self
+  }
+
+  pub fn credentials(&self) -> Option<&Credentials> {
+    self.creds.as_ref()
+  }
+
+  /// Sends a GraphQL query. With persisted queries, only the hash of the query is sent at
+  /// first, and the query itself only if the service does not know the hash yet. Mutations are
+  /// only retried if they cannot have reached the service.
+  #[tracing::instrument(skip_all, fields(operation = query.operation_name), err)]
   pub async fn call<V: Serialize, D: for<'de> Deserialize<'de>>(
     &self,
     query: QueryBody<V>,
   ) -> anyhow::Result<graphql_client::Response<D>> {
-    let mut req = Request::new(Method::POST, self.endpoint.clone());
+    let repeatable = !is_mutation(&query);
+    if !self.persisted_queries.load(Ordering::Relaxed) {
+      return self.post(&serde_json::to_vec(&query)?, repeatable).await;
+    }
+    let extensions = serde_json::json!({
+      "persistedQuery": {
+        "version": 1,
+        "sha256Hash": HEXLOWER.encode(&Sha256::digest(query.query.as_bytes())),
+      }
+    });
+    let body = serde_json::json!({
+      "operationName": query.operation_name,
+      "variables": &query.variables,
+      "extensions": &extensions,
+    });
+    match self
+      .post::<D>(&serde_json::to_vec(&body)?, repeatable)
+      .await
     {
-      let headers = req.headers_mut();
-      headers.insert("content-type", HeaderValue::from_static("application/json"));
-      headers.insert("accept", HeaderValue::from_static("application/json"));
+      Ok(rsp) => match persisted_query_error(&rsp) {
+        None => return Ok(rsp),
+        Some(PERSISTED_QUERY_NOT_FOUND) => {
+          log::debug!(
+            "persisted query {} not found, sending it in full",
+            query.operation_name
+          );
+        }
+        Some(_) => self.disable_persisted_queries(),
+      },
+      // A service without persisted queries may reject a request without a query outright.
+      Err(e)
+        if matches!(
+          e.downcast_ref::<ServiceError>(),
+          Some(ServiceError::Status(StatusCode::BAD_REQUEST))
+        ) =>
+      {
+        self.disable_persisted_queries()
+      }
+      Err(e) => return Err(e),
+    }
+    let mut body = serde_json::to_value(&query)?;
+    if self.persisted_queries.load(Ordering::Relaxed) {
+      // Sent along so that the service persists the query for the next time.
+      body["extensions"] = extensions;
+    }
+    self.post(&serde_json::to_vec(&body)?, repeatable).await
+  }
+
+  /// Sends `queries` concurrently, a few at a time and paced by the rate limit, and returns
+  /// the result of each in the same order. A failed call does not stop the others.
+  pub async fn call_many<V: Serialize, D: for<'de> Deserialize<'de>>(
+    &self,
+    queries: impl IntoIterator<Item = QueryBody<V>>,
+  ) -> Vec<anyhow::Result<graphql_client::Response<D>>> {
+    futures_util::stream::iter(queries)
+      .map(|q| async move {
+        self.throttle().await;
+        self.call(q).await
+      })
+      .buffered(CALL_CONCURRENCY)
+      .collect()
+      .await
+  }
+
+  fn disable_persisted_queries(&self) {
+    if self.persisted_queries.swap(false, Ordering::Relaxed) {
+      log::info!("service does not support persisted queries, sending queries in full");
     }
-    *req.body_mut() = Some(Body::from(serde_json::to_vec(&query)?));
+  }
 
-    if let Some(creds) = &self.creds {
-      creds.annotate_request(&mut req);
+  /// Posts a GraphQL request `body` to the endpoint. Unless it is `repeatable`, it is only sent
+  /// again after failures that mean it was not received.
+  async fn post<D: for<'de> Deserialize<'de>>(
+    &self,
+    body: &[u8],
+    repeatable: bool,
+  ) -> anyhow::Result<graphql_client::Response<D>> {
+    if let Some(credentials) = &self.missing_credentials {
+      // Fail with the reason, rather than with the service rejecting an unsigned call.
+      Credentials::init(credentials)?;
+    }
+    let send = || {
+      let mut req = Request::new(Method::POST, self.endpoint.clone());
+      {
+        let headers = req.headers_mut();
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers.insert("accept", HeaderValue::from_static("application/json"));
+        headers.insert("user-agent", HeaderValue::from_str(&user_agent()).unwrap());
+        headers.extend(self.headers.clone());
+      }
+      *req.body_mut() = Some(Body::from(body.to_vec()));
+      *req.timeout_mut() = Some(self.timeout);
+
+      // Signed for each attempt, as the signature covers the time of the request.
+      if let Some(creds) = &self.creds {
+        creds.annotate_request(&mut req);
+      }
+      self.transport.send(req)
+    };
+    let res = if repeatable {
+      self.retry.send("api call", send).await
+    } else {
+      self.retry.send_unrepeatable("api call", send).await
+    }
+    .map_err(|e| NetworkError {
+      endpoint: self.endpoint.to_string(),
+      source: e,
+    })?;
+    if let Some(rate_limit) = RateLimit::from_headers(res.headers()) {
+      log::info!(
+        "rate limit: {} of {} requests left, resets in {}",
+        rate_limit.remaining,
+        rate_limit
+          .limit
+          .map(|x| x.to_string())
+          .unwrap_or_else(|| "?".into()),
+        rate_limit
+          .reset_in()
+          .map(|x| format!("{}s", x.as_secs()))
+          .unwrap_or_else(|| "?".into()),
+      );
+      *self.rate_limit.lock().unwrap() = Some(rate_limit);
     }
+    let status = res.status();
+    let body = res.bytes().await;
+    let too_old = match &body {
+      Ok(body) => self.check_client_version(body).err(),
+      Err(_) => None,
+    };
+    let response = (|| {
+      if !status.is_success() {
+        return Err(ServiceError::Status(status).into());
+      }
+      let body = body.map_err(|e| anyhow::Error::from(e).context("api call failed"))?;
+      let body: graphql_client::Response<D> =
+        serde_json::from_slice(&body).map_err(ServiceError::Decode)?;
+      Ok(body)
+    })();
+    match (response, too_old) {
+      // The failure is likely down to the unsupported version, so that is what to report.
+      (Err(_), Some(too_old)) => Err(too_old.into()),
+      (Ok(response), Some(too_old)) => {
+        if !self.upgrade_hinted.swap(true, Ordering::Relaxed) {
+          log::warn!("{}; upgrade with `cargo install boatctl`", too_old);
+        }
+        Ok(response)
+      }
+      (response, None) => response,
+    }
+  }
 
+  /// Fails if the service reports that it no longer supports this version of the CLI, and
+  /// otherwise points out a newer release once.
+  fn check_client_version(&self, body: &[u8]) -> Result<(), ClientTooOld> {
+    let policy = match VersionPolicy::from_response(body) {
+      Some(x) => x,
+      None => return Ok(()),
+    };
+    let current = Version::current();
+    if let Some(latest) = policy.check(&current)? {
+      if !self.upgrade_hinted.swap(true, Ordering::Relaxed) {
+        log::warn!(
+          "boat {} is available, this is {}; upgrade with `cargo install boatctl`",
+          latest,
+          current
+        );
+      }
+    }
+    Ok(())
+  }
+
+  /// Rate limit reported by the last API call, if the service reports one.
+  pub fn rate_limit(&self) -> Option<RateLimit> {
+    self.rate_limit.lock().unwrap().clone()
+  }
+
+  /// Waits as long as the rate limit asks before the next call of a bulk operation, so that it
+  /// slows down instead of failing partway once the limit is reached.
+  pub async fn throttle(&self) {
+    let delay = match self.rate_limit() {
+      Some(x) => x.pace(),
+      None => return,
+    };
+    if delay >= THROTTLE_WARN_DELAY {
+      log::warn!(
+        "close to the rate limit, waiting {:.1}s before the next call",
+        delay.as_secs_f64()
+      );
+    }
+    tokio::time::sleep(delay).await;
+  }
+
+  /// Sends a request built by `client` through the transport.
+  async fn send(
+    &self,
+    request: reqwest::Result<Request>,
+  ) -> Result<reqwest::Response, TransportError> {
+    self.transport.send(request?).await
+  }
+
+  /// Sends a minimal query to check that the endpoint is reachable.
+  #[tracing::instrument(skip_all, err)]
+  pub async fn probe(&self) -> anyhow::Result<ProbeResult> {
+    let start = Instant::now();
     let res = self
-      .client
-      .execute(req)
+      .send(
+        self
+          .client
+          .post(self.endpoint.clone())
+          .json(&serde_json::json!({ "query": "{ __typename }" }))
+          .timeout(self.timeout)
+          .build(),
+      )
       .await
-      .map_err(|e| anyhow::Error::from(e).context("api call failed"))?;
-    let status = res.status();
-    if !status.is_success() {
-      anyhow::bail!("api call returned error status: {}", status);
+      .map_err(|e| NetworkError {
+        endpoint: self.endpoint.to_string(),
+        source: e,
+      })?;
+    let latency = start.elapsed();
+    if !res.status().is_success() {
+      anyhow::bail!("endpoint returned error status: {}", res.status());
     }
-    let body: graphql_client::Response<D> = res
-      .json()
+    let server_time = res
+      .headers()
+      .get("date")
+      .and_then(|x| x.to_str().ok())
+      .and_then(|x| httpdate::parse_http_date(x).ok());
+    let version_policy = res
+      .bytes()
       .await
-      .map_err(|e| anyhow::Error::from(e).context("api call failed"))?;
-    Ok(body)
+      .ok()
+      .and_then(|x| VersionPolicy::from_response(&x));
+    Ok(ProbeResult {
+      latency,
+      server_time,
+      version_policy,
+    })
   }
 
+  /// Uploads `package` and creates a deployment from it. With a `journal`, the progress of the
+  /// upload is recorded in it, and an upload that it records as started is resumed rather than
+  /// prepared again.
+  #[tracing::instrument(skip(self, metadata, package, journal), fields(package_size = package.len()), err)]
   pub async fn deploy(
     &self,
     app_id: &str,
     metadata: &AppMetadata,
-    package: &[u8],
+    package: &PackageFile,
+    journal: Option<&UploadJournal>,
+  ) -> anyhow::Result<DeployResult> {
+    let started_at = SystemTime::now();
+    let uploaded_package = match journal.and_then(|x| x.state().prepared) {
+      Some(prepared) if prepared.uploaded => {
+        log::info!("package already uploaded, creating the deployment");
+        prepared.package
+      }
+      Some(PreparedUpload {
+        package: id,
+        multipart: Some(multipart),
+        ..
+      }) => {
+        log::info!(
+          "resuming upload with {} of {} parts done",
+          multipart.etags.len(),
+          multipart.part_urls.len()
+        );
+        self
+          .upload_multipart(app_id, multipart, package, journal)
+          .await
+          .context("cannot resume the upload; run `boat deploy` without --resume to start over")?;
+        id
+      }
+      _ => self.prepare_and_upload(app_id, package, journal).await?,
+    };
+    if let Some(journal) = journal {
+      journal.update(|x| {
+        if let Some(prepared) = &mut x.prepared {
+          prepared.uploaded = true;
+        }
+      })?;
+    }
+
+    log::info!("committing deployment");
+    let pb = progress::spinner("Committing deployment");
+    let rsp = self
+      .create_deployment(app_id, &uploaded_package, package.sha256(), metadata)
+      .await?;
+    pb.finish_and_clear();
+    // The service checks the package against the hash we sent and echoes its own. It only
+    // does so once the deployment is created, so a deployment of a package corrupted on the
+    // way is deleted again before it can be promoted.
+    match &rsp.package_sha256 {
+      Some(x) if !x.eq_ignore_ascii_case(package.sha256()) => {
+        let deleted = match self.delete_deployment(&rsp.id).await {
+          Ok(_) => true,
+          Err(e) => {
+            log::warn!("cannot delete deployment {}: {:#}", rsp.id, e);
+            false
+          }
+        };
+        return Err(
+          UploadError::ChecksumMismatch {
+            expected: package.sha256().to_string(),
+            actual: x.clone(),
+            deployment: rsp.id,
+            deleted,
+          }
+          .into(),
+        );
+      }
+      Some(_) => log::info!("service verified package sha256 {}", package.sha256()),
+      None => log::info!("service did not report the sha256 of the uploaded package"),
+    }
+
+    Ok(DeployResult {
+      id: rsp.id,
+      app_id: rsp.app_id,
+      url: rsp.url,
+      created_at: rsp.created_at,
+      package_sha256: package.sha256().to_string(),
+      package_size: package.len(),
+      live: false,
+      started_at: unix_time(started_at),
+      finished_at: unix_time(SystemTime::now()),
+    })
+  }
+
+  /// Prepares the upload of `package`, as a diff, in parts or whole as the service offers, and
+  /// uploads it. Returns the package to create the deployment from.
+  #[tracing::instrument(skip_all, err)]
+  async fn prepare_and_upload(
+    &self,
+    app_id: &str,
+    package: &PackageFile,
+    journal: Option<&UploadJournal>,
+  ) -> anyhow::Result<String> {
+    let pb = progress::spinner("Preparing deployment");
+    let prep = self.prepare_deployment(app_id, package.sha256()).await?;
+    pb.finish_and_clear();
+    let diff = prep
+      .diff
+      .as_ref()
+      .and_then(|x| Some((x, diff_upload(package, x)?)));
+    let multipart = match (&diff, &prep.multipart) {
+      (None, Some(x)) => Some(MultipartState {
+        upload_id: x.upload_id.clone(),
+        part_size: x.part_size,
+        part_urls: x.part_urls.clone(),
+        etags: BTreeMap::new(),
+      }),
+      _ => None,
+    };
+    let uploaded_package = match &diff {
+      Some((diff, _)) => diff.package.clone(),
+      None => prep.package.clone(),
+    };
+    if let Some(journal) = journal {
+      journal.update(|x| {
+        x.prepared = Some(PreparedUpload {
+          package: uploaded_package.clone(),
+          multipart: multipart.clone(),
+          uploaded: false,
+        })
+      })?;
+    }
+    match (diff, multipart) {
+      (Some((diff, data)), _) => {
+        self
+          .upload(&diff.url, UploadData::file(&data), None)
+          .await?
+      }
+      (None, Some(multipart)) => {
+        self
+          .upload_multipart(app_id, multipart, package, journal)
+          .await?
+      }
+      (None, None) => {
+        let checksum = match &prep.checksum_header {
+          Some(header) => Some((header.as_str(), base64_sha256(package)?)),
+          None => None,
+        };
+        self
+          .upload(&prep.url, UploadData::file(package), checksum)
+          .await?
+      }
+    }
+    Ok(uploaded_package)
+  }
+
+  /// Uploads `data` to a presigned S3 `url`, showing progress. `checksum` is a header to send
+  /// and its value, for a url that was signed with the checksum of `data`.
+  #[tracing::instrument(skip_all, fields(size = data.len()), err)]
+  async fn upload(
+    &self,
+    url: &str,
+    data: UploadData<'_>,
+    checksum: Option<(&str, String)>,
   ) -> anyhow::Result<()> {
-    let q = RunDeploymentPreparation::build_query(schema::run_deployment_preparation::Variables {
-      app_id: app_id.to_string(),
+    log::info!("uploading to s3: {}", url);
+    let pb = progress::bytes(data.len(), "Uploading");
+    let s3_rsp = self
+      .retry
+      .send("s3 upload", || {
+        pb.set_position(0);
+        let upload_pb = pb.clone();
+        let mut req = self.client.put(url).header("content-length", data.len());
+        if let Some((header, value)) = &checksum {
+          req = req.header(*header, value);
+        }
+        self.send(req.body(data.body(move |n| upload_pb.inc(n))).build())
+      })
+      .await;
+    let s3_rsp = match s3_rsp {
+      Ok(x) => x,
+      Err(e) => {
+        pb.abandon_with_message("Upload failed");
+        return Err(UploadError::Network(e).into());
+      }
+    };
+    let s3_status = s3_rsp.status();
+    if !s3_status.is_success() {
+      pb.abandon_with_message("Upload failed");
+      return Err(UploadError::Status(s3_status).into());
+    }
+    pb.finish_with_message("Uploaded");
+    Ok(())
+  }
+
+  /// Uploads `package` in the parts that the service asked for, several at a time, then
+  /// completes the upload. Each part is retried on its own, so that a failed request does not
+  /// restart the whole upload, and parts that already have an `ETag` are skipped. Each
+  /// uploaded part is recorded in `journal`.
+  #[tracing::instrument(skip_all, fields(upload_id = %multipart.upload_id, parts = multipart.part_urls.len()), err)]
+  async fn upload_multipart(
+    &self,
+    app_id: &str,
+    multipart: MultipartState,
+    package: &PackageFile,
+    journal: Option<&UploadJournal>,
+  ) -> anyhow::Result<()> {
+    if multipart.part_size == 0 {
+      anyhow::bail!("service provided a part size of 0 bytes");
+    }
+    // A single part is never larger than the package, even if the service allows it.
+    let part_size = multipart.part_size.min(package.len().max(1));
+    let parts = (0..package.len())
+      .step_by(part_size as usize)
+      .map(|offset| UploadData::File {
+        path: package.path(),
+        offset,
+        len: part_size.min(package.len() - offset),
+      })
+      .collect::<Vec<_>>();
+    if parts.len() != multipart.part_urls.len() {
+      anyhow::bail!(
+        "service provided {} part urls for {} parts",
+        multipart.part_urls.len(),
+        parts.len()
+      );
+    }
+    log::info!(
+      "uploading {} bytes in {} parts of {} bytes",
+      package.len(),
+      parts.len(),
+      part_size
+    );
+
+    let pb = progress::bytes(package.len(), "Uploading");
+    let part_count = parts.len();
+    let pending = parts
+      .into_iter()
+      .zip(&multipart.part_urls)
+      .zip(1i64..)
+      .filter(|((part, _), number)| {
+        let done = multipart.etags.contains_key(number);
+        if done {
+          pb.inc(part.len());
+        }
+        !done
+      })
+      .collect::<Vec<_>>();
+    let result = futures_util::stream::iter(pending)
+      .map(|((part, url), number)| {
+        let pb = &pb;
+        async move {
+          let etag = self
+            .upload_part(url, part, pb)
+            .await
+            .map_err(|e| e.context(format!("cannot upload part {} of {}", number, part_count)))?;
+          if let Some(journal) = journal {
+            journal.update(|x| {
+              if let Some(multipart) = x.prepared.as_mut().and_then(|x| x.multipart.as_mut()) {
+                multipart.etags.insert(number, etag.clone());
+              }
+            })?;
+          }
+          Ok::<_, anyhow::Error>((number, etag))
+        }
+      })
+      .buffer_unordered(MULTIPART_CONCURRENCY)
+      .try_collect::<Vec<_>>()
+      .await;
+    let mut etags = multipart.etags.clone();
+    match result {
+      Ok(x) => etags.extend(x),
+      Err(e) => {
+        pb.abandon_with_message("Upload failed");
+        return Err(e);
+      }
+    };
+    pb.finish_with_message("Uploaded");
+
+    let q = RunMultipartUploadCompletion::build_query(
+      schema::run_multipart_upload_completion::Variables {
+        app_id: app_id.to_string(),
+        upload_id: multipart.upload_id.clone(),
+        parts: etags
+          .into_iter()
+          .map(
+            |(part_number, etag)| schema::run_multipart_upload_completion::UploadPartInput {
+              part_number,
+              etag,
+            },
+          )
+          .collect(),
+      },
+    );
+    let rsp = self
+      .call::<_, schema::run_multipart_upload_completion::ResponseData>(q)
+      .await?
+      .check_service_error()?;
+    if !rsp
+      .data
+      .map(|x| x.complete_multipart_upload)
+      .unwrap_or(false)
+    {
+      anyhow::bail!("service did not complete multipart upload");
+    }
+    Ok(())
+  }
+
+  /// Uploads one part of a multipart upload and returns its `ETag`. Progress of a failed
+  /// attempt is taken back from `pb` before retrying.
+  async fn upload_part(
+    &self,
+    url: &str,
+    data: UploadData<'_>,
+    pb: &ProgressBar,
+  ) -> anyhow::Result<String> {
+    let sent = Arc::new(AtomicU64::new(0));
+    let rsp = self
+      .retry
+      .send("part upload", || {
+        pb.set_position(
+          pb.position()
+            .saturating_sub(sent.swap(0, Ordering::Relaxed)),
+        );
+        let body = {
+          let pb = pb.clone();
+          let sent = sent.clone();
+          data.body(move |n| {
+            pb.inc(n);
+            sent.fetch_add(n, Ordering::Relaxed);
+          })
+        };
+        self.send(
+          self
+            .client
+            .put(url)
+            .header("content-length", data.len())
+            .body(body)
+            .build(),
+        )
+      })
+      .await
+      .map_err(UploadError::Network)?;
+    let status = rsp.status();
+    if !status.is_success() {
+      return Err(UploadError::Status(status).into());
+    }
+    rsp
+      .headers()
+      .get("etag")
+      .and_then(|x| x.to_str().ok())
+      .map(|x| x.to_string())
+      .ok_or_else(|| anyhow::anyhow!("missing etag in response"))
+  }
+
+  /// Uploads an archive of the source maps of a deployment, which the service keeps apart
+  /// from the package to symbolicate stack traces in its logs.
+  #[tracing::instrument(skip(self, archive), err)]
+  pub async fn upload_sourcemaps(&self, deployment_id: &str, archive: &[u8]) -> anyhow::Result<()> {
+    let prep = self
+      .fetch::<RunSourceMapUploadPreparation>(
+        schema::run_source_map_upload_preparation::Variables {
+          deployment_id: deployment_id.to_string(),
+        },
+      )
+      .await?
+      .prepare_source_map_upload;
+    self
+      .upload(&prep.url, UploadData::Bytes(archive), None)
+      .await
+  }
+
+  /// Pages of a paginated query, fetched one at a time as the stream is polled, so that
+  /// reading stops fetching. `fetch` gets the cursor of the page to fetch, `None` for the first
+  /// one, and the stream ends after a page without a next cursor.
+  pub fn paginate<T, C, F, Fut>(fetch: F) -> impl Stream<Item = anyhow::Result<Vec<T>>>
+  where
+    F: FnMut(Option<C>) -> Fut,
+    Fut: Future<Output = anyhow::Result<Page<T, C>>>,
+  {
+    futures_util::stream::try_unfold(
+      (ServiceCursor::Initial, fetch),
+      |(cursor, mut fetch)| async move {
+        let cursor = match cursor {
+          ServiceCursor::Initial => None,
+          ServiceCursor::Next(x) => Some(x),
+          ServiceCursor::End => return Ok(None),
+        };
+        let page = fetch(cursor).await?;
+        let next = match page.next {
+          Some(x) => ServiceCursor::Next(x),
+          None => ServiceCursor::End,
+        };
+        Ok(Some((page.items, (next, fetch))))
+      },
+    )
+  }
+
+  pub async fn app_status(
+    &self,
+    app_id: &str,
+  ) -> anyhow::Result<schema::get_app_status::GetAppStatusApp> {
+    let q = GetAppStatus::build_query(schema::get_app_status::Variables {
+      id: app_id.to_string(),
     });
     let rsp = self
-      .call::<_, schema::run_deployment_preparation::ResponseData>(q)
+      .call::<_, schema::get_app_status::ResponseData>(q)
       .await?
       .check_service_error()?;
-    let prep = rsp
+    rsp
       .data
-      .as_ref()
-      .map(|x| &x.prepare_deployment)
-      .ok_or_else(|| anyhow::anyhow!("missing data in prep"))?;
-    log::info!("uploading to s3: {}", prep.url);
-    let s3_rsp = self
-      .client
-      .put(prep.url.as_str())
-      .body(package.to_vec())
-      .send()
-      .await?;
-    let s3_status = s3_rsp.status();
-    if !s3_status.is_success() {
-      anyhow::bail!("s3 upload failed: {}", s3_status);
+      .and_then(|x| x.app)
+      .ok_or_else(|| anyhow::anyhow!("app {} not found", app_id))
+  }
+
+  pub async fn get_deployment(
+    &self,
+    deployment_id: &str,
+  ) -> anyhow::Result<schema::get_deployment::GetDeploymentDeployment> {
+    let q = GetDeployment::build_query(schema::get_deployment::Variables {
+      id: deployment_id.to_string(),
+    });
+    let rsp = self
+      .call::<_, schema::get_deployment::ResponseData>(q)
+      .await?
+      .check_service_error()?;
+    rsp
+      .data
+      .and_then(|x| x.deployment)
+      .ok_or_else(|| anyhow::anyhow!("deployment {} not found", deployment_id))
+  }
+
+  /// Polls a deployment until it is healthy (and live, if `require_live` is set), giving up
+  /// after `timeout`.
+  #[tracing::instrument(skip(self), err)]
+  pub async fn wait_for_deployment(
+    &self,
+    deployment_id: &str,
+    require_live: bool,
+    timeout: Duration,
+  ) -> anyhow::Result<schema::get_deployment::GetDeploymentDeployment> {
+    let deadline = Instant::now() + timeout;
+    let pb = progress::spinner(&format!("Waiting for deployment {}", deployment_id));
+    let mut interval = WAIT_MIN_INTERVAL;
+    loop {
+      let deployment = self.get_deployment(deployment_id).await?;
+      if deployment.healthy && (deployment.live || !require_live) {
+        pb.finish_and_clear();
+        return Ok(deployment);
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        pb.abandon_with_message("Timed out");
+        anyhow::bail!(
+          "deployment {} did not become {} within {} seconds",
+          deployment_id,
+          if require_live { "live" } else { "healthy" },
+          timeout.as_secs()
+        );
+      }
+      tokio::time::sleep(interval.min(deadline - now)).await;
+      interval = (interval * 2).min(WAIT_MAX_INTERVAL);
     }
-    let metadata = serde_json::to_string(metadata)?;
-    log::info!("committing deployment");
-    let q = RunDeploymentCreation::build_query(schema::run_deployment_creation::Variables {
-      app_id: app_id.to_string(),
-      metadata,
-      package: prep.package.clone(),
+  }
+
+  pub async fn deployment_history(
+    &self,
+    deployment_id: &str,
+  ) -> anyhow::Result<schema::get_deployment_history::GetDeploymentHistoryDeployment> {
+    let q = GetDeploymentHistory::build_query(schema::get_deployment_history::Variables {
+      id: deployment_id.to_string(),
     });
     let rsp = self
-      .call::<_, schema::run_deployment_creation::ResponseData>(q)
+      .call::<_, schema::get_deployment_history::ResponseData>(q)
       .await?
       .check_service_error()?;
-    let rsp = rsp
+    rsp
       .data
-      .as_ref()
-      .map(|x| &x.create_deployment)
-      .ok_or_else(|| anyhow::anyhow!("missing data in result"))?;
+      .and_then(|x| x.deployment)
+      .ok_or_else(|| anyhow::anyhow!("deployment {} not found", deployment_id))
+  }
 
-    {
-      let mut stdout = StandardStream::stdout(ColorChoice::Auto);
-      stdout.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Cyan)))?;
-      writeln!(&mut stdout, "Created deployment {}.", rsp.id)?;
-      stdout.reset()?;
+  pub async fn delete_deployment(
+    &self,
+    deployment_id: &str,
+  ) -> anyhow::Result<schema::run_deployment_deletion::RunDeploymentDeletionDeleteDeployment> {
+    let q = RunDeploymentDeletion::build_query(schema::run_deployment_deletion::Variables {
+      id: deployment_id.to_string(),
+    });
+    let rsp = self
+      .call::<_, schema::run_deployment_deletion::ResponseData>(q)
+      .await?
+      .check_service_error()?;
+    rsp
+      .data
+      .and_then(|x| x.delete_deployment)
+      .ok_or_else(|| anyhow::anyhow!("deployment {} not found", deployment_id))
+  }
+
+  /// Deletes deployments concurrently and returns the result of each, in the order of
+  /// `deployment_ids`.
+  #[tracing::instrument(skip_all, fields(count = deployment_ids.len()))]
+  pub async fn delete_deployments(
+    &self,
+    deployment_ids: &[String],
+  ) -> Vec<anyhow::Result<schema::run_deployment_deletion::RunDeploymentDeletionDeleteDeployment>>
+  {
+    let queries = deployment_ids.iter().map(|id| {
+      RunDeploymentDeletion::build_query(schema::run_deployment_deletion::Variables {
+        id: id.clone(),
+      })
+    });
+    self
+      .call_many::<_, schema::run_deployment_deletion::ResponseData>(queries)
+      .await
+      .into_iter()
+      .zip(deployment_ids)
+      .map(|(rsp, id)| {
+        rsp?
+          .check_service_error()?
+          .data
+          .and_then(|x| x.delete_deployment)
+          .ok_or_else(|| anyhow::anyhow!("deployment {} not found", id))
+      })
+      .collect()
+  }
+
+  pub async fn list_apps(&self) -> anyhow::Result<Vec<schema::list_apps::ListAppsListApp>> {
+    let q = ListApps::build_query(schema::list_apps::Variables);
+    let rsp = self
+      .call::<_, schema::list_apps::ResponseData>(q)
+      .await?
+      .check_service_error()?;
+    Ok(rsp.data.map(|x| x.list_app).unwrap_or_default())
+  }
+
+  /// Fetches the detached secrets stored for an app.
+  pub async fn app_secrets(&self, app_id: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let q = GetAppSecrets::build_query(schema::get_app_secrets::Variables {
+      app_id: app_id.to_string(),
+    });
+    let rsp = self
+      .call::<_, schema::get_app_secrets::ResponseData>(q)
+      .await?
+      .check_service_error()?;
+    Ok(
+      rsp
+        .data
+        .map(|x| x.app_secrets)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| (x.key, x.value))
+        .collect(),
+    )
+  }
+
+  /// Replaces the detached secrets stored for an app.
+  pub async fn put_app_secrets(
+    &self,
+    app_id: &str,
+    secrets: &[(String, String)],
+  ) -> anyhow::Result<()> {
+    let q = RunAppSecretsUpdate::build_query(schema::run_app_secrets_update::Variables {
+      app_id: app_id.to_string(),
+      secrets: secrets
+        .iter()
+        .map(|(k, v)| schema::run_app_secrets_update::AppSecretInput {
+          key: k.clone(),
+          value: v.clone(),
+        })
+        .collect(),
+    });
+    let rsp = self
+      .call::<_, schema::run_app_secrets_update::ResponseData>(q)
+      .await?
+      .check_service_error()?;
+    if !rsp.data.map(|x| x.put_app_secrets).unwrap_or(false) {
+      anyhow::bail!("service did not store secrets");
     }
-    println!("Preview: {}", rsp.url);
-    println!("Visit the dashboard to promote this deployment to live.");
     Ok(())
   }
+
+  pub async fn viewer(&self) -> anyhow::Result<schema::get_viewer::GetViewerViewer> {
+    Ok(
+      self
+        .fetch::<GetViewer>(schema::get_viewer::Variables)
+        .await?
+        .viewer,
+    )
+  }
+
+  #[tracing::instrument(skip(self), err)]
+  pub async fn promote(
+    &self,
+    deployment_id: &str,
+  ) -> anyhow::Result<schema::run_deployment_promotion::RunDeploymentPromotionPromoteDeployment> {
+    let rsp = self
+      .fetch::<RunDeploymentPromotion>(schema::run_deployment_promotion::Variables {
+        id: deployment_id.to_string(),
+      })
+      .await?
+      .promote_deployment;
+    if !rsp.live {
+      anyhow::bail!("deployment {} is not live after promotion", rsp.id);
+    }
+    Ok(rsp)
+  }
+}
+
+/// The service could not be reached, as opposed to reached and reporting an error.
+#[derive(Error, Debug, Diagnostic)]
+#[error("cannot reach the service at {endpoint}")]
+#[diagnostic(
+  code(boatctl::service::network),
+  help("check --endpoint / BOAT_ENDPOINT, your network connection and --proxy")
+)]
+pub struct NetworkError {
+  pub endpoint: String,
+  #[source]
+  pub source: TransportError,
+}
+
+/// An upload of a package or source maps to storage failed.
+#[derive(Error, Debug, Diagnostic)]
+pub enum UploadError {
+  #[error("upload failed: storage returned error status: {0}")]
+  #[diagnostic(
+    code(boatctl::upload::status),
+    help("the upload url may have expired, or storage may be busy; run the command again")
+  )]
+  Status(StatusCode),
+  #[error("upload failed")]
+  #[diagnostic(
+    code(boatctl::upload::network),
+    help("check your network connection and --proxy, or retry more with --retry-attempts")
+  )]
+  Network(#[source] TransportError),
+  #[error(
+    "upload failed: service stored a package with sha256 {actual}, expected {expected}; deployment {deployment} {}",
+    if *.deleted { "was deleted" } else { "could not be deleted" }
+  )]
+  #[diagnostic(
+    code(boatctl::upload::checksum),
+    help("the package was corrupted on the way to storage; run the command again")
+  )]
+  ChecksumMismatch {
+    expected: String,
+    actual: String,
+    deployment: String,
+    deleted: bool,
+  },
+}
+
+/// Error of a request by hash for a query that the service has not persisted yet.
+const PERSISTED_QUERY_NOT_FOUND: &str = "PERSISTED_QUERY_NOT_FOUND";
+
+/// Error of a request by hash to a service that does not support persisted queries.
+const PERSISTED_QUERY_NOT_SUPPORTED: &str = "PERSISTED_QUERY_NOT_SUPPORTED";
+
+/// Whether `query` runs a mutation. The query text is the whole document its operation was
+/// generated from, so the definition of the operation is looked up by name.
+fn is_mutation<V>(query: &QueryBody<V>) -> bool {
+  let words = query
+    .query
+    .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+    .filter(|x| !x.is_empty())
+    .collect::<Vec<_>>();
+  words
+    .windows(2)
+    .any(|x| x[0] == "mutation" && x[1] == query.operation_name)
+}
+
+/// The persisted query error of a response to a request by hash, if it failed with one. The
+/// error is recognized by its code or, from older servers, by its message.
+fn persisted_query_error<D>(rsp: &graphql_client::Response<D>) -> Option<&'static str> {
+  rsp.errors.iter().flatten().find_map(|e| {
+    let code = e
+      .extensions
+      .as_ref()
+      .and_then(|x| x.get("code"))
+      .and_then(|x| x.as_str());
+    [
+      (PERSISTED_QUERY_NOT_FOUND, "PersistedQueryNotFound"),
+      (PERSISTED_QUERY_NOT_SUPPORTED, "PersistedQueryNotSupported"),
+    ]
+    .into_iter()
+    .find(|(x, message)| code == Some(*x) || e.message == *message)
+    .map(|(x, _)| x)
+  })
+}
+
+/// Codes in the `extensions` of GraphQL errors that mean our credentials were rejected.
+const UNAUTHORIZED_CODES: &[&str] = &["UNAUTHENTICATED", "UNAUTHORIZED", "FORBIDDEN"];
+
+/// An error reported by the service, as opposed to a failure to reach it.
+#[derive(Error, Debug)]
+pub enum ServiceError {
+  #[error("api call returned error status: {0}")]
+  Status(StatusCode),
+  /// Every error of a GraphQL response, of which there is at least one.
+  #[error("service returned error: {}{}", .0[0], more_errors(.0.len()))]
+  Response(Vec<GraphqlError>),
+  /// A response that does not have the shape of the schema this CLI was built with.
+  #[error("cannot decode api response")]
+  Decode(#[source] serde_json::Error),
+}
+
+impl ServiceError {
+  /// Whether the service rejected our credentials.
+  pub fn is_unauthorized(&self) -> bool {
+    match self {
+      ServiceError::Status(x) => *x == StatusCode::UNAUTHORIZED || *x == StatusCode::FORBIDDEN,
+      ServiceError::Response(errors) => errors
+        .iter()
+        .filter_map(|x| x.code())
+        .any(|x| UNAUTHORIZED_CODES.contains(&x)),
+      ServiceError::Decode(_) => false,
+    }
+  }
+
+  /// Machine-readable code of the error: the `code` extension of the first GraphQL error that
+  /// has one.
+  pub fn code(&self) -> Option<&str> {
+    match self {
+      ServiceError::Status(_) | ServiceError::Decode(_) => None,
+      ServiceError::Response(errors) => errors.iter().find_map(|x| x.code()),
+    }
+  }
+}
+
+impl Diagnostic for ServiceError {
+  fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+    match self {
+      ServiceError::Status(_) => Some(Box::new("boatctl::service::status")),
+      ServiceError::Response(errors) => Diagnostic::code(&errors[0]),
+      ServiceError::Decode(_) => Some(Box::new("boatctl::service::decode")),
+    }
+  }
+
+  fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+    match self {
+      ServiceError::Status(_) if self.is_unauthorized() => Some(Box::new(
+        "check BOAT_ACCESS_KEY and BOAT_SECRET_KEY or --credentials, and that the key has access \
+         to the app",
+      )),
+      ServiceError::Status(x) if x.is_server_error() => {
+        Some(Box::new("the service is having trouble; try again later"))
+      }
+      ServiceError::Status(_) => Some(Box::new("check --endpoint / BOAT_ENDPOINT")),
+      ServiceError::Response(errors) => errors[0].help(),
+      ServiceError::Decode(_) => Some(Box::new(
+        "the service may have changed its API; check for a newer release of boat",
+      )),
+    }
+  }
+
+  fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+    match self {
+      ServiceError::Response(errors) if errors.len() > 1 => {
+        Some(Box::new(errors[1..].iter().map(|x| x as &dyn Diagnostic)))
+      }
+      _ => None,
+    }
+  }
+}
+
+fn more_errors(count: usize) -> String {
+  match count {
+    0 | 1 => String::new(),
+    2 => " (and 1 more error)".to_string(),
+    n => format!(" (and {} more errors)", n - 1),
+  }
+}
+
+/// One error of a GraphQL response.
+#[derive(Error, Debug, Clone, Serialize)]
+#[error("{message}{}", .path.as_ref().map(|x| format!(" (at {})", x)).unwrap_or_default())]
+pub struct GraphqlError {
+  pub message: String,
+  /// Path of the field that failed, e.g. `deployment/labels/0`.
+  pub path: Option<String>,
+  /// What the service added to the error, such as an error `code` and a request id.
+  pub extensions: HashMap<String, serde_json::Value>,
+}
+
+impl GraphqlError {
+  /// The `code` extension, e.g. `NOT_FOUND`.
+  pub fn code(&self) -> Option<&str> {
+    self.extensions.get("code").and_then(|x| x.as_str())
+  }
+
+  /// The request id extension, to quote when reporting the error.
+  pub fn request_id(&self) -> Option<&str> {
+    ["requestId", "request_id"]
+      .iter()
+      .find_map(|k| self.extensions.get(*k))
+      .and_then(|x| x.as_str())
+  }
+}
+
+impl From<&graphql_client::Error> for GraphqlError {
+  fn from(e: &graphql_client::Error) -> Self {
+    GraphqlError {
+      message: e.message.clone(),
+      path: e.path.as_ref().map(|x| {
+        x.iter()
+          .map(|x| x.to_string())
+          .collect::<Vec<_>>()
+          .join("/")
+      }),
+      extensions: e.extensions.clone().unwrap_or_default(),
+    }
+  }
+}
+
+impl Diagnostic for GraphqlError {
+  fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+    GraphqlError::code(self).map(|x| Box::new(x) as Box<dyn std::fmt::Display>)
+  }
+
+  fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+    self
+      .request_id()
+      .map(|x| Box::new(format!("request id: {}", x)) as Box<dyn std::fmt::Display>)
+  }
+}
+
+/// Base64 SHA-256 of `package`, as storage expects it in checksum headers.
+fn base64_sha256(package: &PackageFile) -> anyhow::Result<String> {
+  let digest = HEXLOWER.decode(package.sha256().as_bytes())?;
+  Ok(BASE64.encode(&digest))
+}
+
+/// The diff of `package` against the base deployment that the service offered, if the diff is
+/// smaller than the package. Any problem with the diff falls back to uploading the whole
+/// package.
+fn diff_upload(package: &PackageFile, diff: &DiffUpload) -> Option<PackageFile> {
+  let base = match serde_json::from_str::<PackageManifest>(&diff.base_manifest) {
+    Ok(x) => x,
+    Err(e) => {
+      log::warn!(
+        "cannot read the manifest of deployment {}: {}",
+        diff.base_deployment_id,
+        e
+      );
+      return None;
+    }
+  };
+  match build_package_diff(package, &base, &diff.base_deployment_id) {
+    Ok(data) if data.len() < package.len() => {
+      log::info!(
+        "uploading diff against deployment {} ({} of {} bytes)",
+        diff.base_deployment_id,
+        data.len(),
+        package.len()
+      );
+      Some(data)
+    }
+    Ok(_) => None,
+    Err(e) => {
+      log::warn!("cannot build package diff: {:#}", e);
+      None
+    }
+  }
+}
+
+/// What `Service::upload` sends: bytes in memory, or a range of a file that is read as it is
+/// sent.
+#[derive(Clone, Copy)]
+enum UploadData<'a> {
+  Bytes(&'a [u8]),
+  File {
+    path: &'a Path,
+    offset: u64,
+    len: u64,
+  },
+}
+
+impl<'a> UploadData<'a> {
+  fn file(package: &'a PackageFile) -> Self {
+    UploadData::File {
+      path: package.path(),
+      offset: 0,
+      len: package.len(),
+    }
+  }
+
+  fn len(&self) -> u64 {
+    match self {
+      UploadData::Bytes(data) => data.len() as u64,
+      UploadData::File { len, .. } => *len,
+    }
+  }
+
+  /// A request body that streams the data in chunks, calling `on_chunk` with the size of each
+  /// chunk as it is sent. Only one chunk of a file is in memory at a time.
+  fn body(&self, on_chunk: impl Fn(u64) + Send + Sync + 'static) -> Body {
+    match *self {
+      UploadData::Bytes(data) => {
+        let chunks = data
+          .chunks(UPLOAD_CHUNK_SIZE)
+          .map(|x| x.to_vec())
+          .collect::<Vec<_>>();
+        Body::wrap_stream(futures_util::stream::iter(chunks.into_iter().map(
+          move |chunk| {
+            on_chunk(chunk.len() as u64);
+            Ok::<_, std::io::Error>(chunk)
+          },
+        )))
+      }
+      UploadData::File { path, offset, len } => {
+        let path: Arc<Path> = Arc::from(path);
+        Body::wrap_stream(futures_util::stream::try_unfold(
+          (None::<tokio::fs::File>, len),
+          move |(file, remaining)| {
+            let path = path.clone();
+            let n = remaining.min(UPLOAD_CHUNK_SIZE as u64);
+            if n != 0 {
+              on_chunk(n);
+            }
+            async move {
+              if n == 0 {
+                return Ok(None);
+              }
+              let mut file = match file {
+                Some(x) => x,
+                None => {
+                  let mut file = tokio::fs::File::open(&path).await?;
+                  file.seek(SeekFrom::Start(offset)).await?;
+                  file
+                }
+              };
+              let mut chunk = vec![0; n as usize];
+              file.read_exact(&mut chunk).await?;
+              Ok::<_, std::io::Error>(Some((chunk, (Some(file), remaining - n))))
+            }
+          },
+        ))
+      }
+    }
+  }
+}
+
+fn unix_time(t: SystemTime) -> u64 {
+  t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
 }
 
 pub trait GqlResponseExt: Sized {
@@ -130,10 +1430,315 @@ pub trait GqlResponseExt: Sized {
 
 impl<D> GqlResponseExt for graphql_client::Response<D> {
   fn check_service_error(self) -> anyhow::Result<Self> {
-    let errors = self.errors.as_ref().map(|x| x.as_slice()).unwrap_or(&[]);
+    let errors = self.errors.as_deref().unwrap_or(&[]);
     if !errors.is_empty() {
-      anyhow::bail!("service returned error: {}", errors[0].message);
+      return Err(ServiceError::Response(errors.iter().map(GraphqlError::from).collect()).into());
     }
     Ok(self)
   }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::transport::MockTransport;
+
+  pub(crate) const ENDPOINT: &str = "https://lighthouse.example.com/graphql";
+
+  /// A `Service` without credentials that sends everything to `mock`.
+  pub(crate) fn service(mock: &MockTransport) -> Service {
+    let connection = ConnectionOptions {
+      timeout: Duration::from_secs(10),
+      connect_timeout: Duration::from_secs(10),
+      proxy: None,
+      no_proxy: true,
+      headers: HeaderMap::new(),
+      debug_http: false,
+      insecure_http: false,
+      tls: TlsOptions::default(),
+    };
+    Service::with_credentials(ENDPOINT, None, &connection)
+      .unwrap()
+      .with_transport(mock.clone())
+  }
+
+  fn body(request: &crate::transport::RecordedRequest) -> serde_json::Value {
+    serde_json::from_slice(request.body.as_ref().unwrap()).unwrap()
+  }
+
+  async fn viewer(service: &Service) -> graphql_client::Response<schema::get_viewer::ResponseData> {
+    let query = GetViewer::build_query(schema::get_viewer::Variables);
+    service.call(query).await.unwrap()
+  }
+
+  #[tokio::test]
+  async fn calls_through_the_transport() {
+    let mock = MockTransport::new();
+    mock.on_graphql(
+      "GetViewer",
+      json!({ "data": { "viewer": { "accountId": "a1", "accountName": "acme", "organization": null } } }),
+    );
+    let service = service(&mock).with_persisted_queries(false);
+    let rsp = viewer(&service).await;
+    assert_eq!(rsp.data.unwrap().viewer.account_name, "acme");
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, Method::POST);
+    assert_eq!(requests[0].url.as_str(), ENDPOINT);
+    assert_eq!(requests[0].headers["content-type"], "application/json");
+    assert_eq!(requests[0].headers["user-agent"], user_agent().as_str());
+    assert_eq!(
+      requests[0].graphql_operation().as_deref(),
+      Some("GetViewer")
+    );
+    assert!(body(&requests[0])["query"].is_string());
+  }
+
+  #[test]
+  fn recognizes_mutations() {
+    let query = GetViewer::build_query(schema::get_viewer::Variables);
+    assert!(!is_mutation(&query));
+    let query = RunDeploymentDeletion::build_query(schema::run_deployment_deletion::Variables {
+      id: "d1".into(),
+    });
+    assert!(is_mutation(&query));
+  }
+
+  #[tokio::test]
+  async fn sends_unknown_persisted_query_in_full() {
+    let mock = MockTransport::new();
+    mock.on_graphql(
+      "GetViewer",
+      json!({ "errors": [{ "message": "PersistedQueryNotFound" }] }),
+    );
+    let service = service(&mock);
+    viewer(&service).await;
+    viewer(&service).await;
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 4);
+    let by_hash = body(&requests[0]);
+    assert!(by_hash.get("query").is_none());
+    assert!(by_hash["extensions"]["persistedQuery"]["sha256Hash"].is_string());
+    let full = body(&requests[1]);
+    assert!(full["query"].is_string());
+    assert_eq!(full["extensions"], by_hash["extensions"]);
+    // Still supported, so the next call tries the hash first again.
+    assert!(body(&requests[2]).get("query").is_none());
+  }
+
+  #[tokio::test]
+  async fn stops_persisted_queries_when_not_supported() {
+    let mock = MockTransport::new();
+    mock.on_graphql(
+      "GetViewer",
+      json!({ "errors": [{ "message": "no", "extensions": { "code": "PERSISTED_QUERY_NOT_SUPPORTED" } }] }),
+    );
+    let service = service(&mock);
+    viewer(&service).await;
+    viewer(&service).await;
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(body(&requests[0]).get("query").is_none());
+    for request in &requests[1..] {
+      let body = body(request);
+      assert!(body["query"].is_string());
+      assert!(body.get("extensions").is_none());
+    }
+  }
+
+  #[tokio::test]
+  async fn stops_persisted_queries_on_bad_request() {
+    let mock = MockTransport::new();
+    mock.on(Method::POST, ENDPOINT, StatusCode::BAD_REQUEST, &[], "");
+    let service = service(&mock);
+    let query = GetViewer::build_query(schema::get_viewer::Variables);
+    let result = service
+      .call::<_, schema::get_viewer::ResponseData>(query)
+      .await;
+    assert!(result.is_err());
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(body(&requests[0]).get("query").is_none());
+    assert!(body(&requests[1])["query"].is_string());
+    assert!(!service.persisted_queries.load(Ordering::Relaxed));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn retries_queries_but_not_mutations() {
+    let mock = MockTransport::new();
+    mock.on(
+      Method::POST,
+      ENDPOINT,
+      StatusCode::SERVICE_UNAVAILABLE,
+      &[],
+      "",
+    );
+    let service = service(&mock).with_persisted_queries(false);
+    let query = GetViewer::build_query(schema::get_viewer::Variables);
+    let result = service
+      .call::<_, schema::get_viewer::ResponseData>(query)
+      .await;
+    assert!(result.is_err());
+    assert_eq!(
+      mock.requests().len(),
+      RetryPolicy::default().attempts as usize
+    );
+
+    assert!(service.delete_deployment("d1").await.is_err());
+    assert_eq!(
+      mock.requests().len(),
+      RetryPolicy::default().attempts as usize + 1
+    );
+  }
+
+  #[tokio::test]
+  async fn reports_unsupported_client_only_if_the_call_fails() {
+    let response = json!({
+      "data": { "viewer": { "accountId": "a1", "accountName": "acme", "organization": null } },
+      "extensions": { "clientVersion": { "minimum": "999.0.0" } },
+    });
+    let mock = MockTransport::new();
+    mock.on_graphql("GetViewer", response.clone());
+    let service = service(&mock);
+    let rsp = viewer(&service).await;
+    assert_eq!(rsp.data.unwrap().viewer.account_id, "a1");
+
+    mock.on(
+      Method::POST,
+      ENDPOINT,
+      StatusCode::BAD_REQUEST,
+      &[],
+      response.to_string(),
+    );
+    let query = GetViewer::build_query(schema::get_viewer::Variables);
+    let e = service
+      .with_persisted_queries(false)
+      .call::<_, schema::get_viewer::ResponseData>(query)
+      .await
+      .err()
+      .unwrap();
+    assert!(e.downcast_ref::<ClientTooOld>().is_some());
+  }
+
+  #[tokio::test]
+  async fn paginates_until_a_page_without_next_cursor() {
+    let fetched = Mutex::new(vec![]);
+    let pages = Service::paginate(|cursor: Option<u32>| {
+      fetched.lock().unwrap().push(cursor);
+      async move {
+        let start = cursor.unwrap_or(0);
+        Ok(Page {
+          items: vec![start, start + 1],
+          next: (start < 4).then(|| start + 2),
+        })
+      }
+    });
+    let items = pages.try_concat().await.unwrap();
+    assert_eq!(items, [0, 1, 2, 3, 4, 5]);
+    assert_eq!(*fetched.lock().unwrap(), [None, Some(2), Some(4)]);
+  }
+
+  #[tokio::test]
+  async fn paginates_lazily() {
+    let fetched = AtomicU64::new(0);
+    let pages = Service::paginate(|cursor: Option<u32>| {
+      fetched.fetch_add(1, Ordering::Relaxed);
+      async move {
+        Ok(Page {
+          items: vec![cursor.unwrap_or(0)],
+          next: Some(cursor.unwrap_or(0) + 1),
+        })
+      }
+    });
+    futures_util::pin_mut!(pages);
+    assert_eq!(pages.next().await.unwrap().unwrap(), [0]);
+    assert_eq!(fetched.load(Ordering::Relaxed), 1);
+  }
+
+  #[tokio::test]
+  async fn rejects_empty_parts() {
+    let mock = MockTransport::new();
+    let service = service(&mock);
+    let (package, ()) = PackageFile::write(|out| Ok(out.write_all(b"package")?)).unwrap();
+    let multipart = MultipartState {
+      upload_id: "u1".to_string(),
+      part_size: 0,
+      part_urls: vec!["https://uploads.example.com/1".to_string()],
+      etags: BTreeMap::new(),
+    };
+    let e = service
+      .upload_multipart("app", multipart, &package, None)
+      .await
+      .unwrap_err();
+    assert!(e.to_string().contains("part size of 0"), "{}", e);
+    assert!(mock.requests().is_empty());
+  }
+
+  #[tokio::test]
+  async fn deletes_deployments_of_corrupted_packages() {
+    let mock = MockTransport::new();
+    let upload_url = "https://uploads.example.com/p1";
+    mock.on_graphql(
+      "RunDeploymentPreparation",
+      json!({ "data": { "prepareDeployment": {
+        "package": "p1", "url": upload_url, "checksumHeader": null, "diff": null, "multipart": null
+      } } }),
+    );
+    mock.on(Method::PUT, upload_url, StatusCode::OK, &[], "");
+    mock.on_graphql(
+      "RunDeploymentCreation",
+      json!({ "data": { "createDeployment": {
+        "id": "d1", "appId": "app", "createdAt": "2022-01-01T00:00:00Z",
+        "url": "https://app.example.com", "packageSha256": "00"
+      } } }),
+    );
+    mock.on_graphql(
+      "RunDeploymentDeletion",
+      json!({ "data": { "deleteDeployment": {
+        "id": "d1", "createdAt": "2022-01-01T00:00:00Z", "live": false
+      } } }),
+    );
+    let service = service(&mock).with_persisted_queries(false);
+    let (package, ()) = PackageFile::write(|out| Ok(out.write_all(b"package")?)).unwrap();
+    let metadata = AppMetadata {
+      env: HashMap::new(),
+      secrets: HashMap::new(),
+      mysql: HashMap::new(),
+      pubsub: HashMap::new(),
+      provenance: None,
+      annotations: Default::default(),
+    };
+    let e = service
+      .deploy("app", &metadata, &package, None)
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(
+        e.downcast_ref::<UploadError>(),
+        Some(UploadError::ChecksumMismatch { deleted: true, .. })
+      ),
+      "{:#}",
+      e
+    );
+    assert!(e.to_string().contains("deployment d1 was deleted"), "{}", e);
+    let operations = mock
+      .requests()
+      .iter()
+      .filter_map(|x| x.graphql_operation())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      operations,
+      [
+        "RunDeploymentPreparation",
+        "RunDeploymentCreation",
+        "RunDeploymentDeletion"
+      ]
+    );
+  }
+}
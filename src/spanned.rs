@@ -0,0 +1,110 @@
+//! A value with its position in the source file, for configs in any format.
+//!
+//! `toml::Spanned` only deserializes from TOML. This wrapper takes the span from the TOML
+//! deserializer and otherwise just the value, with an empty span at the start of the file, so
+//! that JSON and YAML configs can be parsed directly into the same types.
+
+use std::{
+  borrow::Borrow,
+  cell::Cell,
+  cmp::Ordering,
+  hash::{Hash, Hasher},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+thread_local! {
+  /// Whether the deserializer in use is the TOML one, which produces spans.
+  static TOML: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Runs `f`, which deserializes from a format other than TOML.
+pub(crate) fn without_spans<R>(f: impl FnOnce() -> R) -> R {
+  let prev = TOML.with(|x| x.replace(false));
+  let result = f();
+  TOML.with(|x| x.set(prev));
+  result
+}
+
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+  start: usize,
+  end: usize,
+  value: T,
+}
+
+impl<T> Spanned<T> {
+  /// Byte offset of the start of the value, or 0 if it is not known.
+  pub fn start(&self) -> usize {
+    self.start
+  }
+
+  /// Byte offset of the end of the value, or 0 if it is not known.
+  pub fn end(&self) -> usize {
+    self.end
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value
+  }
+
+  pub fn get_ref(&self) -> &T {
+    &self.value
+  }
+}
+
+impl Borrow<str> for Spanned<String> {
+  fn borrow(&self) -> &str {
+    &self.value
+  }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.value.eq(&other.value)
+  }
+}
+
+impl<T: Eq> Eq for Spanned<T> {}
+
+impl<T: Hash> Hash for Spanned<T> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.value.hash(state);
+  }
+}
+
+impl<T: PartialOrd> PartialOrd for Spanned<T> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    self.value.partial_cmp(&other.value)
+  }
+}
+
+impl<T: Ord> Ord for Spanned<T> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.value.cmp(&other.value)
+  }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    if TOML.with(Cell::get) {
+      let x = toml::Spanned::<T>::deserialize(deserializer)?;
+      return Ok(Spanned {
+        start: x.start(),
+        end: x.end(),
+        value: x.into_inner(),
+      });
+    }
+    Ok(Spanned {
+      start: 0,
+      end: 0,
+      value: T::deserialize(deserializer)?,
+    })
+  }
+}
+
+impl<T: Serialize> Serialize for Spanned<T> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.value.serialize(serializer)
+  }
+}
@@ -0,0 +1,293 @@
+use std::{
+  collections::hash_map::RandomState,
+  fmt::Debug,
+  hash::{BuildHasher, Hasher},
+  sync::{Arc, Mutex},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use data_encoding::HEXLOWER;
+use reqwest::{
+  header::{HeaderMap, HeaderName, HeaderValue},
+  Url,
+};
+use tracing::{
+  field::{Field, Visit},
+  span, Event, Level, Metadata, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+/// OTLP/HTTP endpoint of a collector to export the spans of each command to, e.g.
+/// `http://otel-collector:4318`. Spans are sent as JSON to `/v1/traces` when the command ends.
+pub const OTEL_ENDPOINT_ENV: &str = "BOAT_OTEL_ENDPOINT";
+
+/// Extra headers of the export, e.g. for authentication, as the standard
+/// `key1=value1,key2=value2` list.
+const OTEL_HEADERS_ENV: &str = "OTEL_EXPORTER_OTLP_HEADERS";
+
+const OTEL_TRACES_PATH: &str = "/v1/traces";
+
+/// Longest time the export may add to a command.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prefix of the targets of the library and the binary.
+const TARGET_PREFIX: &str = "boat";
+
+/// `STATUS_CODE_ERROR` of OTLP.
+const STATUS_ERROR: u32 = 2;
+
+/// `SPAN_KIND_INTERNAL` of OTLP.
+const SPAN_KIND_INTERNAL: u32 = 1;
+
+/// Collects the spans of `tracing` instrumentation, which otherwise go nowhere, to export them
+/// once the command is done. Set up by `init` if `BOAT_OTEL_ENDPOINT` is set.
+pub struct Exporter {
+  endpoint: Url,
+  headers: HeaderMap,
+  spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+/// Installs the span collector if `BOAT_OTEL_ENDPOINT` is set. Problems with the setting are
+/// logged rather than failing the command.
+pub fn init() -> Option<Exporter> {
+  let endpoint = std::env::var(OTEL_ENDPOINT_ENV).ok()?;
+  let endpoint = match traces_url(&endpoint) {
+    Ok(x) => x,
+    Err(e) => {
+      log::warn!("ignoring {}: {:#}", OTEL_ENDPOINT_ENV, e);
+      return None;
+    }
+  };
+  let headers = std::env::var(OTEL_HEADERS_ENV)
+    .map(|x| parse_headers(&x))
+    .unwrap_or_default();
+  let spans = Arc::new(Mutex::new(vec![]));
+  let layer = SpanCollector {
+    spans: spans.clone(),
+  };
+  if tracing_subscriber::registry()
+    .with(layer)
+    .try_init()
+    .is_err()
+  {
+    log::warn!(
+      "ignoring {}: a tracing subscriber is already set",
+      OTEL_ENDPOINT_ENV
+    );
+    return None;
+  }
+  Some(Exporter {
+    endpoint,
+    headers,
+    spans,
+  })
+}
+
+impl Exporter {
+  /// Sends the spans of closed spans to the collector. A failed export is only logged.
+  pub async fn export(self) {
+    let spans = std::mem::take(&mut *self.spans.lock().unwrap());
+    if spans.is_empty() {
+      return;
+    }
+    let body = serde_json::json!({
+      "resourceSpans": [{
+        "resource": {
+          "attributes": attributes(&[
+            ("service.name".into(), "boat".into()),
+            ("service.version".into(), env!("CARGO_PKG_VERSION").into()),
+            ("os.type".into(), std::env::consts::OS.into()),
+          ]),
+        },
+        "scopeSpans": [{
+          "scope": { "name": "boatctl" },
+          "spans": spans.iter().map(SpanData::to_json).collect::<Vec<_>>(),
+        }],
+      }],
+    });
+    let result = reqwest::Client::new()
+      .post(self.endpoint.clone())
+      .headers(self.headers)
+      .json(&body)
+      .timeout(EXPORT_TIMEOUT)
+      .send()
+      .await
+      .and_then(|x| x.error_for_status());
+    match result {
+      Ok(_) => log::info!("exported {} spans to {}", spans.len(), self.endpoint),
+      Err(e) => log::warn!("cannot export spans to {}: {}", self.endpoint, e),
+    }
+  }
+}
+
+/// The url to post spans to: `endpoint` with `/v1/traces` appended, unless it is there already.
+fn traces_url(endpoint: &str) -> anyhow::Result<Url> {
+  let mut url = Url::parse(endpoint)?;
+  if !url.path().ends_with(OTEL_TRACES_PATH) {
+    let path = format!("{}{}", url.path().trim_end_matches('/'), OTEL_TRACES_PATH);
+    url.set_path(&path);
+  }
+  Ok(url)
+}
+
+fn parse_headers(list: &str) -> HeaderMap {
+  let mut headers = HeaderMap::new();
+  for entry in list.split(',').filter(|x| !x.trim().is_empty()) {
+    let parsed = entry.split_once('=').and_then(|(k, v)| {
+      Some((
+        HeaderName::from_bytes(k.trim().as_bytes()).ok()?,
+        HeaderValue::from_str(v.trim()).ok()?,
+      ))
+    });
+    match parsed {
+      Some((k, v)) => {
+        headers.insert(k, v);
+      }
+      None => log::warn!("ignoring malformed entry of {}", OTEL_HEADERS_ENV),
+    }
+  }
+  headers
+}
+
+fn attributes(pairs: &[(String, String)]) -> Vec<serde_json::Value> {
+  pairs
+    .iter()
+    .map(|(k, v)| serde_json::json!({ "key": k, "value": { "stringValue": v } }))
+    .collect()
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+  let mut id = [0u8; N];
+  for chunk in id.chunks_mut(8) {
+    let random = RandomState::new().build_hasher().finish().to_le_bytes();
+    chunk.copy_from_slice(&random[..chunk.len()]);
+  }
+  id
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+  time
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos()
+    .to_string()
+}
+
+struct SpanData {
+  trace_id: [u8; 16],
+  span_id: [u8; 8],
+  parent_span_id: Option<[u8; 8]>,
+  name: &'static str,
+  start: SystemTime,
+  end: SystemTime,
+  attributes: Vec<(String, String)>,
+  /// Message of the error the span failed with, from `#[instrument(err)]`.
+  error: Option<String>,
+}
+
+impl SpanData {
+  fn to_json(&self) -> serde_json::Value {
+    let mut span = serde_json::json!({
+      "traceId": HEXLOWER.encode(&self.trace_id),
+      "spanId": HEXLOWER.encode(&self.span_id),
+      "name": self.name,
+      "kind": SPAN_KIND_INTERNAL,
+      "startTimeUnixNano": unix_nanos(self.start),
+      "endTimeUnixNano": unix_nanos(self.end),
+      "attributes": attributes(&self.attributes),
+    });
+    if let Some(parent) = &self.parent_span_id {
+      span["parentSpanId"] = HEXLOWER.encode(parent).into();
+    }
+    if let Some(message) = &self.error {
+      span["status"] = serde_json::json!({ "code": STATUS_ERROR, "message": message });
+    }
+    span
+  }
+}
+
+struct SpanCollector {
+  spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanCollector {
+  /// Only spans and events of boat itself, not those of the HTTP stack below it.
+  fn enabled(&self, metadata: &Metadata<'_>, _: Context<'_, S>) -> bool {
+    metadata.target().starts_with(TARGET_PREFIX)
+  }
+
+  fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+    let span = match ctx.span(id) {
+      Some(x) => x,
+      None => return,
+    };
+    let parent = span.parent().and_then(|x| {
+      x.extensions()
+        .get::<SpanData>()
+        .map(|x| (x.trace_id, x.span_id))
+    });
+    let mut data = SpanData {
+      trace_id: parent.map(|x| x.0).unwrap_or_else(random_id),
+      span_id: random_id(),
+      parent_span_id: parent.map(|x| x.1),
+      name: attrs.metadata().name(),
+      start: SystemTime::now(),
+      end: SystemTime::now(),
+      attributes: vec![],
+      error: None,
+    };
+    attrs.record(&mut FieldVisitor(&mut data.attributes));
+    span.extensions_mut().insert(data);
+  }
+
+  fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+    if let Some(span) = ctx.span(id) {
+      if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+        values.record(&mut FieldVisitor(&mut data.attributes));
+      }
+    }
+  }
+
+  fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+    if *event.metadata().level() != Level::ERROR {
+      return;
+    }
+    if let Some(span) = ctx.event_span(event) {
+      if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+        let mut fields = vec![];
+        event.record(&mut FieldVisitor(&mut fields));
+        data.error = Some(
+          fields
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>()
+            .join(", "),
+        );
+      }
+    }
+  }
+
+  fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+    if let Some(span) = ctx.span(&id) {
+      if let Some(mut data) = span.extensions_mut().remove::<SpanData>() {
+        data.end = SystemTime::now();
+        self.spans.lock().unwrap().push(data);
+      }
+    }
+  }
+}
+
+/// Records the fields of a span or event as string attributes.
+struct FieldVisitor<'a>(&'a mut Vec<(String, String)>);
+
+impl Visit for FieldVisitor<'_> {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    self.0.push((field.name().to_string(), value.to_string()));
+  }
+
+  fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+    self
+      .0
+      .push((field.name().to_string(), format!("{:?}", value)));
+  }
+}
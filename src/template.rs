@@ -0,0 +1,142 @@
+use std::{
+  cell::OnceCell,
+  path::{Path, PathBuf},
+  process::Command,
+  time::SystemTime,
+};
+
+/// Variables available to `{{ ... }}` templates in config env values.
+pub const VARIABLES: &[&str] = &["git.sha", "git.short_sha", "timestamp", "profile"];
+
+/// Values of the template variables for one load of a config. Each value is computed at most
+/// once, so that all env values rendered together agree.
+pub struct TemplateContext {
+  dir: PathBuf,
+  profile: Option<String>,
+  timestamp: u64,
+  git_sha: OnceCell<Result<String, String>>,
+}
+
+impl TemplateContext {
+  /// A context for a config in `dir`, loaded with `profile`.
+  pub fn new(dir: &Path, profile: Option<&str>) -> Self {
+    Self {
+      dir: dir.to_path_buf(),
+      profile: profile.map(|x| x.to_string()),
+      timestamp: SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default(),
+      git_sha: OnceCell::new(),
+    }
+  }
+
+  fn git_sha(&self) -> Result<String, String> {
+    self
+      .git_sha
+      .get_or_init(|| {
+        let output = Command::new("git")
+          .arg("-C")
+          .arg(&self.dir)
+          .args(["rev-parse", "HEAD"])
+          .output()
+          .map_err(|e| format!("cannot run git: {}", e))?;
+        if !output.status.success() {
+          return Err(format!("{} is not in a git repository", self.dir.display()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+      })
+      .clone()
+  }
+
+  fn lookup(&self, name: &str) -> Result<String, String> {
+    match name {
+      "git.sha" => self.git_sha(),
+      "git.short_sha" => Ok(self.git_sha()?.chars().take(7).collect()),
+      "timestamp" => Ok(self.timestamp.to_string()),
+      "profile" => Ok(self.profile.clone().unwrap_or_default()),
+      _ => Err(format!("unknown template variable `{}`", name)),
+    }
+  }
+}
+
+/// Renders the `{{ variable }}` placeholders in `value`. A string literal, as in `{{ "{{" }}`,
+/// renders as itself, and may contain `}}`.
+pub fn render(value: &str, ctx: &TemplateContext) -> Result<String, String> {
+  let unterminated = || format!("unterminated template in `{}`", value);
+  let mut out = String::with_capacity(value.len());
+  let mut rest = value;
+  while let Some(start) = rest.find("{{") {
+    out.push_str(&rest[..start]);
+    let inner = rest[start + 2..].trim_start();
+    let after = match inner.strip_prefix('"') {
+      Some(literal) => {
+        let end = literal.find('"').ok_or_else(unterminated)?;
+        out.push_str(&literal[..end]);
+        literal[end + 1..].trim_start()
+      }
+      None => {
+        let end = inner.find("}}").ok_or_else(unterminated)?;
+        out.push_str(&ctx.lookup(inner[..end].trim())?);
+        &inner[end..]
+      }
+    };
+    rest = after
+      .strip_prefix("}}")
+      .ok_or_else(|| format!("expected `}}}}` after the string literal in `{}`", value))?;
+  }
+  out.push_str(rest);
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ctx() -> TemplateContext {
+    TemplateContext::new(Path::new("/nonexistent"), Some("staging"))
+  }
+
+  #[test]
+  fn renders_variables() {
+    let ctx = ctx();
+    assert_eq!(render("no templates", &ctx).unwrap(), "no templates");
+    assert_eq!(render("env-{{profile}}", &ctx).unwrap(), "env-staging");
+    assert_eq!(
+      render("{{ profile }}/{{ profile }}", &ctx).unwrap(),
+      "staging/staging"
+    );
+    assert_eq!(
+      render("{{ timestamp }}", &ctx).unwrap(),
+      ctx.timestamp.to_string()
+    );
+    assert_eq!(
+      render("{{ profile }}", &TemplateContext::new(Path::new("."), None)).unwrap(),
+      ""
+    );
+  }
+
+  #[test]
+  fn renders_literals() {
+    let ctx = ctx();
+    assert_eq!(render(r#"{{ "{{" }}x}}"#, &ctx).unwrap(), "{{x}}");
+    assert_eq!(render(r#"a{{"}}"}}b"#, &ctx).unwrap(), "a}}b");
+    assert_eq!(render(r#"{{ "" }}"#, &ctx).unwrap(), "");
+    assert!(render(r#"{{ "}} }}"#, &ctx).is_err());
+    assert!(render(r#"{{ "a" b }}"#, &ctx).is_err());
+  }
+
+  #[test]
+  fn rejects_bad_templates() {
+    let ctx = ctx();
+    assert_eq!(
+      render("{{ profile", &ctx).unwrap_err(),
+      "unterminated template in `{{ profile`"
+    );
+    assert_eq!(
+      render("{{ home }}", &ctx).unwrap_err(),
+      "unknown template variable `home`"
+    );
+    assert!(render("{{ git.sha }}", &ctx).is_err());
+  }
+}
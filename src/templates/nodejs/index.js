@@ -0,0 +1,3 @@
+Router.get("/", () => {
+  return new Response(`Hello from ${App.env["APP_ENV"]}!`);
+});
@@ -0,0 +1,5 @@
+Router.get("/", () => {
+  return new Response(Package["index.html"], {
+    headers: { "content-type": "text/html" },
+  });
+});
@@ -0,0 +1,5 @@
+Router.post("/", async (req) => {
+  const body = await req.text();
+  console.log(`${App.env["WORKER_NAME"]} received ${body.length} bytes`);
+  return new Response("ok");
+});
@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::user_config;
+
+const PEM_CERTIFICATE_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+/// TLS settings for a self-hosted endpoint behind TLS interception or an mTLS gateway, from
+/// `--ca-cert`, `--client-cert` and `--client-key`. Each setting not given is taken from the
+/// `[tls]` table of `~/.boat/config.toml`, where paths are relative to that file.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TlsOptions {
+  /// PEM file with CA certificates to trust in addition to the system ones.
+  pub ca_cert: Option<PathBuf>,
+  /// PEM file with the client certificate, and any intermediates, for mTLS.
+  pub client_cert: Option<PathBuf>,
+  /// PEM file with the PKCS#8 private key of the client certificate.
+  pub client_key: Option<PathBuf>,
+}
+
+impl TlsOptions {
+  /// A TLS connector that trusts the CA certificates and presents the client certificate,
+  /// or `None` if neither is set.
+  pub fn connector(&self) -> anyhow::Result<Option<native_tls::TlsConnector>> {
+    let options = self.with_user_config()?;
+    if options.ca_cert.is_none() && options.client_cert.is_none() && options.client_key.is_none() {
+      return Ok(None);
+    }
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(path) = &options.ca_cert {
+      let pem = read(path)?;
+      let certs = pem_certificates(&pem);
+      if certs.is_empty() {
+        anyhow::bail!("no PEM certificate in {}", path.display());
+      }
+      for cert in certs {
+        builder.add_root_certificate(
+          native_tls::Certificate::from_pem(cert.as_bytes())
+            .with_context(|| format!("invalid CA certificate in {}", path.display()))?,
+        );
+      }
+    }
+    match (&options.client_cert, &options.client_key) {
+      (Some(cert), Some(key)) => {
+        let identity =
+          native_tls::Identity::from_pkcs8(&read(cert)?, &read(key)?).with_context(|| {
+            format!(
+              "invalid client certificate {} or key {}; the key must be PKCS#8 \
+               (`openssl pkcs8 -topk8 -nocrypt` converts it)",
+              cert.display(),
+              key.display()
+            )
+          })?;
+        builder.identity(identity);
+      }
+      (None, None) => {}
+      _ => anyhow::bail!("a client certificate and key must be given together"),
+    }
+    Ok(Some(builder.build()?))
+  }
+
+  fn with_user_config(&self) -> anyhow::Result<Self> {
+    let config = user_config::load()?.tls;
+    Ok(TlsOptions {
+      ca_cert: self.ca_cert.clone().or(config.ca_cert),
+      client_cert: self.client_cert.clone().or(config.client_cert),
+      client_key: self.client_key.clone().or(config.client_key),
+    })
+  }
+}
+
+fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+  std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))
+}
+
+/// Every certificate of a PEM bundle, as native-tls reads only one at a time.
+fn pem_certificates(pem: &[u8]) -> Vec<String> {
+  let text = String::from_utf8_lossy(pem);
+  let mut certs = vec![];
+  let mut rest = &text[..];
+  while let Some(start) = rest.find(PEM_CERTIFICATE_BEGIN) {
+    let end = match rest[start..].find(PEM_CERTIFICATE_END) {
+      Some(x) => start + x + PEM_CERTIFICATE_END.len(),
+      None => break,
+    };
+    certs.push(rest[start..end].to_string());
+    rest = &rest[end..];
+  }
+  certs
+}
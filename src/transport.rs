@@ -0,0 +1,306 @@
+use std::{io, path::PathBuf};
+
+use async_trait::async_trait;
+use reqwest::{Request, Response};
+use thiserror::Error;
+
+/// How `Service` sends its HTTP requests, both API calls and uploads. `ReqwestTransport` sends
+/// them over the network; with the `test-util` feature, `MockTransport` answers them from
+/// memory instead.
+#[async_trait]
+pub trait Transport: Send + Sync {
+  async fn send(&self, request: Request) -> Result<Response, TransportError>;
+}
+
+/// Why a request got no response.
+#[derive(Error, Debug)]
+pub enum TransportError {
+  #[error(transparent)]
+  Http(#[from] reqwest::Error),
+  #[error("request over Unix socket {} failed", .path.display())]
+  Unix {
+    path: PathBuf,
+    #[source]
+    source: io::Error,
+  },
+}
+
+impl TransportError {
+  /// Whether sending the request again may well succeed: it could not connect, or timed out.
+  pub fn is_transient(&self) -> bool {
+    match self {
+      TransportError::Http(e) => e.is_connect() || e.is_timeout(),
+      TransportError::Unix { source, .. } => matches!(
+        source.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused | io::ErrorKind::TimedOut
+      ),
+    }
+  }
+
+  /// Whether the request failed before it was sent, as the connection could not be made.
+  pub fn is_connect_failure(&self) -> bool {
+    match self {
+      TransportError::Http(e) => e.is_connect(),
+      TransportError::Unix { source, .. } => matches!(
+        source.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+      ),
+    }
+  }
+}
+
+/// Sends requests with a `reqwest::Client`.
+pub struct ReqwestTransport {
+  client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+  pub fn new(client: reqwest::Client) -> Self {
+    ReqwestTransport { client }
+  }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+  async fn send(&self, request: Request) -> Result<Response, TransportError> {
+    Ok(self.client.execute(request).await?)
+  }
+}
+
+#[cfg(unix)]
+pub use unix::UnixTransport;
+
+#[cfg(unix)]
+mod unix {
+  use std::{io, path::PathBuf, sync::Arc};
+
+  use async_trait::async_trait;
+  use reqwest::{header::HeaderValue, Request, Response};
+  use tokio::net::UnixStream;
+
+  use super::{Transport, TransportError};
+  use crate::endpoint::UNIX_SOCKET_API_PATH;
+
+  /// Sends requests to `unix:` urls over a Unix socket, as HTTP/1.1 on a connection of their
+  /// own, and any others, like uploads to storage, with `inner`.
+  pub struct UnixTransport {
+    socket: PathBuf,
+    inner: Arc<dyn Transport>,
+  }
+
+  impl UnixTransport {
+    pub fn new(socket: PathBuf, inner: Arc<dyn Transport>) -> Self {
+      UnixTransport { socket, inner }
+    }
+
+    async fn exchange(&self, request: Request) -> io::Result<Response> {
+      let body = match request.body() {
+        Some(x) => x
+          .as_bytes()
+          .ok_or_else(|| {
+            io::Error::new(
+              io::ErrorKind::InvalidInput,
+              "a streamed body cannot be sent over a Unix socket",
+            )
+          })?
+          .to_vec(),
+        None => vec![],
+      };
+      let mut target = UNIX_SOCKET_API_PATH.to_string();
+      if let Some(query) = request.url().query() {
+        target.push('?');
+        target.push_str(query);
+      }
+      let mut builder = http::Request::builder()
+        .method(request.method().clone())
+        .uri(target);
+      if let Some(headers) = builder.headers_mut() {
+        *headers = request.headers().clone();
+        headers.insert("host", HeaderValue::from_static("localhost"));
+      }
+      let hyper_request = builder
+        .body(hyper::Body::from(body))
+        .map_err(io::Error::other)?;
+
+      let stream = UnixStream::connect(&self.socket).await?;
+      let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(io::Error::other)?;
+      tokio::spawn(async move {
+        if let Err(e) = connection.await {
+          log::debug!("unix socket connection failed: {}", e);
+        }
+      });
+      let response = sender
+        .send_request(hyper_request)
+        .await
+        .map_err(io::Error::other)?;
+      let (parts, body) = response.into_parts();
+      let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(io::Error::other)?;
+      Ok(Response::from(http::Response::from_parts(parts, body)))
+    }
+  }
+
+  #[async_trait]
+  impl Transport for UnixTransport {
+    async fn send(&self, request: Request) -> Result<Response, TransportError> {
+      if request.url().scheme() != "unix" {
+        return self.inner.send(request).await;
+      }
+      let result = match request.timeout().copied() {
+        Some(timeout) => tokio::time::timeout(timeout, self.exchange(request))
+          .await
+          .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))),
+        None => self.exchange(request).await,
+      };
+      result.map_err(|source| TransportError::Unix {
+        path: self.socket.clone(),
+        source,
+      })
+    }
+  }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::{MockTransport, RecordedRequest};
+
+#[cfg(any(test, feature = "test-util"))]
+mod mock {
+  use std::sync::{Arc, Mutex};
+
+  use async_trait::async_trait;
+  use reqwest::{header::HeaderMap, Method, Request, Response, StatusCode, Url};
+
+  use super::{Transport, TransportError};
+
+  /// A `Transport` that answers requests with canned responses and records them, so that
+  /// `Service` can be exercised without a Lighthouse endpoint or storage. Requests that no
+  /// response is set up for get 404 Not Found.
+  #[derive(Clone, Default)]
+  pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+  }
+
+  #[derive(Default)]
+  struct MockState {
+    routes: Vec<Route>,
+    requests: Vec<RecordedRequest>,
+  }
+
+  struct Route {
+    matcher: Matcher,
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+  }
+
+  enum Matcher {
+    Graphql(String),
+    Url(Method, String),
+  }
+
+  /// A request sent through a `MockTransport`.
+  #[derive(Debug, Clone)]
+  pub struct RecordedRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    /// The body, unless it is streamed, like the bodies of uploads are. The `content-length`
+    /// header still gives the size of those.
+    pub body: Option<Vec<u8>>,
+  }
+
+  impl RecordedRequest {
+    /// The `operationName` of a GraphQL request.
+    pub fn graphql_operation(&self) -> Option<String> {
+      let body = serde_json::from_slice::<serde_json::Value>(self.body.as_ref()?).ok()?;
+      Some(body.get("operationName")?.as_str()?.to_string())
+    }
+  }
+
+  impl MockTransport {
+    pub fn new() -> Self {
+      Self::default()
+    }
+
+    /// Answers the GraphQL operation `operation`, e.g. `RunDeploymentPreparation`, with
+    /// `response`, the whole JSON response with its `data` and `errors`.
+    pub fn on_graphql(&self, operation: &str, response: serde_json::Value) -> &Self {
+      self.add(Route {
+        matcher: Matcher::Graphql(operation.to_string()),
+        status: StatusCode::OK,
+        headers: vec![("content-type".into(), "application/json".into())],
+        body: response.to_string().into_bytes(),
+      })
+    }
+
+    /// Answers requests with `method` to `url` with `status`, `headers` and `body`.
+    pub fn on(
+      &self,
+      method: Method,
+      url: &str,
+      status: StatusCode,
+      headers: &[(&str, &str)],
+      body: impl Into<Vec<u8>>,
+    ) -> &Self {
+      self.add(Route {
+        matcher: Matcher::Url(method, url.to_string()),
+        status,
+        headers: headers
+          .iter()
+          .map(|(k, v)| (k.to_string(), v.to_string()))
+          .collect(),
+        body: body.into(),
+      })
+    }
+
+    /// The requests sent so far, oldest first.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+      self.state.lock().unwrap().requests.clone()
+    }
+
+    fn add(&self, route: Route) -> &Self {
+      self.state.lock().unwrap().routes.push(route);
+      self
+    }
+  }
+
+  #[async_trait]
+  impl Transport for MockTransport {
+    async fn send(&self, request: Request) -> Result<Response, TransportError> {
+      let recorded = RecordedRequest {
+        method: request.method().clone(),
+        url: request.url().clone(),
+        headers: request.headers().clone(),
+        body: request
+          .body()
+          .and_then(|x| x.as_bytes())
+          .map(|x| x.to_vec()),
+      };
+      let operation = recorded.graphql_operation();
+      let mut state = self.state.lock().unwrap();
+      // Later routes win, so that a test can override an earlier setup.
+      let route = state.routes.iter().rev().find(|x| match &x.matcher {
+        Matcher::Graphql(name) => {
+          recorded.method == Method::POST && operation.as_deref() == Some(name)
+        }
+        Matcher::Url(method, url) => recorded.method == *method && recorded.url.as_str() == url,
+      });
+      let mut response = http::Response::builder().status(StatusCode::NOT_FOUND);
+      let mut body = vec![];
+      if let Some(route) = route {
+        response = response.status(route.status);
+        for (k, v) in &route.headers {
+          response = response.header(k, v);
+        }
+        body = route.body.clone();
+      }
+      state.requests.push(recorded);
+      Ok(Response::from(
+        response.body(body).expect("valid mock response"),
+      ))
+    }
+  }
+}
@@ -0,0 +1,159 @@
+use std::{
+  collections::BTreeMap,
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{metadata::DeploymentAnnotations, package_file::PackageFile, provenance::Provenance};
+
+/// Where the state of an unfinished upload is kept, relative to the spec directory.
+pub const UPLOAD_STATE_PATH: &str = ".boat/upload-state.json";
+
+/// Copy of the package of an unfinished upload, relative to the spec directory.
+const UPLOAD_PACKAGE_PATH: &str = ".boat/upload-package";
+
+/// How far a `boat deploy` got, so that `boat deploy --resume` can pick up from there after an
+/// interruption instead of building and uploading again. The config, secrets included, is not
+/// kept and is loaded again on resume.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadState {
+  pub app_id: String,
+  pub package_sha256: String,
+  pub annotations: DeploymentAnnotations,
+  /// Provenance of the build that produced the package.
+  #[serde(default)]
+  pub provenance: Option<Provenance>,
+  /// Set once the service has prepared the upload.
+  pub prepared: Option<PreparedUpload>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreparedUpload {
+  /// Package that the deployment is created from once uploaded.
+  pub package: String,
+  /// Set if the package is uploaded in parts, which can be resumed part by part. Any other
+  /// upload starts over on resume.
+  pub multipart: Option<MultipartState>,
+  /// Whether the upload is complete, so that only the deployment is left to create.
+  pub uploaded: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MultipartState {
+  pub upload_id: String,
+  pub part_size: u64,
+  pub part_urls: Vec<String>,
+  /// `ETag`s of the parts uploaded so far, by part number from 1.
+  pub etags: BTreeMap<i64, String>,
+}
+
+/// The `UploadState` of a deployment in progress, written to disk on every change.
+pub struct UploadJournal {
+  dir: PathBuf,
+  state: Mutex<UploadState>,
+}
+
+impl UploadJournal {
+  /// Starts the journal of deploying `package` from the app in `spec_dir`, replacing that of
+  /// any earlier unfinished upload. A copy of the package is kept along with it.
+  pub fn create(
+    spec_dir: &Path,
+    app_id: &str,
+    package: &PackageFile,
+    annotations: &DeploymentAnnotations,
+    provenance: Option<&Provenance>,
+  ) -> anyhow::Result<Self> {
+    let package_path = spec_dir.join(UPLOAD_PACKAGE_PATH);
+    if let Some(parent) = package_path.parent() {
+      std::fs::create_dir_all(parent)
+        .with_context(|| format!("cannot create {}", parent.display()))?;
+    }
+    let _ = std::fs::remove_file(&package_path);
+    // A link is enough when the temporary directory is on the same filesystem.
+    if std::fs::hard_link(package.path(), &package_path).is_err() {
+      std::fs::copy(package.path(), &package_path)
+        .with_context(|| format!("cannot write {}", package_path.display()))?;
+    }
+    let journal = UploadJournal {
+      dir: spec_dir.to_path_buf(),
+      state: Mutex::new(UploadState {
+        app_id: app_id.to_string(),
+        package_sha256: package.sha256().to_string(),
+        annotations: annotations.clone(),
+        provenance: provenance.cloned(),
+        prepared: None,
+      }),
+    };
+    journal.save(&journal.state.lock().unwrap())?;
+    Ok(journal)
+  }
+
+  /// Opens the journal of the unfinished upload of the app in `spec_dir`.
+  pub fn open(spec_dir: &Path) -> anyhow::Result<Self> {
+    let path = spec_dir.join(UPLOAD_STATE_PATH);
+    let state = match std::fs::read(&path) {
+      Ok(x) => serde_json::from_slice(&x)
+        .with_context(|| format!("cannot parse upload state {}", path.display()))?,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+        anyhow::bail!("there is no interrupted deployment to resume")
+      }
+      Err(e) => {
+        return Err(
+          anyhow::Error::from(e).context(format!("cannot read upload state {}", path.display())),
+        )
+      }
+    };
+    Ok(UploadJournal {
+      dir: spec_dir.to_path_buf(),
+      state: Mutex::new(state),
+    })
+  }
+
+  pub fn state(&self) -> UploadState {
+    self.state.lock().unwrap().clone()
+  }
+
+  /// A copy of the kept package, checked against its hash from when the upload started.
+  pub fn package(&self) -> anyhow::Result<PackageFile> {
+    let path = self.dir.join(UPLOAD_PACKAGE_PATH);
+    let package = PackageFile::copy_of(&path)
+      .with_context(|| format!("cannot read package {}", path.display()))?;
+    let expected = self.state.lock().unwrap().package_sha256.clone();
+    if package.sha256() != expected {
+      anyhow::bail!(
+        "package {} has sha256 {}, expected {}",
+        path.display(),
+        package.sha256(),
+        expected
+      );
+    }
+    Ok(package)
+  }
+
+  /// Changes the state with `f` and saves it.
+  pub fn update(&self, f: impl FnOnce(&mut UploadState)) -> anyhow::Result<()> {
+    let mut state = self.state.lock().unwrap();
+    f(&mut state);
+    self.save(&state)
+  }
+
+  /// Removes the state and the package once the deployment is created.
+  pub fn finish(self) -> anyhow::Result<()> {
+    std::fs::remove_file(self.dir.join(UPLOAD_STATE_PATH))?;
+    std::fs::remove_file(self.dir.join(UPLOAD_PACKAGE_PATH))?;
+    Ok(())
+  }
+
+  /// Writes `state` to a temporary file first, so that an interruption never leaves a
+  /// truncated state behind.
+  fn save(&self, state: &UploadState) -> anyhow::Result<()> {
+    let path = self.dir.join(UPLOAD_STATE_PATH);
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)
+      .and_then(|_| std::fs::rename(&tmp, &path))
+      .with_context(|| format!("cannot write upload state {}", path.display()))
+  }
+}
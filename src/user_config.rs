@@ -0,0 +1,211 @@
+//! Defaults that apply to every app, from `~/.boat/config.toml`:
+//!
+//! ```toml
+//! endpoint = "https://lighthouse.example.com/graphql"
+//! profile = "staging"
+//! color = "never"
+//! telemetry = false
+//! credentials = "work-credentials.json"
+//! auth_profile = "work"
+//!
+//! [tls]
+//! ca_cert = "corp-ca.pem"
+//! ```
+//!
+//! A setting is taken from the first of these that has it: a command-line flag, its env
+//! variable, this file, and the built-in default. Relative paths are resolved against the
+//! directory of the file.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+use toml_edit::{Document, Item};
+
+use crate::{output::ColorPreference, tls::TlsOptions};
+
+/// Path of the user config, relative to the home directory.
+const USER_CONFIG_PATH: &str = ".boat/config.toml";
+
+/// Settings that `boat config set` accepts, with tables and keys joined by a dot.
+pub const KEYS: &[&str] = &[
+  "endpoint",
+  "profile",
+  "color",
+  "telemetry",
+  "credentials",
+  "auth_profile",
+  "tls.ca_cert",
+  "tls.client_cert",
+  "tls.client_key",
+];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserConfig {
+  /// Default of `--endpoint`.
+  pub endpoint: Option<String>,
+  /// Default of `--profile`.
+  pub profile: Option<String>,
+  /// Default of `--color`.
+  pub color: Option<ColorPreference>,
+  /// `false` to never export spans, even if `BOAT_OTEL_ENDPOINT` is set.
+  pub telemetry: Option<bool>,
+  /// Default of `--credentials`.
+  pub credentials: Option<PathBuf>,
+  /// Default of `--profile-auth`.
+  pub auth_profile: Option<String>,
+  /// Defaults of `--ca-cert`, `--client-cert` and `--client-key`.
+  #[serde(default)]
+  pub tls: TlsOptions,
+}
+
+/// `~/.boat/config.toml`, or `None` if there is no home directory.
+pub fn path() -> Option<PathBuf> {
+  dirs::home_dir().map(|x| x.join(USER_CONFIG_PATH))
+}
+
+/// Reads the user config, or the defaults if there is none.
+pub fn load() -> anyhow::Result<UserConfig> {
+  let path = match path() {
+    Some(x) => x,
+    None => return Ok(UserConfig::default()),
+  };
+  let text = match std::fs::read_to_string(&path) {
+    Ok(x) => x,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(UserConfig::default()),
+    Err(e) => {
+      return Err(anyhow::Error::from(e).context(format!("cannot read {}", path.display())))
+    }
+  };
+  let mut config: UserConfig =
+    toml::from_str(&text).with_context(|| format!("cannot parse {}", path.display()))?;
+  let base = path.parent().unwrap_or_else(|| Path::new("/"));
+  for path in [
+    &mut config.credentials,
+    &mut config.tls.ca_cert,
+    &mut config.tls.client_cert,
+    &mut config.tls.client_key,
+  ] {
+    *path = path.take().map(|x| base.join(x));
+  }
+  Ok(config)
+}
+
+/// The value of `key` as written in the user config, or `None` if it is not set.
+pub fn get(key: &str) -> anyhow::Result<Option<String>> {
+  check_key(key)?;
+  let (_, doc) = open()?;
+  let item = match key.split_once('.') {
+    Some((table, key)) => doc.get(table).and_then(|x| x.get(key)),
+    None => doc.get(key),
+  };
+  Ok(item.and_then(Item::as_value).map(|x| match x.as_str() {
+    Some(x) => x.to_string(),
+    None => x.to_string().trim().to_string(),
+  }))
+}
+
+/// Every setting of the user config, in the order of `KEYS`.
+pub fn entries() -> anyhow::Result<Vec<(&'static str, String)>> {
+  let mut entries = vec![];
+  for key in KEYS {
+    if let Some(value) = get(key)? {
+      entries.push((*key, value));
+    }
+  }
+  Ok(entries)
+}
+
+/// Sets `key` to `value` in the user config, creating it if needed, and returns its path.
+/// Comments and formatting of the file are kept.
+pub fn set(key: &str, value: &str) -> anyhow::Result<PathBuf> {
+  check_key(key)?;
+  let value = match key {
+    "telemetry" => toml_edit::value(
+      value
+        .parse::<bool>()
+        .map_err(|_| anyhow::anyhow!("`telemetry` must be `true` or `false`"))?,
+    ),
+    "color" => toml_edit::value(value.parse::<ColorPreference>()?.as_str()),
+    _ => toml_edit::value(value),
+  };
+  let (path, mut doc) = open()?;
+  match key.split_once('.') {
+    Some((table, key)) => {
+      if doc.get(table).is_none() {
+        doc.insert(table, toml_edit::table());
+      }
+      doc[table]
+        .as_table_like_mut()
+        .ok_or_else(|| anyhow::anyhow!("`{}` in {} is not a table", table, path.display()))?
+        .insert(key, value);
+    }
+    None => {
+      doc.insert(key, value);
+    }
+  }
+  save(&path, &doc)?;
+  Ok(path)
+}
+
+/// Removes `key` from the user config, so that the built-in default applies, and returns
+/// whether it was set.
+pub fn unset(key: &str) -> anyhow::Result<bool> {
+  check_key(key)?;
+  let (path, mut doc) = open()?;
+  let removed = match key.split_once('.') {
+    Some((table, key)) => {
+      let removed = doc
+        .get_mut(table)
+        .and_then(Item::as_table_like_mut)
+        .and_then(|x| x.remove(key))
+        .is_some();
+      if doc[table].as_table_like().map(|x| x.is_empty()) == Some(true) {
+        doc.remove(table);
+      }
+      removed
+    }
+    None => doc.remove(key).is_some(),
+  };
+  if removed {
+    save(&path, &doc)?;
+  }
+  Ok(removed)
+}
+
+fn check_key(key: &str) -> anyhow::Result<()> {
+  if !KEYS.contains(&key) {
+    anyhow::bail!(
+      "unknown setting `{}` (expected one of {})",
+      key,
+      KEYS.join(", ")
+    );
+  }
+  Ok(())
+}
+
+fn open() -> anyhow::Result<(PathBuf, Document)> {
+  let path = path().ok_or_else(|| anyhow::anyhow!("cannot find the home directory"))?;
+  let text = match std::fs::read_to_string(&path) {
+    Ok(x) => x,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+    Err(e) => {
+      return Err(anyhow::Error::from(e).context(format!("cannot read {}", path.display())))
+    }
+  };
+  let doc = text
+    .parse::<Document>()
+    .with_context(|| format!("cannot parse {}", path.display()))?;
+  Ok((path, doc))
+}
+
+/// Writes `doc` to `path` if it still is a valid user config.
+fn save(path: &Path, doc: &Document) -> anyhow::Result<()> {
+  let text = doc.to_string();
+  toml::from_str::<UserConfig>(&text)
+    .with_context(|| format!("refusing to write an invalid {}", path.display()))?;
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+  }
+  std::fs::write(path, text).with_context(|| format!("cannot write {}", path.display()))
+}
@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+/// Contents of `Boat.workspace.toml`, mapping app names to their directories.
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorkspaceConfig {
+  pub apps: IndexMap<String, String>,
+}
+
+/// An app in a workspace, with its directory resolved against the workspace file.
+#[derive(Debug, Clone)]
+pub struct WorkspaceMember {
+  pub name: String,
+  pub dir: PathBuf,
+}
+
+pub fn load(path: &str) -> anyhow::Result<Vec<WorkspaceMember>> {
+  let path = std::fs::canonicalize(path)
+    .map_err(|e| anyhow::Error::from(e).context(format!("cannot resolve workspace {}", path)))?;
+  let text = std::fs::read_to_string(&path)
+    .map_err(|e| anyhow::Error::from(e).context("cannot read workspace"))?;
+  let workspace: WorkspaceConfig =
+    toml::from_str(&text).map_err(|e| anyhow::Error::from(e).context("cannot parse workspace"))?;
+  let root = path.parent().unwrap_or_else(|| Path::new("/"));
+  Ok(
+    workspace
+      .apps
+      .into_iter()
+      .map(|(name, dir)| WorkspaceMember {
+        name,
+        dir: root.join(dir),
+      })
+      .collect(),
+  )
+}
+
+/// Picks the members named in `names`, or all members if `all` is set.
+pub fn select(
+  members: Vec<WorkspaceMember>,
+  all: bool,
+  names: &[String],
+) -> anyhow::Result<Vec<WorkspaceMember>> {
+  if all {
+    return Ok(members);
+  }
+  names
+    .iter()
+    .map(|name| {
+      members
+        .iter()
+        .find(|x| x.name == *name)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("app {} is not a workspace member", name))
+    })
+    .collect()
+}